@@ -1,17 +1,16 @@
-use bodyparser;
-use exonum::api::{Api, ApiError};
+use contracts::ErrorKind;
+use exonum::api::{self, ServiceApiBuilder, ServiceApiState};
 use exonum::blockchain::*;
 use exonum::crypto::{Hash, PublicKey};
 use exonum::helpers::Height;
 use exonum::node::TransactionSend;
-use exonum::storage::Snapshot;
-use iron::prelude::*;
-use router::Router;
 use schema::*;
 use schema::EmployeeId;
-use serde_json;
-use std::fmt;
+use service::SERVICE_ID;
+use std::cmp::min;
 use transactions::EmployeesTransactions;
+use zen_rolesystem::api_common::{self, HistoryQuery, PageQuery, ServiceStats, SignedExport,
+                                 MAX_PAGE_SIZE};
 
 /// The structure returned by the REST API.
 #[derive(Debug, Serialize, Deserialize)]
@@ -20,180 +19,259 @@ pub struct TransactionResponse {
     pub tx_hash: Hash,
 }
 
-/// Account custom data.
-#[derive(Debug, Serialize)]
-pub struct AccountInfo {
-    custom_data: String,
+/// Query of the endpoints that take an account.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct AccountQuery {
+    /// Public key of the account.
+    pub pub_key: PublicKey,
 }
 
-/// Employees service API description.
-#[derive(Clone)]
-pub struct EmployeesApi<T: TransactionSend + Clone> {
-    /// Exonum blockchain.
-    pub blockchain: Blockchain,
-    /// Channel for transactions.
-    pub channel: T,
+/// Query for an account by the employee ID.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct EmployeeQuery {
+    /// Employee ID of the account.
+    pub id: EmployeeId,
 }
 
-impl<T> EmployeesApi<T>
-    where
-        T: TransactionSend + Clone + 'static,
-{
-    /// Checks that given `Block` has transactions that did modified a specific `Account`
-    fn helper_check_tx_modifies_account(&self, schema: &Schema<&Box<Snapshot>>, block: &Block, account: &Account) -> bool {
-        let block_transactions = schema.block_transactions(block.height());
-
-        let mut found = false;
-
-        // Get transaction bytes by its hash and convert it into an actual `Transaction`
-        for tx_hash in block_transactions.iter() {
-            let transaction = schema.transactions().get(&tx_hash);
-
-            if let Some(tx) = transaction {
-                let tx =  EmployeesTransactions::tx_from_raw(tx);
-
-                if let Ok(tx) = tx {
-                    // Is there any of possible transactions that modifies a specific account?
-                    found = match tx {
-                        EmployeesTransactions::TxCreateAccount(tx) => {
-                            tx.pub_key() == account.pub_key()
-                        }
-
-                        EmployeesTransactions::TxEditAccount(tx) => {
-                            tx.pkey_account() == account.pub_key()
-                        }
-
-                        EmployeesTransactions::TxSetCustomData(tx) => {
-                            tx.pkey_account() == account.pub_key()
-                        }
-
-                        EmployeesTransactions::TxDeleteAccount(tx) => {
-                            tx.pkey_account() == account.pub_key()
-                        }
-                    };
-
-                    if found {
-                        break
-                    }
-                }
-            }
-        }
-
-        found
-    }
+/// Query for a field of the account.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FieldQuery {
+    /// Public key of the account.
+    pub pub_key: PublicKey,
+    /// Field name.
+    pub name: String,
+}
 
-    /// Finds number (height) of the block which contains a latest transaction that modifies
-    /// a given account.
-    ///
-    /// Complexity: `O(n)`, where: `n` is number of blocks in the chain, since lookup in the `Map`s are `O(1)`
-    fn block_by_account(&self, account: &Account) -> Option<Height> {
-        use std::cmp::max;
+/// Query for a transaction by its hash.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct TransactionQuery {
+    /// Hash of the transaction.
+    pub hash: Hash,
+}
 
-        let view = self.blockchain.snapshot();
-        let schema = Schema::new(&view);
-        let blocks = schema.blocks();
+/// Status of a transaction of the service together with its decoded body.
+pub type TransactionInfo = api_common::TransactionInfo<EmployeesTransactions>;
 
-        // Look for a related blocks that contains transactions that are changing given account
-        // and take the latest (with max. height)
-        let mut max_height = None;
+/// Account information together with the proofs.
+pub type AccountWithProof = api_common::AccountWithProof<Account>;
 
-        blocks.iter()
-            // Look for related blocks
-            .filter(|&(_, ref block)| self.helper_check_tx_modifies_account(&schema, block, &account))
+/// Page of the account history.
+pub type AccountHistory = api_common::AccountHistory<EmployeesTransactions>;
 
-            // Find the latest one (with max. height)
-            .for_each( | (_, block) | max_height = Some(max(block.height(), max_height.unwrap_or(Height(0)))));
+/// Page of the accounts.
+#[derive(Debug, Serialize)]
+pub struct AccountsPage {
+    /// Index of the first returned account.
+    pub offset: u64,
+    /// Accounts ordered by the public key.
+    pub accounts: Vec<Account>,
+}
 
-        max_height
-    }
+/// Field of the account and the public keys allowed to read it.
+#[derive(Debug, Serialize)]
+pub struct FieldAccess {
+    /// Field name.
+    pub name: String,
+    /// Version of the current content key.
+    pub key_version: u64,
+    /// Public keys the current content key is wrapped for.
+    pub grantees: Vec<PublicKey>,
+}
 
-    /// Same as `block_by_account` but firstly looks up for an account
-    /// that is associated with the specific `EmployeeId`
-    fn block_by_employee_id(&self, id: &EmployeeId) -> Option<Height> {
-        let view = self.blockchain.snapshot();
-        let employees_schema = EmployeesSchema::new(view);
+/// Encrypted field with its wrapped content keys.
+#[derive(Debug, Serialize)]
+pub struct FieldWithKeys {
+    /// Encrypted field.
+    pub field: DataField,
+    /// Current content key wrapped for every grantee.
+    pub wrapped_keys: Vec<WrappedKey>,
+}
 
-        if let Some(account) = employees_schema.account_by_id(id) {
-            return self.block_by_account(&account);
-        }
+fn account_not_found() -> api::Error {
+    api::Error::NotFound("Account not found".to_owned())
+}
 
-        None
+/// Public API of the Employees service.
+#[derive(Debug, Clone, Copy)]
+pub struct PublicApi;
+
+impl PublicApi {
+    /// Endpoint for sending a signed transaction to the node.
+    pub fn post_transaction(
+        state: &ServiceApiState,
+        query: EmployeesTransactions,
+    ) -> api::Result<TransactionResponse> {
+        let transaction: Box<Transaction> = query.into();
+        let tx_hash = transaction.hash();
+        state.sender().send(transaction)?;
+        Ok(TransactionResponse { tx_hash })
     }
 
-    fn transaction(&self, req: &mut Request) -> IronResult<Response> {
-        match req.get::<bodyparser::Struct<EmployeesTransactions>>() {
-            Ok(Some(transaction)) => {
-                let transaction: Box<Transaction> = transaction.into();
-                let tx_hash = transaction.hash();
-                self.channel.send(transaction).map_err(ApiError::from)?;
-                let json = TransactionResponse { tx_hash };
-                self.ok_response(&serde_json::to_value(&json).unwrap())
-            }
-
-            Ok(None) => Err(ApiError::BadRequest("Empty request body".into()))?,
-            Err(e) => Err(ApiError::BadRequest(e.to_string()))?,
-        }
+    /// Endpoint for getting the status of a transaction of the service: pending,
+    /// committed or failed with the decoded `ErrorKind`.
+    pub fn transaction_info(
+        state: &ServiceApiState,
+        query: TransactionQuery,
+    ) -> api::Result<TransactionInfo> {
+        let view = state.snapshot();
+        api_common::transaction_info(&*view, SERVICE_ID, query.hash, ErrorKind::from_code)
     }
 
-    fn account(&self, req: &mut Request) -> IronResult<Response> {
-        let pub_key: PublicKey = self.url_fragment(req, "pubkey")?;
-        let view = self.blockchain.snapshot();
-        let schema = EmployeesSchema::new(view);
-        if let Some(account) = schema.account(&pub_key) {
-            self.ok_response(&serde_json::to_value(&account).unwrap())
-        } else {
-            self.not_found_response(&serde_json::to_value("Account not found").unwrap())
-        }
+    /// Endpoint for getting a page of the accounts ordered by the public key.
+    pub fn accounts(state: &ServiceApiState, query: PageQuery) -> api::Result<AccountsPage> {
+        let view = state.snapshot();
+        let schema = EmployeesSchema::new(&view);
+
+        let accounts = schema
+            .accounts()
+            .values()
+            .skip(query.offset as usize)
+            .take(min(query.count, MAX_PAGE_SIZE) as usize)
+            .collect();
+
+        Ok(AccountsPage {
+            offset: query.offset,
+            accounts,
+        })
     }
 
-    fn block_by_id(&self, req: &mut Request) -> IronResult<Response> {
-        let employee_id: EmployeeId = self.url_fragment(req, "id")?;
+    /// Endpoint for getting an account together with the proofs that let
+    /// a light client verify it against the latest block.
+    pub fn account(state: &ServiceApiState, query: AccountQuery) -> api::Result<AccountWithProof> {
+        let view = state.snapshot();
+        let schema = EmployeesSchema::new(&view);
+
+        Ok(api_common::account_with_proof(
+            &*view,
+            SERVICE_ID,
+            schema.accounts().get_proof(query.pub_key),
+            schema.account(&query.pub_key),
+        ))
+    }
 
-        if let Some(height) = self.block_by_employee_id(&employee_id) {
-            self.ok_response(&serde_json::to_value(&height).unwrap())
-        } else {
-            self.not_found_response(&serde_json::to_value("Account not found").unwrap())
-        }
+    /// Endpoint for getting height of the block which contains the latest transaction
+    /// of the account associated with the employee ID.
+    ///
+    /// Complexity: `O(1)`, the latest transaction is taken from the account history.
+    pub fn employee_block(state: &ServiceApiState, query: EmployeeQuery) -> api::Result<Height> {
+        let view = state.snapshot();
+        let schema = EmployeesSchema::new(&view);
+
+        let account = schema.account_by_id(&query.id).ok_or_else(account_not_found)?;
+        let tx_hash = schema
+            .account_history(account.pub_key())
+            .last()
+            .ok_or_else(account_not_found)?;
+
+        Schema::new(&view)
+            .transactions_locations()
+            .get(&tx_hash)
+            .map(|location| location.block_height())
+            .ok_or_else(account_not_found)
     }
 
-    /// Endpoint for dumping all accounts from the storage.
-    fn accounts(&self, _: &mut Request) -> IronResult<Response> {
-        let snapshot = self.blockchain.snapshot();
-        let schema = EmployeesSchema::new(snapshot);
-        let idx = schema.accounts();
-        let accounts: Vec<Account> = idx.values().collect();
+    /// Endpoint for getting a page of the account history, from the oldest
+    /// transaction to the newest one.
+    pub fn account_history(
+        state: &ServiceApiState,
+        query: HistoryQuery,
+    ) -> api::Result<AccountHistory> {
+        let view = state.snapshot();
+        let schema = EmployeesSchema::new(&view);
+        let history = schema.account_history(&query.pub_key);
+
+        Ok(api_common::account_history(&*view, &history, &query))
+    }
 
-        self.ok_response(&serde_json::to_value(&accounts).unwrap())
+    /// Endpoint for listing the fields of the account together with the
+    /// public keys that currently hold their content keys.
+    pub fn field_access(
+        state: &ServiceApiState,
+        query: AccountQuery,
+    ) -> api::Result<Vec<FieldAccess>> {
+        let view = state.snapshot();
+        let schema = EmployeesSchema::new(&view);
+
+        Ok(schema
+            .fields(&query.pub_key)
+            .values()
+            .map(|field| FieldAccess {
+                grantees: schema.field_keys(&query.pub_key, field.name()).keys().collect(),
+                name: field.name().to_owned(),
+                key_version: field.key_version(),
+            })
+            .collect())
     }
-}
 
-impl<T: TransactionSend + Clone> fmt::Debug for EmployeesApi<T> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "EmployeesApi {{}}")
+    /// Endpoint for getting an encrypted field together with its wrapped content keys.
+    pub fn field(state: &ServiceApiState, query: FieldQuery) -> api::Result<FieldWithKeys> {
+        let view = state.snapshot();
+        let schema = EmployeesSchema::new(&view);
+
+        let field = schema
+            .field(&query.pub_key, &query.name)
+            .ok_or_else(|| api::Error::NotFound("Field not found".to_owned()))?;
+
+        Ok(FieldWithKeys {
+            field,
+            wrapped_keys: schema.field_keys(&query.pub_key, &query.name).values().collect(),
+        })
     }
-}
 
-impl<T> Api for EmployeesApi<T>
-    where
-        T: 'static + TransactionSend + Clone,
-{
-    fn wire(&self, router: &mut Router) {
-        let self_ = self.clone();
-        let get_accounts =move |req: &mut Request| self_.accounts(req);
+    /// Adds the public endpoints to the builder.
+    pub fn wire(builder: &mut ServiceApiBuilder) {
+        builder
+            .public_scope()
+            .endpoint("v1/accounts", Self::accounts)
+            .endpoint("v1/accounts/info", Self::account)
+            .endpoint("v1/accounts/history", Self::account_history)
+            .endpoint("v1/accounts/fields", Self::field_access)
+            .endpoint("v1/accounts/field", Self::field)
+            .endpoint("v1/employees/block", Self::employee_block)
+            .endpoint("v1/transactions", Self::transaction_info)
+            .endpoint_mut("v1/accounts/transaction", Self::post_transaction);
+    }
+}
 
-        let self_ = self.clone();
-        let get_block_by_id = move |req: &mut Request| self_.block_by_id(req);
+/// Private API of the Employees service for the node operators. It is served
+/// only on the private API address of the node.
+#[derive(Debug, Clone, Copy)]
+pub struct PrivateApi;
 
-        let self_ = self.clone();
-        let get_account = move |req: &mut Request| self_.account(req);
+impl PrivateApi {
+    /// Endpoint for dumping all accounts from the storage.
+    pub fn all_accounts(state: &ServiceApiState, _query: ()) -> api::Result<Vec<Account>> {
+        let view = state.snapshot();
+        Ok(EmployeesSchema::new(&view).accounts().values().collect())
+    }
 
-        let self_ = self.clone();
-        let post_transaction = move |req: &mut Request| self_.transaction(req);
+    /// Endpoint for getting sizes of the service tables and the components
+    /// of the service state hash.
+    pub fn stats(state: &ServiceApiState, _query: ()) -> api::Result<ServiceStats> {
+        let view = state.snapshot();
+        let schema = EmployeesSchema::new(&view);
+
+        Ok(api_common::stats(
+            &*view,
+            schema.schema_version(),
+            schema.index_sizes(),
+            schema.state_hash_components(),
+        ))
+    }
 
+    /// Endpoint for exporting all accounts signed with the service key of the node.
+    pub fn export_accounts(state: &ServiceApiState, _query: ()) -> api::Result<SignedExport> {
+        let view = state.snapshot();
+        let accounts = EmployeesSchema::new(&view).accounts().values().collect();
+        Ok(api_common::export_accounts(state, &*view, accounts))
+    }
 
-        router.get("/v1/accounts", get_accounts, "get_accounts");
-        router.post("/v1/accounts/transaction", post_transaction, "post_transaction");
-        router.get("/v1/accounts/:pubkey", get_account, "account");
-        router.get("/v1/blocks/:id", get_block_by_id, "block_by_id");
+    /// Adds the private endpoints to the builder.
+    pub fn wire(builder: &mut ServiceApiBuilder) {
+        builder
+            .private_scope()
+            .endpoint("v1/accounts/all", Self::all_accounts)
+            .endpoint("v1/admin/stats", Self::stats)
+            .endpoint("v1/admin/export", Self::export_accounts);
     }
-}
\ No newline at end of file
+}
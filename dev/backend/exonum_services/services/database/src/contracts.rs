@@ -1,8 +1,72 @@
 use exonum::{messages::Message, storage::Fork};
-use exonum::blockchain::{ExecutionError, ExecutionResult, Transaction};
-use num_traits::ToPrimitive;
+use exonum::blockchain::{ExecutionError, ExecutionResult, Schema, Transaction};
+use exonum::crypto::{Hash, PublicKey};
+use governance::AdminAction;
+use migration;
+use num_traits::{FromPrimitive, ToPrimitive};
 use schema::*;
-use transactions::{TxCreateAccount, TxDeleteAccount, TxEditAccount, TxSetCustomData};
+use std::collections::BTreeSet;
+use transactions::{TxApproveAdminAction, TxCreateAccount, TxDeleteAccount, TxEditAccount,
+                   TxGrantAccess, TxMigrateSchema, TxProposeAdminAction, TxRevokeAccess,
+                   TxSetCustomData, TxSetField};
+use zen_rolesystem::schema::{permissions, RoleSystemSchema};
+
+/// Returns the set of grantees if every grantee has exactly one wrapped key.
+fn distinct_grantees(wrapped_keys: &[WrappedKey]) -> Option<BTreeSet<PublicKey>> {
+    let grantees: BTreeSet<PublicKey> = wrapped_keys.iter().map(|key| *key.grantee()).collect();
+
+    if grantees.len() == wrapped_keys.len() {
+        Some(grantees)
+    } else {
+        None
+    }
+}
+
+/// Returns the height of the block that is being executed.
+fn current_height(view: &Fork) -> u64 {
+    Schema::new(view).height().next().0
+}
+
+/// Checks that the stored data has the current layout. Until an admin runs `TxMigrateSchema`,
+/// records of the old layout would be read as the new one, so other transactions are rejected.
+fn check_schema_version(view: &Fork) -> ExecutionResult {
+    if EmployeesSchema::new(view).schema_version() != Some(migration::CURRENT_VERSION) {
+        return Err(ErrorKind::SchemaNotMigrated.into())
+    }
+
+    Ok(())
+}
+
+/// Executes the proposal once it has collected `admin_threshold` approvals of the
+/// current `zen-rolesystem` admin set. If the action fails, the whole transaction
+/// is rolled back, so the last approval is not recorded either. The executing
+/// transaction is appended to the history of the affected account.
+fn execute_if_approved(view: &mut Fork, hash: &Hash, proposal: Proposal, tx_hash: &Hash) -> ExecutionResult {
+    let (approvals, threshold) = {
+        let admins = RoleSystemSchema::new(&*view);
+        let approvals = EmployeesSchema::new(&*view)
+            .proposal_approvals(hash)
+            .iter()
+            .filter(|pub_key| admins.is_admin(pub_key))
+            .count();
+        (approvals, admins.admin_threshold() as usize)
+    };
+    if approvals < threshold {
+        return Ok(())
+    }
+
+    let action = match AdminAction::from_json(proposal.action()) {
+        Some(action) => action,
+        None => return Err(ErrorKind::InvalidAdminAction.into()),
+    };
+
+    let mut schema = EmployeesSchema::new(view);
+    action.execute(&mut schema)?;
+    schema.proposal_mark_executed(hash, proposal);
+    schema.account_history_push(action.pub_key(), tx_hash);
+
+    Ok(())
+}
 
 impl Transaction for TxCreateAccount {
     /// Verifies integrity of the transaction by checking the transaction
@@ -14,13 +78,14 @@ impl Transaction for TxCreateAccount {
     /// If a account with the specified public key is not registered and ID number is unique,
     /// then creates a new account, with the specified public key and name, last name, id number.
     fn execute(&self, view: &mut Fork) -> ExecutionResult {
-        let mut schema = EmployeesSchema::new(view);
+        check_schema_version(view)?;
 
-
-        if !schema.is_superuser(&self.pkey_by_who()) {
-            return Err(ErrorKind::NotASuperUser.into())
+        if !RoleSystemSchema::new(&*view).has_permission(self.pkey_by_who(), permissions::CREATE_ACCOUNTS) {
+            return Err(ErrorKind::PermissionDenied.into())
         }
 
+        let mut schema = EmployeesSchema::new(view);
+
         if schema.account(self.pub_key()).is_some() {
             return Err(ErrorKind::AccountAlreadyExists.into())
         }
@@ -31,6 +96,7 @@ impl Transaction for TxCreateAccount {
 
         schema.account_create(self.pub_key(),self.first_name(),
                               self.last_name(), self.id_number());
+        schema.account_history_push(self.pub_key(), &self.hash());
 
         Ok(())
     }
@@ -43,23 +109,22 @@ impl Transaction for TxEditAccount {
         self.verify_signature(self.pkey_by_who())
     }
 
-    /// Retrieves two accounts to apply the edition; they should be previously registered
-    /// with the help of [`TxCreateAccount`] transactions. Checks the editor's
-    /// authorship and superuser rights and applies changes to edited account if the editor's permissions
-    /// are sufficient. Otherwise, performs no op.
+    /// Retrieves the account to apply the edition; it should be previously registered
+    /// with the help of [`TxCreateAccount`] transaction. Only the owner can edit
+    /// the account; accounts of other users are edited by the admin set with the
+    /// `edit_account` [`AdminAction`].
     ///
     /// [`TxCreateAccount`]: ../transactions/struct.TxCreateAccount.html
+    /// [`AdminAction`]: ../governance/enum.AdminAction.html
     fn execute(&self, view: &mut Fork) -> ExecutionResult {
-        let mut schema = EmployeesSchema::new(view);
+        check_schema_version(view)?;
 
-        // Someone is trying to edit an account that doesn't belong to him
         if *self.pkey_by_who() != *self.pkey_account() {
-            // It must be a superuser, otherwise do nothing
-            if !schema.is_superuser(&self.pkey_by_who()) {
-                return Err(ErrorKind::NotASuperUser.into())
-            }
+            return Err(ErrorKind::PermissionDenied.into())
         }
 
+        let mut schema = EmployeesSchema::new(view);
+
         if let Some(account) = schema.account(self.pkey_account()) {
             let first_name = self.first_name();
             let last_name = self.last_name();
@@ -76,6 +141,7 @@ impl Transaction for TxEditAccount {
             }
 
             schema.account_edit(account, self.pkey_account(), first_name, last_name, id_number);
+            schema.account_history_push(self.pkey_account(), &self.hash());
         }
 
         Ok(())
@@ -89,23 +155,22 @@ impl Transaction for TxSetCustomData {
         self.verify_signature(self.pkey_by_who())
     }
 
-    /// Retrieves two accounts to apply the edition; they should be previously registered
-    /// with the help of [`TxCreateAccount`] transactions. Checks the editor's
-    /// authorship and superuser rights and applies changes to edited account if the editor's permissions
-    /// are sufficient. Otherwise, performs no op.
+    /// Replaces custom data of the account; it should be previously registered
+    /// with the help of [`TxCreateAccount`] transaction. Only the owner can change
+    /// the data; data of other users is changed by the admin set with the
+    /// `set_custom_data` [`AdminAction`].
     ///
     /// [`TxCreateAccount`]: ../transactions/struct.TxCreateAccount.html
+    /// [`AdminAction`]: ../governance/enum.AdminAction.html
     fn execute(&self, view: &mut Fork) -> ExecutionResult {
-        let mut schema = EmployeesSchema::new(view);
+        check_schema_version(view)?;
 
-        // Someone is trying to edit an account that doesn't belong to him
         if *self.pkey_by_who() != *self.pkey_account() {
-            // It must be a superuser, otherwise do nothing
-            if !schema.is_superuser(&self.pkey_by_who()) {
-                return Err(ErrorKind::NotASuperUser.into())
-            }
+            return Err(ErrorKind::PermissionDenied.into())
         }
 
+        let mut schema = EmployeesSchema::new(view);
+
         let no_account = schema.account(self.pkey_account()).is_none();
 
         // Account must exist
@@ -116,6 +181,7 @@ impl Transaction for TxSetCustomData {
         // Change custom data in the account
         let account = schema.account(self.pkey_account()).unwrap(); // Guaranteed to be `Some` by `if no_account` clause above
         schema.account_set_custom_data(account, self.pkey_account(), self.custom_data());
+        schema.account_history_push(self.pkey_account(), &self.hash());
 
         Ok(())
     }
@@ -128,23 +194,24 @@ impl Transaction for TxDeleteAccount {
         self.verify_signature(self.pkey_by_who())
     }
 
-    /// Retrieves two accounts to apply the edition; they should be previously registered
-    /// with the help of [`TxCreateAccount`] transactions. Checks the editor's
-    /// authorship and superuser rights and deletes account if the editor's permissions
-    /// are sufficient. Otherwise, performs no op.
+    /// Deletes the account of the signer together with its fields. Accounts of other
+    /// users are deleted by the admin set with the `delete_account` [`AdminAction`].
     ///
-    /// [`TxCreateAccount`]: ../transactions/struct.TxCreateAccount.html
+    /// [`AdminAction`]: ../governance/enum.AdminAction.html
     fn execute(&self, view: &mut Fork) -> ExecutionResult {
-        let mut schema = EmployeesSchema::new(view);
+        check_schema_version(view)?;
 
-        let superuser = schema.is_superuser(&self.pkey_by_who());
+        if *self.pkey_by_who() != *self.pkey_account() {
+            return Err(ErrorKind::PermissionDenied.into())
+        }
+
+        let mut schema = EmployeesSchema::new(view);
         let account = schema.account(self.pkey_account());
 
         if let Some(ref account) = account {
-            if superuser {
-                // Remove the account
-                schema.account_delete(&account.id_number(), self.pkey_account());
-            }
+            // Remove the account
+            schema.account_delete(&account.id_number(), self.pkey_account());
+            schema.account_history_push(self.pkey_account(), &self.hash());
         } else {
             return Err(ErrorKind::AccountNotFound.into())
         }
@@ -153,8 +220,206 @@ impl Transaction for TxDeleteAccount {
     }
 }
 
-/// Error codes emitted by `TxCreateAccount`, `TxEditAccount`, `TxSetCustomData`, `TxDeleteAccount` transactions during execution.
-#[derive(Display, Primitive)]
+impl Transaction for TxSetField {
+    /// Verifies integrity of the transaction by checking the signature
+    /// of the account owner.
+    fn verify(&self) -> bool {
+        self.verify_signature(self.pkey_account())
+    }
+
+    /// Stores the encrypted field under a new content key. The key must be wrapped
+    /// for the owner and at most once for every grantee; previous grants of the field
+    /// are replaced with the new ones.
+    fn execute(&self, view: &mut Fork) -> ExecutionResult {
+        check_schema_version(view)?;
+
+        let mut schema = EmployeesSchema::new(view);
+
+        if schema.account(self.pkey_account()).is_none() {
+            return Err(ErrorKind::AccountNotFound.into())
+        }
+
+        let wrapped_keys = self.wrapped_keys();
+        match distinct_grantees(&wrapped_keys) {
+            Some(ref grantees) if grantees.contains(self.pkey_account()) => {}
+            _ => return Err(ErrorKind::InvalidGrantees.into()),
+        }
+
+        schema.field_set(self.pkey_account(), self.name(), self.ciphertext(), wrapped_keys);
+        schema.account_history_push(self.pkey_account(), &self.hash());
+
+        Ok(())
+    }
+}
+
+impl Transaction for TxGrantAccess {
+    /// Verifies integrity of the transaction by checking the signature
+    /// of the account owner.
+    fn verify(&self) -> bool {
+        self.verify_signature(self.pkey_account())
+    }
+
+    /// Adds the current content key of the field wrapped for a new grantee.
+    fn execute(&self, view: &mut Fork) -> ExecutionResult {
+        check_schema_version(view)?;
+
+        let mut schema = EmployeesSchema::new(view);
+
+        if schema.field(self.pkey_account(), self.name()).is_none() {
+            return Err(ErrorKind::FieldNotFound.into())
+        }
+
+        let key = self.key();
+        if schema.field_keys(self.pkey_account(), self.name()).contains(key.grantee()) {
+            return Err(ErrorKind::AccessAlreadyGranted.into())
+        }
+
+        schema.field_grant(self.pkey_account(), self.name(), key);
+        schema.account_history_push(self.pkey_account(), &self.hash());
+
+        Ok(())
+    }
+}
+
+impl Transaction for TxRevokeAccess {
+    /// Verifies integrity of the transaction by checking the signature
+    /// of the account owner.
+    fn verify(&self) -> bool {
+        self.verify_signature(self.pkey_account())
+    }
+
+    /// Revokes access of the grantee and rotates the content key. The new key must be
+    /// wrapped for exactly the remaining grantees, so the revoked grantee can't read
+    /// the field content written from now on. The owner's access can't be revoked.
+    fn execute(&self, view: &mut Fork) -> ExecutionResult {
+        check_schema_version(view)?;
+
+        let mut schema = EmployeesSchema::new(view);
+
+        if schema.field(self.pkey_account(), self.name()).is_none() {
+            return Err(ErrorKind::FieldNotFound.into())
+        }
+
+        let mut remaining: BTreeSet<PublicKey> =
+            schema.field_keys(self.pkey_account(), self.name()).keys().collect();
+
+        if !remaining.remove(self.grantee()) {
+            return Err(ErrorKind::AccessNotGranted.into())
+        }
+
+        let wrapped_keys = self.wrapped_keys();
+        if self.grantee() == self.pkey_account() || distinct_grantees(&wrapped_keys) != Some(remaining) {
+            return Err(ErrorKind::InvalidGrantees.into())
+        }
+
+        schema.field_set(self.pkey_account(), self.name(), self.ciphertext(), wrapped_keys);
+        schema.account_history_push(self.pkey_account(), &self.hash());
+
+        Ok(())
+    }
+}
+
+impl Transaction for TxMigrateSchema {
+    /// Verifies integrity of the transaction by checking the transaction
+    /// signatures.
+    fn verify(&self) -> bool {
+        self.verify_signature(self.pkey_by_who())
+    }
+
+    /// Rewrites the stored data from the given layout version into the current one.
+    /// The signer must belong to the admin set of the `zen-rolesystem` service, which that
+    /// service bootstraps from its configuration on blockchains without a recorded version.
+    fn execute(&self, view: &mut Fork) -> ExecutionResult {
+        if !RoleSystemSchema::new(&*view).is_admin(self.pkey_by_who()) {
+            return Err(ErrorKind::PermissionDenied.into())
+        }
+
+        EmployeesSchema::new(view).migrate(self.from_version())
+    }
+}
+
+impl Transaction for TxProposeAdminAction {
+    /// Verifies integrity of the transaction by checking the transaction
+    /// signatures.
+    fn verify(&self) -> bool {
+        self.verify_signature(self.pkey_by_who())
+    }
+
+    /// Stores a new proposal with the proposer's approval. The proposer must belong
+    /// to the admin set of `zen-rolesystem` and the action must be a valid [`AdminAction`].
+    /// If the threshold is already reached, the action is executed right away.
+    ///
+    /// [`AdminAction`]: ../governance/enum.AdminAction.html
+    fn execute(&self, view: &mut Fork) -> ExecutionResult {
+        check_schema_version(view)?;
+
+        if !RoleSystemSchema::new(&*view).is_admin(self.pkey_by_who()) {
+            return Err(ErrorKind::PermissionDenied.into())
+        }
+
+        if AdminAction::from_json(self.action()).is_none() {
+            return Err(ErrorKind::InvalidAdminAction.into())
+        }
+
+        if self.expires_at() < current_height(view) {
+            return Err(ErrorKind::ProposalExpired.into())
+        }
+
+        let hash = self.hash();
+        let proposal = EmployeesSchema::new(&mut *view)
+            .proposal_create(&hash, self.pkey_by_who(), self.action(), self.expires_at());
+
+        execute_if_approved(view, &hash, proposal, &hash)
+    }
+}
+
+impl Transaction for TxApproveAdminAction {
+    /// Verifies integrity of the transaction by checking the transaction
+    /// signatures.
+    fn verify(&self) -> bool {
+        self.verify_signature(self.pkey_by_who())
+    }
+
+    /// Records an approval of a pending proposal that hasn't expired yet.
+    /// Executes the action once enough admins have approved it.
+    fn execute(&self, view: &mut Fork) -> ExecutionResult {
+        check_schema_version(view)?;
+
+        if !RoleSystemSchema::new(&*view).is_admin(self.pkey_by_who()) {
+            return Err(ErrorKind::PermissionDenied.into())
+        }
+
+        let height = current_height(view);
+        let proposal = {
+            let mut schema = EmployeesSchema::new(&mut *view);
+
+            let proposal = match schema.proposal(self.proposal()) {
+                Some(proposal) => proposal,
+                None => return Err(ErrorKind::ProposalNotFound.into()),
+            };
+
+            if proposal.executed() {
+                return Err(ErrorKind::ProposalAlreadyExecuted.into())
+            }
+
+            if proposal.expires_at() < height {
+                return Err(ErrorKind::ProposalExpired.into())
+            }
+
+            if schema.proposal_approvals(self.proposal()).contains(self.pkey_by_who()) {
+                return Err(ErrorKind::ProposalAlreadyApproved.into())
+            }
+
+            schema.proposal_approve(self.proposal(), self.pkey_by_who());
+            proposal
+        };
+
+        execute_if_approved(view, self.proposal(), proposal, &self.hash())
+    }
+}
+
+/// Error codes emitted by the service transactions during execution.
+#[derive(Debug, Display, Primitive)]
 pub enum ErrorKind {
     /// Error can be emitted by `TxCreateAccount`
     #[display(fmt = "Account already exists.")] AccountAlreadyExists = 1,
@@ -165,11 +430,45 @@ pub enum ErrorKind {
     /// Error can be emitted by any transaction except `TxCreateAccount`
     #[display(fmt = "Account not found.")] AccountNotFound = 3,
 
-    /// Can be emitted by `TxCreateAccount`, `TxEditAccount`, `TxSetCustomData`, `TxDeleteAccount`
-    #[display(fmt = "Not a superuser.")] NotASuperUser = 4,
+    /// Can be emitted by `TxCreateAccount`, `TxEditAccount`, `TxSetCustomData`, `TxDeleteAccount`,
+    /// `TxMigrateSchema`, `TxProposeAdminAction`, `TxApproveAdminAction`
+    #[display(fmt = "Permission denied.")] PermissionDenied = 4,
 
     /// Can be emitted by `TxEditAccount`, `TxCreateAccount`
     #[display(fmt = "Employee ID already exists")] EmployeeIdAlreadyExists = 5,
+
+    /// Can be emitted by `TxGrantAccess`, `TxRevokeAccess`
+    #[display(fmt = "Field not found.")] FieldNotFound = 6,
+
+    /// Can be emitted by `TxGrantAccess`
+    #[display(fmt = "Access is already granted.")] AccessAlreadyGranted = 7,
+
+    /// Can be emitted by `TxRevokeAccess`
+    #[display(fmt = "Access is not granted.")] AccessNotGranted = 8,
+
+    /// Can be emitted by `TxSetField`, `TxRevokeAccess`
+    #[display(fmt = "Content key must be wrapped exactly for the owner and the grantees.")] InvalidGrantees = 9,
+
+    /// Can be emitted by `TxMigrateSchema`
+    #[display(fmt = "Stored data has a different schema version.")] InvalidSchemaVersion = 10,
+
+    /// Can be emitted by `TxProposeAdminAction`, `TxApproveAdminAction`
+    #[display(fmt = "Invalid admin action.")] InvalidAdminAction = 11,
+
+    /// Can be emitted by `TxApproveAdminAction`
+    #[display(fmt = "Proposal not found.")] ProposalNotFound = 12,
+
+    /// Can be emitted by `TxProposeAdminAction`, `TxApproveAdminAction`
+    #[display(fmt = "Proposal is expired.")] ProposalExpired = 13,
+
+    /// Can be emitted by `TxApproveAdminAction`
+    #[display(fmt = "Proposal is already approved by this admin.")] ProposalAlreadyApproved = 14,
+
+    /// Can be emitted by `TxApproveAdminAction`
+    #[display(fmt = "Proposal is already executed.")] ProposalAlreadyExecuted = 15,
+
+    /// Can be emitted by any transaction except `TxMigrateSchema` until the stored data is migrated
+    #[display(fmt = "Stored data is not migrated to the current layout.")] SchemaNotMigrated = 16,
 }
 
 impl ErrorKind {
@@ -177,6 +476,11 @@ impl ErrorKind {
     pub fn into_code(self) -> u8 {
         self.to_u8().unwrap()
     }
+
+    /// Converts the raw code back to the error, if the code is known
+    pub fn from_code(code: u8) -> Option<Self> {
+        Self::from_u8(code)
+    }
 }
 
 impl From<ErrorKind> for ExecutionError {
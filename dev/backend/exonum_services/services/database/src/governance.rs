@@ -0,0 +1,94 @@
+use contracts::ErrorKind;
+use exonum::blockchain::ExecutionResult;
+use exonum::crypto::PublicKey;
+use exonum::storage::Fork;
+use schema::{EmployeeId, EmployeesSchema};
+use serde_json;
+
+/// Administrative action on the employee accounts that can be executed only by the
+/// admin set of `zen-rolesystem`. Actions are carried in `TxProposeAdminAction` as JSON, e.g.
+/// `{ "type": "delete_account", "pub_key": "..." }`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AdminAction {
+    /// Changes name, last name and ID number of any account.
+    EditAccount {
+        /// Public key of the account owner.
+        pub_key: PublicKey,
+        /// New name.
+        first_name: String,
+        /// New last name.
+        last_name: String,
+        /// New ID document number.
+        id_number: EmployeeId,
+    },
+
+    /// Replaces custom data of any account.
+    SetCustomData {
+        /// Public key of the account owner.
+        pub_key: PublicKey,
+        /// New custom data.
+        custom_data: String,
+    },
+
+    /// Removes any account together with its fields.
+    DeleteAccount {
+        /// Public key of the account owner.
+        pub_key: PublicKey,
+    },
+}
+
+impl AdminAction {
+    /// Parses an action from its JSON representation.
+    pub fn from_json(action: &str) -> Option<Self> {
+        serde_json::from_str(action).ok()
+    }
+
+    /// Returns the public key of the account affected by the action.
+    pub fn pub_key(&self) -> &PublicKey {
+        match *self {
+            AdminAction::EditAccount { ref pub_key, .. }
+            | AdminAction::SetCustomData { ref pub_key, .. }
+            | AdminAction::DeleteAccount { ref pub_key } => pub_key,
+        }
+    }
+
+    /// Applies the action to the storage. Should be called only after
+    /// the proposal has collected enough approvals.
+    pub fn execute(&self, schema: &mut EmployeesSchema<&mut Fork>) -> ExecutionResult {
+        let account = match schema.account(self.pub_key()) {
+            Some(account) => account,
+            None => return Err(ErrorKind::AccountNotFound.into()),
+        };
+
+        match *self {
+            AdminAction::EditAccount {
+                ref pub_key,
+                ref first_name,
+                ref last_name,
+                id_number,
+            } => {
+                if let Some(pk) = schema.account_pk_by_employee_id().get(&id_number) {
+                    if pk != *pub_key {
+                        return Err(ErrorKind::EmployeeIdAlreadyExists.into());
+                    }
+                }
+
+                schema.account_edit(account, pub_key, first_name, last_name, id_number);
+            }
+
+            AdminAction::SetCustomData {
+                ref pub_key,
+                ref custom_data,
+            } => {
+                schema.account_set_custom_data(account, pub_key, custom_data);
+            }
+
+            AdminAction::DeleteAccount { ref pub_key } => {
+                schema.account_delete(&account.id_number(), pub_key);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,4 +1,3 @@
-extern crate bodyparser;
 #[macro_use]
 extern crate display_derive;
 #[macro_use]
@@ -6,14 +5,13 @@ extern crate enum_primitive_derive;
 #[macro_use]
 extern crate exonum;
 extern crate failure;
-extern crate iron;
 extern crate num_traits;
-extern crate router;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
 extern crate toml;
+extern crate zen_rolesystem;
 
 /// Persistent data.
 pub mod schema;
@@ -24,6 +22,12 @@ pub mod transactions;
 /// Contracts.
 pub mod contracts;
 
+/// Multi-signature administration.
+pub mod governance;
+
+/// Schema versions and data migrations.
+pub mod migration;
+
 /// REST API.
 pub mod api;
 
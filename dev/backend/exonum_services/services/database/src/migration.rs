@@ -0,0 +1,126 @@
+//! Versions of the stored data layout and migrations between them.
+//!
+//! Every layout change of the `encoding_struct!` records bumps [`CURRENT_VERSION`]
+//! and adds a step that rewrites the records of the previous version. New blockchains
+//! record the current version in `initialize`; existing ones are migrated by an admin
+//! of `zen-rolesystem` with `TxMigrateSchema`.
+//!
+//! Versions:
+//!
+//! - `0` - data written before the versions were introduced;
+//! - `1` - employee accounts with encrypted data fields;
+//! - `2` - root hashes of the fields and grants tables in the state hash.
+//!
+//! The `Account` record has kept the layout of version `0`; the fields, grants and
+//! account history of version `1` are stored in new tables that start empty. So moving
+//! from `0` to `1` rewrites no records and only stores the version. Moving from `1`
+//! to `2` fills `employees.fields_roots` and `employees.field_keys_roots` from the
+//! stored fields.
+//!
+//! [`CURRENT_VERSION`]: constant.CURRENT_VERSION.html
+
+use contracts::ErrorKind;
+use exonum::blockchain::ExecutionResult;
+use exonum::crypto::PublicKey;
+use exonum::storage::Fork;
+use schema::EmployeesSchema;
+
+/// Layout version written by the current code.
+pub const CURRENT_VERSION: u32 = 2;
+
+/// Rewrites the stored data from the given layout version into the current one
+/// and records the current version.
+///
+/// Data written before the versions were introduced has no recorded version,
+/// so the caller has to state it. Version `CURRENT_VERSION` only records the version.
+pub fn migrate(fork: &mut Fork, from_version: u32) -> ExecutionResult {
+    let stored = EmployeesSchema::new(&*fork).schema_version();
+    match stored {
+        Some(version) if version != from_version || version == CURRENT_VERSION => {
+            return Err(ErrorKind::InvalidSchemaVersion.into())
+        }
+        _ if from_version > CURRENT_VERSION => {
+            return Err(ErrorKind::InvalidSchemaVersion.into())
+        }
+        _ => {}
+    }
+
+    for version in from_version..CURRENT_VERSION {
+        match version {
+            // Version 1 only introduced the version record.
+            0 => {}
+            1 => {
+                let owners: Vec<PublicKey> = EmployeesSchema::new(&*fork).accounts().keys().collect();
+                let mut schema = EmployeesSchema::new(&mut *fork);
+                for owner in owners {
+                    schema.account_field_roots_update(&owner);
+                }
+            }
+            _ => return Err(ErrorKind::InvalidSchemaVersion.into()),
+        }
+    }
+
+    EmployeesSchema::new(fork).schema_version_set(CURRENT_VERSION);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exonum::crypto;
+    use schema::WrappedKey;
+    use exonum::storage::{Database, MemoryDB};
+
+    #[test]
+    fn test_migrate_unversioned_accounts() {
+        let (alice, _) = crypto::gen_keypair();
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        EmployeesSchema::new(&mut fork).account_create(&alice, "Alice", "Smith", 42);
+        db.merge(fork.into_patch()).unwrap();
+
+        let mut fork = db.fork();
+        assert!(migrate(&mut fork, CURRENT_VERSION + 1).is_err());
+        assert!(migrate(&mut fork, 0).is_ok());
+        assert!(migrate(&mut fork, 0).is_err());
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        let schema = EmployeesSchema::new(&snapshot);
+        assert_eq!(schema.schema_version(), Some(CURRENT_VERSION));
+        assert_eq!(schema.account_by_id(&42).unwrap().pub_key(), &alice);
+    }
+
+    #[test]
+    fn test_migrate_field_roots() {
+        let (alice, _) = crypto::gen_keypair();
+        let db = MemoryDB::new();
+
+        // Fields written by version 1 have no roots in the state hash tables
+        let mut fork = db.fork();
+        {
+            let mut schema = EmployeesSchema::new(&mut fork);
+            schema.account_create(&alice, "Alice", "Smith", 42);
+            schema.field_set(&alice, "email", b"ciphertext", vec![WrappedKey::new(&alice, b"key")]);
+        }
+        let expected = EmployeesSchema::new(&fork).state_hash();
+        {
+            let mut schema = EmployeesSchema::new(&mut fork);
+            schema.fields_roots_mut().clear();
+            schema.field_keys_roots_mut().clear();
+            schema.schema_version_set(1);
+        }
+        db.merge(fork.into_patch()).unwrap();
+
+        let mut fork = db.fork();
+        assert!(migrate(&mut fork, 0).is_err());
+        assert!(migrate(&mut fork, 1).is_ok());
+        db.merge(fork.into_patch()).unwrap();
+
+        let snapshot = db.snapshot();
+        let schema = EmployeesSchema::new(&snapshot);
+        assert_eq!(schema.schema_version(), Some(CURRENT_VERSION));
+        assert_eq!(schema.state_hash(), expected);
+        assert_eq!(schema.fields_roots().get(&alice), Some(schema.fields(&alice).merkle_root()));
+    }
+}
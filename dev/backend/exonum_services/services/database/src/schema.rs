@@ -1,5 +1,9 @@
-use exonum::crypto::{Hash, PublicKey};
-use exonum::storage::{Fork, MapIndex, ProofMapIndex, Snapshot, ValueSetIndex};
+use exonum::blockchain::ExecutionResult;
+use exonum::crypto::{self, Hash, PublicKey};
+use exonum::storage::{Entry, Fork, KeySetIndex, MapIndex, ProofListIndex, ProofMapIndex,
+                      Snapshot};
+use migration;
+use zen_rolesystem::schema::Proposal;
 
 /// Employee ID type alias.
 pub type EmployeeId = u64;
@@ -29,6 +33,39 @@ encoding_struct! {
         }
     }
 
+encoding_struct! {
+    /// Encrypted user data field. The content is encrypted client-side with
+    /// a symmetric content key that never appears on chain in plain form.
+    struct DataField {
+        /// UTF-8 string with the field name.
+        name: &str,
+
+        /// Field content encrypted with the content key.
+        ciphertext: &[u8],
+
+        /// Version of the content key. Incremented on every key rotation.
+        key_version: u64,
+    }
+}
+
+encoding_struct! {
+    /// Content key of a field wrapped for one grantee.
+    struct WrappedKey {
+        /// Public key of the grantee.
+        grantee: &PublicKey,
+
+        /// Content key encrypted for the grantee's public key.
+        wrapped_key: &[u8],
+    }
+}
+
+/// Returns the identifier of the field `name` owned by `owner`.
+pub fn field_id(owner: &PublicKey, name: &str) -> Hash {
+    let mut bytes = owner.as_ref().to_vec();
+    bytes.extend_from_slice(name.as_bytes());
+    crypto::hash(&bytes)
+}
+
 /// Additional methods for managing account in immutable fashion.
 impl Account {
     /// Returns a copy of this account with the changed info.
@@ -43,6 +80,14 @@ impl Account {
     }
 }
 
+/// Names of the tables whose root hashes form `EmployeesSchema::state_hash`, in order.
+pub const STATE_HASH_TABLES: [&str; 4] = [
+    "employees.accounts",
+    "employees.fields_roots",
+    "employees.field_keys_roots",
+    "employees.proposals",
+];
+
 /// Schema of the key-value storage used by the demo employees service.
 pub struct EmployeesSchema<T> {
     view: T,
@@ -81,25 +126,107 @@ impl<T: AsRef<Snapshot>> EmployeesSchema<T> {
             .map_or(None, |pk| self.accounts().get(&pk))
     }
 
-    /// Method to get state hash.
+    /// Returns hashes of the transactions that have changed the account,
+    /// from the oldest to the newest.
+    pub fn account_history(&self, pub_key: &PublicKey) -> ProofListIndex<&Snapshot, Hash> {
+        ProofListIndex::new_in_family("employees.account_history", pub_key, self.view.as_ref())
+    }
+
+    /// Returns an immutable table of the encrypted fields of the account.
+    pub fn fields(&self, pub_key: &PublicKey) -> ProofMapIndex<&Snapshot, String, DataField> {
+        ProofMapIndex::new_in_family("employees.fields", pub_key, self.view.as_ref())
+    }
+
+    /// Gets a specific field of the account.
+    pub fn field(&self, pub_key: &PublicKey, name: &str) -> Option<DataField> {
+        self.fields(pub_key).get(&name.to_owned())
+    }
+
+    /// Returns an immutable table of the content keys of the field wrapped for its grantees.
+    /// The owner is a grantee as well.
+    pub fn field_keys(&self, owner: &PublicKey, name: &str) -> ProofMapIndex<&Snapshot, PublicKey, WrappedKey> {
+        ProofMapIndex::new_in_family("employees.field_keys", &field_id(owner, name), self.view.as_ref())
+    }
+
+    /// Returns the root hashes of the fields tables of the accounts. The fields live in
+    /// per-account tables, so their roots are collected here to get into the state hash.
+    pub fn fields_roots(&self) -> ProofMapIndex<&Snapshot, PublicKey, Hash> {
+        ProofMapIndex::new("employees.fields_roots", self.view.as_ref())
+    }
+
+    /// Returns the root hashes of the wrapped keys tables of the fields, by `field_id`.
+    pub fn field_keys_roots(&self) -> ProofMapIndex<&Snapshot, Hash, Hash> {
+        ProofMapIndex::new("employees.field_keys_roots", self.view.as_ref())
+    }
+
+    /// Returns an immutable version of the admin proposals table. Proposals are
+    /// keyed by the hash of the proposing transaction; the action is a JSON-serialized
+    /// [`AdminAction`](../governance/enum.AdminAction.html) of this service.
+    pub fn proposals(&self) -> ProofMapIndex<&Snapshot, Hash, Proposal> {
+        ProofMapIndex::new("employees.proposals", self.view.as_ref())
+    }
+
+    /// Gets a specific proposal from the storage.
+    pub fn proposal(&self, hash: &Hash) -> Option<Proposal> {
+        self.proposals().get(hash)
+    }
+
+    /// Returns an immutable set of admins that have approved the proposal.
+    pub fn proposal_approvals(&self, hash: &Hash) -> KeySetIndex<&Snapshot, PublicKey> {
+        KeySetIndex::new_in_family("employees.proposal_approvals", hash, self.view.as_ref())
+    }
+
+    /// Method to get state hash. The accounts table must stay the first one,
+    /// the account proofs of the API refer to it by index.
     pub fn state_hash(&self) -> Vec<Hash> {
-        vec![self.accounts().merkle_root()]
+        vec![
+            self.accounts().merkle_root(),
+            self.fields_roots().merkle_root(),
+            self.field_keys_roots().merkle_root(),
+            self.proposals().merkle_root(),
+        ]
     }
 
-    /// Returns an immutable version of a superuser public keys set.
-    pub fn superuser_pk(&self) -> ValueSetIndex<&Snapshot, String> {
-        ValueSetIndex::new("employees.superuser_pks", self.view.as_ref())
+    /// Returns the state hash together with the names of the tables, in the same order.
+    pub fn state_hash_components(&self) -> Vec<(&'static str, Hash)> {
+        STATE_HASH_TABLES
+            .iter()
+            .cloned()
+            .zip(self.state_hash())
+            .collect()
     }
 
-    /// Checks that specific public key is belongs to a superuser set.
-    pub fn is_superuser(&self, pub_key: &PublicKey) -> bool {
-        self.superuser_pk().contains(&pub_key.to_string())
+    /// Returns the number of entries in the service tables, except the per-account families.
+    pub fn index_sizes(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            ("employees.accounts", self.accounts().keys().count() as u64),
+            ("employees.accounts_by_id", self.employee_id_to_account_pk().keys().count() as u64),
+            ("employees.fields_roots", self.fields_roots().keys().count() as u64),
+            ("employees.field_keys_roots", self.field_keys_roots().keys().count() as u64),
+            ("employees.proposals", self.proposals().keys().count() as u64),
+        ]
+    }
+
+    /// Returns the layout version of the stored data, `None` if the data was written
+    /// before the versions were introduced.
+    pub fn schema_version(&self) -> Option<u32> {
+        Entry::new("employees.schema_version", self.view.as_ref()).get()
     }
 }
 
 /// A mutable version of the schema with an additional method to persist accounts
 /// to the storage.
 impl<'a> EmployeesSchema<&'a mut Fork> {
+    /// Records the layout version of the stored data.
+    pub fn schema_version_set(&mut self, version: u32) {
+        Entry::new("employees.schema_version", &mut self.view).set(version);
+    }
+
+    /// Rewrites the stored data from the given layout version into the current one.
+    pub fn migrate(&mut self, from_version: u32) -> ExecutionResult {
+        migration::migrate(&mut *self.view, from_version)
+    }
+
     /// Returns a mutable version of the accounts table.
     pub fn accounts_mut(&mut self) -> ProofMapIndex<&mut Fork, PublicKey, Account> {
         ProofMapIndex::new("employees.accounts", &mut self.view)
@@ -110,8 +237,14 @@ impl<'a> EmployeesSchema<&'a mut Fork> {
         MapIndex::new("employees.accounts_by_id", &mut self.view)
     }
 
-    fn superusers_pk_mut(&mut self) -> ValueSetIndex<&mut Fork, String> {
-        ValueSetIndex::new("employees.superuser_pks", &mut self.view)
+    /// Returns a mutable version of the account history.
+    pub fn account_history_mut(&mut self, pub_key: &PublicKey) -> ProofListIndex<&mut Fork, Hash> {
+        ProofListIndex::new_in_family("employees.account_history", pub_key, &mut self.view)
+    }
+
+    /// Appends the transaction to the account history.
+    pub fn account_history_push(&mut self, pub_key: &PublicKey, tx_hash: &Hash) {
+        self.account_history_mut(pub_key).push(*tx_hash);
     }
 
     /// Creates a new account with specified credentials.
@@ -144,12 +277,228 @@ impl<'a> EmployeesSchema<&'a mut Fork> {
 
     /// Changes account basic information.
     pub fn account_delete(&mut self, id: &EmployeeId, pub_key :&PublicKey) {
+        let names: Vec<String> = self.fields(pub_key).keys().collect();
+        for name in names {
+            self.field_remove(pub_key, &name);
+        }
+
         self.account_pk_by_employee_id().remove(id);
         self.accounts_mut().remove(pub_key);
     }
 
-    /// Adds a new superuser public key.
-    pub fn add_superuser_key(&mut self, key: &str) {
-        self.superusers_pk_mut().insert(key.to_string());
+    /// Returns a mutable table of the encrypted fields of the account.
+    pub fn fields_mut(&mut self, pub_key: &PublicKey) -> ProofMapIndex<&mut Fork, String, DataField> {
+        ProofMapIndex::new_in_family("employees.fields", pub_key, &mut self.view)
+    }
+
+    /// Returns a mutable table of the wrapped content keys of the field.
+    pub fn field_keys_mut(&mut self, owner: &PublicKey, name: &str) -> ProofMapIndex<&mut Fork, PublicKey, WrappedKey> {
+        ProofMapIndex::new_in_family("employees.field_keys", &field_id(owner, name), &mut self.view)
     }
-}
\ No newline at end of file
+
+    /// Returns a mutable version of the fields roots table.
+    pub fn fields_roots_mut(&mut self) -> ProofMapIndex<&mut Fork, PublicKey, Hash> {
+        ProofMapIndex::new("employees.fields_roots", &mut self.view)
+    }
+
+    /// Returns a mutable version of the wrapped keys roots table.
+    pub fn field_keys_roots_mut(&mut self) -> ProofMapIndex<&mut Fork, Hash, Hash> {
+        ProofMapIndex::new("employees.field_keys_roots", &mut self.view)
+    }
+
+    /// Stores the root hashes of the fields of `owner` and of the wrapped keys of the field
+    /// `name` into the state hash tables. Empty tables have no root entry.
+    fn field_roots_update(&mut self, owner: &PublicKey, name: &str) {
+        let fields_root = {
+            let fields = self.fields(owner);
+            fields.keys().next().map(|_| fields.merkle_root())
+        };
+        match fields_root {
+            Some(root) => self.fields_roots_mut().put(owner, root),
+            None => self.fields_roots_mut().remove(owner),
+        }
+
+        let keys_root = {
+            let keys = self.field_keys(owner, name);
+            keys.keys().next().map(|_| keys.merkle_root())
+        };
+        let id = field_id(owner, name);
+        match keys_root {
+            Some(root) => self.field_keys_roots_mut().put(&id, root),
+            None => self.field_keys_roots_mut().remove(&id),
+        }
+    }
+
+    /// Recomputes the state hash roots of all fields of the account.
+    pub fn account_field_roots_update(&mut self, owner: &PublicKey) {
+        let names: Vec<String> = self.fields(owner).keys().collect();
+        for name in names {
+            self.field_roots_update(owner, &name);
+        }
+    }
+
+    /// Stores the field content under a new content key. Previously wrapped keys
+    /// are dropped and replaced with `wrapped_keys`.
+    pub fn field_set(&mut self, owner: &PublicKey, name: &str, ciphertext: &[u8], wrapped_keys: Vec<WrappedKey>) {
+        let key_version = self.field(owner, name).map_or(0, |field| field.key_version() + 1);
+        let field = DataField::new(name, ciphertext, key_version);
+        self.fields_mut(owner).put(&name.to_owned(), field);
+
+        {
+            let mut keys = self.field_keys_mut(owner, name);
+            keys.clear();
+            for key in wrapped_keys {
+                keys.put(key.grantee(), key.clone());
+            }
+        }
+        self.field_roots_update(owner, name);
+    }
+
+    /// Gives the grantee the current content key of the field.
+    pub fn field_grant(&mut self, owner: &PublicKey, name: &str, key: WrappedKey) {
+        self.field_keys_mut(owner, name).put(key.grantee(), key.clone());
+        self.field_roots_update(owner, name);
+    }
+
+    /// Removes the field together with all of its wrapped keys.
+    pub fn field_remove(&mut self, owner: &PublicKey, name: &str) {
+        self.field_keys_mut(owner, name).clear();
+        self.fields_mut(owner).remove(&name.to_owned());
+        self.field_roots_update(owner, name);
+    }
+
+    /// Returns a mutable version of the admin proposals table.
+    pub fn proposals_mut(&mut self) -> ProofMapIndex<&mut Fork, Hash, Proposal> {
+        ProofMapIndex::new("employees.proposals", &mut self.view)
+    }
+
+    /// Returns a mutable set of admins that have approved the proposal.
+    pub fn proposal_approvals_mut(&mut self, hash: &Hash) -> KeySetIndex<&mut Fork, PublicKey> {
+        KeySetIndex::new_in_family("employees.proposal_approvals", hash, &mut self.view)
+    }
+
+    /// Stores a new proposal. The proposer's approval is recorded as well.
+    pub fn proposal_create(
+        &mut self,
+        hash: &Hash,
+        proposer: &PublicKey,
+        action: &str,
+        expires_at: u64,
+    ) -> Proposal {
+        let proposal = Proposal::new(proposer, action, expires_at, false);
+
+        self.proposals_mut().put(hash, proposal.clone());
+        self.proposal_approvals_mut(hash).insert(*proposer);
+
+        proposal
+    }
+
+    /// Records an approval of the proposal by the admin.
+    pub fn proposal_approve(&mut self, hash: &Hash, pub_key: &PublicKey) {
+        self.proposal_approvals_mut(hash).insert(*pub_key);
+    }
+
+    /// Marks the proposal as executed so it can't be approved again.
+    pub fn proposal_mark_executed(&mut self, hash: &Hash, proposal: Proposal) {
+        let proposal = Proposal::new(
+            proposal.proposer(),
+            proposal.action(),
+            proposal.expires_at(),
+            true,
+        );
+        self.proposals_mut().put(hash, proposal);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exonum::storage::{Database, MemoryDB};
+
+    fn with_field(db: &MemoryDB, owner: &PublicKey, keys: Vec<WrappedKey>) {
+        let mut fork = db.fork();
+        {
+            let mut schema = EmployeesSchema::new(&mut fork);
+            schema.account_create(owner, "Alice", "Smith", 42);
+            schema.field_set(owner, "email", b"v0", keys);
+        }
+        db.merge(fork.into_patch()).unwrap();
+    }
+
+    #[test]
+    fn test_field_set_rotates_key() {
+        let (alice, _) = crypto::gen_keypair();
+        let (bob, _) = crypto::gen_keypair();
+        let db = MemoryDB::new();
+        with_field(&db, &alice, vec![WrappedKey::new(&alice, b"a0"), WrappedKey::new(&bob, b"b0")]);
+
+        let mut fork = db.fork();
+        let hash_before = EmployeesSchema::new(&fork).state_hash();
+        EmployeesSchema::new(&mut fork).field_set(&alice, "email", b"v1", vec![WrappedKey::new(&alice, b"a1")]);
+
+        let schema = EmployeesSchema::new(&fork);
+        let field = schema.field(&alice, "email").unwrap();
+        assert_eq!(field.key_version(), 1);
+        assert_eq!(field.ciphertext(), b"v1");
+
+        let keys = schema.field_keys(&alice, "email");
+        assert_eq!(keys.keys().collect::<Vec<_>>(), vec![alice]);
+        assert_eq!(keys.get(&alice).unwrap().wrapped_key(), b"a1");
+
+        let hash_after = schema.state_hash();
+        assert_eq!(hash_after[0], hash_before[0]);
+        assert_ne!(hash_after[1], hash_before[1]);
+        assert_ne!(hash_after[2], hash_before[2]);
+        assert_eq!(schema.field_keys_roots().get(&field_id(&alice, "email")), Some(keys.merkle_root()));
+    }
+
+    #[test]
+    fn test_field_grant() {
+        let (alice, _) = crypto::gen_keypair();
+        let (bob, _) = crypto::gen_keypair();
+        let db = MemoryDB::new();
+        with_field(&db, &alice, vec![WrappedKey::new(&alice, b"a0")]);
+
+        let mut fork = db.fork();
+        let hash_before = EmployeesSchema::new(&fork).state_hash();
+        EmployeesSchema::new(&mut fork).field_grant(&alice, "email", WrappedKey::new(&bob, b"b0"));
+
+        let schema = EmployeesSchema::new(&fork);
+        // Granting doesn't rotate the content key
+        assert_eq!(schema.field(&alice, "email").unwrap().key_version(), 0);
+        let keys = schema.field_keys(&alice, "email");
+        assert_eq!(keys.get(&bob).unwrap().wrapped_key(), b"b0");
+        assert!(keys.contains(&alice));
+
+        let hash_after = schema.state_hash();
+        assert_eq!(hash_after[1], hash_before[1]);
+        assert_ne!(hash_after[2], hash_before[2]);
+    }
+
+    #[test]
+    fn test_account_delete_removes_fields() {
+        let (alice, _) = crypto::gen_keypair();
+        let (bob, _) = crypto::gen_keypair();
+        let db = MemoryDB::new();
+        let empty_hash = EmployeesSchema::new(&db.snapshot()).state_hash();
+        with_field(&db, &alice, vec![WrappedKey::new(&alice, b"a0"), WrappedKey::new(&bob, b"b0")]);
+
+        let mut fork = db.fork();
+        {
+            let mut schema = EmployeesSchema::new(&mut fork);
+            schema.field_set(&alice, "phone", b"v0", vec![WrappedKey::new(&alice, b"a0")]);
+            schema.account_delete(&42, &alice);
+        }
+
+        let schema = EmployeesSchema::new(&fork);
+        assert!(schema.account(&alice).is_none());
+        assert!(schema.account_by_id(&42).is_none());
+        assert_eq!(schema.fields(&alice).keys().count(), 0);
+        for name in &["email", "phone"] {
+            assert_eq!(schema.field_keys(&alice, name).keys().count(), 0);
+        }
+        assert_eq!(schema.fields_roots().keys().count(), 0);
+        assert_eq!(schema.field_keys_roots().keys().count(), 0);
+        assert_eq!(schema.state_hash(), empty_hash);
+    }
+}
@@ -1,36 +1,24 @@
-use cmd::EmployeesServiceConfig;
-use exonum::{crypto::Hash, crypto::PublicKey, encoding, messages::RawTransaction, storage::Fork, storage::Snapshot};
-use exonum::blockchain::{ApiContext, Service, Transaction, TransactionSet};
-use exonum::encoding::serialize::FromHex;
-use iron::Handler;
-use router::Router;
+use api;
+use exonum::{crypto::Hash, encoding, messages::RawTransaction, storage::Fork, storage::Snapshot};
+use exonum::api::ServiceApiBuilder;
+use exonum::blockchain::{Service, Transaction, TransactionSet};
+use migration;
 use schema::EmployeesSchema;
-use serde_json;
-use serde_json::value::Value;
+use serde_json::Value;
 use transactions::EmployeesTransactions;
 
 /// Service ID for the `Service` trait.
 pub const SERVICE_ID: u16 = 128;
 
 /// Employees service structure.
-pub struct EmployeesService {
-    /// Public key of the superuser.
-    pub superuser_pkey: String,
-}
+///
+/// Permissions of the editors are taken from the roles of the `zen-rolesystem` service.
+pub struct EmployeesService;
 
 impl EmployeesService {
-    /// Constructor of the Employees service with superuser public key.
-    pub fn new(superuser_pkey: String) -> EmployeesService {
-        EmployeesService { superuser_pkey }
-    }
-
-    /// Checks that `pkey` is belongs to the superuser.
-    pub fn is_superuser(&self, pkey: &PublicKey) -> bool {
-        if let Ok(pk) = PublicKey::from_hex(self.superuser_pkey.clone()) {
-            return *pkey == pk
-        }
-
-        false
+    /// Constructor of the Employees service.
+    pub fn new() -> EmployeesService {
+        EmployeesService
     }
 }
 
@@ -49,32 +37,20 @@ impl Service for EmployeesService {
         schema.state_hash()
     }
 
+    /// Records the layout version of the data of a new blockchain.
+    fn initialize(&self, fork: &mut Fork) -> Value {
+        EmployeesSchema::new(fork).schema_version_set(migration::CURRENT_VERSION);
+        Value::Null
+    }
+
     /// Implement a method to deserialize transactions coming to the node.
     fn tx_from_raw(&self, raw: RawTransaction) -> Result<Box<Transaction>, encoding::Error> {
         EmployeesTransactions::tx_from_raw(raw).map(Into::into)
     }
 
-    /// Implements an initialization routines for the service.
-    /// Stores a superuser public key into the storage.
-    fn initialize(&self, fork: &mut Fork) -> Value {
-        EmployeesSchema::new(fork).add_superuser_key(&self.superuser_pkey);
-
-        serde_json::to_value(EmployeesServiceConfig { superuser_pkey: self.superuser_pkey.clone() }).unwrap()
+    /// Wires the public and private REST API of the service.
+    fn wire_api(&self, builder: &mut ServiceApiBuilder) {
+        api::PublicApi::wire(builder);
+        api::PrivateApi::wire(builder);
     }
-
-    /// Create a REST `Handler` to process web requests to the node.
-    fn public_api_handler(&self, ctx: &ApiContext) -> Option<Box<Handler>> {
-        let mut router = Router::new();
-
-        use api;
-        use exonum::api::Api;
-
-        let api = api::EmployeesApi {
-            channel: ctx.node_channel().clone(),
-            blockchain: ctx.blockchain().clone(),
-        };
-
-        api.wire(&mut router);
-        Some(Box::new(router))
-    }
-}
\ No newline at end of file
+}
@@ -1,5 +1,5 @@
-use exonum::crypto::PublicKey;
-use schema::EmployeeId;
+use exonum::crypto::{Hash, PublicKey};
+use schema::{EmployeeId, WrappedKey};
 use service::SERVICE_ID;
 
 transactions! {
@@ -11,7 +11,7 @@ transactions! {
         /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
         /// `TxCreateAccount` transactions are processed.
         struct TxCreateAccount {
-            /// Public key of the editor. Must be a superuser.
+            /// Public key of the editor. Must have `CREATE_ACCOUNTS` permission.
             pkey_by_who: &PublicKey,
 
             /// Public key of the account's owner.
@@ -32,7 +32,7 @@ transactions! {
         /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
         /// `TxEditAccount` transactions are processed.
         struct TxEditAccount {
-            /// Public key of the editor.
+            /// Public key of the editor. Must be the account owner.
             pkey_by_who: &PublicKey,
 
             /// Public key of the account owner.
@@ -58,7 +58,7 @@ transactions! {
         /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
         /// `TxSetCustomData` transactions are processed.
         struct TxSetCustomData {
-            /// Public key of the editor.
+            /// Public key of the editor. Must be the account owner.
             pkey_by_who: &PublicKey,
 
             /// Public key of the account owner.
@@ -78,7 +78,7 @@ transactions! {
         /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
         /// `TxDeleteAccount` transactions are processed.
         struct TxDeleteAccount {
-            /// Public key of the editor.
+            /// Public key of the editor. Must be the account owner.
             pkey_by_who: &PublicKey,
 
             /// Public key of the account owner.
@@ -89,5 +89,149 @@ transactions! {
             /// [idempotence]: https://en.wikipedia.org/wiki/Idempotence
             seed: u64,
         }
+
+        /// Transaction type for storing an encrypted data field of an account.
+        ///
+        /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
+        /// `TxSetField` transactions are processed.
+        struct TxSetField {
+            /// Public key of the account owner.
+            pkey_account: &PublicKey,
+
+            /// UTF-8 string with the field name.
+            name: &str,
+
+            /// Field content encrypted with a fresh content key.
+            ciphertext: &[u8],
+
+            /// Content key wrapped for every grantee, the owner included.
+            wrapped_keys: Vec<WrappedKey>,
+
+            /// Auxiliary number to guarantee [non-idempotence][idempotence] of transactions.
+            ///
+            /// [idempotence]: https://en.wikipedia.org/wiki/Idempotence
+            seed: u64,
+        }
+
+        /// Transaction type for giving a grantee access to a data field.
+        ///
+        /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
+        /// `TxGrantAccess` transactions are processed.
+        struct TxGrantAccess {
+            /// Public key of the account owner.
+            pkey_account: &PublicKey,
+
+            /// UTF-8 string with the field name.
+            name: &str,
+
+            /// Current content key wrapped for the grantee.
+            key: WrappedKey,
+
+            /// Auxiliary number to guarantee [non-idempotence][idempotence] of transactions.
+            ///
+            /// [idempotence]: https://en.wikipedia.org/wiki/Idempotence
+            seed: u64,
+        }
+
+        /// Transaction type for revoking access to a data field. The field is
+        /// re-encrypted with a new content key that is wrapped for the remaining grantees.
+        ///
+        /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
+        /// `TxRevokeAccess` transactions are processed.
+        struct TxRevokeAccess {
+            /// Public key of the account owner.
+            pkey_account: &PublicKey,
+
+            /// UTF-8 string with the field name.
+            name: &str,
+
+            /// Public key of the grantee losing access.
+            grantee: &PublicKey,
+
+            /// Field content encrypted with the new content key.
+            ciphertext: &[u8],
+
+            /// New content key wrapped for every remaining grantee.
+            wrapped_keys: Vec<WrappedKey>,
+
+            /// Auxiliary number to guarantee [non-idempotence][idempotence] of transactions.
+            ///
+            /// [idempotence]: https://en.wikipedia.org/wiki/Idempotence
+            seed: u64,
+        }
+
+        /// Transaction type for rewriting the stored data into the current layout.
+        ///
+        /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
+        /// `TxMigrateSchema` transactions are processed.
+        struct TxMigrateSchema {
+            /// Public key of the `zen-rolesystem` admin.
+            pkey_by_who: &PublicKey,
+
+            /// Layout version of the stored data.
+            from_version: u32,
+
+            /// Auxiliary number to guarantee [non-idempotence][idempotence] of transactions.
+            ///
+            /// [idempotence]: https://en.wikipedia.org/wiki/Idempotence
+            seed: u64,
+        }
+
+        /// Transaction type for proposing an administrative action on the employee
+        /// accounts. The action is executed after it is approved by `admin_threshold`
+        /// admins of `zen-rolesystem`, the proposer included.
+        ///
+        /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
+        /// `TxProposeAdminAction` transactions are processed.
+        struct TxProposeAdminAction {
+            /// Public key of the proposer. Must belong to the `zen-rolesystem` admin set.
+            pkey_by_who: &PublicKey,
+
+            /// JSON-serialized `AdminAction`.
+            action: &str,
+
+            /// Height of the last block in which the proposal can be approved.
+            expires_at: u64,
+
+            /// Auxiliary number to guarantee [non-idempotence][idempotence] of transactions.
+            ///
+            /// [idempotence]: https://en.wikipedia.org/wiki/Idempotence
+            seed: u64,
+        }
+
+        /// Transaction type for approving a pending administrative action.
+        ///
+        /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
+        /// `TxApproveAdminAction` transactions are processed.
+        struct TxApproveAdminAction {
+            /// Public key of the approver. Must belong to the `zen-rolesystem` admin set.
+            pkey_by_who: &PublicKey,
+
+            /// Hash of the `TxProposeAdminAction` transaction.
+            proposal: &Hash,
+
+            /// Auxiliary number to guarantee [non-idempotence][idempotence] of transactions.
+            ///
+            /// [idempotence]: https://en.wikipedia.org/wiki/Idempotence
+            seed: u64,
+        }
+    }
+}
+
+impl EmployeesTransactions {
+    /// Returns the name of the transaction type, e.g. `TxCreateAccount`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            EmployeesTransactions::TxCreateAccount(_) => "TxCreateAccount",
+            EmployeesTransactions::TxEditAccount(_) => "TxEditAccount",
+            EmployeesTransactions::TxSetCustomData(_) => "TxSetCustomData",
+            EmployeesTransactions::TxDeleteAccount(_) => "TxDeleteAccount",
+            EmployeesTransactions::TxSetField(_) => "TxSetField",
+            EmployeesTransactions::TxGrantAccess(_) => "TxGrantAccess",
+            EmployeesTransactions::TxRevokeAccess(_) => "TxRevokeAccess",
+            EmployeesTransactions::TxMigrateSchema(_) => "TxMigrateSchema",
+            EmployeesTransactions::TxProposeAdminAction(_) => "TxProposeAdminAction",
+            EmployeesTransactions::TxApproveAdminAction(_) => "TxApproveAdminAction",
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,223 @@
+//! Tests of the employee accounts administration by the `zen-rolesystem` admin set.
+
+extern crate exonum;
+#[macro_use]
+extern crate exonum_testkit;
+extern crate serde_json;
+extern crate zen_local_database;
+extern crate zen_rolesystem;
+
+use exonum::blockchain::{Schema, TransactionErrorType};
+use exonum::crypto::{self, Hash, PublicKey, SecretKey};
+use exonum::encoding::serialize::ToHex;
+use exonum::messages::Message;
+use exonum_testkit::{TestKit, TestKitBuilder};
+use zen_local_database::contracts::ErrorKind;
+use zen_local_database::governance::AdminAction;
+use zen_local_database::schema::EmployeesSchema;
+use zen_local_database::service::EmployeesService;
+use zen_local_database::transactions::{TxApproveAdminAction, TxCreateAccount, TxDeleteAccount,
+                                       TxEditAccount, TxProposeAdminAction};
+use zen_rolesystem::governance::AdminAction as RoleSystemAction;
+use zen_rolesystem::schema::{kdf, permissions, Credentials};
+use zen_rolesystem::service::RoleSystemService;
+use zen_rolesystem::transactions as rolesystem;
+
+type KeyPair = (PublicKey, SecretKey);
+
+/// Proposals in the tests can be approved until this height.
+const EXPIRES_AT: u64 = 100;
+
+/// Returns the error code of the committed transaction, `None` if it has succeeded.
+fn error_code(testkit: &TestKit, tx_hash: &Hash) -> Option<u8> {
+    let snapshot = testkit.snapshot();
+    let result = Schema::new(&snapshot)
+        .transaction_results()
+        .get(tx_hash)
+        .expect("Transaction is not committed");
+
+    match result {
+        Ok(()) => None,
+        Err(ref error) => match error.error_type() {
+            TransactionErrorType::Code(code) => Some(code),
+            TransactionErrorType::Panic => {
+                panic!("Transaction has panicked: {:?}", error.description())
+            }
+        },
+    }
+}
+
+fn create_testkit(admins: &[KeyPair], threshold: u16) -> TestKit {
+    let admin_pkeys = admins.iter().map(|&(ref pk, _)| pk.to_hex()).collect();
+    TestKitBuilder::validator()
+        .with_service(RoleSystemService::new(admin_pkeys, threshold, None))
+        .with_service(EmployeesService::new())
+        .create()
+}
+
+/// Executes a `zen-rolesystem` admin action approved by all of the admins.
+fn execute_rolesystem_action(testkit: &mut TestKit, admins: &[KeyPair], action: &RoleSystemAction) {
+    let action = serde_json::to_string(action).unwrap();
+    let proposal =
+        rolesystem::TxProposeAdminAction::new(&admins[0].0, &action, EXPIRES_AT, 0, &admins[0].1);
+    let mut txs = txvec![proposal.clone()];
+    for admin in &admins[1..] {
+        txs.push(Box::new(rolesystem::TxApproveAdminAction::new(
+            &admin.0,
+            &proposal.hash(),
+            0,
+            &admin.1,
+        )));
+    }
+    testkit.create_block_with_transactions(txs);
+}
+
+/// Registers `creator` in `zen-rolesystem` and grants it the right to create employee accounts.
+fn grant_create_accounts(testkit: &mut TestKit, admins: &[KeyPair], creator: &KeyPair) {
+    let credentials =
+        Credentials::new(&Hash::zero(), kdf::MIN_LOG_N, 8, 1, &creator.0, &Hash::zero());
+    let tx = rolesystem::TxCreateAccount::new(&creator.0, "hr", credentials, "", &creator.1);
+    testkit.create_block_with_transactions(txvec![tx]);
+
+    execute_rolesystem_action(
+        testkit,
+        admins,
+        &RoleSystemAction::SetRole {
+            name: "hr".to_owned(),
+            permissions: permissions::CREATE_ACCOUNTS,
+        },
+    );
+    execute_rolesystem_action(
+        testkit,
+        admins,
+        &RoleSystemAction::GrantRole {
+            pub_key: creator.0,
+            role: "hr".to_owned(),
+        },
+    );
+}
+
+fn create_employee(creator: &KeyPair, employee: &PublicKey, id_number: u64) -> TxCreateAccount {
+    TxCreateAccount::new(&creator.0, employee, "Alice", "Smith", id_number, &creator.1)
+}
+
+fn propose(admin: &KeyPair, action: &AdminAction) -> TxProposeAdminAction {
+    let action = serde_json::to_string(action).unwrap();
+    TxProposeAdminAction::new(&admin.0, &action, EXPIRES_AT, 0, &admin.1)
+}
+
+fn has_account(testkit: &TestKit, pub_key: &PublicKey) -> bool {
+    let snapshot = testkit.snapshot();
+    EmployeesSchema::new(&snapshot).account(pub_key).is_some()
+}
+
+/// Creates the testkit with two admins, threshold two and an employee account.
+fn setup(admins: &[KeyPair], employee: &PublicKey) -> TestKit {
+    let creator = crypto::gen_keypair();
+    let mut testkit = create_testkit(admins, 2);
+    grant_create_accounts(&mut testkit, admins, &creator);
+
+    let tx = create_employee(&creator, employee, 42);
+    testkit.create_block_with_transactions(txvec![tx.clone()]);
+    assert_eq!(error_code(&testkit, &tx.hash()), None);
+
+    testkit
+}
+
+#[test]
+fn test_single_admin_cannot_delete_account() {
+    let admins = vec![crypto::gen_keypair(), crypto::gen_keypair()];
+    let (employee, _) = crypto::gen_keypair();
+    let mut testkit = setup(&admins, &employee);
+
+    // A direct deletion of another user's account is rejected even for an admin
+    let direct = TxDeleteAccount::new(&admins[0].0, &employee, 0, &admins[0].1);
+    testkit.create_block_with_transactions(txvec![direct.clone()]);
+    assert_eq!(
+        error_code(&testkit, &direct.hash()),
+        Some(ErrorKind::PermissionDenied.into_code())
+    );
+    assert!(has_account(&testkit, &employee));
+
+    let proposal = propose(&admins[0], &AdminAction::DeleteAccount { pub_key: employee });
+    testkit.create_block_with_transactions(txvec![proposal.clone()]);
+    assert_eq!(error_code(&testkit, &proposal.hash()), None);
+    assert!(has_account(&testkit, &employee));
+
+    // The proposer can't approve its own proposal once more
+    let again = TxApproveAdminAction::new(&admins[0].0, &proposal.hash(), 0, &admins[0].1);
+    testkit.create_block_with_transactions(txvec![again.clone()]);
+    assert_eq!(
+        error_code(&testkit, &again.hash()),
+        Some(ErrorKind::ProposalAlreadyApproved.into_code())
+    );
+    assert!(has_account(&testkit, &employee));
+
+    let approval = TxApproveAdminAction::new(&admins[1].0, &proposal.hash(), 0, &admins[1].1);
+    testkit.create_block_with_transactions(txvec![approval.clone()]);
+    assert_eq!(error_code(&testkit, &approval.hash()), None);
+    assert!(!has_account(&testkit, &employee));
+
+    let snapshot = testkit.snapshot();
+    let schema = EmployeesSchema::new(&snapshot);
+    assert!(schema.proposal(&proposal.hash()).unwrap().executed());
+    assert!(schema.account_by_id(&42).is_none());
+}
+
+#[test]
+fn test_edit_account_by_admins() {
+    let admins = vec![crypto::gen_keypair(), crypto::gen_keypair()];
+    let (employee, _) = crypto::gen_keypair();
+    let mut testkit = setup(&admins, &employee);
+
+    let direct = TxEditAccount::new(&admins[0].0, &employee, "Bob", "Smith", 42, 0, &admins[0].1);
+    testkit.create_block_with_transactions(txvec![direct.clone()]);
+    assert_eq!(
+        error_code(&testkit, &direct.hash()),
+        Some(ErrorKind::PermissionDenied.into_code())
+    );
+
+    let proposal = propose(
+        &admins[0],
+        &AdminAction::EditAccount {
+            pub_key: employee,
+            first_name: "Bob".to_owned(),
+            last_name: "Smith".to_owned(),
+            id_number: 42,
+        },
+    );
+    let approval = TxApproveAdminAction::new(&admins[1].0, &proposal.hash(), 0, &admins[1].1);
+    testkit.create_block_with_transactions(txvec![proposal.clone(), approval.clone()]);
+    assert_eq!(error_code(&testkit, &approval.hash()), None);
+
+    let snapshot = testkit.snapshot();
+    let schema = EmployeesSchema::new(&snapshot);
+    assert_eq!(schema.account(&employee).unwrap().first_name(), "Bob");
+    assert_eq!(schema.account_history(&employee).last(), Some(approval.hash()));
+}
+
+#[test]
+fn test_non_admin_proposal() {
+    let admins = vec![crypto::gen_keypair(), crypto::gen_keypair()];
+    let owner = crypto::gen_keypair();
+    let employee = owner.0;
+    let mut testkit = setup(&admins, &employee);
+
+    let proposal = propose(&owner, &AdminAction::DeleteAccount { pub_key: employee });
+    let invalid = TxProposeAdminAction::new(&admins[0].0, "{}", EXPIRES_AT, 0, &admins[0].1);
+    testkit.create_block_with_transactions(txvec![proposal.clone(), invalid.clone()]);
+    assert_eq!(
+        error_code(&testkit, &proposal.hash()),
+        Some(ErrorKind::PermissionDenied.into_code())
+    );
+    assert_eq!(
+        error_code(&testkit, &invalid.hash()),
+        Some(ErrorKind::InvalidAdminAction.into_code())
+    );
+
+    // The owner still can delete its own account
+    let deletion = TxDeleteAccount::new(&employee, &employee, 0, &owner.1);
+    testkit.create_block_with_transactions(txvec![deletion.clone()]);
+    assert_eq!(error_code(&testkit, &deletion.hash()), None);
+    assert!(!has_account(&testkit, &employee));
+}
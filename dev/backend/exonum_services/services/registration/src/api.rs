@@ -1,17 +1,28 @@
-use bodyparser;
-use exonum::api::{Api, ApiError};
+use actix_web::{self, http, AsyncResponder, FromRequest, HttpMessage, HttpResponse, Query};
+use api_common::{self, HistoryQuery, PageQuery, ServiceStats, SignedExport, MAX_PAGE_SIZE};
+use contracts::ErrorKind;
+use custom_data;
+use exonum::api::backends::actix::{FutureResponse, HttpRequest, RawHandler, RequestHandler};
+use exonum::api::{self, ServiceApiBackend, ServiceApiBuilder, ServiceApiState};
 use exonum::blockchain::*;
-use exonum::crypto::{Hash, PublicKey};
+use exonum::crypto::{self, Hash, PublicKey, Signature};
+use exonum::encoding::serialize::FromHex;
+use governance::{AdminAction, RolesDump};
 use exonum::helpers::Height;
 use exonum::node::TransactionSend;
-use exonum::storage::Snapshot;
-use iron::prelude::*;
-use router::Router;
+use exonum_time::TimeSchema;
+use futures::{Future, IntoFuture};
+use ipfs::{self, Cid, SharedBlockstore};
 use schema::*;
-use schema::EmployeeId;
-use serde_json;
+use rand;
+use serde_json::{self, Value};
+use service::SERVICE_ID;
+use std::cmp::min;
+use std::collections::HashMap;
 use std::fmt;
-use transactions::RoleSystemTransactions;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use transactions::{RoleSystemTransactions, TxProposeAdminAction};
 
 /// The structure returned by the REST API.
 #[derive(Debug, Serialize, Deserialize)]
@@ -20,180 +31,783 @@ pub struct TransactionResponse {
     pub tx_hash: Hash,
 }
 
-/// Account custom data.
+/// Query of the endpoints that take an account.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct AccountQuery {
+    /// Public key of the account.
+    pub pub_key: PublicKey,
+}
+
+/// Account information together with the proofs.
+pub type AccountWithProof = api_common::AccountWithProof<Account>;
+
+/// Query for an account by its username.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UsernameQuery {
+    /// Username of the account.
+    pub username: String,
+}
+
+/// Query for a transaction by its hash.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct TransactionQuery {
+    /// Hash of the transaction.
+    pub hash: Hash,
+}
+
+/// CID of an uploaded photo.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PhotoResponse {
+    /// CID to be used as `photo_ipfs` of an account.
+    pub cid: String,
+}
+
+/// Page of the accounts.
+#[derive(Debug, Serialize)]
+pub struct AccountsPage {
+    /// Index of the first returned account.
+    pub offset: u64,
+    /// Accounts ordered by the public key.
+    pub accounts: Vec<Account>,
+}
+
+/// How long a login nonce stays valid.
+pub const CHALLENGE_TTL: Duration = Duration::from_secs(60);
+
+/// Maximum number of unexpired login nonces of a single account.
+pub const MAX_CHALLENGES_PER_ACCOUNT: usize = 8;
+
+/// Login nonces issued by the node together with the account public key and
+/// the issue time. The map is keyed by the nonce, so a new challenge for the same
+/// account doesn't invalidate the previous ones, up to `MAX_CHALLENGES_PER_ACCOUNT`.
+/// Each nonce can be used only once.
+pub type Challenges = Arc<Mutex<HashMap<Hash, (PublicKey, Instant)>>>;
+
+/// Issues a new login nonce for the account, dropping the expired ones and
+/// the oldest nonces of the account above the limit.
+fn challenge_issue(challenges: &Challenges, pub_key: &PublicKey, now: Instant) -> Hash {
+    let nonce = Hash::new(rand::random());
+    let mut challenges = challenges.lock().unwrap();
+    challenges.retain(|_, &mut (_, issued)| now.duration_since(issued) < CHALLENGE_TTL);
+
+    let mut issued: Vec<(Instant, Hash)> = challenges
+        .iter()
+        .filter(|&(_, &(ref issued_to, _))| issued_to == pub_key)
+        .map(|(nonce, &(_, issued))| (issued, *nonce))
+        .collect();
+    if issued.len() >= MAX_CHALLENGES_PER_ACCOUNT {
+        issued.sort_by_key(|&(issued, _)| issued);
+        let excess = issued.len() + 1 - MAX_CHALLENGES_PER_ACCOUNT;
+        for &(_, ref oldest) in &issued[..excess] {
+            challenges.remove(oldest);
+        }
+    }
+
+    challenges.insert(nonce, (*pub_key, now));
+    nonce
+}
+
+/// Consumes the login nonce. Returns `true` if it was issued to the account
+/// and hasn't expired.
+fn challenge_take(challenges: &Challenges, nonce: &Hash, pub_key: &PublicKey, now: Instant) -> bool {
+    match challenges.lock().unwrap().remove(nonce) {
+        Some((issued_to, issued)) => {
+            issued_to == *pub_key && now.duration_since(issued) < CHALLENGE_TTL
+        }
+        None => false,
+    }
+}
+
+/// Number of photo bytes an account can upload during `PHOTO_QUOTA_PERIOD`.
+pub const PHOTO_QUOTA: usize = 4 * ipfs::MAX_PHOTO_SIZE;
+
+/// Period after which the photo quota of an account is renewed.
+pub const PHOTO_QUOTA_PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Header with the public key of the account uploading a photo.
+pub const PUB_KEY_HEADER: &str = "X-Pub-Key";
+
+/// Header with the signature of the uploaded photo made with the account key.
+pub const SIGNATURE_HEADER: &str = "X-Signature";
+
+/// Photo bytes uploaded by the accounts together with the start of their quota period.
+pub type PhotoQuotas = Arc<Mutex<HashMap<PublicKey, (usize, Instant)>>>;
+
+/// Returns the number of bytes the account can still upload in its quota period.
+fn photo_quota_left(quotas: &PhotoQuotas, pub_key: &PublicKey) -> usize {
+    match quotas.lock().unwrap().get(pub_key) {
+        Some(&(used, started)) if started.elapsed() < PHOTO_QUOTA_PERIOD => {
+            PHOTO_QUOTA.saturating_sub(used)
+        }
+        _ => PHOTO_QUOTA,
+    }
+}
+
+/// Counts `size` bytes against the quota of the account. Returns `false` without
+/// counting them if the quota would be exceeded.
+fn photo_quota_charge(quotas: &PhotoQuotas, pub_key: &PublicKey, size: usize) -> bool {
+    let now = Instant::now();
+    let mut quotas = quotas.lock().unwrap();
+    quotas.retain(|_, &mut (_, started)| now.duration_since(started) < PHOTO_QUOTA_PERIOD);
+
+    let quota = quotas.entry(*pub_key).or_insert((0, now));
+    if quota.0 + size > PHOTO_QUOTA {
+        return false;
+    }
+    quota.0 += size;
+    true
+}
+
+/// Login nonce together with the key derivation parameters of the account.
+#[derive(Debug, Serialize)]
+pub struct ChallengeResponse {
+    /// Nonce to be signed with the password-derived secret key.
+    pub nonce: Hash,
+    /// Salt and scrypt parameters to derive the key from the password.
+    pub credentials: Credentials,
+}
+
+/// Signed login nonce.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LoginRequest {
+    /// Public key of the account.
+    pub pub_key: PublicKey,
+    /// Nonce previously issued by the node.
+    pub nonce: Hash,
+    /// Signature of the nonce made with the password-derived secret key.
+    pub signature: Signature,
+}
+
+/// Guardians of the account and its pending recovery.
+#[derive(Debug, Serialize)]
+pub struct RecoveryInfo {
+    /// Guardian settings, if the account has nominated guardians.
+    pub config: Option<RecoveryConfig>,
+    /// Pending recovery, if any.
+    pub recovery: Option<Recovery>,
+    /// Guardians that have approved the pending recovery.
+    pub approvals: Vec<PublicKey>,
+}
+
+/// Session of the account.
 #[derive(Debug, Serialize)]
-pub struct AccountInfo {
-    custom_data: String,
+pub struct SessionInfo {
+    /// Session record.
+    pub session: Session,
+    /// Whether the session hasn't expired at the current consensus time.
+    pub active: bool,
 }
 
-/// Employees service API description.
+/// Number of blocks during which the proposals submitted through the private API
+/// can be approved by the other admins.
+pub const ADMIN_PROPOSAL_TTL: u64 = 1_000;
+
+/// Admin proposals submitted on behalf of the node.
+#[derive(Debug, Serialize)]
+pub struct ProposalsResponse {
+    /// Hashes of the `TxProposeAdminAction` transactions, one per action.
+    pub proposals: Vec<Hash>,
+    /// Height of the last block in which the proposals can be approved.
+    pub expires_at: u64,
+}
+
+fn account_not_found() -> api::Error {
+    api::Error::NotFound("Account not found".to_owned())
+}
+
+/// Public API of the RoleSystem service.
 #[derive(Clone)]
-pub struct RoleSystemApi<T: TransactionSend + Clone> {
-    /// Exonum blockchain.
-    pub blockchain: Blockchain,
-    /// Channel for transactions.
-    pub channel: T,
-}
-
-impl<T> RoleSystemApi<T>
-    where
-        T: TransactionSend + Clone + 'static,
-{
-    /// Checks that given `Block` has transactions that did modified a specific `Account`
-    fn helper_check_tx_modifies_account(&self, schema: &Schema<&Box<Snapshot>>, block: &Block, account: &Account) -> bool {
-        let block_transactions = schema.block_transactions(block.height());
-
-        let mut found = false;
-
-        // Get transaction bytes by its hash and convert it into an actual `Transaction`
-        for tx_hash in block_transactions.iter() {
-            let transaction = schema.transactions().get(&tx_hash);
-
-            if let Some(tx) = transaction {
-                let tx =  RoleSystemTransactions::tx_from_raw(tx);
-
-                if let Ok(tx) = tx {
-                    // Is there any of possible transactions that modifies a specific account?
-                    found = match tx {
-                        RoleSystemTransactions::TxCreateAccount(tx) => {
-                            tx.pub_key() == account.pub_key()
-                        }
-
-                        // RoleSystemTransactions::TxEditAccount(tx) => {
-                        //     tx.pkey_account() == account.pub_key()
-                        // }
-
-                        // RoleSystemTransactions::TxSetCustomData(tx) => {
-                        //     tx.pkey_account() == account.pub_key()
-                        // }
-
-                        // RoleSystemTransactions::TxDeleteAccount(tx) => {
-                        //     tx.pkey_account() == account.pub_key()
-                        // }
-                    };
-
-                    if found {
-                        break
-                    }
-                }
-            }
+pub struct PublicApi {
+    /// Login nonces issued to the clients.
+    pub challenges: Challenges,
+    /// Local storage of the uploaded photos.
+    pub blockstore: SharedBlockstore,
+    /// Photo bytes uploaded by the accounts.
+    pub photo_quotas: PhotoQuotas,
+}
+
+impl PublicApi {
+    /// Creates the API serving photos from the given blockstore.
+    pub fn new(blockstore: SharedBlockstore) -> Self {
+        PublicApi {
+            challenges: Challenges::default(),
+            blockstore,
+            photo_quotas: PhotoQuotas::default(),
         }
+    }
 
-        found
+    /// Endpoint for sending a signed transaction to the node.
+    pub fn post_transaction(
+        state: &ServiceApiState,
+        query: RoleSystemTransactions,
+    ) -> api::Result<TransactionResponse> {
+        let transaction: Box<Transaction> = query.into();
+        let tx_hash = transaction.hash();
+        state.sender().send(transaction)?;
+        Ok(TransactionResponse { tx_hash })
     }
 
-    /// Finds number (height) of the block which contains a latest transaction that modifies
-    /// a given account.
+    /// Endpoint for getting the status of a transaction of the service: pending,
+    /// committed or failed with the decoded `ErrorKind`.
+    pub fn transaction_info(
+        state: &ServiceApiState,
+        query: TransactionQuery,
+    ) -> api::Result<TransactionInfo> {
+        let view = state.snapshot();
+        api_common::transaction_info(&*view, SERVICE_ID, query.hash, ErrorKind::from_code)
+    }
+
+    /// Endpoint for getting a page of the accounts ordered by the public key.
+    pub fn accounts(state: &ServiceApiState, query: PageQuery) -> api::Result<AccountsPage> {
+        let view = state.snapshot();
+        let schema = RoleSystemSchema::new(&view);
+
+        let accounts = schema
+            .accounts()
+            .values()
+            .skip(query.offset as usize)
+            .take(min(query.count, MAX_PAGE_SIZE) as usize)
+            .collect();
+
+        Ok(AccountsPage {
+            offset: query.offset,
+            accounts,
+        })
+    }
+
+    /// Endpoint for getting an account together with the proofs that let
+    /// a light client verify it against the latest block.
+    pub fn account(state: &ServiceApiState, query: AccountQuery) -> api::Result<AccountWithProof> {
+        let view = state.snapshot();
+        let schema = RoleSystemSchema::new(&view);
+
+        Ok(api_common::account_with_proof(
+            &*view,
+            SERVICE_ID,
+            schema.accounts().get_proof(query.pub_key),
+            schema.account(&query.pub_key),
+        ))
+    }
+
+    /// Endpoint for getting an account by its username.
+    pub fn account_by_username(
+        state: &ServiceApiState,
+        query: UsernameQuery,
+    ) -> api::Result<Account> {
+        let view = state.snapshot();
+        RoleSystemSchema::new(&view)
+            .account_by_username(&query.username)
+            .ok_or_else(account_not_found)
+    }
+
+    /// Endpoint for getting height of the block with the latest transaction
+    /// of the account.
     ///
-    /// Complexity: `O(n)`, where: `n` is number of blocks in the chain, since lookup in the `Map`s are `O(1)`
-    fn block_by_account(&self, account: &Account) -> Option<Height> {
-        use std::cmp::max;
+    /// Complexity: `O(1)`, the latest transaction is taken from the account history.
+    pub fn account_block(state: &ServiceApiState, query: AccountQuery) -> api::Result<Height> {
+        let view = state.snapshot();
+        let tx_hash = RoleSystemSchema::new(&view)
+            .account_history(&query.pub_key)
+            .last()
+            .ok_or_else(account_not_found)?;
+
+        Schema::new(&view)
+            .transactions_locations()
+            .get(&tx_hash)
+            .map(|location| location.block_height())
+            .ok_or_else(account_not_found)
+    }
 
-        let view = self.blockchain.snapshot();
-        let schema = Schema::new(&view);
-        let blocks = schema.blocks();
+    /// Endpoint for getting a page of the account history, from the oldest
+    /// transaction to the newest one.
+    pub fn account_history(
+        state: &ServiceApiState,
+        query: HistoryQuery,
+    ) -> api::Result<AccountHistory> {
+        let view = state.snapshot();
+        let schema = RoleSystemSchema::new(&view);
+        let history = schema.account_history(&query.pub_key);
+
+        Ok(api_common::account_history(&*view, &history, &query))
+    }
 
-        // Look for a related blocks that contains transactions that are changing given account
-        // and take the latest (with max. height)
-        let mut max_height = None;
+    /// Endpoint for dumping all roles from the storage.
+    pub fn roles(state: &ServiceApiState, _query: ()) -> api::Result<Vec<Role>> {
+        let view = state.snapshot();
+        Ok(RoleSystemSchema::new(&view).roles().values().collect())
+    }
 
-        blocks.iter()
-            // Look for related blocks
-            .filter(|&(_, ref block)| self.helper_check_tx_modifies_account(&schema, block, &account))
+    /// Endpoint for getting custom data entries of the account as a JSON object.
+    pub fn custom_data(state: &ServiceApiState, query: AccountQuery) -> api::Result<Value> {
+        let view = state.snapshot();
+        let schema = RoleSystemSchema::new(&view);
+        Ok(custom_data::to_object(schema.custom_data(&query.pub_key).iter()))
+    }
 
-            // Find the latest one (with max. height)
-            .for_each( | (_, block) | max_height = Some(max(block.height(), max_height.unwrap_or(Height(0)))));
+    /// Endpoint for dumping all registered data schemas.
+    pub fn data_schemas(state: &ServiceApiState, _query: ()) -> api::Result<Vec<DataSchema>> {
+        let view = state.snapshot();
+        Ok(RoleSystemSchema::new(&view).data_schemas().values().collect())
+    }
 
-        max_height
+    /// Endpoint for getting names of the roles granted to the account.
+    pub fn account_roles(state: &ServiceApiState, query: AccountQuery) -> api::Result<Vec<String>> {
+        let view = state.snapshot();
+        let schema = RoleSystemSchema::new(&view);
+        Ok(schema.account_roles(&query.pub_key).iter().collect())
     }
 
-    /// Same as `block_by_account` but firstly looks up for an account
-    /// that is associated with the specific `EmployeeId`
-    // fn block_by_employee_id(&self, id: &EmployeeId) -> Option<Height> {
-    //     let view = self.blockchain.snapshot();
-    //     let employees_schema = EmployeesSchema::new(view);
+    /// Endpoint for getting guardians of the account and the state of its recovery.
+    pub fn account_recovery(
+        state: &ServiceApiState,
+        query: AccountQuery,
+    ) -> api::Result<RecoveryInfo> {
+        let view = state.snapshot();
+        let schema = RoleSystemSchema::new(&view);
+
+        Ok(RecoveryInfo {
+            config: schema.recovery_config(&query.pub_key),
+            recovery: schema.recovery(&query.pub_key),
+            approvals: schema.recovery_approvals(&query.pub_key).iter().collect(),
+        })
+    }
 
-    //     if let Some(account) = employees_schema.account_by_id(id) {
-    //         return self.block_by_account(&account);
-    //     }
+    /// Endpoint for getting device keys of the account together with the heights
+    /// at which they have been added.
+    pub fn account_devices(
+        state: &ServiceApiState,
+        query: AccountQuery,
+    ) -> api::Result<Vec<Device>> {
+        let view = state.snapshot();
+        let schema = RoleSystemSchema::new(&view);
+
+        Ok(schema
+            .account_devices(&query.pub_key)
+            .iter()
+            .filter_map(|key| schema.device(&key))
+            .collect())
+    }
 
-    //     None
-    // }
+    /// Endpoint for getting sessions of the account. A session is active while the
+    /// consensus time of the `exonum-time` oracle is before its expiry.
+    pub fn account_sessions(
+        state: &ServiceApiState,
+        query: AccountQuery,
+    ) -> api::Result<Vec<SessionInfo>> {
+        let view = state.snapshot();
+        let now = TimeSchema::new(&view).time().get();
+        let schema = RoleSystemSchema::new(&view);
+
+        Ok(schema
+            .account_sessions(&query.pub_key)
+            .iter()
+            .filter_map(|key| schema.session(&key))
+            .map(|session| SessionInfo {
+                active: now.map_or(false, |now| session.is_active_at(now)),
+                session,
+            })
+            .collect())
+    }
 
-    fn transaction(&self, req: &mut Request) -> IronResult<Response> {
-        match req.get::<bodyparser::Struct<RoleSystemTransactions>>() {
-            Ok(Some(transaction)) => {
-                let transaction: Box<Transaction> = transaction.into();
-                let tx_hash = transaction.hash();
-                self.channel.send(transaction).map_err(ApiError::from)?;
-                let json = TransactionResponse { tx_hash };
-                self.ok_response(&serde_json::to_value(&json).unwrap())
-            }
+    /// Endpoint for issuing a login nonce to an active account.
+    pub fn challenge(
+        &self,
+        state: &ServiceApiState,
+        query: AccountQuery,
+    ) -> api::Result<ChallengeResponse> {
+        let view = state.snapshot();
+        let account = match RoleSystemSchema::new(&view).account(&query.pub_key) {
+            Some(ref account) if account.is_active() => account.clone(),
+            _ => return Err(account_not_found()),
+        };
+
+        let nonce = challenge_issue(&self.challenges, &query.pub_key, Instant::now());
+
+        Ok(ChallengeResponse {
+            nonce,
+            credentials: account.credentials(),
+        })
+    }
 
-            Ok(None) => Err(ApiError::BadRequest("Empty request body".into()))?,
-            Err(e) => Err(ApiError::BadRequest(e.to_string()))?,
+    /// Endpoint for logging in with a signed nonce. The signature is checked
+    /// against the password verifier of the account, so the password itself
+    /// is never sent to the node.
+    pub fn login(&self, state: &ServiceApiState, query: LoginRequest) -> api::Result<Account> {
+        if !challenge_take(&self.challenges, &query.nonce, &query.pub_key, Instant::now()) {
+            return Err(api::Error::Unauthorized);
+        }
+
+        let view = state.snapshot();
+        match RoleSystemSchema::new(&view).account(&query.pub_key) {
+            Some(account)
+                if account.is_active()
+                    && crypto::verify(
+                        &query.signature,
+                        query.nonce.as_ref(),
+                        account.credentials().verifier(),
+                    ) =>
+            {
+                Ok(account)
+            }
+            _ => Err(api::Error::Unauthorized),
         }
     }
 
-    fn account(&self, req: &mut Request) -> IronResult<Response> {
-        let pub_key: PublicKey = self.url_fragment(req, "pubkey")?;
-        let view = self.blockchain.snapshot();
-        let schema = RoleSystemSchema::new(view);
-        if let Some(account) = schema.account(&pub_key) {
-            self.ok_response(&serde_json::to_value(&account).unwrap())
-        } else {
-            self.not_found_response(&serde_json::to_value("Account not found").unwrap())
+    /// Returns the active account uploading a photo and the signature from the
+    /// request headers.
+    fn photo_uploader(request: &HttpRequest) -> api::Result<(PublicKey, Signature)> {
+        let header = |name: &str| {
+            request
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned)
+        };
+        let pub_key = header(PUB_KEY_HEADER).and_then(|pub_key| PublicKey::from_hex(pub_key).ok());
+        let signature =
+            header(SIGNATURE_HEADER).and_then(|signature| Signature::from_hex(signature).ok());
+        let (pub_key, signature) = match (pub_key, signature) {
+            (Some(pub_key), Some(signature)) => (pub_key, signature),
+            _ => return Err(api::Error::Unauthorized),
+        };
+
+        let view = request.state().snapshot();
+        match RoleSystemSchema::new(&view).account(&pub_key) {
+            Some(ref account) if account.is_active() => Ok((pub_key, signature)),
+            _ => Err(api::Error::Unauthorized),
         }
     }
 
-    // fn block_by_id(&self, req: &mut Request) -> IronResult<Response> {
-    //     let employee_id: EmployeeId = self.url_fragment(req, "id")?;
+    /// Endpoint for uploading a photo into the local blockstore. The request body
+    /// is the raw photo content signed by an active account, see `PUB_KEY_HEADER`
+    /// and `SIGNATURE_HEADER`. Every account can upload up to `PHOTO_QUOTA` bytes
+    /// during `PHOTO_QUOTA_PERIOD`.
+    fn upload_photo(
+        blockstore: SharedBlockstore,
+        quotas: PhotoQuotas,
+        request: HttpRequest,
+    ) -> FutureResponse {
+        let uploader = Self::photo_uploader(&request).and_then(|(pub_key, signature)| {
+            match photo_quota_left(&quotas, &pub_key) {
+                0 => Err(api::Error::BadRequest("Photo quota exceeded".to_owned())),
+                quota_left => Ok((pub_key, signature, quota_left)),
+            }
+        });
+        let (pub_key, signature, quota_left) = match uploader {
+            Ok(uploader) => uploader,
+            Err(e) => {
+                let response: Result<HttpResponse, actix_web::Error> = Err(e.into());
+                return Box::new(response.into_future());
+            }
+        };
+
+        request
+            .body()
+            .limit(min(ipfs::MAX_PHOTO_SIZE, quota_left))
+            .from_err()
+            .and_then(move |content| -> Result<HttpResponse, actix_web::Error> {
+                if !crypto::verify(&signature, &content, &pub_key) {
+                    return Err(api::Error::Unauthorized.into());
+                }
+                if !photo_quota_charge(&quotas, &pub_key, content.len()) {
+                    return Err(api::Error::BadRequest("Photo quota exceeded".to_owned()).into());
+                }
+
+                let cid = {
+                    let mut blockstore = blockstore.write().unwrap();
+                    ipfs::add(&mut **blockstore, &content).map_err(api::Error::from)?
+                };
+
+                Ok(HttpResponse::Ok().json(PhotoResponse {
+                    cid: cid.to_string(),
+                }))
+            })
+            .responder()
+    }
+
+    /// Endpoint for getting the photo of the account from the local blockstore.
+    /// Every block is checked against its CID before it is returned.
+    fn photo(blockstore: &SharedBlockstore, request: &HttpRequest) -> api::Result<Vec<u8>> {
+        let query: AccountQuery = Query::from_request(request, &())
+            .map_err(|e| api::Error::BadRequest(e.to_string()))?
+            .into_inner();
 
-    //     if let Some(height) = self.block_by_employee_id(&employee_id) {
-    //         self.ok_response(&serde_json::to_value(&height).unwrap())
-    //     } else {
-    //         self.not_found_response(&serde_json::to_value("Account not found").unwrap())
-    //     }
-    // }
+        let view = request.state().snapshot();
+        let cid = RoleSystemSchema::new(&view)
+            .account(&query.pub_key)
+            .and_then(|account| account.photo_ipfs().parse::<Cid>().ok());
 
-    /// Endpoint for dumping all accounts from the storage.
-    fn accounts(&self, _: &mut Request) -> IronResult<Response> {
-        let snapshot = self.blockchain.snapshot();
-        let schema = RoleSystemSchema::new(snapshot);
-        let idx = schema.accounts();
-        let accounts: Vec<Account> = idx.values().collect();
+        cid.and_then(|cid| ipfs::cat(&**blockstore.read().unwrap(), &cid))
+            .ok_or_else(|| api::Error::NotFound("Photo not found".to_owned()))
+    }
 
-        self.ok_response(&serde_json::to_value(&accounts).unwrap())
+    /// Adds the photo endpoints. Photos are sent as raw bytes, so they bypass
+    /// the JSON endpoints.
+    fn wire_photos(&self, builder: &mut ServiceApiBuilder) {
+        let blockstore = self.blockstore.clone();
+        let get_photo = move |request: HttpRequest| -> FutureResponse {
+            let response: Result<HttpResponse, actix_web::Error> =
+                Self::photo(&blockstore, &request)
+                    .map(|content| {
+                        HttpResponse::Ok()
+                            .content_type("application/octet-stream")
+                            .body(content)
+                    })
+                    .map_err(From::from);
+            Box::new(response.into_future())
+        };
+
+        let blockstore = self.blockstore.clone();
+        let quotas = self.photo_quotas.clone();
+        let post_photo = move |request: HttpRequest| {
+            Self::upload_photo(blockstore.clone(), quotas.clone(), request)
+        };
+
+        builder
+            .public_scope()
+            .web_backend()
+            .raw_handler(RequestHandler {
+                name: "v1/accounts/photo".to_owned(),
+                method: http::Method::GET,
+                inner: Arc::from(get_photo) as Arc<RawHandler>,
+            })
+            .raw_handler(RequestHandler {
+                name: "v1/photos".to_owned(),
+                method: http::Method::POST,
+                inner: Arc::from(post_photo) as Arc<RawHandler>,
+            });
+    }
+
+    /// Adds the public endpoints to the builder.
+    pub fn wire(self, builder: &mut ServiceApiBuilder) {
+        self.wire_photos(builder);
+
+        let api = self.clone();
+        let challenge = move |state: &ServiceApiState, query: AccountQuery| {
+            api.challenge(state, query)
+        };
+        let api = self.clone();
+        let login = move |state: &ServiceApiState, query: LoginRequest| api.login(state, query);
+
+        builder
+            .public_scope()
+            .endpoint("v1/accounts", Self::accounts)
+            .endpoint("v1/accounts/info", Self::account)
+            .endpoint("v1/accounts/by-username", Self::account_by_username)
+            .endpoint("v1/accounts/block", Self::account_block)
+            .endpoint("v1/accounts/history", Self::account_history)
+            .endpoint("v1/accounts/roles", Self::account_roles)
+            .endpoint("v1/accounts/recovery", Self::account_recovery)
+            .endpoint("v1/accounts/devices", Self::account_devices)
+            .endpoint("v1/accounts/sessions", Self::account_sessions)
+            .endpoint("v1/accounts/custom-data", Self::custom_data)
+            .endpoint("v1/data-schemas", Self::data_schemas)
+            .endpoint("v1/roles", Self::roles)
+            .endpoint("v1/auth/challenge", challenge)
+            .endpoint_mut("v1/auth/login", login)
+            .endpoint("v1/transactions", Self::transaction_info)
+            .endpoint_mut("v1/accounts/transaction", Self::post_transaction);
     }
 }
 
-impl<T: TransactionSend + Clone> fmt::Debug for EmployeesApi<T> {
+impl fmt::Debug for PublicApi {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "RoleSystemApi {{}}")
+        write!(f, "PublicApi {{}}")
     }
 }
 
-impl<T> Api for RoleSystemApi<T>
-    where
-        T: 'static + TransactionSend + Clone,
-{
-    fn wire(&self, router: &mut Router) {
-        let self_ = self.clone();
-        let get_accounts =move |req: &mut Request| self_.accounts(req);
+/// Private API of the RoleSystem service for the node operators. It is served
+/// only on the private API address of the node.
+#[derive(Debug, Clone, Copy)]
+pub struct PrivateApi;
+
+impl PrivateApi {
+    /// Endpoint for dumping all accounts from the storage.
+    pub fn all_accounts(state: &ServiceApiState, _query: ()) -> api::Result<Vec<Account>> {
+        let view = state.snapshot();
+        Ok(RoleSystemSchema::new(&view).accounts().values().collect())
+    }
+
+    /// Endpoint for dumping the admin set, the roles and the role grants.
+    pub fn dump_roles(state: &ServiceApiState, _query: ()) -> api::Result<RolesDump> {
+        let view = state.snapshot();
+        Ok(RolesDump::new(&RoleSystemSchema::new(&view)))
+    }
+
+    /// Endpoint for restoring a dump made by `dump_roles`. The admin actions needed
+    /// to reach the dumped state are proposed on behalf of the node and still have
+    /// to be approved by the other admins.
+    pub fn restore_roles(
+        state: &ServiceApiState,
+        query: RolesDump,
+    ) -> api::Result<ProposalsResponse> {
+        let actions = {
+            let view = state.snapshot();
+            query.restore_actions(&RoleSystemSchema::new(&view))
+        };
+
+        Self::propose(state, &actions)
+    }
+
+    /// Endpoint for proposing to deactivate an abusive account.
+    pub fn deactivate_account(
+        state: &ServiceApiState,
+        query: AccountQuery,
+    ) -> api::Result<ProposalsResponse> {
+        {
+            let view = state.snapshot();
+            match RoleSystemSchema::new(&view).account(&query.pub_key) {
+                Some(ref account) if !account.is_active() => {
+                    return Err(api::Error::BadRequest("Account is not active".to_owned()))
+                }
+                Some(_) => {}
+                None => return Err(account_not_found()),
+            }
+        }
+
+        let action = AdminAction::DeactivateAccount {
+            pub_key: query.pub_key,
+        };
+        Self::propose(state, &[action])
+    }
+
+    /// Endpoint for getting sizes of the service tables and the components
+    /// of the service state hash.
+    pub fn stats(state: &ServiceApiState, _query: ()) -> api::Result<ServiceStats> {
+        let view = state.snapshot();
+        let schema = RoleSystemSchema::new(&view);
+
+        Ok(api_common::stats(
+            &*view,
+            schema.schema_version(),
+            schema.index_sizes(),
+            schema.state_hash_components(),
+        ))
+    }
+
+    /// Endpoint for exporting all accounts signed with the service key of the node.
+    pub fn export_accounts(state: &ServiceApiState, _query: ()) -> api::Result<SignedExport> {
+        let view = state.snapshot();
+        let accounts = RoleSystemSchema::new(&view).accounts().values().collect();
+        Ok(api_common::export_accounts(state, &*view, accounts))
+    }
+
+    /// Signs the actions with the service key of the node and sends them as proposals.
+    /// The service key must belong to the admin set.
+    fn propose(state: &ServiceApiState, actions: &[AdminAction]) -> api::Result<ProposalsResponse> {
+        let expires_at = {
+            let view = state.snapshot();
+            if !RoleSystemSchema::new(&view).is_admin(state.public_key()) {
+                return Err(api::Error::BadRequest(
+                    "Service key of the node is not in the admin set".to_owned(),
+                ));
+            }
+            Schema::new(&view).height().0 + ADMIN_PROPOSAL_TTL
+        };
+
+        let mut proposals = Vec::new();
+        for action in actions {
+            let transaction: Box<Transaction> = Box::new(TxProposeAdminAction::new(
+                state.public_key(),
+                &serde_json::to_string(action).unwrap(),
+                expires_at,
+                rand::random(),
+                state.secret_key(),
+            ));
+            proposals.push(transaction.hash());
+            state.sender().send(transaction)?;
+        }
 
-        let self_ = self.clone();
-        let get_block_by_id = move |req: &mut Request| self_.block_by_id(req);
+        Ok(ProposalsResponse {
+            proposals,
+            expires_at,
+        })
+    }
 
-        let self_ = self.clone();
-        let get_account = move |req: &mut Request| self_.account(req);
+    /// Adds the private endpoints to the builder.
+    pub fn wire(builder: &mut ServiceApiBuilder) {
+        builder
+            .private_scope()
+            .endpoint("v1/accounts/all", Self::all_accounts)
+            .endpoint("v1/admin/roles", Self::dump_roles)
+            .endpoint_mut("v1/admin/roles/restore", Self::restore_roles)
+            .endpoint_mut("v1/admin/deactivate", Self::deactivate_account)
+            .endpoint("v1/admin/stats", Self::stats)
+            .endpoint("v1/admin/export", Self::export_accounts);
+    }
+}
 
-        let self_ = self.clone();
-        let post_transaction = move |req: &mut Request| self_.transaction(req);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_photo_quota() {
+        let quotas = PhotoQuotas::default();
+        let (alice, _) = crypto::gen_keypair();
+        let (bob, _) = crypto::gen_keypair();
+
+        assert_eq!(photo_quota_left(&quotas, &alice), PHOTO_QUOTA);
+        assert!(photo_quota_charge(&quotas, &alice, PHOTO_QUOTA - 10));
+        assert_eq!(photo_quota_left(&quotas, &alice), 10);
+        assert!(!photo_quota_charge(&quotas, &alice, 11));
+        assert!(photo_quota_charge(&quotas, &alice, 10));
+        assert_eq!(photo_quota_left(&quotas, &alice), 0);
+
+        // Quotas are counted per account
+        assert_eq!(photo_quota_left(&quotas, &bob), PHOTO_QUOTA);
+    }
 
+    #[test]
+    fn test_challenges() {
+        let challenges = Challenges::default();
+        let (alice, _) = crypto::gen_keypair();
+        let (bob, _) = crypto::gen_keypair();
+        let now = Instant::now();
+
+        // A new challenge doesn't invalidate the previous one of the same account
+        let first = challenge_issue(&challenges, &alice, now);
+        let second = challenge_issue(&challenges, &alice, now);
+        assert_ne!(first, second);
+        assert!(challenge_take(&challenges, &first, &alice, now));
+        assert!(challenge_take(&challenges, &second, &alice, now));
+
+        // Replayed nonces are rejected
+        assert!(!challenge_take(&challenges, &first, &alice, now));
+
+        // Nonces are bound to the account they were issued to
+        let nonce = challenge_issue(&challenges, &alice, now);
+        assert!(!challenge_take(&challenges, &nonce, &bob, now));
+        assert!(!challenge_take(&challenges, &nonce, &alice, now));
+
+        // Expired nonces are rejected and dropped on the next challenge
+        let expired = challenge_issue(&challenges, &alice, now);
+        assert!(!challenge_take(&challenges, &expired, &alice, now + CHALLENGE_TTL));
+        let expired = challenge_issue(&challenges, &alice, now);
+        challenge_issue(&challenges, &bob, now + CHALLENGE_TTL);
+        assert!(!challenges.lock().unwrap().contains_key(&expired));
+    }
 
-        router.get("/v1/accounts", get_accounts, "get_accounts");
-        router.post("/v1/accounts/transaction", post_transaction, "post_transaction");
-        router.get("/v1/accounts/:pubkey", get_account, "account");
-        router.get("/v1/blocks/:id", get_block_by_id, "block_by_id");
+    #[test]
+    fn test_challenges_limit() {
+        let challenges = Challenges::default();
+        let (alice, _) = crypto::gen_keypair();
+        let (bob, _) = crypto::gen_keypair();
+        let now = Instant::now();
+
+        let nonces: Vec<Hash> = (0..MAX_CHALLENGES_PER_ACCOUNT as u64 + 2)
+            .map(|i| challenge_issue(&challenges, &alice, now + Duration::from_millis(i)))
+            .collect();
+        let bobs = challenge_issue(&challenges, &bob, now);
+        assert_eq!(challenges.lock().unwrap().len(), MAX_CHALLENGES_PER_ACCOUNT + 1);
+
+        // The oldest nonces of the account are dropped, other accounts keep theirs
+        let late = now + Duration::from_millis(100);
+        assert!(!challenge_take(&challenges, &nonces[0], &alice, late));
+        assert!(!challenge_take(&challenges, &nonces[1], &alice, late));
+        for nonce in &nonces[2..] {
+            assert!(challenge_take(&challenges, nonce, &alice, late));
+        }
+        assert!(challenge_take(&challenges, &bobs, &bob, late));
     }
-}
\ No newline at end of file
+}
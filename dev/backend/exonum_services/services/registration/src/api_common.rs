@@ -0,0 +1,352 @@
+//! REST API types and endpoint bodies shared by the zen services.
+//!
+//! The services wire their own endpoints and call these helpers with their tables,
+//! transaction set and `ErrorKind`, so the responses have the same format everywhere.
+
+use exonum::api::{self, ServiceApiState};
+use exonum::blockchain::{BlockProof, Schema, TransactionError, TransactionErrorType,
+                         TransactionSet};
+use exonum::crypto::{self, Hash, PublicKey, Signature};
+use exonum::helpers::Height;
+use exonum::storage::{MapProof, ProofListIndex, Snapshot};
+use serde::Serialize;
+use serde_json;
+use std::cmp::min;
+use std::fmt;
+
+/// Maximum number of items returned by paginated endpoints.
+pub const MAX_PAGE_SIZE: u64 = 100;
+
+fn max_page_size() -> u64 {
+    MAX_PAGE_SIZE
+}
+
+/// Query of a page of accounts.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct PageQuery {
+    /// Number of items to skip.
+    #[serde(default)]
+    pub offset: u64,
+    /// Number of items to return, at most `MAX_PAGE_SIZE`.
+    #[serde(default = "max_page_size")]
+    pub count: u64,
+}
+
+/// Query of a page of the account history.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct HistoryQuery {
+    /// Public key of the account.
+    pub pub_key: PublicKey,
+    /// Number of transactions to skip.
+    #[serde(default)]
+    pub offset: u64,
+    /// Number of transactions to return, at most `MAX_PAGE_SIZE`.
+    #[serde(default = "max_page_size")]
+    pub count: u64,
+}
+
+/// Proof of existence (or absence) for specific account.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccountProof<A> {
+    /// Proof to the accounts table in the blockchain state hash.
+    pub to_table: MapProof<Hash, Hash>,
+    /// Proof to the specific account in this table.
+    pub to_account: MapProof<PublicKey, A>,
+}
+
+/// Account information together with the proofs.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccountWithProof<A> {
+    /// Latest block with its precommits.
+    pub block_proof: BlockProof,
+    /// Proof of the account against the block state hash.
+    pub account_proof: AccountProof<A>,
+    /// The account itself, if it exists.
+    pub account: Option<A>,
+}
+
+/// Execution status of a transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionStatus {
+    /// The transaction is in the pool and waits to be included into a block.
+    Pending,
+    /// The transaction is committed and executed successfully.
+    Committed,
+    /// The transaction is committed but its execution failed.
+    Failed,
+}
+
+/// Error returned by a failed transaction.
+#[derive(Debug, Serialize)]
+pub struct TransactionErrorInfo {
+    /// `ErrorKind` code, `None` if the transaction panicked.
+    pub code: Option<u8>,
+    /// Name of the `ErrorKind` variant.
+    pub kind: Option<String>,
+    /// Human-readable description of the error.
+    pub description: Option<String>,
+}
+
+/// Status of a transaction together with its decoded body.
+#[derive(Debug, Serialize)]
+pub struct TransactionInfo<T> {
+    /// Hash of the transaction.
+    pub tx_hash: Hash,
+    /// Execution status.
+    pub status: TransactionStatus,
+    /// Height of the block with the transaction, if committed.
+    pub height: Option<Height>,
+    /// Execution error, if failed.
+    pub error: Option<TransactionErrorInfo>,
+    /// Decoded transaction.
+    pub transaction: T,
+}
+
+impl TransactionErrorInfo {
+    /// Describes the error with the `ErrorKind` restored from its code by `error_kind`.
+    pub fn new<E>(error: &TransactionError, error_kind: fn(u8) -> Option<E>) -> Self
+    where
+        E: fmt::Debug + fmt::Display,
+    {
+        match error.error_type() {
+            TransactionErrorType::Code(code) => {
+                let kind = error_kind(code);
+                TransactionErrorInfo {
+                    code: Some(code),
+                    kind: kind.as_ref().map(|kind| format!("{:?}", kind)),
+                    description: kind.map(|kind| kind.to_string())
+                        .or_else(|| error.description().map(str::to_owned)),
+                }
+            }
+            TransactionErrorType::Panic => TransactionErrorInfo {
+                code: None,
+                kind: None,
+                description: error.description().map(str::to_owned),
+            },
+        }
+    }
+}
+
+/// Transaction from the account history.
+#[derive(Debug, Serialize)]
+pub struct AccountHistoryEntry<T> {
+    /// Hash of the transaction.
+    pub tx_hash: Hash,
+    /// Height of the block with the transaction.
+    pub height: Height,
+    /// Decoded transaction.
+    pub transaction: T,
+}
+
+/// Page of the account history.
+#[derive(Debug, Serialize)]
+pub struct AccountHistory<T> {
+    /// Total number of transactions in the account history.
+    pub total: u64,
+    /// Index of the first returned transaction.
+    pub offset: u64,
+    /// Transactions, from the oldest to the newest.
+    pub transactions: Vec<AccountHistoryEntry<T>>,
+}
+
+/// Number of entries in a service table.
+#[derive(Debug, Serialize)]
+pub struct IndexSize {
+    /// Table name.
+    pub name: &'static str,
+    /// Number of entries.
+    pub size: u64,
+}
+
+/// Root hash of a table included into the service state hash.
+#[derive(Debug, Serialize)]
+pub struct StateHashComponent {
+    /// Table name.
+    pub name: &'static str,
+    /// Root hash of the table.
+    pub hash: Hash,
+}
+
+/// Sizes of the service tables and the components of the service state hash.
+#[derive(Debug, Serialize)]
+pub struct ServiceStats {
+    /// Height of the latest committed block.
+    pub height: Height,
+    /// Layout version of the stored data, `None` for data written before the versions.
+    pub schema_version: Option<u32>,
+    /// Sizes of the service tables.
+    pub indexes: Vec<IndexSize>,
+    /// Components of the service state hash, in order.
+    pub state_hash: Vec<StateHashComponent>,
+}
+
+/// All accounts at the latest committed block.
+#[derive(Debug, Serialize)]
+pub struct AccountsExport<A> {
+    /// Height of the latest committed block.
+    pub height: Height,
+    /// Hash of the latest committed block.
+    pub block_hash: Hash,
+    /// Accounts ordered by the public key.
+    pub accounts: Vec<A>,
+}
+
+/// Accounts export signed with the service key of the node.
+#[derive(Debug, Serialize)]
+pub struct SignedExport {
+    /// JSON-serialized `AccountsExport`.
+    pub data: String,
+    /// Service public key of the node.
+    pub pub_key: PublicKey,
+    /// Signature of the `data` bytes.
+    pub signature: Signature,
+}
+
+/// Returns the status of a transaction of the service `service_id`: pending,
+/// committed or failed with the `ErrorKind` restored by `error_kind`.
+pub fn transaction_info<T, E>(
+    snapshot: &Snapshot,
+    service_id: u16,
+    tx_hash: Hash,
+    error_kind: fn(u8) -> Option<E>,
+) -> api::Result<TransactionInfo<T>>
+where
+    T: TransactionSet,
+    E: fmt::Debug + fmt::Display,
+{
+    let schema = Schema::new(snapshot);
+
+    let transaction = match schema.transactions().get(&tx_hash) {
+        Some(ref raw) if raw.service_id() != service_id => None,
+        Some(raw) => T::tx_from_raw(raw).ok(),
+        None => None,
+    };
+    let transaction =
+        transaction.ok_or_else(|| api::Error::NotFound("Transaction not found".to_owned()))?;
+
+    let height = schema
+        .transactions_locations()
+        .get(&tx_hash)
+        .map(|location| location.block_height());
+    let error = match schema.transaction_results().get(&tx_hash) {
+        Some(Err(ref e)) => Some(TransactionErrorInfo::new(e, error_kind)),
+        _ => None,
+    };
+    let status = match (height, &error) {
+        (None, _) => TransactionStatus::Pending,
+        (Some(_), &None) => TransactionStatus::Committed,
+        (Some(_), &Some(_)) => TransactionStatus::Failed,
+    };
+
+    Ok(TransactionInfo {
+        tx_hash,
+        status,
+        height,
+        error,
+        transaction,
+    })
+}
+
+/// Returns the account together with the proofs that let a light client verify it
+/// against the latest block. The accounts table must be the first table of the
+/// service state hash.
+pub fn account_with_proof<A>(
+    snapshot: &Snapshot,
+    service_id: u16,
+    to_account: MapProof<PublicKey, A>,
+    account: Option<A>,
+) -> AccountWithProof<A> {
+    let general_schema = Schema::new(snapshot);
+
+    let max_height = general_schema.block_hashes_by_height().len() - 1;
+    let block_proof = general_schema
+        .block_and_precommits(Height(max_height))
+        .unwrap();
+
+    let account_proof = AccountProof {
+        to_table: general_schema.get_proof_to_service_table(service_id, 0),
+        to_account,
+    };
+
+    AccountWithProof {
+        block_proof,
+        account_proof,
+        account,
+    }
+}
+
+/// Returns a page of the account history, from the oldest transaction to the newest one.
+pub fn account_history<T: TransactionSet>(
+    snapshot: &Snapshot,
+    history: &ProofListIndex<&Snapshot, Hash>,
+    query: &HistoryQuery,
+) -> AccountHistory<T> {
+    let core_schema = Schema::new(snapshot);
+
+    let transactions = history
+        .iter_from(query.offset)
+        .take(min(query.count, MAX_PAGE_SIZE) as usize)
+        .filter_map(|tx_hash| {
+            let location = core_schema.transactions_locations().get(&tx_hash)?;
+            let raw = core_schema.transactions().get(&tx_hash)?;
+            let transaction = T::tx_from_raw(raw).ok()?;
+
+            Some(AccountHistoryEntry {
+                tx_hash,
+                height: location.block_height(),
+                transaction,
+            })
+        })
+        .collect();
+
+    AccountHistory {
+        total: history.len(),
+        offset: query.offset,
+        transactions,
+    }
+}
+
+/// Returns sizes of the service tables and the components of the service state hash.
+pub fn stats(
+    snapshot: &Snapshot,
+    schema_version: Option<u32>,
+    index_sizes: Vec<(&'static str, u64)>,
+    state_hash_components: Vec<(&'static str, Hash)>,
+) -> ServiceStats {
+    ServiceStats {
+        height: Schema::new(snapshot).height(),
+        schema_version,
+        indexes: index_sizes
+            .into_iter()
+            .map(|(name, size)| IndexSize { name, size })
+            .collect(),
+        state_hash: state_hash_components
+            .into_iter()
+            .map(|(name, hash)| StateHashComponent { name, hash })
+            .collect(),
+    }
+}
+
+/// Exports the accounts read from `snapshot` signed with the service key of the node.
+pub fn export_accounts<A: Serialize>(
+    state: &ServiceApiState,
+    snapshot: &Snapshot,
+    accounts: Vec<A>,
+) -> SignedExport {
+    let general_schema = Schema::new(snapshot);
+    let height = general_schema.height();
+
+    let export = AccountsExport {
+        height,
+        block_hash: general_schema.block_hash_by_height(height).unwrap(),
+        accounts,
+    };
+    let data = serde_json::to_string(&export).unwrap();
+
+    SignedExport {
+        signature: crypto::sign(data.as_bytes(), state.secret_key()),
+        pub_key: *state.public_key(),
+        data,
+    }
+}
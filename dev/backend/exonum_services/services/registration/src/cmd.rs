@@ -1,6 +1,6 @@
 //! Basic clap factory implementation.
 //! This module collect all basic `CommandExtension` that
-//! we can use in `employees` bootstrapping process.
+//! we can use in `rolesystem` bootstrapping process.
 //!
 use exonum::blockchain::Service;
 use exonum::helpers::fabric::{Argument, CommandExtension, CommandName, Context, keys,
@@ -11,12 +11,16 @@ use service::RoleSystemService;
 use std::collections::BTreeMap;
 use toml::Value;
 
-/// Employees configuration that should be saved into the file
+/// RoleSystem configuration that should be saved into the file
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct RoleSystemServiceConfig {
-    /// Superuser public key.
-    // pub superuser_pkey: String,
-    pub tue: bool,
+    /// Public keys of the admin set.
+    pub admin_pkeys: Vec<String>,
+    /// Number of admin approvals required to execute an admin action.
+    pub admin_threshold: u16,
+    /// Directory of the local photo blockstore. Photos are kept in memory if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blockstore_path: Option<String>,
 }
 
 struct GenerateCommonConfig;
@@ -25,18 +29,34 @@ impl CommandExtension for GenerateCommonConfig {
     fn args(&self) -> Vec<Argument> {
         vec![
             Argument::new_named(
-                "EMPLOYEES_SUPERUSER_PUB_KEY",
+                "ROLESYSTEM_ADMIN_PUB_KEYS",
                 true,
-                "Public key of the superuser.",
+                "Public keys of the admin set.",
                 None,
-                "superuser-pkey",
+                "admin-pkey",
+                true,
+            ),
+            Argument::new_named(
+                "ROLESYSTEM_ADMIN_THRESHOLD",
+                false,
+                "Number of admin approvals required to execute an admin action. \
+                 Defaults to the majority of the admin set.",
+                None,
+                "admin-threshold",
                 false,
             ),
         ]
     }
 
     fn execute(&self, mut context: Context) -> Result<Context, failure::Error> {
-        let pkey = context.arg::<String>("EMPLOYEES_SUPERUSER_PUB_KEY").ok();
+        let pkeys = context.arg_multiple::<String>("ROLESYSTEM_ADMIN_PUB_KEYS")?;
+        let threshold = context
+            .arg::<u16>("ROLESYSTEM_ADMIN_THRESHOLD")
+            .unwrap_or(pkeys.len() as u16 / 2 + 1);
+
+        if threshold == 0 || threshold as usize > pkeys.len() {
+            bail!("Admin threshold should be in range 1..={}", pkeys.len());
+        }
 
         let mut values: BTreeMap<String, Value> = context.get(keys::SERVICES_CONFIG).expect(
             "Expected services_config \
@@ -46,8 +66,12 @@ impl CommandExtension for GenerateCommonConfig {
         values.extend(
             vec![
                 (
-                    "superuser_pub_key".to_owned(),
-                    Value::try_from(pkey).unwrap(),
+                    "admin_pub_keys".to_owned(),
+                    Value::try_from(pkeys).unwrap(),
+                ),
+                (
+                    "admin_threshold".to_owned(),
+                    Value::try_from(threshold).unwrap(),
                 ),
             ].into_iter(),
         );
@@ -61,26 +85,45 @@ struct Finalize;
 
 impl CommandExtension for Finalize {
     fn args(&self) -> Vec<Argument> {
-        vec![]
+        vec![
+            Argument::new_named(
+                "ROLESYSTEM_BLOCKSTORE_PATH",
+                false,
+                "Directory of the local photo blockstore.",
+                None,
+                "blockstore-path",
+                false,
+            ),
+        ]
     }
 
     fn execute(&self, mut context: Context) -> Result<Context, failure::Error> {
         let mut node_config: NodeConfig = context.get(keys::NODE_CONFIG).unwrap();
         let common_config = context.get(keys::COMMON_CONFIG).unwrap();
+        let blockstore_path = context.arg::<String>("ROLESYSTEM_BLOCKSTORE_PATH").ok();
 
         // Global config section
-        let superuser_pkey: String = common_config
+        let admin_pkeys: Vec<String> = common_config
+            .services_config
+            .get("admin_pub_keys")
+            .expect("Admin public keys are not found")
+            .clone()
+            .try_into()?;
+
+        let admin_threshold: u16 = common_config
             .services_config
-            .get("superuser_pub_key")
-            .expect("Superuser public key is not found")
+            .get("admin_threshold")
+            .expect("Admin threshold is not found")
             .clone()
             .try_into()?;
 
         node_config.services_configs.insert(
-            "employees_service".to_owned(),
-            Value::try_from(EmployeesServiceConfig {
-                superuser_pkey,
-            }).expect("Could not serialize employees service config"),
+            "rolesystem_service".to_owned(),
+            Value::try_from(RoleSystemServiceConfig {
+                admin_pkeys,
+                admin_threshold,
+                blockstore_path,
+            }).expect("Could not serialize rolesystem service config"),
         );
 
         context.set(keys::NODE_CONFIG, node_config);
@@ -88,7 +131,7 @@ impl CommandExtension for Finalize {
     }
 }
 
-/// An employees service creator for the `NodeBuilder`.
+/// A rolesystem service creator for the `NodeBuilder`.
 #[derive(Debug)]
 pub struct RoleSystemServiceFactory;
 
@@ -112,7 +155,9 @@ impl ServiceFactory for RoleSystemServiceFactory {
                 .unwrap();
 
         Box::new(RoleSystemService::new(
-            rolesystem_service_config.superuser_pkey
+            rolesystem_service_config.admin_pkeys,
+            rolesystem_service_config.admin_threshold,
+            rolesystem_service_config.blockstore_path,
         ))
     }
 }
@@ -1,200 +1,1080 @@
-use exonum::blockchain::{ExecutionError, ExecutionResult, Transaction};
-use exonum::{messages::Message, storage::Fork};
-use num_traits::ToPrimitive;
+use chrono::{DateTime, Duration, Utc};
+use exonum::blockchain::{ExecutionError, ExecutionResult, Schema, Transaction};
+use exonum::crypto::{self, Hash, PublicKey};
+use exonum::{messages::Message, storage::Fork, storage::Snapshot};
+use exonum_time::TimeSchema;
+use custom_data::{self, DataSchemaDefinition};
+use governance::AdminAction;
+use ipfs;
+use migration;
+use num_traits::{FromPrimitive, ToPrimitive};
 use schema::*;
-use transactions::{TxCreateAccount, TxDeleteAccount, TxEditAccount, TxSetCustomData};
+use serde_json;
+use std::collections::HashSet;
+use transactions::{TxAddDevice, TxApproveAdminAction, TxApproveRecovery, TxCancelRecovery,
+                   TxChangeUsername, TxCloseSession, TxCompleteRecovery, TxCreateAccount,
+                   TxDeactivateAccount, TxDeleteAccount, TxEditAccount, TxInitiateRecovery,
+                   TxOpenSession, TxProposeAdminAction, TxReactivateAccount,
+                   TxRegisterDataSchema, TxRemoveCustomData, TxRemoveDevice, TxRotatePassword,
+                   TxSetCustomData, TxSetDataSchema, TxSetGuardians};
+
+/// Returns the account owned by `pub_key` if it exists and is active.
+fn active_account<T: AsRef<Snapshot>>(
+    schema: &RoleSystemSchema<T>,
+    pub_key: &PublicKey,
+) -> Result<Account, ExecutionError> {
+    match schema.account(pub_key) {
+        Some(ref account) if !account.is_active() => Err(ErrorKind::AccountNotActive.into()),
+        Some(account) => Ok(account),
+        None => Err(ErrorKind::AccountNotFound.into()),
+    }
+}
+
+/// Checks that the transaction of the account is signed by its master key, one of its
+/// devices or a session key that hasn't expired at the current consensus time.
+fn check_signer(view: &Fork, account: &PublicKey, signer: &PublicKey) -> ExecutionResult {
+    let schema = RoleSystemSchema::new(view);
+
+    if schema.key_owner(signer) == *account {
+        return Ok(());
+    }
+
+    let now = TimeSchema::new(view).time().get();
+    if now.and_then(|now| schema.session_account(signer, now)) == Some(*account) {
+        Ok(())
+    } else {
+        Err(ErrorKind::PermissionDenied.into())
+    }
+}
+
+/// Checks that the stored data has the current layout. Until the admin set runs
+/// the `migrate_schema` action, records of the old layout would be read as the new one,
+/// so only the admin governance transactions are accepted.
+fn check_schema_version(view: &Fork) -> ExecutionResult {
+    if RoleSystemSchema::new(view).schema_version() == Some(migration::CURRENT_VERSION) {
+        Ok(())
+    } else {
+        Err(ErrorKind::SchemaNotMigrated.into())
+    }
+}
+
+/// Returns the height of the block that is being executed.
+fn current_height(view: &Fork) -> u64 {
+    Schema::new(view).height().next().0
+}
+
+/// Returns the consensus time of the `exonum-time` oracle.
+fn consensus_time(view: &Fork) -> Result<DateTime<Utc>, ExecutionError> {
+    TimeSchema::new(view)
+        .time()
+        .get()
+        .ok_or_else(|| ErrorKind::TimeNotAvailable.into())
+}
+
+/// Executes the proposal once it has collected `admin_threshold` approvals.
+/// Only approvals of the current admin set are counted, so approvals of removed
+/// admins don't carry over a change of the admin set.
+/// If the action fails, the whole transaction is rolled back, so the last
+/// approval is not recorded either. The executing transaction is appended
+/// to the history of the affected account.
+fn execute_if_approved(
+    schema: &mut RoleSystemSchema<&mut Fork>,
+    hash: &Hash,
+    proposal: Proposal,
+    tx_hash: &Hash,
+) -> ExecutionResult {
+    let approvals = schema
+        .proposal_approvals(hash)
+        .iter()
+        .filter(|pub_key| schema.is_admin(pub_key))
+        .count();
+    if approvals < schema.admin_threshold() as usize {
+        return Ok(());
+    }
+
+    let action = match AdminAction::from_json(proposal.action()) {
+        Some(action) => action,
+        None => return Err(ErrorKind::InvalidAdminAction.into()),
+    };
+
+    if !action.is_migration() && schema.schema_version() != Some(migration::CURRENT_VERSION) {
+        return Err(ErrorKind::SchemaNotMigrated.into());
+    }
+
+    action.execute(schema)?;
+    schema.proposal_mark_executed(hash, proposal);
+
+    // Deleted accounts have no history
+    if let Some(pub_key) = action.pub_key() {
+        if schema.account(pub_key).is_some() {
+            schema.account_history_push(pub_key, tx_hash);
+        }
+    }
+
+    Ok(())
+}
 
 impl Transaction for TxCreateAccount {
     /// Verifies integrity of the transaction by checking the transaction
     /// signatures.
     fn verify(&self) -> bool {
-        self.verify_signature(self.pkey_by_who())
+        self.verify_signature(self.pub_key())
     }
 
-    /// If a account with the specified public key is not registered and ID number is unique,
-    /// then creates a new account, with the specified public key and name, last name, id number.
+    /// If an account with the specified public key is not registered and the username is unique,
+    /// then creates a new account with the specified public key, username and password verifier.
     fn execute(&self, view: &mut Fork) -> ExecutionResult {
+        check_schema_version(view)?;
         let mut schema = RoleSystemSchema::new(view);
 
-        // if !schema.is_superuser(&self.pkey_by_who()) {
-        //     return Err(ErrorKind::NotASuperUser.into())
-        // }
-
         if schema.account(self.pub_key()).is_some() {
             return Err(ErrorKind::AccountAlreadyExists.into());
         }
 
-        // TODO - Запосить аккаунт по юзернейму
-        if schema
-            .account_pk_by_username()
-            .get(&self.id_number())
-            .is_some()
-        {
-            return Err(ErrorKind::EmployeeIdAlreadyExists.into());
+        if schema.device(self.pub_key()).is_some() {
+            return Err(ErrorKind::KeyAlreadyUsed.into());
+        }
+
+        if schema.pub_key_by_username(self.username()).is_some() {
+            return Err(ErrorKind::UsernameAlreadyExists.into());
+        }
+
+        if !self.credentials().is_valid() {
+            return Err(ErrorKind::InvalidCredentials.into());
+        }
+
+        if !ipfs::is_valid_photo(self.photo_ipfs()) {
+            return Err(ErrorKind::InvalidPhotoCid.into());
         }
 
         schema.account_create(
             self.pub_key(),
             self.username(),
-            self.encrypted_password(),
+            self.credentials(),
             self.photo_ipfs(),
-            self.is_active(),
-            self.custom_data,
+            true,
         );
+        schema.account_history_push(self.pub_key(), &self.hash());
 
         Ok(())
     }
 }
 
 impl Transaction for TxEditAccount {
+    /// Verifies integrity of the transaction by checking the signature
+    /// of the signer.
+    fn verify(&self) -> bool {
+        self.verify_signature(self.pkey_by_who())
+    }
+
+    /// Retrieves the account to apply the edition; it should be previously registered
+    /// with the help of [`TxCreateAccount`] transaction and must be active.
+    /// Applies changes to the account if the new username is not taken by another account.
+    ///
+    /// [`TxCreateAccount`]: ../transactions/struct.TxCreateAccount.html
+    fn execute(&self, view: &mut Fork) -> ExecutionResult {
+        check_schema_version(view)?;
+        check_signer(view, self.pkey_account(), self.pkey_by_who())?;
+        let mut schema = RoleSystemSchema::new(view);
+
+        let account = active_account(&schema, self.pkey_account())?;
+        let username = self.username();
+
+        // If there's an account with the same username exists,
+        // check that it's the same account as we've been editing now.
+        //
+        // Otherwise, do nothing since a username supposed to be unique
+        if let Some(pk) = schema.pub_key_by_username(username) {
+            if pk != *self.pkey_account() {
+                return Err(ErrorKind::UsernameAlreadyExists.into());
+            }
+        }
+
+        if !ipfs::is_valid_photo(self.photo_ipfs()) {
+            return Err(ErrorKind::InvalidPhotoCid.into());
+        }
+
+        schema.account_edit(
+            account,
+            self.pkey_account(),
+            username,
+            self.photo_ipfs(),
+        );
+        schema.account_history_push(self.pkey_account(), &self.hash());
+
+        Ok(())
+    }
+}
+
+impl Transaction for TxChangeUsername {
+    /// Verifies integrity of the transaction by checking the signature
+    /// of the signer.
+    fn verify(&self) -> bool {
+        self.verify_signature(self.pkey_by_who())
+    }
+
+    /// Moves the account to a new username if the username is not taken by another account.
+    /// The old username is released in the same transaction.
+    fn execute(&self, view: &mut Fork) -> ExecutionResult {
+        check_schema_version(view)?;
+        check_signer(view, self.pkey_account(), self.pkey_by_who())?;
+        let mut schema = RoleSystemSchema::new(view);
+
+        let account = active_account(&schema, self.pkey_account())?;
+
+        if let Some(pk) = schema.pub_key_by_username(self.username()) {
+            if pk != *self.pkey_account() {
+                return Err(ErrorKind::UsernameAlreadyExists.into());
+            }
+
+            // The account already holds this username, nothing to move
+            return Ok(());
+        }
+
+        schema.account_change_username(account, self.username());
+        schema.account_history_push(self.pkey_account(), &self.hash());
+
+        Ok(())
+    }
+}
+
+impl Transaction for TxRotatePassword {
+    /// Verifies integrity of the transaction by checking the signature
+    /// of the signer.
+    fn verify(&self) -> bool {
+        self.verify_signature(self.pkey_by_who())
+    }
+
+    /// Replaces the password verifier of an active account.
+    fn execute(&self, view: &mut Fork) -> ExecutionResult {
+        check_schema_version(view)?;
+        check_signer(view, self.pkey_account(), self.pkey_by_who())?;
+        let mut schema = RoleSystemSchema::new(view);
+
+        let account = active_account(&schema, self.pkey_account())?;
+
+        if !self.credentials().is_valid() {
+            return Err(ErrorKind::InvalidCredentials.into());
+        }
+
+        schema.account_set_credentials(account, self.credentials());
+        schema.account_history_push(self.pkey_account(), &self.hash());
+
+        Ok(())
+    }
+}
+
+impl Transaction for TxSetCustomData {
+    /// Verifies integrity of the transaction by checking the signature
+    /// of the signer.
+    fn verify(&self) -> bool {
+        self.verify_signature(self.pkey_by_who())
+    }
+
+    /// Sets a single custom data entry of an active account. The value must be valid JSON
+    /// that fits into the size limits and, if the account has declared a data schema,
+    /// the entry must be allowed by that schema.
+    fn execute(&self, view: &mut Fork) -> ExecutionResult {
+        check_schema_version(view)?;
+        check_signer(view, self.pkey_account(), self.pkey_by_who())?;
+        let mut schema = RoleSystemSchema::new(view);
+
+        active_account(&schema, self.pkey_account())?;
+
+        let value = match custom_data::parse_entry(self.key(), self.value()) {
+            Some(value) => value,
+            None => return Err(ErrorKind::InvalidCustomData.into()),
+        };
+
+        if let Some(data_schema) = schema.account_data_schema(self.pkey_account()) {
+            let definition = match DataSchemaDefinition::from_json(data_schema.definition()) {
+                Some(definition) => definition,
+                None => return Err(ErrorKind::InvalidDataSchema.into()),
+            };
+
+            if !definition.validate(self.key(), &value) {
+                return Err(ErrorKind::InvalidCustomData.into());
+            }
+        }
+
+        {
+            let entries = schema.custom_data(self.pkey_account());
+            if !entries.contains(&self.key().to_owned())
+                && entries.keys().count() as u64 >= custom_data::MAX_ENTRIES
+            {
+                return Err(ErrorKind::InvalidCustomData.into());
+            }
+        }
+        schema.custom_data_put(self.pkey_account(), self.key(), value.to_string());
+
+        schema.account_history_push(self.pkey_account(), &self.hash());
+
+        Ok(())
+    }
+}
+
+impl Transaction for TxRemoveCustomData {
+    /// Verifies integrity of the transaction by checking the signature
+    /// of the signer.
+    fn verify(&self) -> bool {
+        self.verify_signature(self.pkey_by_who())
+    }
+
+    /// Removes a single custom data entry of an active account.
+    fn execute(&self, view: &mut Fork) -> ExecutionResult {
+        check_schema_version(view)?;
+        check_signer(view, self.pkey_account(), self.pkey_by_who())?;
+        let mut schema = RoleSystemSchema::new(view);
+
+        active_account(&schema, self.pkey_account())?;
+
+        let key = self.key().to_owned();
+        if !schema.custom_data(self.pkey_account()).contains(&key) {
+            return Err(ErrorKind::InvalidCustomData.into());
+        }
+
+        schema.custom_data_remove(self.pkey_account(), &key);
+        schema.account_history_push(self.pkey_account(), &self.hash());
+
+        Ok(())
+    }
+}
+
+impl Transaction for TxRegisterDataSchema {
     /// Verifies integrity of the transaction by checking the transaction
     /// signatures.
     fn verify(&self) -> bool {
         self.verify_signature(self.pkey_by_who())
     }
 
-    /// Retrieves two accounts to apply the edition; they should be previously registered
-    /// with the help of [`TxCreateAccount`] transactions. Checks the editor's
-    /// authorship and superuser rights and applies changes to edited account if the editor's permissions
-    /// are sufficient. Otherwise, performs no op.
+    /// Registers a data schema under a new name. The author is the account of the signing
+    /// key, master or device. Registered schemas can't be changed,
+    /// so accounts that have declared a schema are not affected by other users.
+    fn execute(&self, view: &mut Fork) -> ExecutionResult {
+        check_schema_version(view)?;
+        let mut schema = RoleSystemSchema::new(view);
+
+        let author = schema.key_owner(self.pkey_by_who());
+        active_account(&schema, &author)?;
+
+        if schema.data_schema(self.name()).is_some() {
+            return Err(ErrorKind::DataSchemaAlreadyExists.into());
+        }
+
+        if self.name().is_empty() || DataSchemaDefinition::from_json(self.definition()).is_none() {
+            return Err(ErrorKind::InvalidDataSchema.into());
+        }
+
+        schema.data_schema_register(self.name(), self.definition());
+
+        Ok(())
+    }
+}
+
+impl Transaction for TxSetDataSchema {
+    /// Verifies integrity of the transaction by checking the signature
+    /// of the signer.
+    fn verify(&self) -> bool {
+        self.verify_signature(self.pkey_by_who())
+    }
+
+    /// Declares the data schema of an active account. Existing custom data entries
+    /// must be allowed by the new schema.
+    fn execute(&self, view: &mut Fork) -> ExecutionResult {
+        check_schema_version(view)?;
+        check_signer(view, self.pkey_account(), self.pkey_by_who())?;
+        let mut schema = RoleSystemSchema::new(view);
+
+        active_account(&schema, self.pkey_account())?;
+
+        let definition = match schema.data_schema(self.name()) {
+            Some(data_schema) => match DataSchemaDefinition::from_json(data_schema.definition()) {
+                Some(definition) => definition,
+                None => return Err(ErrorKind::InvalidDataSchema.into()),
+            },
+            None => return Err(ErrorKind::DataSchemaNotFound.into()),
+        };
+
+        let all_valid = schema.custom_data(self.pkey_account()).iter().all(|(key, value)| {
+            serde_json::from_str(&value).map_or(false, |value| definition.validate(&key, &value))
+        });
+
+        if !all_valid {
+            return Err(ErrorKind::InvalidCustomData.into());
+        }
+
+        schema
+            .account_data_schemas_mut()
+            .put(self.pkey_account(), self.name().to_owned());
+        schema.account_history_push(self.pkey_account(), &self.hash());
+
+        Ok(())
+    }
+}
+
+impl Transaction for TxDeactivateAccount {
+    /// Verifies integrity of the transaction by checking the signature
+    /// of the signer.
+    fn verify(&self) -> bool {
+        self.verify_signature(self.pkey_by_who())
+    }
+
+    /// Marks an active account as inactive. An inactive account can't send
+    /// any mutating transactions except [`TxReactivateAccount`].
     ///
-    /// [`TxCreateAccount`]: ../transactions/struct.TxCreateAccount.html
+    /// [`TxReactivateAccount`]: ../transactions/struct.TxReactivateAccount.html
     fn execute(&self, view: &mut Fork) -> ExecutionResult {
-        let mut schema = EmployeesSchema::new(view);
+        check_schema_version(view)?;
+        check_signer(view, self.pkey_account(), self.pkey_by_who())?;
+        let mut schema = RoleSystemSchema::new(view);
+
+        let account = active_account(&schema, self.pkey_account())?;
+        schema.account_set_active(account, self.pkey_account(), false);
+        schema.account_history_push(self.pkey_account(), &self.hash());
+
+        Ok(())
+    }
+}
+
+impl Transaction for TxReactivateAccount {
+    /// Verifies integrity of the transaction by checking the signature
+    /// of the signer.
+    fn verify(&self) -> bool {
+        self.verify_signature(self.pkey_by_who())
+    }
+
+    /// Marks a previously deactivated account as active again.
+    fn execute(&self, view: &mut Fork) -> ExecutionResult {
+        check_schema_version(view)?;
+        check_signer(view, self.pkey_account(), self.pkey_by_who())?;
+        let mut schema = RoleSystemSchema::new(view);
+
+        let account = match schema.account(self.pkey_account()) {
+            Some(account) => account,
+            None => return Err(ErrorKind::AccountNotFound.into()),
+        };
 
-        // Someone is trying to edit an account that doesn't belong to him
-        if *self.pkey_by_who() != *self.pkey_account() {
-            // It must be a superuser, otherwise do nothing
-            // if !schema.is_superuser(&self.pkey_by_who()) {
-            return Err(ErrorKind::NotASuperUser.into());
-            // }
+        if account.is_active() {
+            return Err(ErrorKind::AccountAlreadyActive.into());
         }
 
-        if let Some(account) = schema.account(self.pkey_account()) {
-            let username = self.first_name();
-            let encrypted_password = self.last_name();
-            let photo_ipfs = self.photo_ipfs();
-            let is_active = self.is_active();
-            let custom_data = self.custom_data();
+        schema.account_set_active(account, self.pkey_account(), true);
+        schema.account_history_push(self.pkey_account(), &self.hash());
 
-            // If there's an account with the same ID number exists,
-            // check that it's the same account as we've been editing now.
-            //
-            // Otherwise, do nothing since an ID number supposed to be unique
-            // if let Some(pk) = schema.account_pk_by_employee_id().get(&id_number) {
-            //     if pk != *self.pkey_account() {
-            //         return Err(ErrorKind::EmployeeIdAlreadyExists.into());
-            //     }
-            // }
+        Ok(())
+    }
+}
+
+impl Transaction for TxDeleteAccount {
+    /// Verifies integrity of the transaction by checking the signature
+    /// of the signer.
+    fn verify(&self) -> bool {
+        self.verify_signature(self.pkey_by_who())
+    }
 
-            schema.account_edit(
-                account,
-                self.pkey_account(),
-                username,
-                encrypted_password,
-                photo_ipfs,
-                is_active,
-                custom_data,
-            );
+    /// Removes an active account together with its history and releases its username.
+    fn execute(&self, view: &mut Fork) -> ExecutionResult {
+        check_schema_version(view)?;
+        check_signer(view, self.pkey_account(), self.pkey_by_who())?;
+        let mut schema = RoleSystemSchema::new(view);
+
+        let account = active_account(&schema, self.pkey_account())?;
+        schema.account_delete(&account);
+
+        Ok(())
+    }
+}
+
+impl Transaction for TxAddDevice {
+    /// Verifies integrity of the transaction by checking the signature
+    /// of the account owner and the signature of the device key, so an account
+    /// can't claim a key it doesn't own.
+    fn verify(&self) -> bool {
+        let proof = TxAddDevice::device_proof(self.pkey_account(), self.device_key(), self.seed());
+        self.verify_signature(self.pkey_account())
+            && crypto::verify(self.device_signature(), &proof, self.device_key())
+    }
+
+    /// Adds a device key to an active account. The key must not be used
+    /// by another account or device.
+    fn execute(&self, view: &mut Fork) -> ExecutionResult {
+        check_schema_version(view)?;
+        let height = current_height(view);
+        let mut schema = RoleSystemSchema::new(view);
+
+        active_account(&schema, self.pkey_account())?;
+
+        if schema.account(self.device_key()).is_some() || schema.device(self.device_key()).is_some() {
+            return Err(ErrorKind::KeyAlreadyUsed.into());
         }
 
+        schema.device_add(self.pkey_account(), self.device_key(), self.name(), height);
+        schema.account_history_push(self.pkey_account(), &self.hash());
+
+        Ok(())
+    }
+}
+
+impl Transaction for TxRemoveDevice {
+    /// Verifies integrity of the transaction by checking the signature
+    /// of the account owner.
+    fn verify(&self) -> bool {
+        self.verify_signature(self.pkey_account())
+    }
+
+    /// Removes a device key of an active account. The key can't sign
+    /// transactions of the account after that.
+    fn execute(&self, view: &mut Fork) -> ExecutionResult {
+        check_schema_version(view)?;
+        let mut schema = RoleSystemSchema::new(view);
+
+        active_account(&schema, self.pkey_account())?;
+
+        let device = match schema.device(self.device_key()) {
+            Some(ref device) if device.account() != self.pkey_account() => {
+                return Err(ErrorKind::DeviceNotFound.into())
+            }
+            Some(device) => device,
+            None => return Err(ErrorKind::DeviceNotFound.into()),
+        };
+
+        schema.device_remove(&device);
+        schema.account_history_push(self.pkey_account(), &self.hash());
+
         Ok(())
     }
 }
 
-// impl Transaction for TxSetCustomData {
+impl Transaction for TxSetGuardians {
+    /// Verifies integrity of the transaction by checking the signature
+    /// of the account owner.
+    fn verify(&self) -> bool {
+        self.verify_signature(self.pkey_account())
+    }
+
+    /// Replaces guardians of an active account. Guardians must be distinct active
+    /// accounts other than the account itself; an empty list removes the guardians.
+    /// Can't be applied while a recovery is pending.
+    fn execute(&self, view: &mut Fork) -> ExecutionResult {
+        check_schema_version(view)?;
+        let mut schema = RoleSystemSchema::new(view);
+
+        active_account(&schema, self.pkey_account())?;
+
+        if schema.recovery(self.pkey_account()).is_some() {
+            return Err(ErrorKind::RecoveryInProgress.into());
+        }
+
+        let guardians = self.guardians();
+        if guardians.is_empty() {
+            schema.guardians_clear(self.pkey_account());
+            schema.account_history_push(self.pkey_account(), &self.hash());
+            return Ok(());
+        }
+
+        let valid = {
+            let distinct: HashSet<&PublicKey> = guardians.iter().collect();
+            guardians.len() <= recovery::MAX_GUARDIANS
+                && distinct.len() == guardians.len()
+                && !distinct.contains(self.pkey_account())
+                && guardians.iter().all(|pub_key| schema.is_activated(pub_key))
+                && self.threshold() >= 1
+                && self.threshold() as usize <= guardians.len()
+                && self.delay() >= recovery::MIN_DELAY
+        };
+
+        if !valid {
+            return Err(ErrorKind::InvalidGuardians.into());
+        }
+
+        let config = RecoveryConfig::new(guardians, self.threshold(), self.delay());
+        schema.guardians_set(self.pkey_account(), config);
+        schema.account_history_push(self.pkey_account(), &self.hash());
+
+        Ok(())
+    }
+}
+
+impl Transaction for TxInitiateRecovery {
+    /// Verifies integrity of the transaction by checking the signature
+    /// of the guardian.
+    fn verify(&self) -> bool {
+        self.verify_signature(self.pkey_by_who())
+    }
+
+    /// Starts a recovery of the account to a key that isn't used by another account
+    /// or device. Only one recovery of the account can be pending at a time.
+    /// The guardian's own account must exist and be active. Admin keys are replaced
+    /// by `AdminAction::SetAdmins`, so accounts of admins can't be recovered.
+    fn execute(&self, view: &mut Fork) -> ExecutionResult {
+        check_schema_version(view)?;
+        let height = current_height(view);
+        let mut schema = RoleSystemSchema::new(view);
+
+        if schema.account(self.pkey_account()).is_none() {
+            return Err(ErrorKind::AccountNotFound.into());
+        }
+
+        if schema.is_admin(self.pkey_account()) {
+            return Err(ErrorKind::AdminRecoveryForbidden.into());
+        }
+
+        active_account(&schema, self.pkey_by_who())?;
+
+        let is_guardian = schema
+            .recovery_config(self.pkey_account())
+            .map_or(false, |config| config.is_guardian(self.pkey_by_who()));
+
+        if !is_guardian {
+            return Err(ErrorKind::PermissionDenied.into());
+        }
+
+        if schema.recovery(self.pkey_account()).is_some() {
+            return Err(ErrorKind::RecoveryInProgress.into());
+        }
+
+        if schema.account(self.new_key()).is_some() || schema.device(self.new_key()).is_some() {
+            return Err(ErrorKind::KeyAlreadyUsed.into());
+        }
+
+        schema.recovery_initiate(self.pkey_account(), self.new_key(), self.pkey_by_who(), height);
+        schema.account_history_push(self.pkey_account(), &self.hash());
+
+        Ok(())
+    }
+}
+
+impl Transaction for TxApproveRecovery {
+    /// Verifies integrity of the transaction by checking the signature
+    /// of the guardian.
+    fn verify(&self) -> bool {
+        self.verify_signature(self.pkey_by_who())
+    }
+
+    /// Records an approval of the pending recovery to the given key.
+    /// The guardian's own account must exist and be active.
+    fn execute(&self, view: &mut Fork) -> ExecutionResult {
+        check_schema_version(view)?;
+        let mut schema = RoleSystemSchema::new(view);
+
+        active_account(&schema, self.pkey_by_who())?;
+
+        let is_guardian = schema
+            .recovery_config(self.pkey_account())
+            .map_or(false, |config| config.is_guardian(self.pkey_by_who()));
+
+        if !is_guardian {
+            return Err(ErrorKind::PermissionDenied.into());
+        }
+
+        match schema.recovery(self.pkey_account()) {
+            Some(ref recovery) if recovery.new_key() == self.new_key() => (),
+            _ => return Err(ErrorKind::RecoveryNotFound.into()),
+        }
+
+        if schema.recovery_approvals(self.pkey_account()).contains(self.pkey_by_who()) {
+            return Err(ErrorKind::RecoveryAlreadyApproved.into());
+        }
+
+        schema.recovery_approve(self.pkey_account(), self.pkey_by_who());
+        schema.account_history_push(self.pkey_account(), &self.hash());
+
+        Ok(())
+    }
+}
+
+impl Transaction for TxCancelRecovery {
+    /// Verifies integrity of the transaction by checking the signature
+    /// of the account owner.
+    fn verify(&self) -> bool {
+        self.verify_signature(self.pkey_account())
+    }
+
+    /// Cancels the pending recovery. The owner still holding the old key can
+    /// cancel it at any time before completion, even if the account is inactive.
+    fn execute(&self, view: &mut Fork) -> ExecutionResult {
+        check_schema_version(view)?;
+        let mut schema = RoleSystemSchema::new(view);
+
+        if schema.account(self.pkey_account()).is_none() {
+            return Err(ErrorKind::AccountNotFound.into());
+        }
+
+        if schema.recovery(self.pkey_account()).is_none() {
+            return Err(ErrorKind::RecoveryNotFound.into());
+        }
+
+        schema.recovery_cancel(self.pkey_account());
+        schema.account_history_push(self.pkey_account(), &self.hash());
+
+        Ok(())
+    }
+}
+
+impl Transaction for TxCompleteRecovery {
+    /// Verifies integrity of the transaction by checking the signature
+    /// of the new key.
+    fn verify(&self) -> bool {
+        self.verify_signature(self.new_key())
+    }
+
+    /// Moves the account to the new key once `threshold` guardians have approved
+    /// the recovery and `delay` blocks have passed since its initiation.
+    /// Fails if the account has joined the admin set after the initiation.
+    fn execute(&self, view: &mut Fork) -> ExecutionResult {
+        check_schema_version(view)?;
+        let height = current_height(view);
+        let mut schema = RoleSystemSchema::new(view);
+
+        let account = match schema.account(self.pkey_account()) {
+            Some(account) => account,
+            None => return Err(ErrorKind::AccountNotFound.into()),
+        };
+
+        if schema.is_admin(self.pkey_account()) {
+            return Err(ErrorKind::AdminRecoveryForbidden.into());
+        }
+
+        let recovery = match schema.recovery(self.pkey_account()) {
+            Some(ref recovery) if recovery.new_key() != self.new_key() => {
+                return Err(ErrorKind::RecoveryNotFound.into())
+            }
+            Some(recovery) => recovery,
+            None => return Err(ErrorKind::RecoveryNotFound.into()),
+        };
+
+        let config = schema
+            .recovery_config(self.pkey_account())
+            .expect("Recovery is pending without guardians");
+
+        let approvals = schema.recovery_approvals(self.pkey_account()).iter().count();
+        if approvals < config.threshold() as usize
+            || height < recovery.started_at().saturating_add(config.delay())
+        {
+            return Err(ErrorKind::RecoveryNotReady.into());
+        }
+
+        if !self.credentials().is_valid() {
+            return Err(ErrorKind::InvalidCredentials.into());
+        }
+
+        if schema.account(self.new_key()).is_some() || schema.device(self.new_key()).is_some() {
+            return Err(ErrorKind::KeyAlreadyUsed.into());
+        }
+
+        schema.account_move(account, self.new_key(), self.credentials());
+        schema.account_history_push(self.new_key(), &self.hash());
+
+        Ok(())
+    }
+}
+
+impl Transaction for TxOpenSession {
+    /// Verifies integrity of the transaction by checking the signature
+    /// of the signer.
+    fn verify(&self) -> bool {
+        self.verify_signature(self.pkey_by_who())
+    }
+
+    /// Opens a session of an active account that expires `duration` seconds after
+    /// the current consensus time. The session key can't be an account or a device key
+    /// and can't be reused while an unexpired session with this key exists.
+    /// Sessions can't open other sessions.
+    fn execute(&self, view: &mut Fork) -> ExecutionResult {
+        check_schema_version(view)?;
+        let now = consensus_time(view)?;
+        let mut schema = RoleSystemSchema::new(view);
+
+        if schema.key_owner(self.pkey_by_who()) != *self.pkey_account() {
+            return Err(ErrorKind::PermissionDenied.into());
+        }
+
+        active_account(&schema, self.pkey_account())?;
+
+        if self.duration() == 0 || self.duration() > MAX_SESSION_DURATION
+            || self.session_key() == self.pkey_account()
+        {
+            return Err(ErrorKind::InvalidSession.into());
+        }
+
+        if schema.account(self.session_key()).is_some()
+            || schema.device(self.session_key()).is_some()
+        {
+            return Err(ErrorKind::KeyAlreadyUsed.into());
+        }
+
+        if let Some(session) = schema.session(self.session_key()) {
+            if session.is_active_at(now) {
+                return Err(ErrorKind::SessionAlreadyExists.into());
+            }
+        }
+
+        let expires_at = now + Duration::seconds(self.duration() as i64);
+        schema.session_open(self.pkey_account(), self.session_key(), expires_at);
+        schema.account_history_push(self.pkey_account(), &self.hash());
+
+        Ok(())
+    }
+}
+
+impl Transaction for TxCloseSession {
     /// Verifies integrity of the transaction by checking the transaction
     /// signatures.
-    // fn verify(&self) -> bool {
-    //     self.verify_signature(self.pkey_by_who())
-    // }
-
-    /// Retrieves two accounts to apply the edition; they should be previously registered
-    /// with the help of [`TxCreateAccount`] transactions. Checks the editor's
-    /// authorship and superuser rights and applies changes to edited account if the editor's permissions
-    /// are sufficient. Otherwise, performs no op.
+    fn verify(&self) -> bool {
+        self.verify_signature(self.pkey_by_who())
+    }
+
+    /// Closes the session if the signer is the account owner, one of its devices
+    /// or the session key itself. Expired sessions can be closed as well.
+    fn execute(&self, view: &mut Fork) -> ExecutionResult {
+        check_schema_version(view)?;
+        let mut schema = RoleSystemSchema::new(view);
+
+        let session = match schema.session(self.session_key()) {
+            Some(session) => session,
+            None => return Err(ErrorKind::SessionNotFound.into()),
+        };
+
+        if self.pkey_by_who() != session.pub_key()
+            && schema.key_owner(self.pkey_by_who()) != *session.account()
+        {
+            return Err(ErrorKind::PermissionDenied.into());
+        }
+
+        schema.session_close(&session);
+        schema.account_history_push(session.account(), &self.hash());
+
+        Ok(())
+    }
+}
+
+impl Transaction for TxProposeAdminAction {
+    /// Verifies integrity of the transaction by checking the transaction
+    /// signatures.
+    fn verify(&self) -> bool {
+        self.verify_signature(self.pkey_by_who())
+    }
+
+    /// Stores a new proposal with the proposer's approval. The proposer must
+    /// belong to the admin set and the action must be a valid [`AdminAction`].
+    /// If the threshold is already reached, the action is executed right away.
+    /// Until the stored data is migrated, only the `migrate_schema` action is accepted.
     ///
-    /// [`TxCreateAccount`]: ../transactions/struct.TxCreateAccount.html
-    // fn execute(&self, view: &mut Fork) -> ExecutionResult {
-    //     let mut schema = EmployeesSchema::new(view);
-
-        // Someone is trying to edit an account that doesn't belong to him
-        // if *self.pkey_by_who() != *self.pkey_account() {
-        //     // It must be a superuser, otherwise do nothing
-        //     if !schema.is_superuser(&self.pkey_by_who()) {
-        //         return Err(ErrorKind::NotASuperUser.into());
-        //     }
-        // }
-
-        // let no_account = schema.account(self.pkey_account()).is_none();
-
-        // Account must exist
-        // if no_account {
-        //     return Err(ErrorKind::AccountNotFound.into());
-        // }
-
-        // Change custom data in the account
-        // let account = schema.account(self.pkey_account()).unwrap(); // Guaranteed to be `Some` by `if no_account` clause above
-        // schema.account_set_custom_data(account, self.pkey_account(), self.custom_data());
-
-        // Ok(())
-    // }
-// }
-
-// impl Transaction for TxDeleteAccount {
-//     /// Verifies integrity of the transaction by checking the transaction
-//     /// signatures.
-//     fn verify(&self) -> bool {
-//         self.verify_signature(self.pkey_by_who())
-//     }
-
-//     /// Retrieves two accounts to apply the edition; they should be previously registered
-//     /// with the help of [`TxCreateAccount`] transactions. Checks the editor's
-//     /// authorship and superuser rights and deletes account if the editor's permissions
-//     /// are sufficient. Otherwise, performs no op.
-//     ///
-//     /// [`TxCreateAccount`]: ../transactions/struct.TxCreateAccount.html
-//     fn execute(&self, view: &mut Fork) -> ExecutionResult {
-//         let mut schema = EmployeesSchema::new(view);
-
-//         let superuser = schema.is_superuser(&self.pkey_by_who());
-//         let account = schema.account(self.pkey_account());
-
-//         if let Some(ref account) = account {
-//             if superuser {
-//                 // Remove the account
-//                 schema.account_delete(&account.id_number(), self.pkey_account());
-//             }
-//         } else {
-//             return Err(ErrorKind::AccountNotFound.into());
-//         }
-
-//         Ok(())
-//     }
-// }
-
-/// Error codes emitted by `TxCreateAccount`, `TxEditAccount`, `TxSetCustomData`, `TxDeleteAccount` transactions during execution.
-#[derive(Display, Primitive)]
+    /// [`AdminAction`]: ../governance/enum.AdminAction.html
+    fn execute(&self, view: &mut Fork) -> ExecutionResult {
+        let height = current_height(view);
+        let mut schema = RoleSystemSchema::new(view);
+
+        if !schema.is_admin(self.pkey_by_who()) {
+            return Err(ErrorKind::PermissionDenied.into());
+        }
+
+        match AdminAction::from_json(self.action()) {
+            Some(ref action)
+                if !action.is_migration()
+                    && schema.schema_version() != Some(migration::CURRENT_VERSION) =>
+            {
+                return Err(ErrorKind::SchemaNotMigrated.into())
+            }
+            Some(_) => {}
+            None => return Err(ErrorKind::InvalidAdminAction.into()),
+        }
+
+        if self.expires_at() < height {
+            return Err(ErrorKind::ProposalExpired.into());
+        }
+
+        let hash = self.hash();
+        let proposal =
+            schema.proposal_create(&hash, self.pkey_by_who(), self.action(), self.expires_at());
+
+        execute_if_approved(&mut schema, &hash, proposal, &hash)
+    }
+}
+
+impl Transaction for TxApproveAdminAction {
+    /// Verifies integrity of the transaction by checking the transaction
+    /// signatures.
+    fn verify(&self) -> bool {
+        self.verify_signature(self.pkey_by_who())
+    }
+
+    /// Records an approval of a pending proposal that hasn't expired yet.
+    /// Executes the action once enough admins have approved it.
+    fn execute(&self, view: &mut Fork) -> ExecutionResult {
+        let height = current_height(view);
+        let mut schema = RoleSystemSchema::new(view);
+
+        if !schema.is_admin(self.pkey_by_who()) {
+            return Err(ErrorKind::PermissionDenied.into());
+        }
+
+        let proposal = match schema.proposal(self.proposal()) {
+            Some(proposal) => proposal,
+            None => return Err(ErrorKind::ProposalNotFound.into()),
+        };
+
+        if proposal.executed() {
+            return Err(ErrorKind::ProposalAlreadyExecuted.into());
+        }
+
+        if proposal.expires_at() < height {
+            return Err(ErrorKind::ProposalExpired.into());
+        }
+
+        if schema.proposal_approvals(self.proposal()).contains(self.pkey_by_who()) {
+            return Err(ErrorKind::ProposalAlreadyApproved.into());
+        }
+
+        schema.proposal_approve(self.proposal(), self.pkey_by_who());
+
+        execute_if_approved(&mut schema, self.proposal(), proposal, &self.hash())
+    }
+}
+
+/// Error codes emitted by the service transactions during execution.
+#[derive(Debug, Display, Primitive)]
 pub enum ErrorKind {
     /// Error can be emitted by `TxCreateAccount`
     #[display(fmt = "Account already exists.")]
     AccountAlreadyExists = 1,
 
-    /// Error can be emitted by `TxEditAccount`, `TxSetCustomData`
-    #[display(fmt = "Editor not found.")]
-    EditorNotFound = 2,
-
     /// Error can be emitted by any transaction except `TxCreateAccount`
     #[display(fmt = "Account not found.")]
     AccountNotFound = 3,
 
-    /// Can be emitted by `TxCreateAccount`, `TxEditAccount`, `TxSetCustomData`, `TxDeleteAccount`
-    #[display(fmt = "Not a superuser.")]
-    NotASuperUser = 4,
+    /// Can be emitted by `TxProposeAdminAction`, `TxApproveAdminAction`, by recovery
+    /// transactions signed by a non-guardian and by account transactions signed
+    /// by a key that doesn't belong to the account
+    #[display(fmt = "Permission denied.")]
+    PermissionDenied = 4,
+
+    /// Can be emitted by `TxEditAccount`, `TxCreateAccount`, `TxChangeUsername`
+    #[display(fmt = "Username already exists.")]
+    UsernameAlreadyExists = 5,
+
+    /// Can be emitted by any transaction except `TxCreateAccount`, `TxReactivateAccount`
+    #[display(fmt = "Account is not active.")]
+    AccountNotActive = 6,
+
+    /// Can be emitted by `TxReactivateAccount`
+    #[display(fmt = "Account is already active.")]
+    AccountAlreadyActive = 7,
+
+    /// Can be emitted by `AdminAction::GrantRole`
+    #[display(fmt = "Role not found.")]
+    RoleNotFound = 8,
+
+    /// Can be emitted by `AdminAction::GrantRole`
+    #[display(fmt = "Role is already granted.")]
+    RoleAlreadyGranted = 9,
+
+    /// Can be emitted by `AdminAction::RevokeRole`
+    #[display(fmt = "Role is not granted.")]
+    RoleNotGranted = 10,
 
-    /// Can be emitted by `TxEditAccount`, `TxCreateAccount`
-    #[display(fmt = "Employee ID already exists")]
-    EmployeeIdAlreadyExists = 5,
+    /// Can be emitted by `TxProposeAdminAction`, `TxApproveAdminAction`
+    #[display(fmt = "Invalid admin action.")]
+    InvalidAdminAction = 11,
+
+    /// Can be emitted by `TxApproveAdminAction`
+    #[display(fmt = "Proposal not found.")]
+    ProposalNotFound = 12,
+
+    /// Can be emitted by `TxProposeAdminAction`, `TxApproveAdminAction`
+    #[display(fmt = "Proposal is expired.")]
+    ProposalExpired = 13,
+
+    /// Can be emitted by `TxApproveAdminAction`
+    #[display(fmt = "Proposal is already approved by this admin.")]
+    ProposalAlreadyApproved = 14,
+
+    /// Can be emitted by `TxApproveAdminAction`
+    #[display(fmt = "Proposal is already executed.")]
+    ProposalAlreadyExecuted = 15,
+
+    /// Can be emitted by `TxCreateAccount`, `TxRotatePassword`, `TxCompleteRecovery`
+    #[display(fmt = "Invalid credentials.")]
+    InvalidCredentials = 16,
+
+    /// Can be emitted by `TxSetCustomData`, `TxRemoveCustomData`, `TxSetDataSchema`
+    #[display(fmt = "Invalid custom data.")]
+    InvalidCustomData = 17,
+
+    /// Can be emitted by `TxSetDataSchema`
+    #[display(fmt = "Data schema not found.")]
+    DataSchemaNotFound = 18,
+
+    /// Can be emitted by `TxRegisterDataSchema`
+    #[display(fmt = "Data schema already exists.")]
+    DataSchemaAlreadyExists = 19,
+
+    /// Can be emitted by `TxRegisterDataSchema`
+    #[display(fmt = "Invalid data schema.")]
+    InvalidDataSchema = 20,
+
+    /// Can be emitted by `TxCreateAccount`, `TxEditAccount`
+    #[display(fmt = "Invalid photo CID.")]
+    InvalidPhotoCid = 21,
+
+    /// Can be emitted by `TxOpenSession`
+    #[display(fmt = "Session already exists.")]
+    SessionAlreadyExists = 22,
+
+    /// Can be emitted by `TxCloseSession`
+    #[display(fmt = "Session not found.")]
+    SessionNotFound = 23,
+
+    /// Can be emitted by `TxOpenSession`
+    #[display(fmt = "Invalid session.")]
+    InvalidSession = 24,
+
+    /// Can be emitted by `TxOpenSession`
+    #[display(fmt = "Consensus time is not available.")]
+    TimeNotAvailable = 25,
+
+    /// Can be emitted by `TxRemoveDevice`
+    #[display(fmt = "Device not found.")]
+    DeviceNotFound = 26,
+
+    /// Can be emitted by `TxAddDevice`, `TxCreateAccount`, `TxInitiateRecovery`,
+    /// `TxCompleteRecovery`, `TxOpenSession`
+    #[display(fmt = "Key is already used.")]
+    KeyAlreadyUsed = 27,
+
+    /// Can be emitted by `TxSetGuardians`
+    #[display(fmt = "Invalid guardians.")]
+    InvalidGuardians = 28,
+
+    /// Can be emitted by `TxSetGuardians`, `TxInitiateRecovery`
+    #[display(fmt = "Recovery is already in progress.")]
+    RecoveryInProgress = 29,
+
+    /// Can be emitted by `TxApproveRecovery`, `TxCancelRecovery`, `TxCompleteRecovery`
+    #[display(fmt = "Recovery not found.")]
+    RecoveryNotFound = 30,
+
+    /// Can be emitted by `TxApproveRecovery`
+    #[display(fmt = "Recovery is already approved by this guardian.")]
+    RecoveryAlreadyApproved = 31,
+
+    /// Can be emitted by `TxCompleteRecovery`
+    #[display(fmt = "Recovery is not approved or the delay hasn't passed.")]
+    RecoveryNotReady = 32,
+
+    /// Can be emitted by `AdminAction::MigrateSchema`
+    #[display(fmt = "Stored data has a different schema version.")]
+    InvalidSchemaVersion = 33,
+
+    /// Can be emitted by any transaction until the stored data is migrated to the
+    /// current layout, except `TxProposeAdminAction` and `TxApproveAdminAction`
+    /// with the `migrate_schema` action
+    #[display(fmt = "Stored data is not migrated to the current layout.")]
+    SchemaNotMigrated = 34,
+
+    /// Can be emitted by `TxInitiateRecovery`, `TxCompleteRecovery`
+    #[display(fmt = "Account of an admin can't be recovered.")]
+    AdminRecoveryForbidden = 35,
 }
 
 impl ErrorKind {
@@ -202,6 +1082,11 @@ impl ErrorKind {
     pub fn into_code(self) -> u8 {
         self.to_u8().unwrap()
     }
+
+    /// Converts the raw code back to the error, if the code is known
+    pub fn from_code(code: u8) -> Option<Self> {
+        Self::from_u8(code)
+    }
 }
 
 impl From<ErrorKind> for ExecutionError {
@@ -0,0 +1,259 @@
+use serde_json::{self, Map, Value};
+use std::collections::BTreeMap;
+
+/// Maximal length of a custom data key in bytes.
+pub const MAX_KEY_LEN: usize = 64;
+
+/// Maximal length of a JSON-serialized custom data value in bytes.
+pub const MAX_VALUE_LEN: usize = 1024;
+
+/// Maximal number of custom data entries of a single account.
+pub const MAX_ENTRIES: u64 = 64;
+
+/// Maximal length of a JSON-serialized data schema definition in bytes.
+pub const MAX_DEFINITION_LEN: usize = 16 * 1024;
+
+/// Type of a custom data value.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldType {
+    /// JSON string.
+    String,
+    /// JSON number without a fractional part.
+    Integer,
+    /// Any JSON number.
+    Number,
+    /// JSON boolean.
+    Boolean,
+    /// JSON array.
+    Array,
+    /// JSON object.
+    Object,
+}
+
+/// Constraints of a single custom data entry.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldSpec {
+    /// Type of the value.
+    #[serde(rename = "type")]
+    pub field_type: FieldType,
+    /// Maximal length of a string or an array.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<usize>,
+    /// Minimal value of a number.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    /// Maximal value of a number.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+}
+
+/// JSON-schema-like description of the custom data of an account, e.g.
+/// `{ "fields": { "phone": { "type": "string", "max_length": 32 } } }`.
+/// Keys that are not listed in `fields` are rejected.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DataSchemaDefinition {
+    /// Allowed keys and their constraints.
+    pub fields: BTreeMap<String, FieldSpec>,
+}
+
+impl FieldSpec {
+    /// Checks that the value has the declared type and fits into the constraints.
+    pub fn validate(&self, value: &Value) -> bool {
+        let type_matches = match (self.field_type, value) {
+            (FieldType::String, &Value::String(_)) => true,
+            (FieldType::Integer, &Value::Number(ref n)) => n.is_i64() || n.is_u64(),
+            (FieldType::Number, &Value::Number(_)) => true,
+            (FieldType::Boolean, &Value::Bool(_)) => true,
+            (FieldType::Array, &Value::Array(_)) => true,
+            (FieldType::Object, &Value::Object(_)) => true,
+            _ => false,
+        };
+
+        if !type_matches {
+            return false;
+        }
+
+        let length = match *value {
+            Value::String(ref s) => Some(s.chars().count()),
+            Value::Array(ref items) => Some(items.len()),
+            _ => None,
+        };
+
+        if let (Some(length), Some(max_length)) = (length, self.max_length) {
+            if length > max_length {
+                return false;
+            }
+        }
+
+        if let Some(number) = value.as_f64() {
+            if self.min.map_or(false, |min| number < min) || self.max.map_or(false, |max| number > max) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+impl DataSchemaDefinition {
+    /// Parses a definition from its JSON representation.
+    pub fn from_json(definition: &str) -> Option<Self> {
+        if definition.len() > MAX_DEFINITION_LEN {
+            return None;
+        }
+
+        serde_json::from_str(definition).ok()
+    }
+
+    /// Checks that the entry is declared by the schema and the value fits into its constraints.
+    pub fn validate(&self, key: &str, value: &Value) -> bool {
+        self.fields.get(key).map_or(false, |spec| spec.validate(value))
+    }
+}
+
+/// Parses a custom data value and checks the size limits of the entry.
+/// Returns the value in its canonical JSON form.
+pub fn parse_entry(key: &str, value: &str) -> Option<Value> {
+    if key.is_empty() || key.len() > MAX_KEY_LEN || value.len() > MAX_VALUE_LEN {
+        return None;
+    }
+
+    serde_json::from_str(value).ok()
+}
+
+/// Collects custom data entries into a JSON object.
+pub fn to_object<I: Iterator<Item = (String, String)>>(entries: I) -> Value {
+    let object: Map<String, Value> = entries
+        .filter_map(|(key, value)| serde_json::from_str(&value).ok().map(|value| (key, value)))
+        .collect();
+
+    Value::Object(object)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema() -> DataSchemaDefinition {
+        DataSchemaDefinition::from_json(
+            r#"{ "fields": {
+                "phone": { "type": "string", "max_length": 5 },
+                "age": { "type": "integer", "min": 0, "max": 150 },
+                "rating": { "type": "number" },
+                "verified": { "type": "boolean" },
+                "tags": { "type": "array", "max_length": 2 },
+                "address": { "type": "object" }
+            } }"#,
+        ).unwrap()
+    }
+
+    fn json(value: &str) -> Value {
+        serde_json::from_str(value).unwrap()
+    }
+
+    #[test]
+    fn test_valid_entries() {
+        let schema = schema();
+        assert!(schema.validate("phone", &json(r#""12345""#)));
+        assert!(schema.validate("age", &json("42")));
+        assert!(schema.validate("rating", &json("4.5")));
+        assert!(schema.validate("rating", &json("-3")));
+        assert!(schema.validate("verified", &json("true")));
+        assert!(schema.validate("tags", &json(r#"["a", 1]"#)));
+        assert!(schema.validate("address", &json(r#"{ "city": "Moscow" }"#)));
+    }
+
+    #[test]
+    fn test_type_mismatch() {
+        let schema = schema();
+        assert!(!schema.validate("phone", &json("12345")));
+        assert!(!schema.validate("age", &json("4.5")));
+        assert!(!schema.validate("age", &json(r#""42""#)));
+        assert!(!schema.validate("rating", &json("null")));
+        assert!(!schema.validate("verified", &json("1")));
+        assert!(!schema.validate("tags", &json(r#"{ "a": 1 }"#)));
+        assert!(!schema.validate("address", &json(r#"["Moscow"]"#)));
+    }
+
+    #[test]
+    fn test_constraints() {
+        let schema = schema();
+        // Lengths are counted in characters, not bytes
+        assert!(schema.validate("phone", &json(r#""абвгд""#)));
+        assert!(!schema.validate("phone", &json(r#""123456""#)));
+        assert!(!schema.validate("tags", &json("[1, 2, 3]")));
+        assert!(schema.validate("age", &json("0")));
+        assert!(schema.validate("age", &json("150")));
+        assert!(!schema.validate("age", &json("-1")));
+        assert!(!schema.validate("age", &json("151")));
+    }
+
+    #[test]
+    fn test_unknown_key() {
+        assert!(!schema().validate("email", &json(r#""alice@example.com""#)));
+    }
+
+    #[test]
+    fn test_invalid_definitions() {
+        // Missing required parts of the definition
+        assert!(DataSchemaDefinition::from_json("{}").is_none());
+        assert!(DataSchemaDefinition::from_json(r#"{ "fields": { "phone": {} } }"#).is_none());
+        assert!(DataSchemaDefinition::from_json(r#"{ "fields": { "phone": { "max_length": 5 } } }"#).is_none());
+
+        // Unknown types and attributes
+        assert!(DataSchemaDefinition::from_json(r#"{ "fields": { "phone": { "type": "date" } } }"#).is_none());
+        assert!(DataSchemaDefinition::from_json(r#"{ "fields": { "phone": { "type": "string", "pattern": "\\d+" } } }"#).is_none());
+        assert!(DataSchemaDefinition::from_json(r#"{ "fields": {}, "required": ["phone"] }"#).is_none());
+        assert!(DataSchemaDefinition::from_json(r#"{ "fields": { "age": { "type": "integer", "min": "0" } } }"#).is_none());
+        assert!(DataSchemaDefinition::from_json("not json").is_none());
+
+        assert_eq!(
+            DataSchemaDefinition::from_json(r#"{ "fields": {} }"#).unwrap().fields.len(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_definition_size_limit() {
+        let padding = " ".repeat(MAX_DEFINITION_LEN);
+        let definition = format!(r#"{{ "fields": {{}} }}{}"#, padding);
+        assert!(DataSchemaDefinition::from_json(&definition).is_none());
+        assert!(DataSchemaDefinition::from_json(&definition[..MAX_DEFINITION_LEN]).is_some());
+    }
+
+    #[test]
+    fn test_parse_entry() {
+        assert_eq!(parse_entry("phone", r#""12345""#), Some(json(r#""12345""#)));
+        assert_eq!(parse_entry("tags", "[1, 2]"), Some(json("[1,2]")));
+        assert!(parse_entry("phone", "12345 extra").is_none());
+        assert!(parse_entry("phone", "").is_none());
+    }
+
+    #[test]
+    fn test_entry_size_limits() {
+        assert!(parse_entry("", "1").is_none());
+        assert!(parse_entry(&"k".repeat(MAX_KEY_LEN), "1").is_some());
+        assert!(parse_entry(&"k".repeat(MAX_KEY_LEN + 1), "1").is_none());
+
+        let value = format!(r#""{}""#, "v".repeat(MAX_VALUE_LEN - 2));
+        assert!(parse_entry("key", &value).is_some());
+        let value = format!(r#""{}""#, "v".repeat(MAX_VALUE_LEN - 1));
+        assert!(parse_entry("key", &value).is_none());
+    }
+
+    #[test]
+    fn test_to_object() {
+        let entries = vec![
+            ("phone".to_owned(), r#""12345""#.to_owned()),
+            ("broken".to_owned(), "{".to_owned()),
+            ("age".to_owned(), "42".to_owned()),
+        ];
+        assert_eq!(
+            to_object(entries.into_iter()),
+            json(r#"{ "phone": "12345", "age": 42 }"#)
+        );
+    }
+}
@@ -0,0 +1,317 @@
+use contracts::ErrorKind;
+use exonum::blockchain::ExecutionResult;
+use exonum::crypto::PublicKey;
+use exonum::storage::{Fork, Snapshot};
+use ipfs;
+use schema::{Role, RoleSystemSchema};
+use serde_json;
+use std::collections::BTreeSet;
+
+/// Administrative action that can be executed only by the admin set.
+/// Actions are carried in `TxProposeAdminAction` as JSON, e.g.
+/// `{ "type": "grant_role", "pub_key": "...", "role": "moderator" }`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AdminAction {
+    /// Changes username and photo of any account.
+    EditAccount {
+        /// Public key of the account owner.
+        pub_key: PublicKey,
+        /// New username.
+        username: String,
+        /// CID of the new photo stored in IPFS.
+        photo_ipfs: String,
+    },
+
+    /// Marks any active account as inactive.
+    DeactivateAccount {
+        /// Public key of the account owner.
+        pub_key: PublicKey,
+    },
+
+    /// Removes any account and releases its username.
+    DeleteAccount {
+        /// Public key of the account owner.
+        pub_key: PublicKey,
+    },
+
+    /// Creates a role or replaces its permissions.
+    SetRole {
+        /// Role name.
+        name: String,
+        /// Bitmask of `schema::permissions`.
+        permissions: u64,
+    },
+
+    /// Grants an existing role to an existing account.
+    GrantRole {
+        /// Public key of the account owner.
+        pub_key: PublicKey,
+        /// Role name.
+        role: String,
+    },
+
+    /// Revokes a previously granted role from the account.
+    RevokeRole {
+        /// Public key of the account owner.
+        pub_key: PublicKey,
+        /// Role name.
+        role: String,
+    },
+
+    /// Replaces the admin set and the approval threshold.
+    SetAdmins {
+        /// Public keys of the new admin set.
+        admins: Vec<PublicKey>,
+        /// Number of approvals required to execute an action.
+        threshold: u16,
+    },
+
+    /// Rewrites the stored data into the current layout, see `migration`.
+    MigrateSchema {
+        /// Layout version of the stored data.
+        from_version: u32,
+    },
+}
+
+impl AdminAction {
+    /// Parses an action from its JSON representation.
+    pub fn from_json(action: &str) -> Option<Self> {
+        serde_json::from_str(action).ok()
+    }
+
+    /// Returns the public key of the account affected by the action, if any.
+    pub fn pub_key(&self) -> Option<&PublicKey> {
+        match *self {
+            AdminAction::EditAccount { ref pub_key, .. }
+            | AdminAction::DeactivateAccount { ref pub_key }
+            | AdminAction::DeleteAccount { ref pub_key }
+            | AdminAction::GrantRole { ref pub_key, .. }
+            | AdminAction::RevokeRole { ref pub_key, .. } => Some(pub_key),
+            AdminAction::SetRole { .. }
+            | AdminAction::SetAdmins { .. }
+            | AdminAction::MigrateSchema { .. } => None,
+        }
+    }
+
+    /// Checks whether the action migrates the stored data, the only action accepted
+    /// before the data has the current layout.
+    pub fn is_migration(&self) -> bool {
+        match *self {
+            AdminAction::MigrateSchema { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// Applies the action to the storage. Should be called only after
+    /// the proposal has collected enough approvals.
+    pub fn execute(&self, schema: &mut RoleSystemSchema<&mut Fork>) -> ExecutionResult {
+        match *self {
+            AdminAction::EditAccount {
+                ref pub_key,
+                ref username,
+                ref photo_ipfs,
+            } => {
+                let account = match schema.account(pub_key) {
+                    Some(account) => account,
+                    None => return Err(ErrorKind::AccountNotFound.into()),
+                };
+
+                if let Some(pk) = schema.pub_key_by_username(username) {
+                    if pk != *pub_key {
+                        return Err(ErrorKind::UsernameAlreadyExists.into());
+                    }
+                }
+
+                if !ipfs::is_valid_photo(photo_ipfs) {
+                    return Err(ErrorKind::InvalidPhotoCid.into());
+                }
+
+                schema.account_edit(account, pub_key, username, photo_ipfs);
+            }
+
+            AdminAction::DeactivateAccount { ref pub_key } => {
+                let account = match schema.account(pub_key) {
+                    Some(ref account) if !account.is_active() => {
+                        return Err(ErrorKind::AccountNotActive.into())
+                    }
+                    Some(account) => account,
+                    None => return Err(ErrorKind::AccountNotFound.into()),
+                };
+
+                schema.account_set_active(account, pub_key, false);
+            }
+
+            AdminAction::DeleteAccount { ref pub_key } => {
+                let account = match schema.account(pub_key) {
+                    Some(account) => account,
+                    None => return Err(ErrorKind::AccountNotFound.into()),
+                };
+
+                schema.account_delete(&account);
+            }
+
+            AdminAction::SetRole {
+                ref name,
+                permissions,
+            } => {
+                schema.role_set(name, permissions);
+            }
+
+            AdminAction::GrantRole {
+                ref pub_key,
+                ref role,
+            } => {
+                if schema.account(pub_key).is_none() {
+                    return Err(ErrorKind::AccountNotFound.into());
+                }
+
+                if schema.role(role).is_none() {
+                    return Err(ErrorKind::RoleNotFound.into());
+                }
+
+                if schema.account_roles(pub_key).contains(role) {
+                    return Err(ErrorKind::RoleAlreadyGranted.into());
+                }
+
+                schema.role_grant(pub_key, role);
+            }
+
+            AdminAction::RevokeRole {
+                ref pub_key,
+                ref role,
+            } => {
+                if !schema.account_roles(pub_key).contains(role) {
+                    return Err(ErrorKind::RoleNotGranted.into());
+                }
+
+                schema.role_revoke(pub_key, role);
+            }
+
+            AdminAction::SetAdmins {
+                ref admins,
+                threshold,
+            } => {
+                let distinct: BTreeSet<&PublicKey> = admins.iter().collect();
+                if distinct.len() != admins.len()
+                    || threshold == 0
+                    || threshold as usize > admins.len()
+                {
+                    return Err(ErrorKind::InvalidAdminAction.into());
+                }
+
+                schema.admins_set(admins, threshold);
+            }
+
+            AdminAction::MigrateSchema { from_version } => {
+                return schema.migrate(from_version);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Roles granted to an account.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RoleGrants {
+    /// Public key of the account owner.
+    pub pub_key: PublicKey,
+    /// Names of the granted roles.
+    pub roles: Vec<String>,
+}
+
+/// Copy of the admin set and the role tables used to back them up and restore.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RolesDump {
+    /// Public keys of the admin set.
+    pub admins: Vec<PublicKey>,
+    /// Number of approvals required to execute an admin action.
+    pub admin_threshold: u16,
+    /// All roles.
+    pub roles: Vec<Role>,
+    /// Roles granted to the accounts; accounts without roles are omitted.
+    pub grants: Vec<RoleGrants>,
+}
+
+impl RolesDump {
+    /// Dumps the admin set and the role tables from the storage.
+    pub fn new<T: AsRef<Snapshot>>(schema: &RoleSystemSchema<T>) -> Self {
+        let grants = schema
+            .accounts()
+            .keys()
+            .map(|pub_key| RoleGrants {
+                roles: schema.account_roles(&pub_key).iter().collect(),
+                pub_key,
+            })
+            .filter(|grants| !grants.roles.is_empty())
+            .collect();
+
+        RolesDump {
+            admins: schema.admins().iter().collect(),
+            admin_threshold: schema.admin_threshold(),
+            roles: schema.roles().values().collect(),
+            grants,
+        }
+    }
+
+    /// Returns the admin actions that bring the storage to the dumped state.
+    /// Roles missing from the dump are kept since there is no action to remove
+    /// a role; grants of the accounts that no longer exist are skipped.
+    /// The admin set is replaced last, so that the other actions can still be
+    /// approved by the current admins.
+    pub fn restore_actions<T: AsRef<Snapshot>>(
+        &self,
+        schema: &RoleSystemSchema<T>,
+    ) -> Vec<AdminAction> {
+        let mut actions = Vec::new();
+
+        for role in &self.roles {
+            if schema.role(role.name()).as_ref() != Some(role) {
+                actions.push(AdminAction::SetRole {
+                    name: role.name().to_owned(),
+                    permissions: role.permissions(),
+                });
+            }
+        }
+
+        for grants in &self.grants {
+            if schema.account(&grants.pub_key).is_none() {
+                continue;
+            }
+
+            let granted = schema.account_roles(&grants.pub_key);
+            for role in grants.roles.iter().filter(|role| !granted.contains(role)) {
+                actions.push(AdminAction::GrantRole {
+                    pub_key: grants.pub_key,
+                    role: role.clone(),
+                });
+            }
+        }
+
+        for pub_key in schema.accounts().keys() {
+            let dumped: BTreeSet<String> = self.grants
+                .iter()
+                .filter(|grants| grants.pub_key == pub_key)
+                .flat_map(|grants| grants.roles.iter().cloned())
+                .collect();
+
+            for role in schema.account_roles(&pub_key).iter() {
+                if !dumped.contains(&role) {
+                    actions.push(AdminAction::RevokeRole { pub_key, role });
+                }
+            }
+        }
+
+        let admins: BTreeSet<PublicKey> = schema.admins().iter().collect();
+        let dumped_admins: BTreeSet<PublicKey> = self.admins.iter().cloned().collect();
+        if admins != dumped_admins || schema.admin_threshold() != self.admin_threshold {
+            actions.push(AdminAction::SetAdmins {
+                admins: self.admins.clone(),
+                threshold: self.admin_threshold,
+            });
+        }
+
+        actions
+    }
+}
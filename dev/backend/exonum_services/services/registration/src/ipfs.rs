@@ -0,0 +1,617 @@
+//! Minimal IPFS support for profile photos: content identifiers (CIDs), UnixFS file
+//! chunking compatible with `ipfs add` defaults and a local blockstore.
+//!
+//! Files are split into 256 KiB chunks. Each chunk is wrapped into a `dag-pb` node
+//! with UnixFS `File` data, and the nodes are linked into a balanced tree with
+//! at most 174 links per node. Blocks are addressed by CIDv0 (`Qm...`): base58btc
+//! encoding of the sha2-256 multihash of the block.
+
+use exonum::crypto::{self, Hash, HASH_SIZE};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+/// Size of a file chunk.
+pub const CHUNK_SIZE: usize = 256 * 1024;
+
+/// Maximal number of links in a single node of the file tree.
+pub const MAX_LINKS: usize = 174;
+
+/// Maximal size of an uploaded photo.
+pub const MAX_PHOTO_SIZE: usize = 10 * 1024 * 1024;
+
+/// Multihash code of sha2-256.
+const SHA2_256: u64 = 0x12;
+
+/// Multicodec code of `dag-pb`.
+pub const DAG_PB: u64 = 0x70;
+
+/// Multicodec code of raw binary blocks.
+pub const RAW: u64 = 0x55;
+
+/// UnixFS node type of a file.
+const UNIXFS_FILE: u64 = 2;
+
+const BASE58_ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+const BASE32_ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// Errors of CID parsing.
+#[derive(Debug, Display, PartialEq)]
+pub enum CidError {
+    /// The string is not a valid base58btc or base32 string.
+    #[display(fmt = "Invalid CID encoding.")]
+    InvalidEncoding,
+
+    /// The multihash is malformed or isn't sha2-256.
+    #[display(fmt = "Unsupported multihash.")]
+    UnsupportedMultihash,
+
+    /// Only CIDv0 and CIDv1 are supported.
+    #[display(fmt = "Unsupported CID version.")]
+    UnsupportedVersion,
+
+    /// Only `dag-pb` and `raw` codecs are supported.
+    #[display(fmt = "Unsupported codec.")]
+    UnsupportedCodec,
+}
+
+/// Content identifier of an IPFS block. Only sha2-256 multihashes are supported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Cid {
+    version: u8,
+    codec: u64,
+    digest: Hash,
+}
+
+impl Cid {
+    /// Creates CIDv0 of a `dag-pb` block with the given sha2-256 digest.
+    pub fn new_v0(digest: Hash) -> Self {
+        Cid {
+            version: 0,
+            codec: DAG_PB,
+            digest,
+        }
+    }
+
+    /// Creates CIDv1 of a block with the given codec and sha2-256 digest.
+    pub fn new_v1(codec: u64, digest: Hash) -> Self {
+        Cid {
+            version: 1,
+            codec,
+            digest,
+        }
+    }
+
+    /// Computes CIDv0 of a `dag-pb` block.
+    pub fn for_block(block: &[u8]) -> Self {
+        Cid::new_v0(crypto::hash(block))
+    }
+
+    /// Returns the CID version.
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Returns the multicodec code of the block.
+    pub fn codec(&self) -> u64 {
+        self.codec
+    }
+
+    /// Returns the sha2-256 digest of the block.
+    pub fn digest(&self) -> &Hash {
+        &self.digest
+    }
+
+    /// Checks that the block has the content addressed by this CID.
+    pub fn matches(&self, block: &[u8]) -> bool {
+        crypto::hash(block) == self.digest
+    }
+
+    /// Returns the binary representation of the CID.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        if self.version == 1 {
+            write_varint(&mut bytes, 1);
+            write_varint(&mut bytes, self.codec);
+        }
+        write_varint(&mut bytes, SHA2_256);
+        write_varint(&mut bytes, HASH_SIZE as u64);
+        bytes.extend_from_slice(self.digest.as_ref());
+        bytes
+    }
+
+    /// Parses the binary representation of the CID.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CidError> {
+        // CIDv0 is a bare sha2-256 multihash.
+        if bytes.len() == HASH_SIZE + 2 && bytes[0] == SHA2_256 as u8 {
+            return parse_multihash(bytes).map(Cid::new_v0);
+        }
+
+        let mut reader = Reader::new(bytes);
+        match reader.varint() {
+            Some(1) => {}
+            Some(_) => return Err(CidError::UnsupportedVersion),
+            None => return Err(CidError::InvalidEncoding),
+        }
+
+        let codec = reader.varint().ok_or(CidError::InvalidEncoding)?;
+        if codec != DAG_PB && codec != RAW {
+            return Err(CidError::UnsupportedCodec);
+        }
+
+        parse_multihash(reader.rest()).map(|digest| Cid::new_v1(codec, digest))
+    }
+}
+
+impl FromStr for Cid {
+    type Err = CidError;
+
+    /// Parses CIDv0 (`Qm...`) or base32 CIDv1 (`b...`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() == 46 && s.starts_with("Qm") {
+            let bytes = base58_decode(s).ok_or(CidError::InvalidEncoding)?;
+            return parse_multihash(&bytes).map(Cid::new_v0);
+        }
+
+        if s.starts_with('b') {
+            let bytes = base32_decode(&s[1..]).ok_or(CidError::InvalidEncoding)?;
+            let cid = Cid::from_bytes(&bytes)?;
+            return if cid.version == 1 {
+                Ok(cid)
+            } else {
+                Err(CidError::UnsupportedVersion)
+            };
+        }
+
+        Err(CidError::InvalidEncoding)
+    }
+}
+
+impl fmt::Display for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.version == 0 {
+            write!(f, "{}", base58_encode(&self.to_bytes()))
+        } else {
+            write!(f, "b{}", base32_encode(&self.to_bytes()))
+        }
+    }
+}
+
+/// Checks the photo reference of an account: either empty or a well-formed CID.
+pub fn is_valid_photo(photo: &str) -> bool {
+    photo.is_empty() || photo.parse::<Cid>().is_ok()
+}
+
+/// Storage of IPFS blocks addressed by CID.
+pub trait Blockstore {
+    /// Gets a block. Blocks that don't match their CID are not returned.
+    fn get(&self, cid: &Cid) -> Option<Vec<u8>>;
+
+    /// Stores a block.
+    fn put(&mut self, cid: &Cid, block: &[u8]) -> io::Result<()>;
+}
+
+/// Blockstore shared between the service and its API handlers.
+pub type SharedBlockstore = Arc<RwLock<Box<Blockstore + Send + Sync>>>;
+
+/// In-memory blockstore.
+#[derive(Debug, Default)]
+pub struct MemoryBlockstore {
+    blocks: HashMap<Cid, Vec<u8>>,
+}
+
+impl MemoryBlockstore {
+    /// Creates an empty blockstore.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Blockstore for MemoryBlockstore {
+    fn get(&self, cid: &Cid) -> Option<Vec<u8>> {
+        self.blocks
+            .get(cid)
+            .filter(|block| cid.matches(block))
+            .cloned()
+    }
+
+    fn put(&mut self, cid: &Cid, block: &[u8]) -> io::Result<()> {
+        self.blocks.insert(*cid, block.to_vec());
+        Ok(())
+    }
+}
+
+/// Blockstore keeping every block in a separate file named by its CID.
+#[derive(Debug)]
+pub struct FsBlockstore {
+    root: PathBuf,
+}
+
+impl FsBlockstore {
+    /// Creates a blockstore in the given directory.
+    pub fn new<P: Into<PathBuf>>(root: P) -> io::Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(&root)?;
+        Ok(FsBlockstore { root })
+    }
+}
+
+impl Blockstore for FsBlockstore {
+    fn get(&self, cid: &Cid) -> Option<Vec<u8>> {
+        fs::read(self.root.join(cid.to_string()))
+            .ok()
+            .filter(|block| cid.matches(block))
+    }
+
+    fn put(&mut self, cid: &Cid, block: &[u8]) -> io::Result<()> {
+        fs::write(self.root.join(cid.to_string()), block)
+    }
+}
+
+/// Chunks the file, stores its blocks and returns CID of the file root.
+pub fn add<B: Blockstore + ?Sized>(store: &mut B, content: &[u8]) -> io::Result<Cid> {
+    // (CID, size of the subtree blocks, size of the file content)
+    let mut level = Vec::new();
+
+    let mut chunks: Vec<&[u8]> = content.chunks(CHUNK_SIZE).collect();
+    if chunks.is_empty() {
+        chunks.push(&[]);
+    }
+
+    for chunk in chunks {
+        let block = encode_node(&[], &encode_unixfs(chunk, chunk.len() as u64, &[]));
+        let cid = Cid::for_block(&block);
+        store.put(&cid, &block)?;
+        level.push((cid, block.len() as u64, chunk.len() as u64));
+    }
+
+    while level.len() > 1 {
+        let mut parents = Vec::new();
+
+        for children in level.chunks(MAX_LINKS) {
+            let links: Vec<Vec<u8>> = children
+                .iter()
+                .map(|&(ref cid, tsize, _)| encode_link(cid, tsize))
+                .collect();
+            let blocksizes: Vec<u64> = children.iter().map(|&(_, _, size)| size).collect();
+            let filesize = blocksizes.iter().sum();
+
+            let block = encode_node(&links, &encode_unixfs(&[], filesize, &blocksizes));
+            let cid = Cid::for_block(&block);
+            store.put(&cid, &block)?;
+
+            let tsize = block.len() as u64 + children.iter().map(|&(_, tsize, _)| tsize).sum::<u64>();
+            parents.push((cid, tsize, filesize));
+        }
+
+        level = parents;
+    }
+
+    Ok(level[0].0)
+}
+
+/// Reads the file with the given root CID. Returns `None` if some block is missing,
+/// doesn't match its CID or is not a UnixFS file node.
+pub fn cat<B: Blockstore + ?Sized>(store: &B, cid: &Cid) -> Option<Vec<u8>> {
+    let block = store.get(cid)?;
+
+    if cid.codec() == RAW {
+        return Some(block);
+    }
+
+    let (links, data) = decode_node(&block)?;
+    let mut content = decode_unixfs(&data)?;
+
+    for link in links {
+        content.extend(cat(store, &link)?);
+    }
+
+    Some(content)
+}
+
+fn parse_multihash(bytes: &[u8]) -> Result<Hash, CidError> {
+    let mut reader = Reader::new(bytes);
+    if reader.varint() != Some(SHA2_256) || reader.varint() != Some(HASH_SIZE as u64) {
+        return Err(CidError::UnsupportedMultihash);
+    }
+
+    Hash::from_slice(reader.rest()).ok_or(CidError::UnsupportedMultihash)
+}
+
+fn encode_unixfs(data: &[u8], filesize: u64, blocksizes: &[u64]) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_varint_field(&mut out, 1, UNIXFS_FILE);
+    if !data.is_empty() {
+        write_bytes_field(&mut out, 2, data);
+    }
+    write_varint_field(&mut out, 3, filesize);
+    for &size in blocksizes {
+        write_varint_field(&mut out, 4, size);
+    }
+    out
+}
+
+fn encode_link(cid: &Cid, tsize: u64) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_bytes_field(&mut out, 1, &cid.to_bytes());
+    write_bytes_field(&mut out, 2, &[]);
+    write_varint_field(&mut out, 3, tsize);
+    out
+}
+
+fn encode_node(links: &[Vec<u8>], data: &[u8]) -> Vec<u8> {
+    // `dag-pb` puts links before data.
+    let mut out = Vec::new();
+    for link in links {
+        write_bytes_field(&mut out, 2, link);
+    }
+    write_bytes_field(&mut out, 1, data);
+    out
+}
+
+fn decode_node(block: &[u8]) -> Option<(Vec<Cid>, Vec<u8>)> {
+    let mut reader = Reader::new(block);
+    let mut links = Vec::new();
+    let mut data = Vec::new();
+
+    while !reader.is_empty() {
+        match reader.field()? {
+            (1, Field::Bytes(bytes)) => data = bytes.to_vec(),
+            (2, Field::Bytes(link)) => {
+                let mut link_reader = Reader::new(link);
+                let mut cid = None;
+                while !link_reader.is_empty() {
+                    if let (1, Field::Bytes(bytes)) = link_reader.field()? {
+                        cid = Cid::from_bytes(bytes).ok();
+                    }
+                }
+                links.push(cid?);
+            }
+            _ => {}
+        }
+    }
+
+    Some((links, data))
+}
+
+fn decode_unixfs(data: &[u8]) -> Option<Vec<u8>> {
+    let mut reader = Reader::new(data);
+    let mut node_type = None;
+    let mut content = Vec::new();
+
+    while !reader.is_empty() {
+        match reader.field()? {
+            (1, Field::Varint(value)) => node_type = Some(value),
+            (2, Field::Bytes(bytes)) => content = bytes.to_vec(),
+            _ => {}
+        }
+    }
+
+    if node_type == Some(UNIXFS_FILE) {
+        Some(content)
+    } else {
+        None
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn write_varint_field(out: &mut Vec<u8>, number: u64, value: u64) {
+    write_varint(out, number << 3);
+    write_varint(out, value);
+}
+
+fn write_bytes_field(out: &mut Vec<u8>, number: u64, bytes: &[u8]) {
+    write_varint(out, number << 3 | 2);
+    write_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+/// Protobuf field value.
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+/// Reader of varints and protobuf fields.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    fn varint(&mut self) -> Option<u64> {
+        let mut value = 0u64;
+        for (i, &byte) in self.bytes.iter().enumerate().take(10) {
+            value |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                self.bytes = &self.bytes[i + 1..];
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    fn field(&mut self) -> Option<(u64, Field<'a>)> {
+        let key = self.varint()?;
+        match key & 7 {
+            0 => Some((key >> 3, Field::Varint(self.varint()?))),
+            2 => {
+                let len = self.varint()? as usize;
+                if len > self.bytes.len() {
+                    return None;
+                }
+                let (bytes, rest) = self.bytes.split_at(len);
+                self.bytes = rest;
+                Some((key >> 3, Field::Bytes(bytes)))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn base58_encode(bytes: &[u8]) -> String {
+    let zeros = bytes.iter().take_while(|&&b| b == 0).count();
+    // Digits of the number in base 58, least significant first.
+    let mut digits: Vec<u8> = Vec::new();
+
+    for &byte in bytes {
+        let mut carry = u32::from(byte);
+        for digit in &mut digits {
+            carry += u32::from(*digit) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+
+    let mut out = String::with_capacity(zeros + digits.len());
+    for _ in 0..zeros {
+        out.push('1');
+    }
+    for &digit in digits.iter().rev() {
+        out.push(BASE58_ALPHABET[digit as usize] as char);
+    }
+    out
+}
+
+fn base58_decode(s: &str) -> Option<Vec<u8>> {
+    let zeros = s.bytes().take_while(|&b| b == b'1').count();
+    // Bytes of the number, least significant first.
+    let mut bytes: Vec<u8> = Vec::new();
+
+    for c in s.bytes() {
+        let mut carry = BASE58_ALPHABET.iter().position(|&a| a == c)? as u32;
+        for byte in &mut bytes {
+            carry += u32::from(*byte) * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+
+    let mut out = vec![0; zeros];
+    out.extend(bytes.iter().rev());
+    Some(out)
+}
+
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for &byte in bytes {
+        buffer = buffer << 8 | u32::from(byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[(buffer >> bits & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[(buffer << (5 - bits) & 31) as usize] as char);
+    }
+    out
+}
+
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for c in s.bytes() {
+        let value = BASE32_ALPHABET.iter().position(|&a| a == c)? as u32;
+        buffer = buffer << 5 | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add_to_memory(content: &[u8]) -> (MemoryBlockstore, Cid) {
+        let mut store = MemoryBlockstore::new();
+        let cid = add(&mut store, content).unwrap();
+        (store, cid)
+    }
+
+    #[test]
+    fn test_add_matches_go_ipfs() {
+        assert_eq!(
+            add_to_memory(b"").1.to_string(),
+            "QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH"
+        );
+        assert_eq!(
+            add_to_memory(b"hello world\n").1.to_string(),
+            "QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o"
+        );
+    }
+
+    #[test]
+    fn test_cid_string_roundtrip() {
+        let cid: Cid = "QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o".parse().unwrap();
+        assert_eq!(cid.version(), 0);
+        assert_eq!(cid.to_string(), "QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o");
+
+        let v1 = Cid::new_v1(DAG_PB, *cid.digest());
+        assert!(v1.to_string().starts_with("bafybei"));
+        assert_eq!(v1.to_string().parse::<Cid>(), Ok(v1));
+    }
+
+    #[test]
+    fn test_cid_rejects_malformed() {
+        assert_eq!("".parse::<Cid>(), Err(CidError::InvalidEncoding));
+        assert_eq!(
+            "QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff50".parse::<Cid>(),
+            Err(CidError::InvalidEncoding)
+        );
+        assert!("bafybei".parse::<Cid>().is_err());
+    }
+
+    #[test]
+    fn test_cat_multiple_chunks() {
+        let content: Vec<u8> = (0..CHUNK_SIZE * 3 + 17).map(|i| i as u8).collect();
+        let (store, cid) = add_to_memory(&content);
+        assert_eq!(cat(&store, &cid), Some(content));
+    }
+
+    #[test]
+    fn test_cat_rejects_tampered_block() {
+        let (mut store, cid) = add_to_memory(b"hello world\n");
+        store.blocks.insert(cid, b"tampered".to_vec());
+        assert_eq!(cat(&store, &cid), None);
+    }
+}
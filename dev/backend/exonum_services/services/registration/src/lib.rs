@@ -1,14 +1,17 @@
-extern crate bodyparser;
+extern crate actix_web;
+extern crate chrono;
 #[macro_use]
 extern crate display_derive;
 #[macro_use]
 extern crate enum_primitive_derive;
 #[macro_use]
 extern crate exonum;
+extern crate exonum_time;
+#[macro_use]
 extern crate failure;
-extern crate iron;
+extern crate futures;
 extern crate num_traits;
-extern crate router;
+extern crate rand;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
@@ -24,9 +27,24 @@ pub mod transactions;
 /// Contracts.
 pub mod contracts;
 
+/// Multi-signature administration.
+pub mod governance;
+
+/// Typed custom data.
+pub mod custom_data;
+
+/// IPFS photo storage.
+pub mod ipfs;
+
+/// Schema versions and data migrations.
+pub mod migration;
+
 /// REST API.
 pub mod api;
 
+/// REST API types and helpers shared with the other zen services.
+pub mod api_common;
+
 /// Service declaration.
 pub mod service;
 
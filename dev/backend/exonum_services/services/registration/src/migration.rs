@@ -0,0 +1,575 @@
+//! Versions of the stored data layout and migrations between them.
+//!
+//! `encoding_struct!` records are decoded without any layout checks, so a field change
+//! makes the existing records unreadable. Every layout change bumps [`CURRENT_VERSION`]
+//! and adds a step that rewrites the records of the previous version. New blockchains
+//! record the current version in `initialize`; existing ones are migrated by the admin
+//! set with the `migrate_schema` admin action. Until then, every other transaction is
+//! rejected with `SchemaNotMigrated`. Blockchains created before the versions were
+//! introduced have no admin set either, so the service takes it from its configuration
+//! with [`bootstrap_admins`] before committing the first block after the upgrade.
+//!
+//! Versions:
+//!
+//! - `0` - accounts with `encrypted_password`, a photo hash and inline custom data;
+//! - `1` - accounts with password verifiers, photo CIDs and custom data entries
+//!   stored in `rolesystem.custom_data`;
+//! - `2` - root hashes of the per-account tables in the state hash.
+//!
+//! Moving from `1` to `2` rewrites no records, moves the username index and the declared
+//! data schemas into the proof tables `rolesystem.usernames` and `rolesystem.account_schemas`
+//! and fills the roots tables from the stored per-account tables.
+//!
+//! [`CURRENT_VERSION`]: constant.CURRENT_VERSION.html
+//! [`bootstrap_admins`]: fn.bootstrap_admins.html
+
+use cmd::RoleSystemServiceConfig;
+use contracts::ErrorKind;
+use exonum::blockchain::{ExecutionResult, Schema};
+use exonum::crypto::{self, Hash, PublicKey};
+use exonum::encoding::serialize::FromHex;
+use exonum::encoding::Field;
+use exonum::storage::{Fork, MapIndex, ProofMapIndex, Snapshot, StorageValue};
+use ipfs::Cid;
+use schema::{kdf, Account, Credentials, RoleSystemSchema};
+use serde_json::{self, Value};
+use std::collections::BTreeMap;
+
+/// Layout version written by the current code.
+pub const CURRENT_VERSION: u32 = 2;
+
+/// Username index of versions `0` and `1`, replaced with `rolesystem.usernames` in version `2`.
+const LEGACY_USERNAMES: &str = "rolesystem.accounts_by_username";
+
+/// Declared data schemas of versions `0` and `1`, replaced with `rolesystem.account_schemas`
+/// in version `2`.
+const LEGACY_ACCOUNT_SCHEMAS: &str = "rolesystem.account_data_schemas";
+
+/// Custom data key of the version `0` custom data that isn't a JSON object.
+pub const LEGACY_CUSTOM_DATA_KEY: &str = "legacy";
+
+encoding_struct! {
+    /// Account layout of version `0`.
+    struct AccountV0 {
+        pub_key: &PublicKey,
+
+        /// UTF-8 string with the owner's username.
+        username: &str,
+
+        /// Password encrypted by the secret key of the account.
+        encrypted_password: &str,
+
+        /// Hash of the user's photo, zero if there is no photo.
+        photo_ipfs: &Hash,
+
+        is_active: bool,
+
+        /// Custom data.
+        custom_data: &str,
+    }
+}
+
+/// Rewrites the stored data from the given layout version into the current one
+/// and records the current version.
+///
+/// Data written before the versions were introduced has no recorded version,
+/// so the caller has to state it. Version `CURRENT_VERSION` only records the version.
+pub fn migrate(fork: &mut Fork, from_version: u32) -> ExecutionResult {
+    let stored = RoleSystemSchema::new(&*fork).schema_version();
+    match stored {
+        Some(version) if version != from_version || version == CURRENT_VERSION => {
+            return Err(ErrorKind::InvalidSchemaVersion.into())
+        }
+        _ if from_version > CURRENT_VERSION => {
+            return Err(ErrorKind::InvalidSchemaVersion.into())
+        }
+        _ => {}
+    }
+
+    for version in from_version..CURRENT_VERSION {
+        match version {
+            0 => migrate_v0(fork)?,
+            1 => migrate_v1(fork),
+            _ => return Err(ErrorKind::InvalidSchemaVersion.into()),
+        }
+    }
+
+    RoleSystemSchema::new(fork).schema_version_set(CURRENT_VERSION);
+    Ok(())
+}
+
+/// Stores the admin set of a blockchain created before the versions were introduced.
+///
+/// `initialize` runs only in the genesis block, so such blockchains have neither
+/// a recorded version nor an admin set, and nobody could approve `migrate_schema`.
+/// The admin set is taken from the `rolesystem` entry of the actual configuration,
+/// which the validators can change with the `exonum-configuration` service.
+/// Called by the service before each commit; does nothing if the version is recorded,
+/// the admin set isn't empty or the configuration has no valid admin set.
+pub fn bootstrap_admins(fork: &mut Fork) {
+    let admins = {
+        let schema = RoleSystemSchema::new(&*fork);
+        if schema.schema_version().is_some() || schema.admins().iter().next().is_some() {
+            return;
+        }
+        configured_admins(fork.as_ref())
+    };
+
+    if let Some((admins, threshold)) = admins {
+        RoleSystemSchema::new(fork).admins_set(&admins, threshold);
+    }
+}
+
+/// Returns the admin set and the threshold from the actual service configuration.
+fn configured_admins(snapshot: &Snapshot) -> Option<(Vec<PublicKey>, u16)> {
+    let services = Schema::new(snapshot).actual_configuration().services;
+    let config: RoleSystemServiceConfig = services
+        .get("rolesystem")
+        .and_then(|value| serde_json::from_value(value.clone()).ok())?;
+
+    let admins = config
+        .admin_pkeys
+        .iter()
+        .map(|pkey| PublicKey::from_hex(pkey).ok())
+        .collect::<Option<Vec<PublicKey>>>()?;
+
+    if config.admin_threshold == 0 || config.admin_threshold as usize > admins.len() {
+        return None;
+    }
+    Some((admins, config.admin_threshold))
+}
+
+/// Checks that `raw` is a well-formed record of the `T` layout. `encoding_struct!`
+/// records are written with their segments in the field order right after the header,
+/// which differs between the account layouts, so a record of one layout never passes
+/// the check of the other one.
+fn has_layout<T>(raw: &[u8]) -> bool
+where
+    for<'a> T: Field<'a>,
+{
+    // The record is checked the way it is checked as a field of a message:
+    // a segment pointer followed by the record itself.
+    let mut buffer = vec![0; 8];
+    Field::write(&raw, &mut buffer, 0, 8);
+    T::check(&buffer, 0.into(), 8.into(), 8.into()).is_ok()
+}
+
+/// Converts version `0` accounts.
+///
+/// Legacy accounts have no password verifier, so the account key itself becomes
+/// the verifier until the owner sends `TxRotatePassword`; the wrapped key hash
+/// commits to the old encrypted password. Photo hashes are kept as CIDv0.
+/// Custom data objects are split into entries, any other custom data is kept
+/// as a string under [`LEGACY_CUSTOM_DATA_KEY`]. Usernames are indexed in key order;
+/// if several legacy accounts share a username, only the first one is indexed.
+///
+/// Unversioned blockchains may already hold accounts of the current layout, written
+/// by the upgraded nodes before the migration was enforced. Such records are kept as is,
+/// only their usernames are indexed. A record of neither layout fails the migration.
+///
+/// [`LEGACY_CUSTOM_DATA_KEY`]: constant.LEGACY_CUSTOM_DATA_KEY.html
+fn migrate_v0(fork: &mut Fork) -> ExecutionResult {
+    let records: Vec<(PublicKey, Vec<u8>)> = {
+        let accounts: ProofMapIndex<&Snapshot, PublicKey, Vec<u8>> =
+            ProofMapIndex::new("rolesystem.accounts", fork.as_ref());
+        accounts.iter().collect()
+    };
+
+    let mut usernames = BTreeMap::new();
+    {
+        let mut schema = RoleSystemSchema::new(&mut *fork);
+        for (pub_key, raw) in records {
+            if has_layout::<Account>(&raw) {
+                let username = schema.account(&pub_key).unwrap().username().to_owned();
+                usernames.entry(username).or_insert(pub_key);
+                continue;
+            }
+            migrate_account_v0(&mut schema, pub_key, raw, &mut usernames)?;
+        }
+    }
+
+    let mut index: MapIndex<&mut Fork, String, PublicKey> = MapIndex::new(LEGACY_USERNAMES, fork);
+    index.clear();
+    for (username, pub_key) in usernames {
+        index.put(&username, pub_key);
+    }
+
+    Ok(())
+}
+
+/// Converts a single version `0` account and collects its username.
+fn migrate_account_v0(
+    schema: &mut RoleSystemSchema<&mut Fork>,
+    pub_key: PublicKey,
+    raw: Vec<u8>,
+    usernames: &mut BTreeMap<String, PublicKey>,
+) -> ExecutionResult {
+    if !has_layout::<AccountV0>(&raw) {
+        return Err(ErrorKind::InvalidSchemaVersion.into());
+    }
+    let account = AccountV0::from_bytes(raw.into());
+
+    let credentials = Credentials::new(
+        &Hash::zero(),
+        kdf::MIN_LOG_N,
+        8,
+        1,
+        &pub_key,
+        &crypto::hash(account.encrypted_password().as_bytes()),
+    );
+
+    let photo_ipfs = if *account.photo_ipfs() == Hash::zero() {
+        String::new()
+    } else {
+        Cid::new_v0(*account.photo_ipfs()).to_string()
+    };
+
+    // Puts the account with the new layout over the legacy record.
+    let username = account.username().to_owned();
+    let migrated = Account::new(
+        &pub_key,
+        &username,
+        credentials,
+        &photo_ipfs,
+        account.is_active(),
+    );
+    schema.accounts_mut().put(&pub_key, migrated);
+    usernames.entry(username).or_insert(pub_key);
+
+    let mut entries = schema.custom_data_mut(&pub_key);
+    match serde_json::from_str(account.custom_data()) {
+        Ok(Value::Object(object)) => {
+            for (key, value) in object {
+                entries.put(&key, value.to_string());
+            }
+        }
+        _ if account.custom_data().is_empty() => {}
+        _ => {
+            let value = Value::String(account.custom_data().to_owned());
+            entries.put(&LEGACY_CUSTOM_DATA_KEY.to_owned(), value.to_string());
+        }
+    }
+
+    Ok(())
+}
+
+/// Moves the username index and the declared data schemas into proof tables and fills
+/// the roots tables of the per-account tables for all accounts.
+fn migrate_v1(fork: &mut Fork) {
+    let usernames: Vec<(String, PublicKey)> = {
+        let mut index: MapIndex<&mut Fork, String, PublicKey> =
+            MapIndex::new(LEGACY_USERNAMES, &mut *fork);
+        let usernames = index.iter().collect();
+        index.clear();
+        usernames
+    };
+
+    let account_schemas: Vec<(PublicKey, String)> = {
+        let mut index: MapIndex<&mut Fork, PublicKey, String> =
+            MapIndex::new(LEGACY_ACCOUNT_SCHEMAS, &mut *fork);
+        let account_schemas = index.iter().collect();
+        index.clear();
+        account_schemas
+    };
+
+    let owners: Vec<PublicKey> = RoleSystemSchema::new(&*fork).accounts().keys().collect();
+    let mut schema = RoleSystemSchema::new(fork);
+    for (username, pub_key) in usernames {
+        schema.account_pk_by_username().put(&username, pub_key);
+    }
+    for (pub_key, name) in account_schemas {
+        schema.account_data_schemas_mut().put(&pub_key, name);
+    }
+    for owner in owners {
+        schema.custom_data_root_update(&owner);
+        schema.account_history_root_update(&owner);
+        schema.account_roles_root_update(&owner);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exonum::blockchain::{ConsensusConfig, StoredConfiguration};
+    use exonum::encoding::serialize::ToHex;
+    use exonum::helpers::Height;
+    use exonum::storage::{Database, MemoryDB};
+    use governance::AdminAction;
+
+    /// Writes version `0` accounts the way the old service stored them.
+    fn legacy_database(accounts: &[AccountV0]) -> MemoryDB {
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        {
+            let mut index: ProofMapIndex<&mut Fork, PublicKey, AccountV0> =
+                ProofMapIndex::new("rolesystem.accounts", &mut fork);
+            for account in accounts {
+                index.put(account.pub_key(), account.clone());
+            }
+        }
+        db.merge(fork.into_patch()).unwrap();
+        db
+    }
+
+    fn run_migration(db: &MemoryDB, from_version: u32) -> ExecutionResult {
+        let mut fork = db.fork();
+        let result = migrate(&mut fork, from_version);
+        db.merge(fork.into_patch()).unwrap();
+        result
+    }
+
+    #[test]
+    fn test_migrate_v0_accounts() {
+        let (alice, _) = crypto::gen_keypair();
+        let (bob, _) = crypto::gen_keypair();
+        let photo = crypto::hash(b"photo");
+        let db = legacy_database(&[
+            AccountV0::new(
+                &alice,
+                "alice",
+                "encrypted",
+                &photo,
+                true,
+                r#"{"phone":"123","age":30}"#,
+            ),
+            AccountV0::new(&bob, "bob", "secret", &Hash::zero(), false, "plain text"),
+        ]);
+
+        assert!(run_migration(&db, 0).is_ok());
+
+        let snapshot = db.snapshot();
+        let schema = RoleSystemSchema::new(&snapshot);
+        assert_eq!(schema.schema_version(), Some(CURRENT_VERSION));
+
+        let account = schema.account(&alice).unwrap();
+        assert_eq!(account.username(), "alice");
+        assert_eq!(account.photo_ipfs(), Cid::new_v0(photo).to_string());
+        assert!(account.is_active());
+        assert!(account.credentials().is_valid());
+        assert_eq!(account.credentials().verifier(), &alice);
+        assert_eq!(account.credentials().wrapped_key_hash(), &crypto::hash(b"encrypted"));
+        let custom_data = schema.custom_data(&alice);
+        assert_eq!(custom_data.get(&"phone".to_owned()), Some(r#""123""#.to_owned()));
+        assert_eq!(custom_data.get(&"age".to_owned()), Some("30".to_owned()));
+
+        let account = schema.account(&bob).unwrap();
+        assert_eq!(account.photo_ipfs(), "");
+        assert!(!account.is_active());
+        assert_eq!(
+            schema.custom_data(&bob).get(&LEGACY_CUSTOM_DATA_KEY.to_owned()),
+            Some(r#""plain text""#.to_owned())
+        );
+
+        assert_eq!(schema.pub_key_by_username("alice"), Some(alice));
+        assert_eq!(schema.pub_key_by_username("bob"), Some(bob));
+        assert_eq!(
+            schema.custom_data_roots().get(&alice),
+            Some(schema.custom_data(&alice).merkle_root())
+        );
+    }
+
+    #[test]
+    fn test_migrate_v1_fills_roots() {
+        let (alice, _) = crypto::gen_keypair();
+        let (bob, _) = crypto::gen_keypair();
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        {
+            // Version `1` kept usernames and declared schemas in plain maps
+            // and wrote the per-account tables without the roots
+            let mut usernames: MapIndex<&mut Fork, String, PublicKey> =
+                MapIndex::new(LEGACY_USERNAMES, &mut fork);
+            usernames.put(&"alice".to_owned(), alice);
+            usernames.put(&"bob".to_owned(), bob);
+        }
+        {
+            let mut account_schemas: MapIndex<&mut Fork, PublicKey, String> =
+                MapIndex::new(LEGACY_ACCOUNT_SCHEMAS, &mut fork);
+            account_schemas.put(&alice, "contacts".to_owned());
+        }
+        {
+            let mut schema = RoleSystemSchema::new(&mut fork);
+            for &(pub_key, username) in &[(alice, "alice"), (bob, "bob")] {
+                let credentials =
+                    Credentials::new(&Hash::zero(), kdf::MIN_LOG_N, 8, 1, &pub_key, &Hash::zero());
+                let account = Account::new(&pub_key, username, credentials, "", true);
+                schema.accounts_mut().put(&pub_key, account);
+            }
+            schema.custom_data_mut(&alice).put(&"phone".to_owned(), r#""123""#.to_owned());
+            schema.account_history_mut(&bob).push(Hash::zero());
+            schema.schema_version_set(1);
+        }
+        db.merge(fork.into_patch()).unwrap();
+        {
+            let snapshot = db.snapshot();
+            let schema = RoleSystemSchema::new(&snapshot);
+            assert_eq!(schema.custom_data_roots().keys().count(), 0);
+            assert_eq!(schema.pub_key_by_username("alice"), None);
+        }
+
+        assert!(run_migration(&db, 1).is_ok());
+
+        let snapshot = db.snapshot();
+        let schema = RoleSystemSchema::new(&snapshot);
+        assert_eq!(schema.schema_version(), Some(CURRENT_VERSION));
+        assert_eq!(
+            schema.custom_data_roots().get(&alice),
+            Some(schema.custom_data(&alice).merkle_root())
+        );
+        assert_eq!(schema.custom_data_roots().get(&bob), None);
+        assert_eq!(
+            schema.account_history_roots().get(&bob),
+            Some(schema.account_history(&bob).merkle_root())
+        );
+        assert_eq!(schema.account_history_roots().get(&alice), None);
+        assert_eq!(schema.pub_key_by_username("alice"), Some(alice));
+        assert_eq!(schema.pub_key_by_username("bob"), Some(bob));
+        let legacy: MapIndex<&Snapshot, String, PublicKey> =
+            MapIndex::new(LEGACY_USERNAMES, snapshot.as_ref());
+        assert_eq!(legacy.keys().count(), 0);
+        assert_eq!(schema.account_data_schemas().get(&alice), Some("contacts".to_owned()));
+    }
+
+    #[test]
+    fn test_migrate_rejects_wrong_version() {
+        let db = legacy_database(&[]);
+        assert!(run_migration(&db, CURRENT_VERSION + 1).is_err());
+
+        assert!(run_migration(&db, 0).is_ok());
+        assert!(run_migration(&db, 0).is_err());
+        assert!(run_migration(&db, CURRENT_VERSION).is_err());
+    }
+
+    #[test]
+    fn test_migrate_current_layout_records_version() {
+        let (alice, _) = crypto::gen_keypair();
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        {
+            let credentials =
+                Credentials::new(&Hash::zero(), kdf::MIN_LOG_N, 8, 1, &alice, &Hash::zero());
+            let mut schema = RoleSystemSchema::new(&mut fork);
+            schema.account_create(&alice, "alice", credentials, "", true);
+        }
+        db.merge(fork.into_patch()).unwrap();
+
+        assert!(run_migration(&db, CURRENT_VERSION).is_ok());
+
+        let snapshot = db.snapshot();
+        let schema = RoleSystemSchema::new(&snapshot);
+        assert_eq!(schema.schema_version(), Some(CURRENT_VERSION));
+        assert_eq!(schema.account(&alice).unwrap().username(), "alice");
+    }
+
+    #[test]
+    fn test_migrate_v0_keeps_current_layout_records() {
+        let (alice, _) = crypto::gen_keypair();
+        let (bob, _) = crypto::gen_keypair();
+        let db = legacy_database(&[
+            AccountV0::new(&bob, "bob", "secret", &Hash::zero(), true, ""),
+        ]);
+        let mut fork = db.fork();
+        {
+            let credentials =
+                Credentials::new(&Hash::zero(), kdf::MIN_LOG_N, 8, 1, &alice, &Hash::zero());
+            let mut schema = RoleSystemSchema::new(&mut fork);
+            schema.account_create(&alice, "alice", credentials, "", true);
+        }
+        db.merge(fork.into_patch()).unwrap();
+        let written = RoleSystemSchema::new(&db.snapshot()).account(&alice).unwrap();
+
+        assert!(run_migration(&db, 0).is_ok());
+
+        let snapshot = db.snapshot();
+        let schema = RoleSystemSchema::new(&snapshot);
+        assert_eq!(schema.account(&alice), Some(written));
+        assert_eq!(schema.account(&bob).unwrap().username(), "bob");
+        assert_eq!(schema.pub_key_by_username("alice"), Some(alice));
+        assert_eq!(schema.pub_key_by_username("bob"), Some(bob));
+    }
+
+    #[test]
+    fn test_migrate_v0_rejects_unknown_layout() {
+        let (alice, _) = crypto::gen_keypair();
+        let db = MemoryDB::new();
+        let mut fork = db.fork();
+        {
+            let mut index: ProofMapIndex<&mut Fork, PublicKey, Vec<u8>> =
+                ProofMapIndex::new("rolesystem.accounts", &mut fork);
+            index.put(&alice, vec![1, 2, 3]);
+        }
+        db.merge(fork.into_patch()).unwrap();
+
+        assert!(run_migration(&db, 0).is_err());
+        assert_eq!(RoleSystemSchema::new(&db.snapshot()).schema_version(), None);
+    }
+
+    /// Commits the first configuration of the blockchain with the given service config.
+    fn commit_configuration(fork: &mut Fork, config: &RoleSystemServiceConfig) {
+        let mut services = BTreeMap::new();
+        services.insert("rolesystem".to_owned(), serde_json::to_value(config).unwrap());
+
+        Schema::new(fork).commit_configuration(StoredConfiguration {
+            previous_cfg_hash: Hash::zero(),
+            actual_from: Height(0),
+            validator_keys: Vec::new(),
+            consensus: ConsensusConfig::default(),
+            services,
+        });
+    }
+
+    #[test]
+    fn test_bootstrap_admins_and_migrate_v0() {
+        let (alice, _) = crypto::gen_keypair();
+        let (admin, _) = crypto::gen_keypair();
+        let db = legacy_database(&[
+            AccountV0::new(&alice, "alice", "encrypted", &Hash::zero(), true, ""),
+        ]);
+
+        let mut fork = db.fork();
+        commit_configuration(
+            &mut fork,
+            &RoleSystemServiceConfig {
+                admin_pkeys: vec![admin.to_hex()],
+                admin_threshold: 1,
+                blockstore_path: None,
+            },
+        );
+        assert!(!RoleSystemSchema::new(&fork).is_admin(&admin));
+
+        bootstrap_admins(&mut fork);
+        {
+            let schema = RoleSystemSchema::new(&fork);
+            assert!(schema.is_admin(&admin));
+            assert_eq!(schema.admin_threshold(), 1);
+            assert_eq!(schema.schema_version(), None);
+        }
+
+        let action = AdminAction::MigrateSchema { from_version: 0 };
+        assert!(action.execute(&mut RoleSystemSchema::new(&mut fork)).is_ok());
+
+        let schema = RoleSystemSchema::new(&fork);
+        assert_eq!(schema.schema_version(), Some(CURRENT_VERSION));
+        assert_eq!(schema.account(&alice).unwrap().username(), "alice");
+    }
+
+    #[test]
+    fn test_bootstrap_admins_skips_versioned_data() {
+        let (admin, _) = crypto::gen_keypair();
+        let db = legacy_database(&[]);
+        assert!(run_migration(&db, 0).is_ok());
+
+        let mut fork = db.fork();
+        commit_configuration(
+            &mut fork,
+            &RoleSystemServiceConfig {
+                admin_pkeys: vec![admin.to_hex()],
+                admin_threshold: 1,
+                blockstore_path: None,
+            },
+        );
+        bootstrap_admins(&mut fork);
+
+        assert!(!RoleSystemSchema::new(&fork).is_admin(&admin));
+    }
+}
@@ -1,8 +1,91 @@
-use exonum::crypto::{Hash, PublicKey};
-use exonum::storage::{Fork, MapIndex, ProofMapIndex, Snapshot, ValueSetIndex};
+use chrono::{DateTime, Utc};
+use exonum::blockchain::ExecutionResult;
+use exonum::crypto::{self, Hash, PublicKey};
+use exonum::storage::{Entry, Fork, KeySetIndex, ProofListIndex, ProofMapIndex, Snapshot};
+use migration;
+use std::cmp;
 
-// Employee ID type alias.
-// pub type EmployeeId = u64;
+/// Permission bits that can be granted to an account through a [`Role`].
+///
+/// [`Role`]: ../struct.Role.html
+pub mod permissions {
+    /// Allows to create accounts on behalf of other users.
+    pub const CREATE_ACCOUNTS: u64 = 1 << 0;
+
+    // Bits 1 and 2 used to allow editing and deleting accounts of other users.
+    // These operations are admin actions now and the bits are not reused.
+
+    /// All of the permissions above.
+    pub const ALL: u64 = CREATE_ACCOUNTS;
+}
+
+/// Bounds of the scrypt parameters accepted in [`Credentials`].
+///
+/// [`Credentials`]: ../struct.Credentials.html
+pub mod kdf {
+    /// Minimal allowed `log2(N)`.
+    pub const MIN_LOG_N: u8 = 14;
+
+    /// Maximal allowed `log2(N)`.
+    pub const MAX_LOG_N: u8 = 22;
+
+    /// Maximal allowed block size `r`.
+    pub const MAX_R: u32 = 16;
+
+    /// Maximal allowed parallelization `p`.
+    pub const MAX_P: u32 = 8;
+}
+
+encoding_struct! {
+    /// Password verifier of the account. The password itself never leaves the client:
+    /// the client derives an Ed25519 seed from the password with scrypt and publishes
+    /// only the public key of the derived keypair. Login is a signature of a server
+    /// nonce with the derived secret key.
+    struct Credentials {
+        /// Random salt of the key derivation.
+        salt: &Hash,
+
+        /// scrypt cost parameter, `log2(N)`.
+        log_n: u8,
+
+        /// scrypt block size.
+        r: u32,
+
+        /// scrypt parallelization.
+        p: u32,
+
+        /// Public key derived from the password.
+        verifier: &PublicKey,
+
+        /// Hash of the account secret key wrapped by the password-derived key.
+        /// The wrapped key itself is kept by the user.
+        wrapped_key_hash: &Hash,
+    }
+}
+
+impl Credentials {
+    /// Checks that the key derivation parameters are in the allowed bounds.
+    pub fn is_valid(&self) -> bool {
+        self.log_n() >= kdf::MIN_LOG_N && self.log_n() <= kdf::MAX_LOG_N
+            && self.r() >= 1 && self.r() <= kdf::MAX_R
+            && self.p() >= 1 && self.p() <= kdf::MAX_P
+    }
+}
+
+/// Bounds of the guardian recovery settings accepted in [`RecoveryConfig`].
+///
+/// [`RecoveryConfig`]: ../struct.RecoveryConfig.html
+pub mod recovery {
+    /// Maximal number of guardians of a single account.
+    pub const MAX_GUARDIANS: usize = 16;
+
+    /// Minimal number of blocks between the initiation and the completion of a recovery,
+    /// so the owner has time to cancel it with the old key.
+    pub const MIN_DELAY: u64 = 10;
+}
+
+/// Maximal lifetime of a session key in seconds.
+pub const MAX_SESSION_DURATION: u64 = 30 * 24 * 60 * 60;
 
 // Declare the data to be stored in the blockchain, namely accounts with data.
 encoding_struct! {
@@ -13,52 +96,239 @@ encoding_struct! {
         /// UTF-8 string with the owner's username.
         username: &str,
 
-        /// password_encrypted_by_secret_key
-        encrypted_password: &str,
+        /// Password verifier.
+        credentials: Credentials,
+
+        /// CID of the user's photo stored in IPFS, empty if there is no photo.
+        photo_ipfs: &str,
+
+        /// Whether the account is allowed to send mutating transactions.
+        is_active: bool,
+    }
+}
+
+encoding_struct! {
+    /// Named set of permissions that can be granted to accounts.
+    struct Role {
+        /// UTF-8 string with the role name.
+        name: &str,
+
+        /// Bitmask of `permissions` granted by the role.
+        permissions: u64,
+    }
+}
+
+encoding_struct! {
+    /// Registered description of custom data entries.
+    struct DataSchema {
+        /// UTF-8 string with the schema name.
+        name: &str,
+
+        /// JSON-serialized [`DataSchemaDefinition`](../custom_data/struct.DataSchemaDefinition.html).
+        definition: &str,
+    }
+}
 
-        /// user's photo stored in IPFS
-        photo_ipfs: &Hash,
+encoding_struct! {
+    /// Administrative action waiting for approvals of the admin set.
+    struct Proposal {
+        /// Public key of the admin that has proposed the action.
+        proposer: &PublicKey,
 
-        is_active: &bool,
+        /// JSON-serialized [`AdminAction`](../governance/enum.AdminAction.html).
+        action: &str,
 
-        /// Custom data.
-        custom_data: &str,
+        /// Height of the last block in which the proposal can be approved.
+        expires_at: u64,
+
+        /// Whether the action has been executed.
+        executed: bool,
+    }
+}
+
+encoding_struct! {
+    /// Key delegated by the account owner that can act for the account
+    /// until the consensus time reaches `expires_at`.
+    struct Session {
+        /// Public key of the session.
+        pub_key: &PublicKey,
+
+        /// Public key of the account owner.
+        account: &PublicKey,
+
+        /// Consensus time of the `exonum-time` oracle at which the session expires.
+        expires_at: DateTime<Utc>,
+    }
+}
+
+encoding_struct! {
+    /// Additional key of the account, e.g. of a laptop or a phone, that can sign
+    /// transactions on behalf of the account.
+    struct Device {
+        /// Public key of the device.
+        pub_key: &PublicKey,
+
+        /// Public key of the account owner.
+        account: &PublicKey,
+
+        /// UTF-8 string with the device name.
+        name: &str,
+
+        /// Height of the block in which the device has been added.
+        added_at: u64,
+    }
+}
+
+encoding_struct! {
+    /// Guardian accounts that can move the account to a new key
+    /// if the owner loses the old one.
+    struct RecoveryConfig {
+        /// Public keys of the guardian accounts.
+        guardians: Vec<PublicKey>,
+
+        /// Number of guardian approvals required to recover the account.
+        threshold: u16,
+
+        /// Number of blocks between the initiation and the completion of a recovery.
+        delay: u64,
+    }
+}
+
+encoding_struct! {
+    /// Pending move of the account to a new key.
+    struct Recovery {
+        /// Public key the account is moved to.
+        new_key: &PublicKey,
+
+        /// Height of the block in which the recovery has been initiated.
+        started_at: u64,
+    }
+}
+
+impl RecoveryConfig {
+    /// Checks that the key belongs to the guardians of the account.
+    pub fn is_guardian(&self, pub_key: &PublicKey) -> bool {
+        self.guardians().contains(pub_key)
+    }
+
+    /// Returns a copy of this config with one guardian key replaced by another.
+    pub fn replace_guardian(self, old: &PublicKey, new: &PublicKey) -> Self {
+        let guardians = self
+            .guardians()
+            .into_iter()
+            .map(|pub_key| if pub_key == *old { *new } else { pub_key })
+            .collect();
+        Self::new(guardians, self.threshold(), self.delay())
+    }
+
+    /// Returns a copy of this config without the guardian. The threshold is lowered
+    /// to the number of the remaining guardians if needed. Returns `None` if
+    /// no guardians remain.
+    pub fn remove_guardian(self, pub_key: &PublicKey) -> Option<Self> {
+        let guardians: Vec<PublicKey> = self
+            .guardians()
+            .into_iter()
+            .filter(|guardian| guardian != pub_key)
+            .collect();
+
+        if guardians.is_empty() {
+            return None;
+        }
+
+        let threshold = cmp::min(self.threshold() as usize, guardians.len()) as u16;
+        Some(Self::new(guardians, threshold, self.delay()))
+    }
+}
+
+impl Session {
+    /// Checks that the session hasn't expired at the given consensus time.
+    pub fn is_active_at(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at() > now
     }
 }
 
 /// Additional methods for managing account in immutable fashion.
 impl Account {
+    /// Returns a copy of this account with the changed info.
+    pub fn set_info(self, username: &str, photo_ipfs: &str) -> Self {
+        Self::new(
+            self.pub_key(),
+            username,
+            self.credentials(),
+            photo_ipfs,
+            self.is_active(),
+        )
+    }
+
     // backlog - Returns a copy of this account with the changed info.
-    pub fn set_photo(self, photo_ipfs: &Hash) -> Self {
+    pub fn set_photo(self, photo_ipfs: &str) -> Self {
         Self::new(
             self.pub_key(),
             self.username(),
-            self.encrypted_password(),
+            self.credentials(),
             photo_ipfs,
             self.is_active(),
-            self.custom_data(),
         )
     }
 
-    /// Returns a copy of this account with changed custom data.
-    pub fn set_custom_data(self, custom_data: &str) -> Self {
+    /// Returns a copy of this account with the changed username.
+    pub fn set_username(self, username: &str) -> Self {
+        Self::new(
+            self.pub_key(),
+            username,
+            self.credentials(),
+            self.photo_ipfs(),
+            self.is_active(),
+        )
+    }
+
+    /// Returns a copy of this account with the changed activity flag.
+    pub fn set_active(self, is_active: bool) -> Self {
         Self::new(
             self.pub_key(),
             self.username(),
-            self.encrypted_password(),
+            self.credentials(),
+            self.photo_ipfs(),
+            is_active,
+        )
+    }
+
+    /// Returns a copy of this account with the changed password verifier.
+    pub fn set_credentials(self, credentials: Credentials) -> Self {
+        Self::new(
+            self.pub_key(),
+            self.username(),
+            credentials,
             self.photo_ipfs(),
             self.is_active(),
-            custom_data,
         )
     }
 }
 
+/// Names of the tables whose root hashes form `RoleSystemSchema::state_hash`, in order.
+pub const STATE_HASH_TABLES: [&str; 14] = [
+    "rolesystem.accounts",
+    "rolesystem.roles",
+    "rolesystem.proposals",
+    "rolesystem.data_schemas",
+    "rolesystem.sessions",
+    "rolesystem.devices",
+    "rolesystem.recovery_configs",
+    "rolesystem.recoveries",
+    "rolesystem.custom_data_roots",
+    "rolesystem.account_history_roots",
+    "rolesystem.usernames",
+    "rolesystem.account_roles_roots",
+    "rolesystem.admins",
+    "rolesystem.account_schemas",
+];
+
 /// Schema of the key-value storage used by the demo employees service.
 pub struct RoleSystemSchema<T> {
     view: T,
 }
 
-/// Declare the layout of data managed by the service. An instance of [`MapIndex`] is used
+/// Declare the layout of data managed by the service. An instance of [`ProofMapIndex`] is used
 /// to keep accounts in the storage. Index values are serialized [`Account`] structs.
 impl<T: AsRef<Snapshot>> RoleSystemSchema<T> {
     /// Creates a new schema instance.
@@ -76,36 +346,288 @@ impl<T: AsRef<Snapshot>> RoleSystemSchema<T> {
         self.accounts().get(pub_key)
     }
 
+    /// Returns hashes of the transactions that have changed the account,
+    /// from the oldest to the newest.
+    pub fn account_history(&self, pub_key: &PublicKey) -> ProofListIndex<&Snapshot, Hash> {
+        ProofListIndex::new_in_family("rolesystem.account_history", pub_key, self.view.as_ref())
+    }
+
+    /// Returns the root hashes of the account histories, collected here to get into
+    /// the state hash.
+    pub fn account_history_roots(&self) -> ProofMapIndex<&Snapshot, PublicKey, Hash> {
+        ProofMapIndex::new("rolesystem.account_history_roots", self.view.as_ref())
+    }
+
+    /// Returns an immutable version of the data schemas table.
+    pub fn data_schemas(&self) -> ProofMapIndex<&Snapshot, String, DataSchema> {
+        ProofMapIndex::new("rolesystem.data_schemas", self.view.as_ref())
+    }
+
+    /// Gets a specific data schema from the storage.
+    pub fn data_schema(&self, name: &str) -> Option<DataSchema> {
+        self.data_schemas().get(&name.to_owned())
+    }
+
+    /// Returns an immutable version of account PK to declared data schema name table.
+    pub fn account_data_schemas(&self) -> ProofMapIndex<&Snapshot, PublicKey, String> {
+        ProofMapIndex::new("rolesystem.account_schemas", self.view.as_ref())
+    }
+
+    /// Gets the data schema declared by the account.
+    pub fn account_data_schema(&self, pub_key: &PublicKey) -> Option<DataSchema> {
+        self.account_data_schemas()
+            .get(pub_key)
+            .and_then(|name| self.data_schema(&name))
+    }
+
+    /// Returns immutable custom data entries of the account. Values are JSON-serialized;
+    /// together the family key and the entry key form a `(PublicKey, String)` key.
+    pub fn custom_data(&self, pub_key: &PublicKey) -> ProofMapIndex<&Snapshot, String, String> {
+        ProofMapIndex::new_in_family("rolesystem.custom_data", pub_key, self.view.as_ref())
+    }
+
+    /// Returns the root hashes of the custom data of the accounts. Custom data is stored
+    /// in per-account tables, so their roots are collected here to get into the state hash.
+    pub fn custom_data_roots(&self) -> ProofMapIndex<&Snapshot, PublicKey, Hash> {
+        ProofMapIndex::new("rolesystem.custom_data_roots", self.view.as_ref())
+    }
+
     /// Method to get state hash.
     pub fn state_hash(&self) -> Vec<Hash> {
-        vec![self.accounts().merkle_root()]
+        vec![
+            self.accounts().merkle_root(),
+            self.roles().merkle_root(),
+            self.proposals().merkle_root(),
+            self.data_schemas().merkle_root(),
+            self.sessions().merkle_root(),
+            self.devices().merkle_root(),
+            self.recovery_configs().merkle_root(),
+            self.recoveries().merkle_root(),
+            self.custom_data_roots().merkle_root(),
+            self.account_history_roots().merkle_root(),
+            self.accounts_by_username().merkle_root(),
+            self.account_roles_roots().merkle_root(),
+            self.admins_hash(),
+            self.account_data_schemas().merkle_root(),
+        ]
+    }
+
+    /// Returns the state hash together with the names of the tables, in the same order.
+    pub fn state_hash_components(&self) -> Vec<(&'static str, Hash)> {
+        STATE_HASH_TABLES
+            .iter()
+            .cloned()
+            .zip(self.state_hash())
+            .collect()
     }
 
-    // pub fn is_activated(&self, pub_key: &PublicKey) -> bool {
-    //     self.superuser_pk().contains(&pub_key.to_string())
-    // }
+    /// Returns the number of entries in the service tables, except the per-account families.
+    pub fn index_sizes(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            ("rolesystem.accounts", self.accounts().keys().count() as u64),
+            ("rolesystem.usernames", self.accounts_by_username().keys().count() as u64),
+            ("rolesystem.roles", self.roles().keys().count() as u64),
+            ("rolesystem.admins", self.admins().iter().count() as u64),
+            ("rolesystem.proposals", self.proposals().keys().count() as u64),
+            ("rolesystem.data_schemas", self.data_schemas().keys().count() as u64),
+            ("rolesystem.account_schemas", self.account_data_schemas().keys().count() as u64),
+            ("rolesystem.sessions", self.sessions().keys().count() as u64),
+            ("rolesystem.devices", self.devices().keys().count() as u64),
+            ("rolesystem.recovery_configs", self.recovery_configs().keys().count() as u64),
+            ("rolesystem.recoveries", self.recoveries().keys().count() as u64),
+            ("rolesystem.custom_data_roots", self.custom_data_roots().keys().count() as u64),
+            ("rolesystem.account_history_roots", self.account_history_roots().keys().count() as u64),
+            ("rolesystem.account_roles_roots", self.account_roles_roots().keys().count() as u64),
+        ]
+    }
 
-    // backlog - Returns an immutable version of employee ID to account PK table.
-    // fn employee_id_to_account_pk(&self) -> MapIndex<&Snapshot, EmployeeId, PublicKey> {
-    //     MapIndex::new("employees.accounts_by_id", self.view.as_ref())
-    // }
+    /// Returns an immutable version of the guardian settings table.
+    pub fn recovery_configs(&self) -> ProofMapIndex<&Snapshot, PublicKey, RecoveryConfig> {
+        ProofMapIndex::new("rolesystem.recovery_configs", self.view.as_ref())
+    }
 
-    // TODO:  Searches an account by given username
-    // pub fn pub_key_by_username(&self, username: &str) -> Option<Account> {
-    //     self.employee_id_to_account_pk()
-    //         .get(id)
-    //         .map_or(None, |pk| self.accounts().get(&pk))
-    // }
+    /// Gets guardian settings of the account.
+    pub fn recovery_config(&self, pub_key: &PublicKey) -> Option<RecoveryConfig> {
+        self.recovery_configs().get(pub_key)
+    }
 
-    /// Returns an immutable version of a superuser public keys set.
-    // pub fn superuser_pk(&self) -> ValueSetIndex<&Snapshot, String> {
-    //     ValueSetIndex::new("employees.superuser_pks", self.view.as_ref())
-    // }
+    /// Returns an immutable set of accounts that have nominated the key as a guardian.
+    pub fn guarded_accounts(&self, pub_key: &PublicKey) -> KeySetIndex<&Snapshot, PublicKey> {
+        KeySetIndex::new_in_family("rolesystem.guarded_accounts", pub_key, self.view.as_ref())
+    }
+
+    /// Returns an immutable version of the pending recoveries table keyed by the account.
+    pub fn recoveries(&self) -> ProofMapIndex<&Snapshot, PublicKey, Recovery> {
+        ProofMapIndex::new("rolesystem.recoveries", self.view.as_ref())
+    }
+
+    /// Gets a pending recovery of the account.
+    pub fn recovery(&self, pub_key: &PublicKey) -> Option<Recovery> {
+        self.recoveries().get(pub_key)
+    }
+
+    /// Returns an immutable set of guardians that have approved the pending recovery.
+    pub fn recovery_approvals(&self, pub_key: &PublicKey) -> KeySetIndex<&Snapshot, PublicKey> {
+        KeySetIndex::new_in_family("rolesystem.recovery_approvals", pub_key, self.view.as_ref())
+    }
+
+    /// Returns an immutable version of the devices table keyed by the device key.
+    pub fn devices(&self) -> ProofMapIndex<&Snapshot, PublicKey, Device> {
+        ProofMapIndex::new("rolesystem.devices", self.view.as_ref())
+    }
+
+    /// Gets a specific device from the storage.
+    pub fn device(&self, pub_key: &PublicKey) -> Option<Device> {
+        self.devices().get(pub_key)
+    }
+
+    /// Returns an immutable set of device keys of the account.
+    pub fn account_devices(&self, pub_key: &PublicKey) -> KeySetIndex<&Snapshot, PublicKey> {
+        KeySetIndex::new_in_family("rolesystem.account_devices", pub_key, self.view.as_ref())
+    }
+
+    /// Returns the account the key belongs to: the account itself for a master key
+    /// or the owner of the device for a device key.
+    pub fn key_owner(&self, pub_key: &PublicKey) -> PublicKey {
+        self.device(pub_key).map_or(*pub_key, |device| *device.account())
+    }
+
+    /// Returns an immutable version of the sessions table keyed by the session key.
+    pub fn sessions(&self) -> ProofMapIndex<&Snapshot, PublicKey, Session> {
+        ProofMapIndex::new("rolesystem.sessions", self.view.as_ref())
+    }
+
+    /// Gets a specific session from the storage.
+    pub fn session(&self, pub_key: &PublicKey) -> Option<Session> {
+        self.sessions().get(pub_key)
+    }
+
+    /// Returns an immutable set of session keys opened by the account.
+    pub fn account_sessions(&self, pub_key: &PublicKey) -> KeySetIndex<&Snapshot, PublicKey> {
+        KeySetIndex::new_in_family("rolesystem.account_sessions", pub_key, self.view.as_ref())
+    }
+
+    /// Returns the account on behalf of which the session key can act
+    /// at the given consensus time.
+    pub fn session_account(&self, pub_key: &PublicKey, now: DateTime<Utc>) -> Option<PublicKey> {
+        self.session(pub_key)
+            .filter(|session| session.is_active_at(now))
+            .map(|session| *session.account())
+    }
+
+    /// Returns an immutable version of the roles table.
+    pub fn roles(&self) -> ProofMapIndex<&Snapshot, String, Role> {
+        ProofMapIndex::new("rolesystem.roles", self.view.as_ref())
+    }
+
+    /// Gets a specific role from the storage.
+    pub fn role(&self, name: &str) -> Option<Role> {
+        self.roles().get(&name.to_owned())
+    }
+
+    /// Returns an immutable set of role names granted to the account.
+    pub fn account_roles(&self, pub_key: &PublicKey) -> KeySetIndex<&Snapshot, String> {
+        KeySetIndex::new_in_family("rolesystem.account_roles", pub_key, self.view.as_ref())
+    }
+
+    /// Returns the hashes of the role sets granted to the accounts. Role sets aren't
+    /// Merkelized, so each value is the hash of the hashes of the role names in the set order.
+    pub fn account_roles_roots(&self) -> ProofMapIndex<&Snapshot, PublicKey, Hash> {
+        ProofMapIndex::new("rolesystem.account_roles_roots", self.view.as_ref())
+    }
+
+    /// Returns the union of permissions of all roles granted to the account.
+    pub fn account_permissions(&self, pub_key: &PublicKey) -> u64 {
+        self.account_roles(pub_key)
+            .iter()
+            .filter_map(|name| self.role(&name))
+            .fold(0, |acc, role| acc | role.permissions())
+    }
+
+    /// Checks that the key is granted all of the given permissions. Device keys
+    /// have the permissions of their account; deactivated accounts have no permissions.
+    pub fn has_permission(&self, pub_key: &PublicKey, permissions: u64) -> bool {
+        let owner = self.key_owner(pub_key);
+
+        if self.account(&owner).map_or(false, |account| !account.is_active()) {
+            return false;
+        }
+
+        self.account_permissions(&owner) & permissions == permissions
+    }
+
+    /// Checks that an account with the given public key exists and is active.
+    pub fn is_activated(&self, pub_key: &PublicKey) -> bool {
+        self.account(pub_key).map_or(false, |account| account.is_active())
+    }
+
+    /// Returns an immutable set of admin public keys.
+    pub fn admins(&self) -> KeySetIndex<&Snapshot, PublicKey> {
+        KeySetIndex::new("rolesystem.admins", self.view.as_ref())
+    }
 
-    // Checks that specific public key is belongs to a superuser set.
-    // pub fn is_superuser(&self, pub_key: &PublicKey) -> bool {
-    //     self.superuser_pk().contains(&pub_key.to_string())
-    // }
+    /// Checks that the key belongs to the admin set.
+    pub fn is_admin(&self, pub_key: &PublicKey) -> bool {
+        self.admins().contains(pub_key)
+    }
+
+    /// Returns the number of admin approvals required to execute a proposal.
+    pub fn admin_threshold(&self) -> u16 {
+        Entry::new("rolesystem.admin_threshold", self.view.as_ref())
+            .get()
+            .unwrap_or(1)
+    }
+
+    /// Returns the hash of the admin keys in the set order followed by the threshold.
+    /// The admin set is small, so it is hashed as a whole instead of keeping a proof table.
+    pub fn admins_hash(&self) -> Hash {
+        let mut bytes: Vec<u8> = self
+            .admins()
+            .iter()
+            .flat_map(|pub_key| pub_key.as_ref().to_vec())
+            .collect();
+        let threshold = self.admin_threshold();
+        bytes.extend((0..2).map(|i| (threshold >> (8 * i)) as u8));
+        crypto::hash(&bytes)
+    }
+
+    /// Returns an immutable version of the proposals table.
+    /// Proposals are keyed by the hash of the proposing transaction.
+    pub fn proposals(&self) -> ProofMapIndex<&Snapshot, Hash, Proposal> {
+        ProofMapIndex::new("rolesystem.proposals", self.view.as_ref())
+    }
+
+    /// Gets a specific proposal from the storage.
+    pub fn proposal(&self, hash: &Hash) -> Option<Proposal> {
+        self.proposals().get(hash)
+    }
+
+    /// Returns an immutable set of admins that have approved the proposal.
+    pub fn proposal_approvals(&self, hash: &Hash) -> KeySetIndex<&Snapshot, PublicKey> {
+        KeySetIndex::new_in_family("rolesystem.proposal_approvals", hash, self.view.as_ref())
+    }
+
+    /// Returns an immutable version of username to account PK table.
+    pub fn accounts_by_username(&self) -> ProofMapIndex<&Snapshot, String, PublicKey> {
+        ProofMapIndex::new("rolesystem.usernames", self.view.as_ref())
+    }
+
+    /// Returns a public key of the account registered under the given username.
+    pub fn pub_key_by_username(&self, username: &str) -> Option<PublicKey> {
+        self.accounts_by_username().get(&username.to_owned())
+    }
+
+    /// Searches an account by given username.
+    pub fn account_by_username(&self, username: &str) -> Option<Account> {
+        self.pub_key_by_username(username)
+            .map_or(None, |pk| self.accounts().get(&pk))
+    }
+
+    /// Returns the layout version of the stored data, `None` if the data was written
+    /// before the versions were introduced.
+    pub fn schema_version(&self) -> Option<u32> {
+        Entry::new("rolesystem.schema_version", self.view.as_ref()).get()
+    }
 }
 
 /// A mutable version of the schema with an additional method to persist accounts
@@ -116,66 +638,584 @@ impl<'a> RoleSystemSchema<&'a mut Fork> {
         ProofMapIndex::new("rolesystem.accounts", &mut self.view)
     }
 
+    /// Returns a mutable version of the account history. Changes made through the list
+    /// must be followed by [`account_history_root_update`](#method.account_history_root_update).
+    pub fn account_history_mut(&mut self, pub_key: &PublicKey) -> ProofListIndex<&mut Fork, Hash> {
+        ProofListIndex::new_in_family("rolesystem.account_history", pub_key, &mut self.view)
+    }
+
+    /// Returns a mutable version of the account history roots table.
+    pub fn account_history_roots_mut(&mut self) -> ProofMapIndex<&mut Fork, PublicKey, Hash> {
+        ProofMapIndex::new("rolesystem.account_history_roots", &mut self.view)
+    }
+
+    /// Stores the root hash of the account history, or removes it if the history is empty.
+    pub fn account_history_root_update(&mut self, pub_key: &PublicKey) {
+        let root = {
+            let history = self.account_history(pub_key);
+            if history.is_empty() {
+                None
+            } else {
+                Some(history.merkle_root())
+            }
+        };
+        match root {
+            Some(root) => self.account_history_roots_mut().put(pub_key, root),
+            None => self.account_history_roots_mut().remove(pub_key),
+        }
+    }
+
+    /// Appends the transaction to the account history.
+    pub fn account_history_push(&mut self, pub_key: &PublicKey, tx_hash: &Hash) {
+        self.account_history_mut(pub_key).push(*tx_hash);
+        self.account_history_root_update(pub_key);
+    }
+
     /// Creates a new account with specified credentials.
     pub fn account_create(
         &mut self,
         pub_key: &PublicKey,
         username: &str,
-        encrypted_password: &str,
-        photo_ipfs: &Hash,
-        is_active: &bool,
-        custom_data: &str,
+        credentials: Credentials,
+        photo_ipfs: &str,
+        is_active: bool,
     ) {
-        let account = Account::new(pub_key, username, encrypted_password, photo_ipfs, is_active,custom_data,);
+        let account = Account::new(pub_key, username, credentials, photo_ipfs, is_active);
 
-        // Save account and ID into the DB
+        // Save account and username into the DB
         self.accounts_mut().put(&pub_key, account);
-        // self.account_pk_by_employee_id().put(&id_number, *pub_key);
+        self.account_pk_by_username().put(&username.to_owned(), *pub_key);
     }
 
-    /// Changes account basic information.
+    /// Changes account basic information. Moves the username index entry
+    /// if the username has changed.
     pub fn account_edit(
         &mut self,
+        account: Account,
         pub_key: &PublicKey,
         username: &str,
-        encrypted_password: &str,
-        photo_ipfs: &Hash,
-        is_active: &bool,
-        custom_data: &str,
+        photo_ipfs: &str,
     ) {
-        let account = account.set_info(username, encrypted_password, photo_ipfs, is_active, custom_data);
+        if account.username() != username {
+            self.account_pk_by_username().remove(&account.username().to_owned());
+            self.account_pk_by_username().put(&username.to_owned(), *pub_key);
+        }
+
+        let account = account.set_info(username, photo_ipfs);
+
+        self.accounts_mut().put(pub_key, account);
+    }
+
+    /// Moves the account to a new username. The old username is released
+    /// and the index entry is rewritten within the same fork.
+    pub fn account_change_username(&mut self, account: Account, username: &str) {
+        let pub_key = *account.pub_key();
+        let old_username = account.username().to_owned();
+        let account = account.set_username(username);
+
+        self.account_pk_by_username().remove(&old_username);
+        self.account_pk_by_username().put(&username.to_owned(), pub_key);
+        self.accounts_mut().put(&pub_key, account);
+    }
 
+    /// Replaces the password verifier of the account.
+    pub fn account_set_credentials(&mut self, account: Account, credentials: Credentials) {
+        let pub_key = *account.pub_key();
+        let account = account.set_credentials(credentials);
+        self.accounts_mut().put(&pub_key, account);
+    }
+
+    /// Deactivates or reactivates the account.
+    pub fn account_set_active(&mut self, account: Account, pub_key: &PublicKey, is_active: bool) {
+        let account = account.set_active(is_active);
         self.accounts_mut().put(pub_key, account);
     }
 
-    /// Changes account basic information.
-    pub fn account_set_custom_data(
+    /// Removes the account, releases its username and drops its history, roles, devices,
+    /// sessions, guardians and custom data, so an account created later with the same key
+    /// starts from scratch. The account stops being a guardian of other accounts.
+    pub fn account_delete(&mut self, account: &Account) {
+        self.account_keys_clear(account.pub_key());
+        self.recovery_cancel(account.pub_key());
+        self.guardians_clear(account.pub_key());
+        self.guardian_remove(account.pub_key());
+
+        self.custom_data_clear(account.pub_key());
+        self.account_data_schemas_mut().remove(account.pub_key());
+        self.account_pk_by_username().remove(&account.username().to_owned());
+        self.account_roles_mut(account.pub_key()).clear();
+        self.account_roles_root_update(account.pub_key());
+        self.account_history_mut(account.pub_key()).clear();
+        self.account_history_root_update(account.pub_key());
+        self.accounts_mut().remove(account.pub_key());
+    }
+
+    /// Removes all devices and sessions of the account.
+    pub fn account_keys_clear(&mut self, pub_key: &PublicKey) {
+        let devices: Vec<PublicKey> = self.account_devices(pub_key).iter().collect();
+        for device in devices {
+            self.devices_mut().remove(&device);
+        }
+        self.account_devices_mut(pub_key).clear();
+
+        let sessions: Vec<PublicKey> = self.account_sessions(pub_key).iter().collect();
+        for session in sessions {
+            self.sessions_mut().remove(&session);
+        }
+        self.account_sessions_mut(pub_key).clear();
+    }
+
+    /// Moves the account to a new key with new credentials. The history, roles,
+    /// custom data, guardians and the username index entry follow the account.
+    /// Devices and sessions are dropped since they could be lost together with the old key.
+    /// Guardian settings of other accounts are updated to the new key. The admin set
+    /// isn't touched, so accounts of admins must not be moved.
+    pub fn account_move(&mut self, account: Account, new_key: &PublicKey, credentials: Credentials) {
+        let old_key = *account.pub_key();
+
+        self.recovery_cancel(&old_key);
+        self.account_keys_clear(&old_key);
+
+        let history: Vec<Hash> = self.account_history(&old_key).iter().collect();
+        self.account_history_mut(&old_key).clear();
+        self.account_history_mut(new_key).extend(history);
+        self.account_history_root_update(&old_key);
+        self.account_history_root_update(new_key);
+
+        let roles: Vec<String> = self.account_roles(&old_key).iter().collect();
+        self.account_roles_mut(&old_key).clear();
+        for role in roles {
+            self.account_roles_mut(new_key).insert(role);
+        }
+        self.account_roles_root_update(&old_key);
+        self.account_roles_root_update(new_key);
+
+        let entries: Vec<(String, String)> = self.custom_data(&old_key).iter().collect();
+        self.custom_data_clear(&old_key);
+        for (key, value) in entries {
+            self.custom_data_put(new_key, &key, value);
+        }
+
+        if let Some(name) = self.account_data_schemas().get(&old_key) {
+            self.account_data_schemas_mut().remove(&old_key);
+            self.account_data_schemas_mut().put(new_key, name);
+        }
+
+        if let Some(config) = self.recovery_config(&old_key) {
+            self.guardians_clear(&old_key);
+            self.guardians_set(new_key, config);
+        }
+
+        let guarded: Vec<PublicKey> = self.guarded_accounts(&old_key).iter().collect();
+        self.guarded_accounts_mut(&old_key).clear();
+        for pub_key in guarded {
+            if let Some(config) = self.recovery_config(&pub_key) {
+                let config = config.replace_guardian(&old_key, new_key);
+                self.recovery_configs_mut().put(&pub_key, config);
+            }
+            if self.recovery_approvals(&pub_key).contains(&old_key) {
+                self.recovery_approvals_mut(&pub_key).remove(&old_key);
+                self.recovery_approvals_mut(&pub_key).insert(*new_key);
+            }
+            self.guarded_accounts_mut(new_key).insert(pub_key);
+        }
+
+        self.account_pk_by_username().put(&account.username().to_owned(), *new_key);
+
+        let moved = Account::new(
+            new_key,
+            account.username(),
+            credentials,
+            account.photo_ipfs(),
+            account.is_active(),
+        );
+        self.accounts_mut().remove(&old_key);
+        self.accounts_mut().put(new_key, moved);
+    }
+
+    /// Returns a mutable version of username to account PK table.
+    pub fn account_pk_by_username(&mut self) -> ProofMapIndex<&mut Fork, String, PublicKey> {
+        ProofMapIndex::new("rolesystem.usernames", &mut self.view)
+    }
+
+    /// Records the layout version of the stored data.
+    pub fn schema_version_set(&mut self, version: u32) {
+        Entry::new("rolesystem.schema_version", &mut self.view).set(version);
+    }
+
+    /// Rewrites the stored data from the given layout version into the current one.
+    pub fn migrate(&mut self, from_version: u32) -> ExecutionResult {
+        migration::migrate(&mut *self.view, from_version)
+    }
+
+    /// Returns a mutable version of the roles table.
+    pub fn roles_mut(&mut self) -> ProofMapIndex<&mut Fork, String, Role> {
+        ProofMapIndex::new("rolesystem.roles", &mut self.view)
+    }
+
+    /// Returns a mutable set of role names granted to the account. Changes made through
+    /// the set must be followed by [`account_roles_root_update`](#method.account_roles_root_update).
+    pub fn account_roles_mut(&mut self, pub_key: &PublicKey) -> KeySetIndex<&mut Fork, String> {
+        KeySetIndex::new_in_family("rolesystem.account_roles", pub_key, &mut self.view)
+    }
+
+    /// Creates a role or replaces permissions of an existing one.
+    pub fn role_set(&mut self, name: &str, permissions: u64) {
+        let role = Role::new(name, permissions);
+        self.roles_mut().put(&name.to_owned(), role);
+    }
+
+    /// Returns a mutable version of the role sets hashes table.
+    pub fn account_roles_roots_mut(&mut self) -> ProofMapIndex<&mut Fork, PublicKey, Hash> {
+        ProofMapIndex::new("rolesystem.account_roles_roots", &mut self.view)
+    }
+
+    /// Stores the hash of the roles granted to the account, or removes it
+    /// if the account has no roles.
+    pub fn account_roles_root_update(&mut self, pub_key: &PublicKey) {
+        let hashes: Vec<u8> = self
+            .account_roles(pub_key)
+            .iter()
+            .flat_map(|name| crypto::hash(name.as_bytes()).as_ref().to_vec())
+            .collect();
+        if hashes.is_empty() {
+            self.account_roles_roots_mut().remove(pub_key);
+        } else {
+            self.account_roles_roots_mut().put(pub_key, crypto::hash(&hashes));
+        }
+    }
+
+    /// Grants the role to the account.
+    pub fn role_grant(&mut self, pub_key: &PublicKey, name: &str) {
+        self.account_roles_mut(pub_key).insert(name.to_owned());
+        self.account_roles_root_update(pub_key);
+    }
+
+    /// Revokes the role from the account.
+    pub fn role_revoke(&mut self, pub_key: &PublicKey, name: &str) {
+        self.account_roles_mut(pub_key).remove(name);
+        self.account_roles_root_update(pub_key);
+    }
+
+    /// Replaces the admin set and the approval threshold.
+    pub fn admins_set(&mut self, admins: &[PublicKey], threshold: u16) {
+        {
+            let mut index: KeySetIndex<&mut Fork, PublicKey> =
+                KeySetIndex::new("rolesystem.admins", &mut self.view);
+            index.clear();
+            for pub_key in admins {
+                index.insert(*pub_key);
+            }
+        }
+
+        Entry::new("rolesystem.admin_threshold", &mut self.view).set(threshold);
+    }
+
+    /// Returns a mutable version of the data schemas table.
+    pub fn data_schemas_mut(&mut self) -> ProofMapIndex<&mut Fork, String, DataSchema> {
+        ProofMapIndex::new("rolesystem.data_schemas", &mut self.view)
+    }
+
+    /// Registers a new data schema.
+    pub fn data_schema_register(&mut self, name: &str, definition: &str) {
+        let schema = DataSchema::new(name, definition);
+        self.data_schemas_mut().put(&name.to_owned(), schema);
+    }
+
+    /// Returns a mutable version of account PK to declared data schema name table.
+    pub fn account_data_schemas_mut(&mut self) -> ProofMapIndex<&mut Fork, PublicKey, String> {
+        ProofMapIndex::new("rolesystem.account_schemas", &mut self.view)
+    }
+
+    /// Returns mutable custom data entries of the account. Changes made through the table
+    /// must be followed by [`custom_data_root_update`](#method.custom_data_root_update).
+    pub fn custom_data_mut(&mut self, pub_key: &PublicKey) -> ProofMapIndex<&mut Fork, String, String> {
+        ProofMapIndex::new_in_family("rolesystem.custom_data", pub_key, &mut self.view)
+    }
+
+    /// Returns a mutable version of the custom data roots table.
+    pub fn custom_data_roots_mut(&mut self) -> ProofMapIndex<&mut Fork, PublicKey, Hash> {
+        ProofMapIndex::new("rolesystem.custom_data_roots", &mut self.view)
+    }
+
+    /// Stores the root hash of the custom data of the account, or removes it
+    /// if the account has no custom data.
+    pub fn custom_data_root_update(&mut self, pub_key: &PublicKey) {
+        let root = {
+            let entries = self.custom_data(pub_key);
+            entries.keys().next().map(|_| entries.merkle_root())
+        };
+        match root {
+            Some(root) => self.custom_data_roots_mut().put(pub_key, root),
+            None => self.custom_data_roots_mut().remove(pub_key),
+        }
+    }
+
+    /// Sets a custom data entry of the account.
+    pub fn custom_data_put(&mut self, pub_key: &PublicKey, key: &str, value: String) {
+        self.custom_data_mut(pub_key).put(&key.to_owned(), value);
+        self.custom_data_root_update(pub_key);
+    }
+
+    /// Removes a custom data entry of the account.
+    pub fn custom_data_remove(&mut self, pub_key: &PublicKey, key: &str) {
+        self.custom_data_mut(pub_key).remove(&key.to_owned());
+        self.custom_data_root_update(pub_key);
+    }
+
+    /// Removes all custom data of the account.
+    pub fn custom_data_clear(&mut self, pub_key: &PublicKey) {
+        self.custom_data_mut(pub_key).clear();
+        self.custom_data_root_update(pub_key);
+    }
+
+    /// Returns a mutable version of the guardian settings table.
+    pub fn recovery_configs_mut(&mut self) -> ProofMapIndex<&mut Fork, PublicKey, RecoveryConfig> {
+        ProofMapIndex::new("rolesystem.recovery_configs", &mut self.view)
+    }
+
+    /// Returns a mutable set of accounts that have nominated the key as a guardian.
+    pub fn guarded_accounts_mut(&mut self, pub_key: &PublicKey) -> KeySetIndex<&mut Fork, PublicKey> {
+        KeySetIndex::new_in_family("rolesystem.guarded_accounts", pub_key, &mut self.view)
+    }
+
+    /// Replaces guardian settings of the account.
+    pub fn guardians_set(&mut self, pub_key: &PublicKey, config: RecoveryConfig) {
+        self.guardians_clear(pub_key);
+
+        for guardian in config.guardians() {
+            self.guarded_accounts_mut(&guardian).insert(*pub_key);
+        }
+        self.recovery_configs_mut().put(pub_key, config);
+    }
+
+    /// Removes guardian settings of the account.
+    pub fn guardians_clear(&mut self, pub_key: &PublicKey) {
+        if let Some(config) = self.recovery_config(pub_key) {
+            for guardian in config.guardians() {
+                self.guarded_accounts_mut(&guardian).remove(pub_key);
+            }
+            self.recovery_configs_mut().remove(pub_key);
+        }
+    }
+
+    /// Removes the key from the guardians of other accounts and from the approvals
+    /// of their pending recoveries. Accounts left without guardians lose their
+    /// guardian settings and pending recoveries.
+    pub fn guardian_remove(&mut self, guardian: &PublicKey) {
+        let guarded: Vec<PublicKey> = self.guarded_accounts(guardian).iter().collect();
+        self.guarded_accounts_mut(guardian).clear();
+
+        for pub_key in guarded {
+            self.recovery_approvals_mut(&pub_key).remove(guardian);
+
+            let config = self
+                .recovery_config(&pub_key)
+                .and_then(|config| config.remove_guardian(guardian));
+            match config {
+                Some(config) => self.recovery_configs_mut().put(&pub_key, config),
+                None => {
+                    self.recovery_cancel(&pub_key);
+                    self.recovery_configs_mut().remove(&pub_key);
+                }
+            }
+        }
+    }
+
+    /// Returns a mutable version of the pending recoveries table.
+    pub fn recoveries_mut(&mut self) -> ProofMapIndex<&mut Fork, PublicKey, Recovery> {
+        ProofMapIndex::new("rolesystem.recoveries", &mut self.view)
+    }
+
+    /// Returns a mutable set of guardians that have approved the pending recovery.
+    pub fn recovery_approvals_mut(&mut self, pub_key: &PublicKey) -> KeySetIndex<&mut Fork, PublicKey> {
+        KeySetIndex::new_in_family("rolesystem.recovery_approvals", pub_key, &mut self.view)
+    }
+
+    /// Starts a recovery of the account. The initiating guardian's approval is recorded as well.
+    pub fn recovery_initiate(
         &mut self,
-        account: Account,
         pub_key: &PublicKey,
-        custom_data: &str,
+        new_key: &PublicKey,
+        guardian: &PublicKey,
+        height: u64,
     ) {
-        let account = account.set_custom_data(custom_data);
-        self.accounts_mut().put(pub_key, account);
+        let recovery = Recovery::new(new_key, height);
+        self.recoveries_mut().put(pub_key, recovery);
+        self.recovery_approvals_mut(pub_key).insert(*guardian);
+    }
+
+    /// Records an approval of the pending recovery by the guardian.
+    pub fn recovery_approve(&mut self, pub_key: &PublicKey, guardian: &PublicKey) {
+        self.recovery_approvals_mut(pub_key).insert(*guardian);
+    }
+
+    /// Drops the pending recovery of the account, if any.
+    pub fn recovery_cancel(&mut self, pub_key: &PublicKey) {
+        self.recoveries_mut().remove(pub_key);
+        self.recovery_approvals_mut(pub_key).clear();
+    }
+
+    /// Returns a mutable version of the devices table.
+    pub fn devices_mut(&mut self) -> ProofMapIndex<&mut Fork, PublicKey, Device> {
+        ProofMapIndex::new("rolesystem.devices", &mut self.view)
+    }
+
+    /// Returns a mutable set of device keys of the account.
+    pub fn account_devices_mut(&mut self, pub_key: &PublicKey) -> KeySetIndex<&mut Fork, PublicKey> {
+        KeySetIndex::new_in_family("rolesystem.account_devices", pub_key, &mut self.view)
+    }
+
+    /// Adds a device key to the account.
+    pub fn device_add(&mut self, account: &PublicKey, pub_key: &PublicKey, name: &str, height: u64) {
+        let device = Device::new(pub_key, account, name, height);
+        self.devices_mut().put(pub_key, device);
+        self.account_devices_mut(account).insert(*pub_key);
+    }
+
+    /// Removes the device key from its account.
+    pub fn device_remove(&mut self, device: &Device) {
+        self.account_devices_mut(device.account()).remove(device.pub_key());
+        self.devices_mut().remove(device.pub_key());
+    }
+
+    /// Returns a mutable version of the sessions table.
+    pub fn sessions_mut(&mut self) -> ProofMapIndex<&mut Fork, PublicKey, Session> {
+        ProofMapIndex::new("rolesystem.sessions", &mut self.view)
+    }
+
+    /// Returns a mutable set of session keys opened by the account.
+    pub fn account_sessions_mut(&mut self, pub_key: &PublicKey) -> KeySetIndex<&mut Fork, PublicKey> {
+        KeySetIndex::new_in_family("rolesystem.account_sessions", pub_key, &mut self.view)
+    }
+
+    /// Opens a session of the account. An expired session with the same key
+    /// is replaced, even if it was opened by another account.
+    pub fn session_open(&mut self, account: &PublicKey, pub_key: &PublicKey, expires_at: DateTime<Utc>) {
+        if let Some(old) = self.session(pub_key) {
+            self.account_sessions_mut(old.account()).remove(pub_key);
+        }
+
+        let session = Session::new(pub_key, account, expires_at);
+        self.sessions_mut().put(pub_key, session);
+        self.account_sessions_mut(account).insert(*pub_key);
+    }
+
+    /// Closes the session before its expiry.
+    pub fn session_close(&mut self, session: &Session) {
+        self.account_sessions_mut(session.account()).remove(session.pub_key());
+        self.sessions_mut().remove(session.pub_key());
+    }
+
+    /// Returns a mutable version of the proposals table.
+    pub fn proposals_mut(&mut self) -> ProofMapIndex<&mut Fork, Hash, Proposal> {
+        ProofMapIndex::new("rolesystem.proposals", &mut self.view)
+    }
+
+    /// Returns a mutable set of admins that have approved the proposal.
+    pub fn proposal_approvals_mut(&mut self, hash: &Hash) -> KeySetIndex<&mut Fork, PublicKey> {
+        KeySetIndex::new_in_family("rolesystem.proposal_approvals", hash, &mut self.view)
     }
 
-    // Changes account basic information.
-    // pub fn account_delete(&mut self, id: &EmployeeId, pub_key: &PublicKey) {
-    //     self.account_pk_by_employee_id().remove(id);
-    //     self.accounts_mut().remove(pub_key);
-    // }
+    /// Stores a new proposal. The proposer's approval is recorded as well.
+    pub fn proposal_create(
+        &mut self,
+        hash: &Hash,
+        proposer: &PublicKey,
+        action: &str,
+        expires_at: u64,
+    ) -> Proposal {
+        let proposal = Proposal::new(proposer, action, expires_at, false);
+
+        self.proposals_mut().put(hash, proposal.clone());
+        self.proposal_approvals_mut(hash).insert(*proposer);
+
+        proposal
+    }
+
+    /// Records an approval of the proposal by the admin.
+    pub fn proposal_approve(&mut self, hash: &Hash, pub_key: &PublicKey) {
+        self.proposal_approvals_mut(hash).insert(*pub_key);
+    }
+
+    /// Marks the proposal as executed so it can't be approved again.
+    pub fn proposal_mark_executed(&mut self, hash: &Hash, proposal: Proposal) {
+        let proposal = Proposal::new(
+            proposal.proposer(),
+            proposal.action(),
+            proposal.expires_at(),
+            true,
+        );
+        self.proposals_mut().put(hash, proposal);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exonum::storage::{Database, MemoryDB};
 
-    // Adds a new superuser public key.
-    // pub fn add_superuser_key(&mut self, key: &str) {
-    //     self.superusers_pk_mut().insert(key.to_string());
-    // }
+    fn with_account(db: &MemoryDB, pub_key: &PublicKey, username: &str) {
+        let mut fork = db.fork();
+        {
+            let credentials =
+                Credentials::new(&Hash::zero(), kdf::MIN_LOG_N, 8, 1, pub_key, &Hash::zero());
+            RoleSystemSchema::new(&mut fork).account_create(pub_key, username, credentials, "", true);
+        }
+        db.merge(fork.into_patch()).unwrap();
+    }
+
+    /// Returns the state hash component of the table.
+    fn component(schema: &RoleSystemSchema<&Fork>, table: &str) -> Hash {
+        schema
+            .state_hash_components()
+            .into_iter()
+            .find(|&(name, _)| name == table)
+            .map(|(_, hash)| hash)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_admins_in_state_hash() {
+        let (alice, _) = crypto::gen_keypair();
+        let (bob, _) = crypto::gen_keypair();
+        let db = MemoryDB::new();
+
+        let mut fork = db.fork();
+        RoleSystemSchema::new(&mut fork).admins_set(&[alice, bob], 1);
+        let one_of_two = component(&RoleSystemSchema::new(&fork), "rolesystem.admins");
 
-    /// Returns a mutable version of employee ID to account PK table.
-    // pub fn account_pk_by_employee_id(&mut self) -> MapIndex<&mut Fork, EmployeeId, PublicKey> {
-    //     MapIndex::new("rolesystem.accounts_by_id", &mut self.view)
-    // }
+        RoleSystemSchema::new(&mut fork).admins_set(&[alice, bob], 2);
+        let two_of_two = component(&RoleSystemSchema::new(&fork), "rolesystem.admins");
+        assert_ne!(two_of_two, one_of_two);
 
-    // fn superusers_pk_mut(&mut self) -> ValueSetIndex<&mut Fork, String> {
-    //     ValueSetIndex::new("rolesystem.superuser_pks", &mut self.view)
-    // }
+        RoleSystemSchema::new(&mut fork).admins_set(&[alice], 1);
+        let schema = RoleSystemSchema::new(&fork);
+        assert_ne!(component(&schema, "rolesystem.admins"), one_of_two);
+        assert_eq!(component(&schema, "rolesystem.admins"), schema.admins_hash());
+    }
+
+    #[test]
+    fn test_custom_data_in_state_hash() {
+        let (alice, _) = crypto::gen_keypair();
+        let db = MemoryDB::new();
+        with_account(&db, &alice, "alice");
+
+        let mut fork = db.fork();
+        let empty = component(&RoleSystemSchema::new(&fork), "rolesystem.custom_data_roots");
+        RoleSystemSchema::new(&mut fork).custom_data_put(&alice, "phone", r#""123""#.to_owned());
+        {
+            let schema = RoleSystemSchema::new(&fork);
+            assert_ne!(component(&schema, "rolesystem.custom_data_roots"), empty);
+            assert_eq!(
+                schema.custom_data_roots().get(&alice),
+                Some(schema.custom_data(&alice).merkle_root())
+            );
+        }
+
+        RoleSystemSchema::new(&mut fork).custom_data_remove(&alice, "phone");
+        let schema = RoleSystemSchema::new(&fork);
+        assert_eq!(schema.custom_data_roots().get(&alice), None);
+        assert_eq!(component(&schema, "rolesystem.custom_data_roots"), empty);
+    }
 }
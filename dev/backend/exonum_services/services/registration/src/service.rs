@@ -1,43 +1,53 @@
+use api;
 use cmd::RoleSystemServiceConfig;
-use exonum::blockchain::{ApiContext, Service, Transaction, TransactionSet};
+use exonum::api::ServiceApiBuilder;
+use exonum::blockchain::{Service, Transaction, TransactionSet};
 use exonum::encoding::serialize::FromHex;
 use exonum::{
     crypto::Hash, crypto::PublicKey, encoding, messages::RawTransaction, storage::Fork,
     storage::Snapshot,
 };
-use iron::Handler;
-use router::Router;
+use ipfs::{FsBlockstore, MemoryBlockstore, SharedBlockstore};
+use migration;
 use schema::RoleSystemSchema;
 use serde_json;
 use serde_json::value::Value;
+use std::sync::{Arc, RwLock};
 use transactions::RoleSystemTransactions;
 
+/// Service ID for the `Service` trait.
 pub const SERVICE_ID: u16 = 101;
 
+/// RoleSystem service structure.
 pub struct RoleSystemService {
-    // Public key of the superuser.
-// pub superuser_pkey: String,
+    /// Public keys of the admin set.
+    pub admin_pkeys: Vec<String>,
+    /// Number of admin approvals required to execute an admin action.
+    pub admin_threshold: u16,
+    /// Local storage of the account photos.
+    pub blockstore: SharedBlockstore,
 }
 
 impl RoleSystemService {
-    /// Constructor of the RoleSystem service with superuser public key.
-    pub fn new() -> RoleSystemService {
-        RoleSystemService
-    }
-
-    // backlog - Checks that `pkey` is activated.
-    // pub fn is_activated(&self, pkey: &PublicKey) -> bool {
-    //     if let Ok(pk) = PublicKey::from_hex(self.superuser_pkey.clone()) {
-    //         return *pkey == pk
-    //     }
-    //     false
-    // }
+    /// Constructor of the RoleSystem service with the admin set and its threshold.
+    /// Photos are kept in `blockstore_path` or in memory if no path is given.
+    pub fn new(
+        admin_pkeys: Vec<String>,
+        admin_threshold: u16,
+        blockstore_path: Option<String>,
+    ) -> RoleSystemService {
+        let blockstore: SharedBlockstore = match blockstore_path {
+            Some(path) => Arc::new(RwLock::new(Box::new(
+                FsBlockstore::new(path).expect("Could not open the photo blockstore"),
+            ))),
+            None => Arc::new(RwLock::new(Box::new(MemoryBlockstore::new()))),
+        };
 
-    pub fn is_activated() -> bool {
-        if let Ok(pk) = PublicKey::from_hex(self.superuser_pkey.clone()) {
-            return *pkey == pk;
+        RoleSystemService {
+            admin_pkeys,
+            admin_threshold,
+            blockstore,
         }
-        false
     }
 }
 
@@ -62,25 +72,42 @@ impl Service for RoleSystemService {
     }
 
     /// Implements an initialization routines for the service.
-    /// Stores a superuser public key into the storage.
-    // fn initialize(&self, fork: &mut Fork) -> Value {
-    //     EmployeesSchema::new(fork).add_superuser_key(&self.superuser_pkey);
-    //     serde_json::to_value(EmployeesServiceConfig { superuser_pkey: self.superuser_pkey.clone() }).unwrap()
-    // }
+    /// Stores the admin set and the approval threshold from the service config.
+    fn initialize(&self, fork: &mut Fork) -> Value {
+        let admins: Vec<PublicKey> = self.admin_pkeys
+            .iter()
+            .map(|pkey| {
+                PublicKey::from_hex(pkey).expect("Admin public key is not a valid hex string")
+            })
+            .collect();
 
-    /// Create a REST `Handler` to process web requests to the node.
-    fn public_api_handler(&self, ctx: &ApiContext) -> Option<Box<Handler>> {
-        let mut router = Router::new();
+        assert!(
+            self.admin_threshold > 0 && self.admin_threshold as usize <= admins.len(),
+            "Admin threshold should be in range 1..={}",
+            admins.len()
+        );
 
-        use api;
-        use exonum::api::Api;
+        let mut schema = RoleSystemSchema::new(fork);
+        schema.admins_set(&admins, self.admin_threshold);
+        schema.schema_version_set(migration::CURRENT_VERSION);
 
-        let api = api::RoleSystemApi {
-            channel: ctx.node_channel().clone(),
-            blockchain: ctx.blockchain().clone(),
-        };
+        serde_json::to_value(RoleSystemServiceConfig {
+            admin_pkeys: self.admin_pkeys.clone(),
+            admin_threshold: self.admin_threshold,
+            blockstore_path: None,
+        }).unwrap()
+    }
+
+    /// Stores the admin set of a blockchain created before the layout versions were
+    /// introduced, so that it can approve the `migrate_schema` action. Does nothing
+    /// once the admin set is stored or the version is recorded.
+    fn before_commit(&self, fork: &mut Fork) {
+        migration::bootstrap_admins(fork);
+    }
 
-        api.wire(&mut router);
-        Some(Box::new(router))
+    /// Wires the public and private REST API of the service.
+    fn wire_api(&self, builder: &mut ServiceApiBuilder) {
+        api::PublicApi::new(self.blockstore.clone()).wire(builder);
+        api::PrivateApi::wire(builder);
     }
 }
@@ -1,13 +1,15 @@
-use exonum::crypto::{ PublicKey, Hash };
-// use schema::EmployeeId;
+use exonum::crypto::{ PublicKey, Hash, Signature };
+use schema::Credentials;
 use service::SERVICE_ID;
 
 transactions! {
     pub RoleSystemTransactions {
         const SERVICE_ID = SERVICE_ID;
 
-- хэш пароля с шифрованием открытым ключём
-
+        /// Transaction type for creating a new account.
+        ///
+        /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
+        /// `TxCreateAccount` transactions are processed.
         struct TxCreateAccount {
             /// Public key of the account's owner.
             pub_key: &PublicKey,
@@ -15,16 +17,21 @@ transactions! {
             /// UTF-8 string with the owner's username.
             username: &str,
 
-            /// password_encrypted_by_secret_key
-            encrypted_password: &str,
+            /// Password verifier.
+            credentials: Credentials,
 
-            /// user's photo stored in IPFS 
-            photo_ipfs: &Hash,
+            /// CID of the user's photo stored in IPFS, empty if there is no photo.
+            photo_ipfs: &str,
 
         }
 
-        /// backlog - не сейчас
+        /// Transaction type for editing an account in database.
+        ///
+        /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
+        /// `TxEditAccount` transactions are processed.
         struct TxEditAccount {
+            /// Public key of the signer: the account owner, one of its devices or an open session.
+            pkey_by_who: &PublicKey,
 
             /// Public key of the account owner.
             pkey_account: &PublicKey,
@@ -32,34 +39,394 @@ transactions! {
             /// UTF-8 string with the owner's username.
             username: &str,
 
-            /// password_encrypted_by_secret_key
-            encrypted_password: &str,
+            /// CID of the user's photo stored in IPFS, empty if there is no photo.
+            photo_ipfs: &str,
 
-            /// user's photo stored in IPFS 
-            photo_ipfs: &Hash,
+            seed: u64,
+        }
+
+        /// Transaction type for changing the account password. The client unwraps
+        /// the secret key with the old password, wraps it with the new one and
+        /// publishes the new verifier.
+        ///
+        /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
+        /// `TxRotatePassword` transactions are processed.
+        struct TxRotatePassword {
+            /// Public key of the signer: the account owner, one of its devices or an open session.
+            pkey_by_who: &PublicKey,
+
+            /// Public key of the account owner.
+            pkey_account: &PublicKey,
+
+            /// New password verifier.
+            credentials: Credentials,
 
             seed: u64,
         }
 
-        /// Transaction type editing an account's custom data in database.
+        /// Transaction type for setting a single custom data entry of an account.
+        ///
+        /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
+        /// `TxSetCustomData` transactions are processed.
         struct TxSetCustomData {
+            /// Public key of the signer: the account owner, one of its devices or an open session.
+            pkey_by_who: &PublicKey,
+
+            /// Public key of the account owner.
+            pkey_account: &PublicKey,
+
+            /// UTF-8 string with the entry key.
+            key: &str,
+
+            /// JSON-serialized entry value.
+            value: &str,
+
+            seed: u64,
+        }
+
+        /// Transaction type for removing a single custom data entry of an account.
+        ///
+        /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
+        /// `TxRemoveCustomData` transactions are processed.
+        struct TxRemoveCustomData {
+            /// Public key of the signer: the account owner, one of its devices or an open session.
+            pkey_by_who: &PublicKey,
 
             /// Public key of the account owner.
             pkey_account: &PublicKey,
 
-            /// Optional set of custom data bytes.
-            custom_data: &str,
+            /// UTF-8 string with the entry key.
+            key: &str,
+
+            seed: u64,
+        }
+
+        /// Transaction type for registering a new data schema.
+        ///
+        /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
+        /// `TxRegisterDataSchema` transactions are processed.
+        struct TxRegisterDataSchema {
+            /// Public key of the author. Must be a master or a device key of an active account.
+            pkey_by_who: &PublicKey,
+
+            /// UTF-8 string with the schema name.
+            name: &str,
+
+            /// JSON-serialized `DataSchemaDefinition`.
+            definition: &str,
+
+            seed: u64,
+        }
+
+        /// Transaction type for declaring the data schema of an account's custom data.
+        ///
+        /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
+        /// `TxSetDataSchema` transactions are processed.
+        struct TxSetDataSchema {
+            /// Public key of the signer: the account owner, one of its devices or an open session.
+            pkey_by_who: &PublicKey,
+
+            /// Public key of the account owner.
+            pkey_account: &PublicKey,
+
+            /// UTF-8 string with the name of a registered schema.
+            name: &str,
+
+            seed: u64,
+        }
+
+        /// Transaction type for moving an account to a new username.
+        ///
+        /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
+        /// `TxChangeUsername` transactions are processed.
+        struct TxChangeUsername {
+            /// Public key of the signer: the account owner, one of its devices or an open session.
+            pkey_by_who: &PublicKey,
+
+            /// Public key of the account owner.
+            pkey_account: &PublicKey,
+
+            /// UTF-8 string with the new username.
+            username: &str,
 
             seed: u64,
         }
 
         /// Transaction type for account's deactivation.
+        ///
+        /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
+        /// `TxDeactivateAccount` transactions are processed.
         struct TxDeactivateAccount {
+            /// Public key of the signer: the account owner, one of its devices or an open session.
+            pkey_by_who: &PublicKey,
+
+            /// Public key of the account owner.
+            pkey_account: &PublicKey,
+
+            seed: u64,
+        }
+
+        /// Transaction type for account's reactivation.
+        ///
+        /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
+        /// `TxReactivateAccount` transactions are processed.
+        struct TxReactivateAccount {
+            /// Public key of the signer: the account owner, one of its devices or an open session.
+            pkey_by_who: &PublicKey,
+
+            /// Public key of the account owner.
+            pkey_account: &PublicKey,
+
+            seed: u64,
+        }
+
+        /// Transaction type for account's deletion by its owner.
+        ///
+        /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
+        /// `TxDeleteAccount` transactions are processed.
+        struct TxDeleteAccount {
+            /// Public key of the signer: the account owner, one of its devices or an open session.
+            pkey_by_who: &PublicKey,
+
+            /// Public key of the account owner.
+            pkey_account: &PublicKey,
+
+            seed: u64,
+        }
+
+        /// Transaction type for opening a session. The session key can act for the account
+        /// for `duration` seconds of the consensus time.
+        ///
+        /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
+        /// `TxOpenSession` transactions are processed.
+        struct TxOpenSession {
+            /// Public key of the signer: the account owner or one of its devices.
+            pkey_by_who: &PublicKey,
+
+            /// Public key of the account owner.
+            pkey_account: &PublicKey,
+
+            /// Public key of the session.
+            session_key: &PublicKey,
+
+            /// Lifetime of the session in seconds.
+            duration: u64,
+
+            seed: u64,
+        }
+
+        /// Transaction type for closing a session before its expiry. Can be signed
+        /// by the account owner, its device or by the session key itself.
+        ///
+        /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
+        /// `TxCloseSession` transactions are processed.
+        struct TxCloseSession {
+
+            /// Public key of the signer: the account owner, one of its devices
+            /// or the session key itself.
+            pkey_by_who: &PublicKey,
+
+            /// Public key of the session.
+            session_key: &PublicKey,
+
+            seed: u64,
+        }
+
+        /// Transaction type for adding a device key to an account. The device key can sign
+        /// transactions of the account until it is removed.
+        ///
+        /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
+        /// `TxAddDevice` transactions are processed.
+        struct TxAddDevice {
+
+            /// Public key of the account owner. Only the master key can manage devices.
+            pkey_account: &PublicKey,
+
+            /// Public key of the device.
+            device_key: &PublicKey,
+
+            /// UTF-8 string with the device name, e.g. `laptop`.
+            name: &str,
+
+            /// Signature of [`device_proof`](#method.device_proof) made with the device key.
+            device_signature: &Signature,
+
+            seed: u64,
+        }
+
+        /// Transaction type for removing a device key from an account.
+        ///
+        /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
+        /// `TxRemoveDevice` transactions are processed.
+        struct TxRemoveDevice {
+
+            /// Public key of the account owner. Only the master key can manage devices.
+            pkey_account: &PublicKey,
+
+            /// Public key of the device.
+            device_key: &PublicKey,
+
+            seed: u64,
+        }
+
+        /// Transaction type for nominating guardians that can recover the account.
+        ///
+        /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
+        /// `TxSetGuardians` transactions are processed.
+        struct TxSetGuardians {
+
+            /// Public key of the account owner. Only the master key can change guardians.
+            pkey_account: &PublicKey,
+
+            /// Public keys of the guardian accounts.
+            guardians: Vec<PublicKey>,
+
+            /// Number of guardian approvals required to recover the account.
+            threshold: u16,
+
+            /// Number of blocks between the initiation and the completion of a recovery.
+            delay: u64,
+
+            seed: u64,
+        }
+
+        /// Transaction type for proposing a new key for an account whose owner has lost
+        /// the old one. The initiating guardian's approval is counted.
+        ///
+        /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
+        /// `TxInitiateRecovery` transactions are processed.
+        struct TxInitiateRecovery {
+            /// Public key of the guardian.
+            pkey_by_who: &PublicKey,
+
+            /// Public key of the recovered account.
+            pkey_account: &PublicKey,
+
+            /// Public key the account is moved to.
+            new_key: &PublicKey,
+
+            seed: u64,
+        }
+
+        /// Transaction type for approving a pending recovery.
+        ///
+        /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
+        /// `TxApproveRecovery` transactions are processed.
+        struct TxApproveRecovery {
+            /// Public key of the guardian.
+            pkey_by_who: &PublicKey,
+
+            /// Public key of the recovered account.
+            pkey_account: &PublicKey,
+
+            /// Public key the account is moved to. Must match the pending recovery.
+            new_key: &PublicKey,
+
+            seed: u64,
+        }
+
+        /// Transaction type for cancelling a pending recovery with the old key.
+        ///
+        /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
+        /// `TxCancelRecovery` transactions are processed.
+        struct TxCancelRecovery {
 
             /// Public key of the account owner.
             pkey_account: &PublicKey,
 
             seed: u64,
         }
+
+        /// Transaction type for moving the account to the new key once the recovery
+        /// is approved and the delay has passed. Signed by the new key.
+        ///
+        /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
+        /// `TxCompleteRecovery` transactions are processed.
+        struct TxCompleteRecovery {
+            /// Public key the account is moved to.
+            new_key: &PublicKey,
+
+            /// Public key of the recovered account.
+            pkey_account: &PublicKey,
+
+            /// Password verifier for the new key.
+            credentials: Credentials,
+
+            seed: u64,
+        }
+
+        /// Transaction type for proposing an administrative action. The action is
+        /// executed after it is approved by `admin_threshold` admins, the proposer included.
+        ///
+        /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
+        /// `TxProposeAdminAction` transactions are processed.
+        struct TxProposeAdminAction {
+            /// Public key of the proposer. Must belong to the admin set.
+            pkey_by_who: &PublicKey,
+
+            /// JSON-serialized `AdminAction`.
+            action: &str,
+
+            /// Height of the last block in which the proposal can be approved.
+            expires_at: u64,
+
+            seed: u64,
+        }
+
+        /// Transaction type for approving a pending administrative action.
+        ///
+        /// See [the `Transaction` trait implementation](#impl-Transaction) for details how
+        /// `TxApproveAdminAction` transactions are processed.
+        struct TxApproveAdminAction {
+            /// Public key of the approver. Must belong to the admin set.
+            pkey_by_who: &PublicKey,
+
+            /// Hash of the `TxProposeAdminAction` transaction.
+            proposal: &Hash,
+
+            seed: u64,
+        }
+    }
+}
+
+impl TxAddDevice {
+    /// Returns the message the device key signs to prove that the device agrees to be
+    /// bound to the account: the account key, the device key and the transaction seed.
+    pub fn device_proof(pkey_account: &PublicKey, device_key: &PublicKey, seed: u64) -> Vec<u8> {
+        let mut message = b"zen-rolesystem:device:".to_vec();
+        message.extend_from_slice(pkey_account.as_ref());
+        message.extend_from_slice(device_key.as_ref());
+        message.extend((0..8).map(|i| (seed >> (8 * i)) as u8));
+        message
+    }
+}
+
+impl RoleSystemTransactions {
+    /// Returns the name of the transaction type, e.g. `TxCreateAccount`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            RoleSystemTransactions::TxCreateAccount(_) => "TxCreateAccount",
+            RoleSystemTransactions::TxEditAccount(_) => "TxEditAccount",
+            RoleSystemTransactions::TxRotatePassword(_) => "TxRotatePassword",
+            RoleSystemTransactions::TxSetCustomData(_) => "TxSetCustomData",
+            RoleSystemTransactions::TxRemoveCustomData(_) => "TxRemoveCustomData",
+            RoleSystemTransactions::TxRegisterDataSchema(_) => "TxRegisterDataSchema",
+            RoleSystemTransactions::TxSetDataSchema(_) => "TxSetDataSchema",
+            RoleSystemTransactions::TxChangeUsername(_) => "TxChangeUsername",
+            RoleSystemTransactions::TxDeactivateAccount(_) => "TxDeactivateAccount",
+            RoleSystemTransactions::TxReactivateAccount(_) => "TxReactivateAccount",
+            RoleSystemTransactions::TxDeleteAccount(_) => "TxDeleteAccount",
+            RoleSystemTransactions::TxOpenSession(_) => "TxOpenSession",
+            RoleSystemTransactions::TxCloseSession(_) => "TxCloseSession",
+            RoleSystemTransactions::TxAddDevice(_) => "TxAddDevice",
+            RoleSystemTransactions::TxRemoveDevice(_) => "TxRemoveDevice",
+            RoleSystemTransactions::TxSetGuardians(_) => "TxSetGuardians",
+            RoleSystemTransactions::TxInitiateRecovery(_) => "TxInitiateRecovery",
+            RoleSystemTransactions::TxApproveRecovery(_) => "TxApproveRecovery",
+            RoleSystemTransactions::TxCancelRecovery(_) => "TxCancelRecovery",
+            RoleSystemTransactions::TxCompleteRecovery(_) => "TxCompleteRecovery",
+            RoleSystemTransactions::TxProposeAdminAction(_) => "TxProposeAdminAction",
+            RoleSystemTransactions::TxApproveAdminAction(_) => "TxApproveAdminAction",
+        }
     }
-}
\ No newline at end of file
+}
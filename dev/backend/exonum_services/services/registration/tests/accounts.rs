@@ -0,0 +1,148 @@
+//! Tests of the account lifecycle: creation, deactivation, reactivation and deletion.
+
+extern crate exonum;
+#[macro_use]
+extern crate exonum_testkit;
+extern crate zen_rolesystem;
+
+mod common;
+
+use common::{create_account, create_testkit, error_code, KeyPair};
+use exonum::crypto::{self, PublicKey};
+use exonum::messages::Message;
+use exonum_testkit::TestKit;
+use zen_rolesystem::contracts::ErrorKind;
+use zen_rolesystem::schema::RoleSystemSchema;
+use zen_rolesystem::transactions::{TxDeactivateAccount, TxDeleteAccount, TxEditAccount,
+                                   TxReactivateAccount};
+
+fn edit(key: &KeyPair, username: &str, seed: u64) -> TxEditAccount {
+    TxEditAccount::new(&key.0, &key.0, username, "", seed, &key.1)
+}
+
+fn deactivate(key: &KeyPair, seed: u64) -> TxDeactivateAccount {
+    TxDeactivateAccount::new(&key.0, &key.0, seed, &key.1)
+}
+
+fn reactivate(key: &KeyPair, seed: u64) -> TxReactivateAccount {
+    TxReactivateAccount::new(&key.0, &key.0, seed, &key.1)
+}
+
+fn delete(key: &KeyPair, seed: u64) -> TxDeleteAccount {
+    TxDeleteAccount::new(&key.0, &key.0, seed, &key.1)
+}
+
+fn is_active(testkit: &TestKit, pub_key: &PublicKey) -> Option<bool> {
+    let snapshot = testkit.snapshot();
+    RoleSystemSchema::new(&snapshot)
+        .account(pub_key)
+        .map(|account| account.is_active())
+}
+
+/// Creates the account of `alice`.
+fn setup(alice: &KeyPair) -> TestKit {
+    let mut testkit = create_testkit();
+    let creation = create_account(alice, "alice");
+    testkit.create_block_with_transactions(txvec![creation.clone()]);
+    assert_eq!(error_code(&testkit, &creation.hash()), None);
+    testkit
+}
+
+#[test]
+fn test_create_account() {
+    let alice = crypto::gen_keypair();
+    let mut testkit = setup(&alice);
+    assert_eq!(is_active(&testkit, &alice.0), Some(true));
+
+    let again = create_account(&alice, "alice_again");
+    testkit.create_block_with_transactions(txvec![again.clone()]);
+    assert_eq!(
+        error_code(&testkit, &again.hash()),
+        Some(ErrorKind::AccountAlreadyExists.into_code())
+    );
+
+    let snapshot = testkit.snapshot();
+    let schema = RoleSystemSchema::new(&snapshot);
+    assert_eq!(schema.account(&alice.0).unwrap().username(), "alice");
+    assert_eq!(schema.pub_key_by_username("alice_again"), None);
+}
+
+#[test]
+fn test_deactivate_and_reactivate() {
+    let alice = crypto::gen_keypair();
+    let mut testkit = setup(&alice);
+
+    let reactivation = reactivate(&alice, 0);
+    testkit.create_block_with_transactions(txvec![reactivation.clone()]);
+    assert_eq!(
+        error_code(&testkit, &reactivation.hash()),
+        Some(ErrorKind::AccountAlreadyActive.into_code())
+    );
+
+    let deactivation = deactivate(&alice, 0);
+    testkit.create_block_with_transactions(txvec![deactivation.clone()]);
+    assert_eq!(error_code(&testkit, &deactivation.hash()), None);
+    assert_eq!(is_active(&testkit, &alice.0), Some(false));
+
+    // An inactive account can only be reactivated
+    let rename = edit(&alice, "alice_inactive", 0);
+    let deactivation = deactivate(&alice, 1);
+    let deletion = delete(&alice, 0);
+    testkit.create_block_with_transactions(txvec![
+        rename.clone(),
+        deactivation.clone(),
+        deletion.clone(),
+    ]);
+    for tx_hash in &[rename.hash(), deactivation.hash(), deletion.hash()] {
+        assert_eq!(
+            error_code(&testkit, tx_hash),
+            Some(ErrorKind::AccountNotActive.into_code())
+        );
+    }
+
+    let reactivation = reactivate(&alice, 1);
+    testkit.create_block_with_transactions(txvec![reactivation.clone()]);
+    assert_eq!(error_code(&testkit, &reactivation.hash()), None);
+    assert_eq!(is_active(&testkit, &alice.0), Some(true));
+
+    let rename = edit(&alice, "alice_active", 1);
+    testkit.create_block_with_transactions(txvec![rename.clone()]);
+    assert_eq!(error_code(&testkit, &rename.hash()), None);
+}
+
+#[test]
+fn test_delete_account() {
+    let alice = crypto::gen_keypair();
+    let mut testkit = setup(&alice);
+
+    let deletion = delete(&alice, 0);
+    testkit.create_block_with_transactions(txvec![deletion.clone()]);
+    assert_eq!(error_code(&testkit, &deletion.hash()), None);
+    assert_eq!(is_active(&testkit, &alice.0), None);
+
+    let rename = edit(&alice, "alice_deleted", 0);
+    let deactivation = deactivate(&alice, 0);
+    let reactivation = reactivate(&alice, 0);
+    let deletion = delete(&alice, 1);
+    testkit.create_block_with_transactions(txvec![
+        rename.clone(),
+        deactivation.clone(),
+        reactivation.clone(),
+        deletion.clone(),
+    ]);
+    for tx_hash in &[
+        rename.hash(),
+        deactivation.hash(),
+        reactivation.hash(),
+        deletion.hash(),
+    ] {
+        assert_eq!(
+            error_code(&testkit, tx_hash),
+            Some(ErrorKind::AccountNotFound.into_code())
+        );
+    }
+
+    let snapshot = testkit.snapshot();
+    let schema = RoleSystemSchema::new(&snapshot);
+    assert_eq!(schema.pub_key_by_username("alice"), None);
+}
@@ -0,0 +1,207 @@
+//! Tests of the REST API: the transaction status and the private operator endpoints.
+
+extern crate exonum;
+#[macro_use]
+extern crate exonum_testkit;
+#[macro_use]
+extern crate serde_json;
+extern crate zen_rolesystem;
+
+mod common;
+
+use common::{create_account, create_testkit, testkit_builder, KeyPair};
+use exonum::api;
+use exonum::blockchain::{Schema, Transaction};
+use exonum::crypto::{self, Hash, PublicKey, Signature};
+use exonum::helpers::Height;
+use exonum::messages::Message;
+use exonum_testkit::{ApiKind, TestKit};
+use serde_json::Value;
+use zen_rolesystem::api::{AccountQuery, TransactionQuery};
+use zen_rolesystem::contracts::ErrorKind;
+use zen_rolesystem::governance::AdminAction;
+use zen_rolesystem::migration;
+use zen_rolesystem::schema::RoleSystemSchema;
+use zen_rolesystem::transactions::TxProposeAdminAction;
+
+fn transaction_info(testkit: &TestKit, hash: &Hash) -> Value {
+    testkit
+        .api()
+        .public(ApiKind::Service("rolesystem"))
+        .query(&TransactionQuery { hash: *hash })
+        .get("v1/transactions")
+        .unwrap()
+}
+
+/// Adds the service key of the node to the admin set, so the node can propose admin actions.
+fn add_node_to_admins(testkit: &mut TestKit, admin: &KeyPair) {
+    let node_key = *testkit.us().service_keypair().0;
+    let action = AdminAction::SetAdmins {
+        admins: vec![admin.0, node_key],
+        threshold: 1,
+    };
+    let action = serde_json::to_string(&action).unwrap();
+    let tx = TxProposeAdminAction::new(&admin.0, &action, 100, 0, &admin.1);
+    testkit.create_block_with_transaction(tx);
+
+    let snapshot = testkit.snapshot();
+    assert!(RoleSystemSchema::new(&snapshot).is_admin(&node_key));
+}
+
+fn deactivate(testkit: &TestKit, pub_key: &PublicKey) -> api::Result<Value> {
+    testkit
+        .api()
+        .private(ApiKind::Service("rolesystem"))
+        .query(&AccountQuery { pub_key: *pub_key })
+        .post("v1/admin/deactivate")
+}
+
+#[test]
+fn test_transaction_status() {
+    let alice = crypto::gen_keypair();
+    let mut testkit = create_testkit();
+
+    let creation = create_account(&alice, "alice");
+    let transaction: Box<Transaction> = Box::new(creation.clone());
+    testkit.api().send(transaction);
+    testkit.poll_events();
+    let info = transaction_info(&testkit, &creation.hash());
+    assert_eq!(info["status"], "pending");
+    assert_eq!(info["height"], Value::Null);
+    assert_eq!(info["error"], Value::Null);
+    assert_eq!(info["transaction"], serde_json::to_value(&creation).unwrap());
+
+    testkit.create_block();
+    let info = transaction_info(&testkit, &creation.hash());
+    assert_eq!(info["status"], "committed");
+    assert_eq!(info["height"], 1);
+    assert_eq!(info["error"], Value::Null);
+
+    let again = create_account(&alice, "alice_again");
+    testkit.create_block_with_transaction(again.clone());
+    let info = transaction_info(&testkit, &again.hash());
+    assert_eq!(info["status"], "failed");
+    assert_eq!(info["height"], 2);
+    assert_eq!(
+        info["error"],
+        json!({
+            "code": ErrorKind::AccountAlreadyExists.into_code(),
+            "kind": "AccountAlreadyExists",
+            "description": ErrorKind::AccountAlreadyExists.to_string(),
+        })
+    );
+
+    // Unknown transactions are not found
+    let missing = testkit
+        .api()
+        .public(ApiKind::Service("rolesystem"))
+        .query(&TransactionQuery { hash: Hash::zero() })
+        .get::<Value>("v1/transactions");
+    assert!(missing.is_err());
+}
+
+#[test]
+fn test_admin_stats() {
+    let alice = crypto::gen_keypair();
+    let mut testkit = create_testkit();
+    testkit.create_block_with_transaction(create_account(&alice, "alice"));
+
+    let stats: Value = testkit
+        .api()
+        .private(ApiKind::Service("rolesystem"))
+        .get("v1/admin/stats")
+        .unwrap();
+    assert_eq!(stats["height"], 1);
+    assert_eq!(stats["schema_version"], migration::CURRENT_VERSION);
+
+    let snapshot = testkit.snapshot();
+    let schema = RoleSystemSchema::new(&snapshot);
+    let state_hash: Vec<Value> = schema
+        .state_hash_components()
+        .into_iter()
+        .map(|(name, hash)| json!({ "name": name, "hash": hash }))
+        .collect();
+    assert_eq!(stats["state_hash"], Value::Array(state_hash));
+    assert_eq!(stats["state_hash"][0]["hash"], json!(schema.accounts().merkle_root()));
+
+    let accounts = stats["indexes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|index| index["name"] == "rolesystem.accounts")
+        .map(|index| index["size"].clone());
+    assert_eq!(accounts, Some(json!(1)));
+
+    // The operator endpoints are not served on the public API address
+    let public = testkit
+        .api()
+        .public(ApiKind::Service("rolesystem"))
+        .get::<Value>("v1/admin/stats");
+    assert!(public.is_err());
+}
+
+#[test]
+fn test_admin_export() {
+    let (alice, bob) = (crypto::gen_keypair(), crypto::gen_keypair());
+    let mut testkit = create_testkit();
+    testkit.create_block_with_transactions(txvec![
+        create_account(&alice, "alice"),
+        create_account(&bob, "bob"),
+    ]);
+
+    let export: Value = testkit
+        .api()
+        .private(ApiKind::Service("rolesystem"))
+        .get("v1/admin/export")
+        .unwrap();
+    let data = export["data"].as_str().unwrap();
+    let pub_key: PublicKey = serde_json::from_value(export["pub_key"].clone()).unwrap();
+    let signature: Signature = serde_json::from_value(export["signature"].clone()).unwrap();
+
+    assert_eq!(&pub_key, testkit.us().service_keypair().0);
+    assert!(crypto::verify(&signature, data.as_bytes(), &pub_key));
+    let tampered = data.replace("alice", "alica");
+    assert!(!crypto::verify(&signature, tampered.as_bytes(), &pub_key));
+
+    let data: Value = serde_json::from_str(data).unwrap();
+    let snapshot = testkit.snapshot();
+    let core_schema = Schema::new(&snapshot);
+    assert_eq!(data["height"], 1);
+    assert_eq!(data["block_hash"], json!(core_schema.block_hash_by_height(Height(1))));
+    let usernames: Vec<&str> = data["accounts"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|account| account["username"].as_str().unwrap())
+        .collect();
+    assert_eq!(usernames.len(), 2);
+    assert!(usernames.contains(&"alice") && usernames.contains(&"bob"));
+}
+
+#[test]
+fn test_admin_deactivate() {
+    let (admin, alice) = (crypto::gen_keypair(), crypto::gen_keypair());
+    let mut testkit = testkit_builder(&[admin.0], 1).create();
+    testkit.create_block_with_transaction(create_account(&alice, "alice"));
+
+    // The node can't propose actions until its service key is in the admin set
+    assert!(deactivate(&testkit, &alice.0).is_err());
+
+    add_node_to_admins(&mut testkit, &admin);
+    let response = deactivate(&testkit, &alice.0).unwrap();
+    let proposals: Vec<Hash> = serde_json::from_value(response["proposals"].clone()).unwrap();
+    assert_eq!(proposals.len(), 1);
+
+    testkit.create_block();
+    {
+        let snapshot = testkit.snapshot();
+        let schema = RoleSystemSchema::new(&snapshot);
+        assert!(!schema.account(&alice.0).unwrap().is_active());
+        assert!(schema.proposal(&proposals[0]).is_some());
+    }
+
+    // Inactive and unknown accounts are rejected without a proposal
+    assert!(deactivate(&testkit, &alice.0).is_err());
+    let (unknown, _) = crypto::gen_keypair();
+    assert!(deactivate(&testkit, &unknown).is_err());
+}
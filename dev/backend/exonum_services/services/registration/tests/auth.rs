@@ -0,0 +1,89 @@
+//! Tests of the login with a signed nonce.
+
+extern crate exonum;
+#[macro_use]
+extern crate exonum_testkit;
+extern crate serde_json;
+extern crate zen_rolesystem;
+
+mod common;
+
+use common::{create_testkit, credentials, KeyPair};
+use exonum::crypto::{self, Hash, PublicKey, SecretKey};
+use exonum::encoding::serialize::FromHex;
+use exonum_testkit::{ApiKind, TestKit, TestKitApi};
+use serde_json::Value;
+use zen_rolesystem::api::{AccountQuery, LoginRequest};
+use zen_rolesystem::transactions::TxCreateAccount;
+
+/// Creates an account whose password-derived key is `verifier`.
+fn setup(account: &KeyPair, verifier: &KeyPair) -> TestKit {
+    let mut testkit = create_testkit();
+
+    let tx = TxCreateAccount::new(&account.0, "alice", credentials(&verifier.0), "", &account.1);
+    testkit.create_block_with_transactions(txvec![tx]);
+    testkit
+}
+
+fn challenge(api: &TestKitApi, pub_key: &PublicKey) -> Hash {
+    let response: Value = api.public(ApiKind::Service("rolesystem"))
+        .query(&AccountQuery { pub_key: *pub_key })
+        .get("v1/auth/challenge")
+        .unwrap();
+    Hash::from_hex(response["nonce"].as_str().unwrap()).unwrap()
+}
+
+fn login(api: &TestKitApi, pub_key: &PublicKey, nonce: Hash, key: &SecretKey) -> bool {
+    let request = LoginRequest {
+        pub_key: *pub_key,
+        nonce,
+        signature: crypto::sign(nonce.as_ref(), key),
+    };
+    api.public(ApiKind::Service("rolesystem"))
+        .query(&request)
+        .post::<Value>("v1/auth/login")
+        .is_ok()
+}
+
+#[test]
+fn test_login() {
+    let (account, verifier) = (crypto::gen_keypair(), crypto::gen_keypair());
+    let testkit = setup(&account, &verifier);
+    let api = testkit.api();
+
+    let nonce = challenge(&api, &account.0);
+    assert!(login(&api, &account.0, nonce, &verifier.1));
+
+    // The nonce can be used only once
+    assert!(!login(&api, &account.0, nonce, &verifier.1));
+}
+
+#[test]
+fn test_login_with_concurrent_challenges() {
+    let (account, verifier) = (crypto::gen_keypair(), crypto::gen_keypair());
+    let testkit = setup(&account, &verifier);
+    let api = testkit.api();
+
+    // Someone else asking for a challenge of the account doesn't break the login
+    let nonce = challenge(&api, &account.0);
+    let other = challenge(&api, &account.0);
+    assert!(login(&api, &account.0, nonce, &verifier.1));
+    assert!(login(&api, &account.0, other, &verifier.1));
+}
+
+#[test]
+fn test_login_rejected() {
+    let (account, verifier) = (crypto::gen_keypair(), crypto::gen_keypair());
+    let testkit = setup(&account, &verifier);
+    let api = testkit.api();
+
+    // Signed with the account key instead of the password-derived one
+    let nonce = challenge(&api, &account.0);
+    assert!(!login(&api, &account.0, nonce, &account.1));
+
+    // A failed attempt consumes the nonce
+    assert!(!login(&api, &account.0, nonce, &verifier.1));
+
+    // Nonces not issued by the node
+    assert!(!login(&api, &account.0, crypto::hash(b"nonce"), &verifier.1));
+}
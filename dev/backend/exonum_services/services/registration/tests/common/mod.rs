@@ -0,0 +1,55 @@
+//! Helpers shared by the integration tests.
+
+// Each test crate uses only a part of the helpers.
+#![allow(dead_code)]
+
+use exonum::blockchain::{Schema, TransactionErrorType};
+use exonum::crypto::{self, Hash, PublicKey, SecretKey};
+use exonum::encoding::serialize::ToHex;
+use exonum_testkit::{TestKit, TestKitBuilder};
+use zen_rolesystem::schema::{kdf, Credentials};
+use zen_rolesystem::service::RoleSystemService;
+use zen_rolesystem::transactions::TxCreateAccount;
+
+pub type KeyPair = (PublicKey, SecretKey);
+
+/// Returns the testkit builder with the service administered by `admins`.
+pub fn testkit_builder(admins: &[PublicKey], threshold: u16) -> TestKitBuilder {
+    let admins = admins.iter().map(|pub_key| pub_key.to_hex()).collect();
+    TestKitBuilder::validator().with_service(RoleSystemService::new(admins, threshold, None))
+}
+
+/// Creates the testkit with an admin whose key isn't used by the test.
+pub fn create_testkit() -> TestKit {
+    let (admin, _) = crypto::gen_keypair();
+    testkit_builder(&[admin], 1).create()
+}
+
+/// Returns the cheapest valid credentials with the given password verifier.
+pub fn credentials(verifier: &PublicKey) -> Credentials {
+    Credentials::new(&Hash::zero(), kdf::MIN_LOG_N, 8, 1, verifier, &Hash::zero())
+}
+
+/// Creates an account whose password verifier is its own key.
+pub fn create_account(key: &KeyPair, username: &str) -> TxCreateAccount {
+    TxCreateAccount::new(&key.0, username, credentials(&key.0), "", &key.1)
+}
+
+/// Returns the error code of the committed transaction, `None` if it has succeeded.
+pub fn error_code(testkit: &TestKit, tx_hash: &Hash) -> Option<u8> {
+    let snapshot = testkit.snapshot();
+    let result = Schema::new(&snapshot)
+        .transaction_results()
+        .get(tx_hash)
+        .expect("Transaction is not committed");
+
+    match result {
+        Ok(()) => None,
+        Err(ref error) => match error.error_type() {
+            TransactionErrorType::Code(code) => Some(code),
+            TransactionErrorType::Panic => {
+                panic!("Transaction has panicked: {:?}", error.description())
+            }
+        },
+    }
+}
@@ -0,0 +1,170 @@
+//! Tests of the device keys of an account.
+
+extern crate chrono;
+extern crate exonum;
+#[macro_use]
+extern crate exonum_testkit;
+extern crate exonum_time;
+extern crate zen_rolesystem;
+
+mod common;
+
+use chrono::{TimeZone, Utc};
+use common::{create_account, error_code, testkit_builder, KeyPair};
+use exonum::blockchain::Transaction;
+use exonum::crypto::{self, PublicKey};
+use exonum::helpers::Height;
+use exonum::messages::Message;
+use exonum_testkit::TestKit;
+use exonum_time::{time_provider::MockTimeProvider, TimeService};
+use zen_rolesystem::contracts::ErrorKind;
+use zen_rolesystem::schema::RoleSystemSchema;
+use zen_rolesystem::transactions::{TxAddDevice, TxEditAccount, TxOpenSession, TxRemoveDevice};
+
+/// Creates the testkit with the time oracle and waits until the consensus time is set.
+fn create_testkit() -> TestKit {
+    let (admin, _) = crypto::gen_keypair();
+    let mut testkit = testkit_builder(&[admin], 1)
+        .with_service(TimeService::with_provider(MockTimeProvider::new(
+            Utc.timestamp(10, 0),
+        )))
+        .create();
+    testkit.create_blocks_until(Height(2));
+    testkit
+}
+
+fn add_device(account: &KeyPair, device: &KeyPair, seed: u64) -> TxAddDevice {
+    let proof = TxAddDevice::device_proof(&account.0, &device.0, seed);
+    let signature = crypto::sign(&proof, &device.1);
+    TxAddDevice::new(&account.0, &device.0, "laptop", &signature, seed, &account.1)
+}
+
+fn edit_account(signer: &KeyPair, account: &PublicKey, username: &str) -> TxEditAccount {
+    TxEditAccount::new(&signer.0, account, username, "", 0, &signer.1)
+}
+
+/// Creates the account and adds the device key to it.
+fn setup(account: &KeyPair, device: &KeyPair) -> TestKit {
+    let mut testkit = create_testkit();
+    testkit.create_block_with_transactions(txvec![create_account(account, "alice")]);
+
+    let tx = add_device(account, device, 0);
+    testkit.create_block_with_transactions(txvec![tx.clone()]);
+    assert_eq!(error_code(&testkit, &tx.hash()), None);
+
+    testkit
+}
+
+#[test]
+fn test_add_device() {
+    let (account, device) = (crypto::gen_keypair(), crypto::gen_keypair());
+    let testkit = setup(&account, &device);
+
+    let snapshot = testkit.snapshot();
+    let schema = RoleSystemSchema::new(&snapshot);
+    assert_eq!(schema.device(&device.0).unwrap().account(), &account.0);
+    assert!(schema.account_devices(&account.0).contains(&device.0));
+    assert_eq!(schema.key_owner(&device.0), account.0);
+}
+
+#[test]
+fn test_add_device_without_device_signature() {
+    let (account, device, other) = (
+        crypto::gen_keypair(),
+        crypto::gen_keypair(),
+        crypto::gen_keypair(),
+    );
+
+    // The device signature must be made with the device key over this very transaction
+    let proof = TxAddDevice::device_proof(&account.0, &device.0, 0);
+    let foreign = crypto::sign(&proof, &other.1);
+    let tx = TxAddDevice::new(&account.0, &device.0, "laptop", &foreign, 0, &account.1);
+    assert!(!tx.verify());
+
+    let replayed = add_device(&account, &device, 0);
+    let tx = TxAddDevice::new(
+        &account.0,
+        &device.0,
+        "laptop",
+        replayed.device_signature(),
+        1,
+        &account.1,
+    );
+    assert!(!tx.verify());
+
+    assert!(add_device(&account, &device, 0).verify());
+}
+
+#[test]
+fn test_device_key_is_taken() {
+    let (account, device) = (crypto::gen_keypair(), crypto::gen_keypair());
+    let mut testkit = setup(&account, &device);
+
+    // The device key can't be registered as an account or opened as a session
+    let creation = create_account(&device, "mallory");
+    let session = TxOpenSession::new(&account.0, &account.0, &device.0, 60, 0, &account.1);
+    testkit.create_block_with_transactions(txvec![creation.clone(), session.clone()]);
+    assert_eq!(
+        error_code(&testkit, &creation.hash()),
+        Some(ErrorKind::KeyAlreadyUsed.into_code())
+    );
+    assert_eq!(
+        error_code(&testkit, &session.hash()),
+        Some(ErrorKind::KeyAlreadyUsed.into_code())
+    );
+
+    // An account key can't be bound as a device or opened as a session of another account
+    let other = crypto::gen_keypair();
+    testkit.create_block_with_transactions(txvec![create_account(&other, "bob")]);
+    let binding = add_device(&other, &account, 0);
+    let session = TxOpenSession::new(&other.0, &other.0, &account.0, 60, 0, &other.1);
+    testkit.create_block_with_transactions(txvec![binding.clone(), session.clone()]);
+    assert_eq!(
+        error_code(&testkit, &binding.hash()),
+        Some(ErrorKind::KeyAlreadyUsed.into_code())
+    );
+    assert_eq!(
+        error_code(&testkit, &session.hash()),
+        Some(ErrorKind::KeyAlreadyUsed.into_code())
+    );
+}
+
+#[test]
+fn test_use_and_remove_device() {
+    let (account, device) = (crypto::gen_keypair(), crypto::gen_keypair());
+    let mut testkit = setup(&account, &device);
+
+    let edit = edit_account(&device, &account.0, "alice_laptop");
+    testkit.create_block_with_transactions(txvec![edit.clone()]);
+    assert_eq!(error_code(&testkit, &edit.hash()), None);
+    {
+        let snapshot = testkit.snapshot();
+        let schema = RoleSystemSchema::new(&snapshot);
+        assert_eq!(schema.account(&account.0).unwrap().username(), "alice_laptop");
+    }
+
+    // Devices can't manage other devices
+    let by_device = TxRemoveDevice::new(&device.0, &device.0, 0, &device.1);
+    testkit.create_block_with_transactions(txvec![by_device.clone()]);
+    assert_eq!(
+        error_code(&testkit, &by_device.hash()),
+        Some(ErrorKind::AccountNotFound.into_code())
+    );
+
+    let removal = TxRemoveDevice::new(&account.0, &device.0, 1, &account.1);
+    testkit.create_block_with_transactions(txvec![removal.clone()]);
+    assert_eq!(error_code(&testkit, &removal.hash()), None);
+
+    let revoked = edit_account(&device, &account.0, "alice");
+    testkit.create_block_with_transactions(txvec![revoked.clone()]);
+    assert_eq!(
+        error_code(&testkit, &revoked.hash()),
+        Some(ErrorKind::PermissionDenied.into_code())
+    );
+
+    let snapshot = testkit.snapshot();
+    let schema = RoleSystemSchema::new(&snapshot);
+    assert!(schema.device(&device.0).is_none());
+    assert_eq!(schema.account_devices(&account.0).iter().count(), 0);
+    assert_eq!(schema.account(&account.0).unwrap().username(), "alice_laptop");
+}
@@ -0,0 +1,286 @@
+//! Tests of the M-of-N administration: proposals, approvals and their execution.
+
+extern crate exonum;
+#[macro_use]
+extern crate exonum_testkit;
+extern crate serde_json;
+extern crate zen_rolesystem;
+
+mod common;
+
+use common::{create_account, error_code, testkit_builder, KeyPair};
+use exonum::crypto::{self, Hash, PublicKey};
+use exonum::messages::Message;
+use exonum_testkit::TestKit;
+use zen_rolesystem::contracts::ErrorKind;
+use zen_rolesystem::governance::AdminAction;
+use zen_rolesystem::schema::{permissions, RoleSystemSchema};
+use zen_rolesystem::transactions::{TxApproveAdminAction, TxProposeAdminAction};
+
+/// Proposals in the tests can be approved until this height.
+const EXPIRES_AT: u64 = 100;
+
+fn create_testkit(admins: &[KeyPair], threshold: u16) -> TestKit {
+    let admin_pkeys: Vec<PublicKey> = admins.iter().map(|&(pk, _)| pk).collect();
+    testkit_builder(&admin_pkeys, threshold).create()
+}
+
+fn gen_admins(count: usize) -> Vec<KeyPair> {
+    (0..count).map(|_| crypto::gen_keypair()).collect()
+}
+
+fn set_role(name: &str) -> AdminAction {
+    AdminAction::SetRole {
+        name: name.to_owned(),
+        permissions: 1,
+    }
+}
+
+fn propose(
+    admin: &KeyPair,
+    action: &AdminAction,
+    expires_at: u64,
+) -> TxProposeAdminAction {
+    let action = serde_json::to_string(action).unwrap();
+    TxProposeAdminAction::new(&admin.0, &action, expires_at, 0, &admin.1)
+}
+
+fn approve(admin: &KeyPair, proposal: &Hash) -> TxApproveAdminAction {
+    TxApproveAdminAction::new(&admin.0, proposal, 0, &admin.1)
+}
+
+fn has_role(testkit: &TestKit, name: &str) -> bool {
+    let snapshot = testkit.snapshot();
+    RoleSystemSchema::new(&snapshot).role(name).is_some()
+}
+
+#[test]
+fn test_proposal_executed_at_threshold() {
+    let admins = gen_admins(3);
+    let mut testkit = create_testkit(&admins, 2);
+
+    let proposal = propose(&admins[0], &set_role("moderator"), EXPIRES_AT);
+    testkit.create_block_with_transactions(txvec![proposal.clone()]);
+    assert_eq!(error_code(&testkit, &proposal.hash()), None);
+    assert!(!has_role(&testkit, "moderator"));
+
+    let approval = approve(&admins[1], &proposal.hash());
+    testkit.create_block_with_transactions(txvec![approval.clone()]);
+    assert_eq!(error_code(&testkit, &approval.hash()), None);
+    assert!(has_role(&testkit, "moderator"));
+
+    let snapshot = testkit.snapshot();
+    let schema = RoleSystemSchema::new(&snapshot);
+    assert!(schema.proposal(&proposal.hash()).unwrap().executed());
+
+    let late = approve(&admins[2], &proposal.hash());
+    testkit.create_block_with_transactions(txvec![late.clone()]);
+    assert_eq!(
+        error_code(&testkit, &late.hash()),
+        Some(ErrorKind::ProposalAlreadyExecuted.into_code())
+    );
+}
+
+#[test]
+fn test_proposal_executed_right_away_with_threshold_one() {
+    let admins = gen_admins(2);
+    let mut testkit = create_testkit(&admins, 1);
+
+    let proposal = propose(&admins[1], &set_role("moderator"), EXPIRES_AT);
+    testkit.create_block_with_transactions(txvec![proposal]);
+    assert!(has_role(&testkit, "moderator"));
+}
+
+#[test]
+fn test_double_approval() {
+    let admins = gen_admins(3);
+    let mut testkit = create_testkit(&admins, 3);
+
+    let proposal = propose(&admins[0], &set_role("moderator"), EXPIRES_AT);
+    testkit.create_block_with_transactions(txvec![proposal.clone()]);
+
+    let by_proposer = approve(&admins[0], &proposal.hash());
+    let first = approve(&admins[1], &proposal.hash());
+    let second = TxApproveAdminAction::new(&admins[1].0, &proposal.hash(), 1, &admins[1].1);
+    testkit.create_block_with_transactions(txvec![
+        by_proposer.clone(),
+        first.clone(),
+        second.clone(),
+    ]);
+
+    let code = Some(ErrorKind::ProposalAlreadyApproved.into_code());
+    assert_eq!(error_code(&testkit, &by_proposer.hash()), code);
+    assert_eq!(error_code(&testkit, &first.hash()), None);
+    assert_eq!(error_code(&testkit, &second.hash()), code);
+    assert!(!has_role(&testkit, "moderator"));
+
+    let snapshot = testkit.snapshot();
+    let schema = RoleSystemSchema::new(&snapshot);
+    assert_eq!(schema.proposal_approvals(&proposal.hash()).iter().count(), 2);
+}
+
+#[test]
+fn test_non_admin() {
+    let admins = gen_admins(2);
+    let outsider = crypto::gen_keypair();
+    let mut testkit = create_testkit(&admins, 2);
+
+    let proposal = propose(&admins[0], &set_role("moderator"), EXPIRES_AT);
+    let foreign = propose(&outsider, &set_role("moderator"), EXPIRES_AT);
+    testkit.create_block_with_transactions(txvec![proposal.clone(), foreign.clone()]);
+
+    let approval = approve(&outsider, &proposal.hash());
+    testkit.create_block_with_transactions(txvec![approval.clone()]);
+
+    let code = Some(ErrorKind::PermissionDenied.into_code());
+    assert_eq!(error_code(&testkit, &foreign.hash()), code);
+    assert_eq!(error_code(&testkit, &approval.hash()), code);
+    assert!(!has_role(&testkit, "moderator"));
+}
+
+#[test]
+fn test_expired_proposal() {
+    let admins = gen_admins(2);
+    let mut testkit = create_testkit(&admins, 2);
+
+    // The first block after the genesis has height 1.
+    let stale = propose(&admins[0], &set_role("stale"), 0);
+    let proposal = propose(&admins[0], &set_role("moderator"), 2);
+    testkit.create_block_with_transactions(txvec![stale.clone(), proposal.clone()]);
+    assert_eq!(
+        error_code(&testkit, &stale.hash()),
+        Some(ErrorKind::ProposalExpired.into_code())
+    );
+    assert_eq!(error_code(&testkit, &proposal.hash()), None);
+
+    testkit.create_block();
+
+    let approval = approve(&admins[1], &proposal.hash());
+    testkit.create_block_with_transactions(txvec![approval.clone()]);
+    assert_eq!(
+        error_code(&testkit, &approval.hash()),
+        Some(ErrorKind::ProposalExpired.into_code())
+    );
+    assert!(!has_role(&testkit, "moderator"));
+}
+
+#[test]
+fn test_set_admins_with_pending_proposal() {
+    let admins = gen_admins(3);
+    let mut testkit = create_testkit(&admins, 2);
+
+    let pending = propose(&admins[0], &set_role("moderator"), EXPIRES_AT);
+    testkit.create_block_with_transactions(txvec![pending.clone()]);
+
+    // Raises the threshold to all three admins.
+    let set_admins = propose(
+        &admins[1],
+        &AdminAction::SetAdmins {
+            admins: admins.iter().map(|&(pk, _)| pk).collect(),
+            threshold: 3,
+        },
+        EXPIRES_AT,
+    );
+    let set_admins_approval = approve(&admins[2], &set_admins.hash());
+    testkit.create_block_with_transactions(txvec![set_admins, set_admins_approval]);
+    {
+        let snapshot = testkit.snapshot();
+        assert_eq!(RoleSystemSchema::new(&snapshot).admin_threshold(), 3);
+    }
+
+    // Two approvals were enough when the proposal was made, but not anymore.
+    let second = approve(&admins[1], &pending.hash());
+    testkit.create_block_with_transactions(txvec![second.clone()]);
+    assert_eq!(error_code(&testkit, &second.hash()), None);
+    assert!(!has_role(&testkit, "moderator"));
+
+    let third = approve(&admins[2], &pending.hash());
+    testkit.create_block_with_transactions(txvec![third.clone()]);
+    assert_eq!(error_code(&testkit, &third.hash()), None);
+    assert!(has_role(&testkit, "moderator"));
+}
+
+#[test]
+fn test_removed_admin_approval_not_counted() {
+    let admins = gen_admins(3);
+    let mut testkit = create_testkit(&admins, 2);
+
+    let pending = propose(&admins[0], &set_role("moderator"), EXPIRES_AT);
+    testkit.create_block_with_transactions(txvec![pending.clone()]);
+
+    // Removes the proposer from the admin set.
+    let set_admins = propose(
+        &admins[1],
+        &AdminAction::SetAdmins {
+            admins: vec![admins[1].0, admins[2].0],
+            threshold: 2,
+        },
+        EXPIRES_AT,
+    );
+    let set_admins_approval = approve(&admins[2], &set_admins.hash());
+    testkit.create_block_with_transactions(txvec![set_admins, set_admins_approval]);
+
+    let second = approve(&admins[1], &pending.hash());
+    testkit.create_block_with_transactions(txvec![second]);
+    assert!(!has_role(&testkit, "moderator"));
+
+    let third = approve(&admins[2], &pending.hash());
+    testkit.create_block_with_transactions(txvec![third]);
+    assert!(has_role(&testkit, "moderator"));
+}
+
+#[test]
+fn test_role_grants_permission() {
+    let admins = gen_admins(1);
+    let (alice, bob) = (crypto::gen_keypair(), crypto::gen_keypair());
+    let mut testkit = create_testkit(&admins, 1);
+
+    testkit.create_block_with_transactions(txvec![
+        create_account(&alice, "alice"),
+        create_account(&bob, "bob"),
+    ]);
+
+    let actions = vec![
+        AdminAction::SetRole {
+            name: "hr".to_owned(),
+            permissions: permissions::CREATE_ACCOUNTS,
+        },
+        AdminAction::SetRole {
+            name: "viewer".to_owned(),
+            permissions: 0,
+        },
+        AdminAction::GrantRole {
+            pub_key: alice.0,
+            role: "hr".to_owned(),
+        },
+        AdminAction::GrantRole {
+            pub_key: bob.0,
+            role: "viewer".to_owned(),
+        },
+    ];
+    for action in &actions {
+        let proposal = propose(&admins[0], action, EXPIRES_AT);
+        testkit.create_block_with_transactions(txvec![proposal.clone()]);
+        assert_eq!(error_code(&testkit, &proposal.hash()), None);
+    }
+    {
+        let snapshot = testkit.snapshot();
+        let schema = RoleSystemSchema::new(&snapshot);
+        assert!(schema.has_permission(&alice.0, permissions::CREATE_ACCOUNTS));
+        assert!(!schema.has_permission(&bob.0, permissions::CREATE_ACCOUNTS));
+        assert!(schema.account_roles_roots().get(&alice.0).is_some());
+    }
+
+    let revoke = AdminAction::RevokeRole {
+        pub_key: alice.0,
+        role: "hr".to_owned(),
+    };
+    let proposal = propose(&admins[0], &revoke, EXPIRES_AT);
+    testkit.create_block_with_transactions(txvec![proposal.clone()]);
+    assert_eq!(error_code(&testkit, &proposal.hash()), None);
+
+    let snapshot = testkit.snapshot();
+    let schema = RoleSystemSchema::new(&snapshot);
+    assert!(!schema.has_permission(&alice.0, permissions::CREATE_ACCOUNTS));
+    assert_eq!(schema.account_roles_roots().get(&alice.0), None);
+}
@@ -0,0 +1,97 @@
+//! Tests of the account history.
+
+extern crate exonum;
+#[macro_use]
+extern crate exonum_testkit;
+extern crate serde_json;
+extern crate zen_rolesystem;
+
+mod common;
+
+use common::{create_account, create_testkit, KeyPair};
+use exonum::crypto::{self, Hash, PublicKey};
+use exonum::messages::Message;
+use exonum_testkit::{ApiKind, TestKit};
+use serde_json::Value;
+use zen_rolesystem::api_common::HistoryQuery;
+use zen_rolesystem::schema::RoleSystemSchema;
+use zen_rolesystem::transactions::{TxDeleteAccount, TxEditAccount};
+
+fn edit_account(key: &KeyPair, username: &str) -> TxEditAccount {
+    TxEditAccount::new(&key.0, &key.0, username, "", 0, &key.1)
+}
+
+/// Returns the total size of the history and the hashes of the returned page.
+fn history(testkit: &TestKit, pub_key: &PublicKey, offset: u64, count: u64) -> (u64, Vec<Hash>) {
+    let query = HistoryQuery {
+        pub_key: *pub_key,
+        offset,
+        count,
+    };
+    let response: Value = testkit
+        .api()
+        .public(ApiKind::Service("rolesystem"))
+        .query(&query)
+        .get("v1/accounts/history")
+        .unwrap();
+
+    let hashes = response["transactions"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| serde_json::from_value(entry["tx_hash"].clone()).unwrap())
+        .collect();
+    (response["total"].as_u64().unwrap(), hashes)
+}
+
+#[test]
+fn test_history_endpoint() {
+    let alice = crypto::gen_keypair();
+    let mut testkit = create_testkit();
+
+    let creation = create_account(&alice, "alice");
+    testkit.create_block_with_transactions(txvec![creation.clone()]);
+    let rename = edit_account(&alice, "alice_smith");
+    testkit.create_block_with_transactions(txvec![rename.clone()]);
+
+    assert_eq!(
+        history(&testkit, &alice.0, 0, 100),
+        (2, vec![creation.hash(), rename.hash()])
+    );
+    assert_eq!(history(&testkit, &alice.0, 1, 100), (2, vec![rename.hash()]));
+    assert_eq!(history(&testkit, &alice.0, 0, 1), (2, vec![creation.hash()]));
+
+    let (unknown, _) = crypto::gen_keypair();
+    assert_eq!(history(&testkit, &unknown, 0, 100), (0, vec![]));
+
+    let snapshot = testkit.snapshot();
+    let schema = RoleSystemSchema::new(&snapshot);
+    assert_eq!(
+        schema.account_history_roots().get(&alice.0),
+        Some(schema.account_history(&alice.0).merkle_root())
+    );
+}
+
+#[test]
+fn test_recreated_account_has_new_history() {
+    let alice = crypto::gen_keypair();
+    let mut testkit = create_testkit();
+
+    testkit.create_block_with_transactions(txvec![
+        create_account(&alice, "alice"),
+        edit_account(&alice, "alice_smith"),
+    ]);
+
+    let deletion = TxDeleteAccount::new(&alice.0, &alice.0, 0, &alice.1);
+    testkit.create_block_with_transactions(txvec![deletion]);
+    assert_eq!(history(&testkit, &alice.0, 0, 100), (0, vec![]));
+    {
+        let snapshot = testkit.snapshot();
+        let schema = RoleSystemSchema::new(&snapshot);
+        assert_eq!(schema.account_history_roots().get(&alice.0), None);
+    }
+
+    let creation = create_account(&alice, "alice_again");
+    testkit.create_block_with_transactions(txvec![creation.clone()]);
+    assert_eq!(history(&testkit, &alice.0, 0, 100), (1, vec![creation.hash()]));
+}
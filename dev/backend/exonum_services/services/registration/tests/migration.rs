@@ -0,0 +1,87 @@
+//! Tests of the transactions on a blockchain whose data isn't migrated to the current layout.
+
+extern crate exonum;
+#[macro_use]
+extern crate exonum_testkit;
+extern crate serde_json;
+extern crate zen_rolesystem;
+
+mod common;
+
+use common::{create_account, error_code, testkit_builder, KeyPair};
+use exonum::crypto::{self, PublicKey};
+use exonum::messages::Message;
+use exonum::storage::{Entry, KeySetIndex};
+use exonum_testkit::TestKit;
+use zen_rolesystem::contracts::ErrorKind;
+use zen_rolesystem::governance::AdminAction;
+use zen_rolesystem::migration::CURRENT_VERSION;
+use zen_rolesystem::schema::RoleSystemSchema;
+use zen_rolesystem::transactions::TxProposeAdminAction;
+
+fn propose(admin: &KeyPair, action: &AdminAction) -> TxProposeAdminAction {
+    let action = serde_json::to_string(action).unwrap();
+    TxProposeAdminAction::new(&admin.0, &action, 100, 0, &admin.1)
+}
+
+/// Turns the blockchain into one created before the layout versions were introduced:
+/// there is neither a recorded version nor an admin set.
+fn drop_version_and_admins(testkit: &mut TestKit) {
+    let mut fork = testkit.blockchain_mut().fork();
+    {
+        let mut version: Entry<_, u32> = Entry::new("rolesystem.schema_version", &mut fork);
+        version.remove();
+    }
+    {
+        let mut admins: KeySetIndex<_, PublicKey> = KeySetIndex::new("rolesystem.admins", &mut fork);
+        admins.clear();
+    }
+    testkit.blockchain_mut().merge(fork.into_patch()).unwrap();
+}
+
+#[test]
+fn test_transactions_rejected_until_migrated() {
+    let (admin, alice) = (crypto::gen_keypair(), crypto::gen_keypair());
+    let mut testkit = testkit_builder(&[admin.0], 1).create();
+    drop_version_and_admins(&mut testkit);
+
+    // The admin set is taken from the service configuration before the next commit
+    testkit.create_block();
+    {
+        let snapshot = testkit.snapshot();
+        let schema = RoleSystemSchema::new(&snapshot);
+        assert!(schema.is_admin(&admin.0));
+        assert_eq!(schema.schema_version(), None);
+    }
+
+    let creation = create_account(&alice, "alice");
+    let role = propose(
+        &admin,
+        &AdminAction::SetRole {
+            name: "hr".to_owned(),
+            permissions: 1,
+        },
+    );
+    testkit.create_block_with_transactions(txvec![creation.clone(), role.clone()]);
+    assert_eq!(
+        error_code(&testkit, &creation.hash()),
+        Some(ErrorKind::SchemaNotMigrated.into_code())
+    );
+    assert_eq!(
+        error_code(&testkit, &role.hash()),
+        Some(ErrorKind::SchemaNotMigrated.into_code())
+    );
+
+    let migration = propose(&admin, &AdminAction::MigrateSchema { from_version: 0 });
+    testkit.create_block_with_transactions(txvec![migration.clone()]);
+    assert_eq!(error_code(&testkit, &migration.hash()), None);
+
+    let creation = create_account(&alice, "alice_after_migration");
+    testkit.create_block_with_transactions(txvec![creation.clone()]);
+    assert_eq!(error_code(&testkit, &creation.hash()), None);
+
+    let snapshot = testkit.snapshot();
+    let schema = RoleSystemSchema::new(&snapshot);
+    assert_eq!(schema.schema_version(), Some(CURRENT_VERSION));
+    assert!(schema.account(&alice.0).is_some());
+}
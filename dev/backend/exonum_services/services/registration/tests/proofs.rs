@@ -0,0 +1,63 @@
+//! Tests of the account proofs returned by the public API.
+
+extern crate exonum;
+#[macro_use]
+extern crate exonum_testkit;
+extern crate zen_rolesystem;
+
+mod common;
+
+use common::{create_account, create_testkit};
+use exonum::blockchain::Blockchain;
+use exonum::crypto::{self, PublicKey};
+use exonum_testkit::{ApiKind, TestKit};
+use zen_rolesystem::api::{AccountQuery, AccountWithProof};
+use zen_rolesystem::schema::Account;
+use zen_rolesystem::service::SERVICE_ID;
+
+fn account_info(testkit: &TestKit, pub_key: &PublicKey) -> AccountWithProof {
+    testkit
+        .api()
+        .public(ApiKind::Service("rolesystem"))
+        .query(&AccountQuery { pub_key: *pub_key })
+        .get("v1/accounts/info")
+        .unwrap()
+}
+
+/// Checks both proofs against the block state hash and returns the proven account.
+fn verify(info: AccountWithProof, pub_key: &PublicKey) -> Option<Account> {
+    let to_table = info.account_proof.to_table.check().unwrap();
+    assert_eq!(to_table.merkle_root(), *info.block_proof.block.state_hash());
+
+    let to_account = info.account_proof.to_account.check().unwrap();
+    let table_key = Blockchain::service_table_unique_key(SERVICE_ID, 0);
+    assert_eq!(
+        to_table.entries(),
+        vec![(&table_key, &to_account.merkle_root())]
+    );
+
+    let proven = to_account
+        .all_entries()
+        .into_iter()
+        .find(|&(key, _)| key == pub_key)
+        .expect("Account key is not covered by the proof")
+        .1
+        .cloned();
+    assert_eq!(proven, info.account);
+    proven
+}
+
+#[test]
+fn test_account_proof() {
+    let (alice, bob) = (crypto::gen_keypair(), crypto::gen_keypair());
+    let mut testkit = create_testkit();
+    testkit.create_block_with_transactions(txvec![create_account(&alice, "alice")]);
+
+    let account = verify(account_info(&testkit, &alice.0), &alice.0).unwrap();
+    assert_eq!(account.pub_key(), &alice.0);
+    assert_eq!(account.username(), "alice");
+
+    // Absence of an account is proven as well
+    let info = account_info(&testkit, &bob.0);
+    assert_eq!(verify(info, &bob.0), None);
+}
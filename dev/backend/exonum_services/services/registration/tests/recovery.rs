@@ -0,0 +1,322 @@
+//! Tests of the account recovery by guardians.
+
+extern crate exonum;
+#[macro_use]
+extern crate exonum_testkit;
+extern crate serde_json;
+extern crate zen_rolesystem;
+
+mod common;
+
+use common::{create_account, credentials, error_code, testkit_builder, KeyPair};
+use exonum::crypto::{self, PublicKey};
+use exonum::helpers::Height;
+use exonum::messages::Message;
+use exonum_testkit::TestKit;
+use zen_rolesystem::contracts::ErrorKind;
+use zen_rolesystem::governance::AdminAction;
+use zen_rolesystem::schema::{recovery, RoleSystemSchema};
+use zen_rolesystem::transactions::{TxApproveRecovery, TxCancelRecovery, TxCompleteRecovery,
+                                   TxDeactivateAccount, TxDeleteAccount, TxInitiateRecovery,
+                                   TxProposeAdminAction, TxSetGuardians};
+
+fn set_guardians(owner: &KeyPair, guardians: &[&KeyPair], threshold: u16) -> TxSetGuardians {
+    let guardians = guardians.iter().map(|guardian| guardian.0).collect();
+    TxSetGuardians::new(&owner.0, guardians, threshold, recovery::MIN_DELAY, 0, &owner.1)
+}
+
+fn initiate(guardian: &KeyPair, owner: &PublicKey, new_key: &PublicKey) -> TxInitiateRecovery {
+    TxInitiateRecovery::new(&guardian.0, owner, new_key, 0, &guardian.1)
+}
+
+fn approve(guardian: &KeyPair, owner: &PublicKey, new_key: &PublicKey) -> TxApproveRecovery {
+    TxApproveRecovery::new(&guardian.0, owner, new_key, 0, &guardian.1)
+}
+
+fn complete(new_key: &KeyPair, owner: &PublicKey, seed: u64) -> TxCompleteRecovery {
+    TxCompleteRecovery::new(&new_key.0, owner, credentials(&new_key.0), seed, &new_key.1)
+}
+
+/// Creates the owner and the guardian accounts and sets the guardians of the owner.
+fn setup(owner: &KeyPair, guardians: &[&KeyPair], threshold: u16) -> TestKit {
+    let (admin, _) = crypto::gen_keypair();
+    setup_with_admin(&admin, owner, guardians, threshold)
+}
+
+fn setup_with_admin(
+    admin: &PublicKey,
+    owner: &KeyPair,
+    guardians: &[&KeyPair],
+    threshold: u16,
+) -> TestKit {
+    let mut testkit = testkit_builder(&[*admin], 1).create();
+
+    let mut txs = txvec![create_account(owner, "owner")];
+    for (i, guardian) in guardians.iter().enumerate() {
+        txs.push(Box::new(create_account(guardian, &format!("guardian{}", i))));
+    }
+    testkit.create_block_with_transactions(txs);
+
+    let tx = set_guardians(owner, guardians, threshold);
+    testkit.create_block_with_transactions(txvec![tx.clone()]);
+    assert_eq!(error_code(&testkit, &tx.hash()), None);
+
+    testkit
+}
+
+#[test]
+fn test_recovery() {
+    let (owner, g1, g2, new_key) = (
+        crypto::gen_keypair(),
+        crypto::gen_keypair(),
+        crypto::gen_keypair(),
+        crypto::gen_keypair(),
+    );
+    let mut testkit = setup(&owner, &[&g1, &g2], 2);
+
+    // Initiated at height 3, can be completed from height 3 + MIN_DELAY.
+    let initiation = initiate(&g1, &owner.0, &new_key.0);
+    testkit.create_block_with_transactions(txvec![initiation.clone()]);
+    assert_eq!(error_code(&testkit, &initiation.hash()), None);
+
+    let early = complete(&new_key, &owner.0, 0);
+    testkit.create_block_with_transactions(txvec![early.clone()]);
+    assert_eq!(
+        error_code(&testkit, &early.hash()),
+        Some(ErrorKind::RecoveryNotReady.into_code())
+    );
+
+    let approval = approve(&g2, &owner.0, &new_key.0);
+    let before_delay = complete(&new_key, &owner.0, 1);
+    testkit.create_block_with_transactions(txvec![approval.clone(), before_delay.clone()]);
+    assert_eq!(error_code(&testkit, &approval.hash()), None);
+    assert_eq!(
+        error_code(&testkit, &before_delay.hash()),
+        Some(ErrorKind::RecoveryNotReady.into_code())
+    );
+
+    testkit.create_blocks_until(Height(2 + recovery::MIN_DELAY));
+    let completion = complete(&new_key, &owner.0, 2);
+    testkit.create_block_with_transactions(txvec![completion.clone()]);
+    assert_eq!(error_code(&testkit, &completion.hash()), None);
+
+    let snapshot = testkit.snapshot();
+    let schema = RoleSystemSchema::new(&snapshot);
+    assert!(schema.account(&owner.0).is_none());
+    assert_eq!(schema.account(&new_key.0).unwrap().username(), "owner");
+    assert_eq!(schema.pub_key_by_username("owner"), Some(new_key.0));
+    assert!(schema.recovery(&new_key.0).is_none());
+    assert!(schema.recovery_config(&new_key.0).unwrap().is_guardian(&g1.0));
+    assert!(schema.guarded_accounts(&g1.0).contains(&new_key.0));
+}
+
+#[test]
+fn test_cancel_recovery() {
+    let (owner, g1, new_key) = (
+        crypto::gen_keypair(),
+        crypto::gen_keypair(),
+        crypto::gen_keypair(),
+    );
+    let mut testkit = setup(&owner, &[&g1], 1);
+
+    testkit.create_block_with_transactions(txvec![initiate(&g1, &owner.0, &new_key.0)]);
+
+    let cancel = TxCancelRecovery::new(&owner.0, 0, &owner.1);
+    testkit.create_block_with_transactions(txvec![cancel.clone()]);
+    assert_eq!(error_code(&testkit, &cancel.hash()), None);
+    {
+        let snapshot = testkit.snapshot();
+        let schema = RoleSystemSchema::new(&snapshot);
+        assert!(schema.recovery(&owner.0).is_none());
+        assert_eq!(schema.recovery_approvals(&owner.0).iter().count(), 0);
+    }
+
+    testkit.create_blocks_until(Height(3 + recovery::MIN_DELAY));
+    let completion = complete(&new_key, &owner.0, 0);
+    testkit.create_block_with_transactions(txvec![completion.clone()]);
+    assert_eq!(
+        error_code(&testkit, &completion.hash()),
+        Some(ErrorKind::RecoveryNotFound.into_code())
+    );
+
+    let snapshot = testkit.snapshot();
+    assert!(RoleSystemSchema::new(&snapshot).account(&owner.0).is_some());
+}
+
+#[test]
+fn test_inactive_guardian() {
+    let (owner, g1, g2, new_key) = (
+        crypto::gen_keypair(),
+        crypto::gen_keypair(),
+        crypto::gen_keypair(),
+        crypto::gen_keypair(),
+    );
+    let mut testkit = setup(&owner, &[&g1, &g2], 2);
+
+    let deactivation = TxDeactivateAccount::new(&g1.0, &g1.0, 0, &g1.1);
+    testkit.create_block_with_transactions(txvec![deactivation]);
+
+    // An inactive account can't be nominated as a guardian
+    let nomination = set_guardians(&owner, &[&g1, &g2], 1);
+    testkit.create_block_with_transactions(txvec![nomination.clone()]);
+    assert_eq!(
+        error_code(&testkit, &nomination.hash()),
+        Some(ErrorKind::InvalidGuardians.into_code())
+    );
+
+    let by_inactive = initiate(&g1, &owner.0, &new_key.0);
+    testkit.create_block_with_transactions(txvec![by_inactive.clone()]);
+    assert_eq!(
+        error_code(&testkit, &by_inactive.hash()),
+        Some(ErrorKind::AccountNotActive.into_code())
+    );
+
+    let initiation = initiate(&g2, &owner.0, &new_key.0);
+    let approval = approve(&g1, &owner.0, &new_key.0);
+    testkit.create_block_with_transactions(txvec![initiation.clone(), approval.clone()]);
+    assert_eq!(error_code(&testkit, &initiation.hash()), None);
+    assert_eq!(
+        error_code(&testkit, &approval.hash()),
+        Some(ErrorKind::AccountNotActive.into_code())
+    );
+
+    let snapshot = testkit.snapshot();
+    let schema = RoleSystemSchema::new(&snapshot);
+    assert!(!schema.recovery_approvals(&owner.0).contains(&g1.0));
+}
+
+#[test]
+fn test_deleted_guardian() {
+    let (owner, g1, g2, new_key) = (
+        crypto::gen_keypair(),
+        crypto::gen_keypair(),
+        crypto::gen_keypair(),
+        crypto::gen_keypair(),
+    );
+    let mut testkit = setup(&owner, &[&g1, &g2], 2);
+
+    testkit.create_block_with_transactions(txvec![initiate(&g1, &owner.0, &new_key.0)]);
+
+    let deletion = TxDeleteAccount::new(&g1.0, &g1.0, 0, &g1.1);
+    testkit.create_block_with_transactions(txvec![deletion.clone()]);
+    assert_eq!(error_code(&testkit, &deletion.hash()), None);
+    {
+        let snapshot = testkit.snapshot();
+        let schema = RoleSystemSchema::new(&snapshot);
+        let config = schema.recovery_config(&owner.0).unwrap();
+        assert_eq!(config.guardians(), vec![g2.0]);
+        assert_eq!(config.threshold(), 1);
+        assert_eq!(schema.guarded_accounts(&g1.0).iter().count(), 0);
+        assert!(!schema.recovery_approvals(&owner.0).contains(&g1.0));
+        assert!(schema.recovery(&owner.0).is_some());
+    }
+
+    let by_deleted = approve(&g1, &owner.0, &new_key.0);
+    testkit.create_block_with_transactions(txvec![by_deleted.clone()]);
+    assert_eq!(
+        error_code(&testkit, &by_deleted.hash()),
+        Some(ErrorKind::AccountNotFound.into_code())
+    );
+
+    // Deleting the last guardian drops the guardian settings and the pending recovery.
+    let deletion = TxDeleteAccount::new(&g2.0, &g2.0, 0, &g2.1);
+    testkit.create_block_with_transactions(txvec![deletion]);
+
+    let snapshot = testkit.snapshot();
+    let schema = RoleSystemSchema::new(&snapshot);
+    assert!(schema.recovery_config(&owner.0).is_none());
+    assert!(schema.recovery(&owner.0).is_none());
+    assert_eq!(schema.guarded_accounts(&g2.0).iter().count(), 0);
+}
+
+#[test]
+fn test_recovered_guardian() {
+    let (owner, g1, ward, new_key) = (
+        crypto::gen_keypair(),
+        crypto::gen_keypair(),
+        crypto::gen_keypair(),
+        crypto::gen_keypair(),
+    );
+    let mut testkit = setup(&owner, &[&g1], 1);
+
+    let guarding = set_guardians(&ward, &[&owner], 1);
+    testkit.create_block_with_transactions(txvec![create_account(&ward, "ward"), guarding.clone()]);
+    assert_eq!(error_code(&testkit, &guarding.hash()), None);
+
+    // Initiated at height 4, can be completed from height 4 + MIN_DELAY.
+    testkit.create_block_with_transactions(txvec![initiate(&g1, &owner.0, &new_key.0)]);
+    testkit.create_blocks_until(Height(3 + recovery::MIN_DELAY));
+    let completion = complete(&new_key, &owner.0, 0);
+    testkit.create_block_with_transactions(txvec![completion.clone()]);
+    assert_eq!(error_code(&testkit, &completion.hash()), None);
+    {
+        let snapshot = testkit.snapshot();
+        let schema = RoleSystemSchema::new(&snapshot);
+        let config = schema.recovery_config(&ward.0).unwrap();
+        assert_eq!(config.guardians(), vec![new_key.0]);
+        assert!(schema.guarded_accounts(&new_key.0).contains(&ward.0));
+        assert_eq!(schema.guarded_accounts(&owner.0).iter().count(), 0);
+    }
+
+    // The old key can't act as a guardian anymore, the new one can
+    let (ward_key, _) = crypto::gen_keypair();
+    let by_old_key = initiate(&owner, &ward.0, &ward_key);
+    let by_new_key = initiate(&new_key, &ward.0, &ward_key);
+    testkit.create_block_with_transactions(txvec![by_old_key.clone(), by_new_key.clone()]);
+    assert_eq!(
+        error_code(&testkit, &by_old_key.hash()),
+        Some(ErrorKind::AccountNotFound.into_code())
+    );
+    assert_eq!(error_code(&testkit, &by_new_key.hash()), None);
+}
+
+#[test]
+fn test_admin_recovery_forbidden() {
+    let (owner, g1, other, new_key) = (
+        crypto::gen_keypair(),
+        crypto::gen_keypair(),
+        crypto::gen_keypair(),
+        crypto::gen_keypair(),
+    );
+    let mut testkit = setup_with_admin(&owner.0, &owner, &[&g1], 1);
+
+    let by_admin_guardian = initiate(&g1, &owner.0, &new_key.0);
+    testkit.create_block_with_transactions(txvec![by_admin_guardian.clone()]);
+    assert_eq!(
+        error_code(&testkit, &by_admin_guardian.hash()),
+        Some(ErrorKind::AdminRecoveryForbidden.into_code())
+    );
+
+    // An account that joins the admin set during its recovery can't be moved either
+    let guarding = set_guardians(&other, &[&g1], 1);
+    let initiation = initiate(&g1, &other.0, &new_key.0);
+    testkit.create_block_with_transactions(txvec![
+        create_account(&other, "other"),
+        guarding.clone(),
+        initiation.clone(),
+    ]);
+    assert_eq!(error_code(&testkit, &initiation.hash()), None);
+
+    let action = AdminAction::SetAdmins {
+        admins: vec![owner.0, other.0],
+        threshold: 1,
+    };
+    let action = serde_json::to_string(&action).unwrap();
+    let set_admins = TxProposeAdminAction::new(&owner.0, &action, 100, 0, &owner.1);
+    testkit.create_block_with_transactions(txvec![set_admins.clone()]);
+    assert_eq!(error_code(&testkit, &set_admins.hash()), None);
+
+    testkit.create_blocks_until(Height(4 + recovery::MIN_DELAY));
+    let completion = complete(&new_key, &other.0, 0);
+    testkit.create_block_with_transactions(txvec![completion.clone()]);
+    assert_eq!(
+        error_code(&testkit, &completion.hash()),
+        Some(ErrorKind::AdminRecoveryForbidden.into_code())
+    );
+
+    let snapshot = testkit.snapshot();
+    let schema = RoleSystemSchema::new(&snapshot);
+    assert!(schema.is_admin(&other.0));
+    assert!(schema.account(&other.0).is_some());
+    assert!(schema.account(&new_key.0).is_none());
+}
@@ -0,0 +1,123 @@
+//! Tests of the session keys and their expiry by the consensus time.
+
+extern crate chrono;
+extern crate exonum;
+#[macro_use]
+extern crate exonum_testkit;
+extern crate exonum_time;
+extern crate zen_rolesystem;
+
+mod common;
+
+use chrono::{Duration, TimeZone, Utc};
+use common::{create_account, error_code, testkit_builder, KeyPair};
+use exonum::crypto::{self, PublicKey};
+use exonum::helpers::Height;
+use exonum::messages::Message;
+use exonum_testkit::TestKit;
+use exonum_time::{schema::TimeSchema, time_provider::MockTimeProvider, TimeService};
+use zen_rolesystem::contracts::ErrorKind;
+use zen_rolesystem::schema::RoleSystemSchema;
+use zen_rolesystem::transactions::{TxEditAccount, TxOpenSession};
+
+/// Lifetime of the sessions in the tests, in seconds.
+const DURATION: u64 = 60;
+
+/// Creates the testkit with the time oracle driven by `time` and an account of `alice`.
+fn create_testkit(time: &MockTimeProvider, alice: &KeyPair) -> TestKit {
+    let (admin, _) = crypto::gen_keypair();
+    let mut testkit = testkit_builder(&[admin], 1)
+        .with_service(TimeService::with_provider(time.clone()))
+        .create();
+    testkit.create_blocks_until(Height(2));
+
+    testkit.create_block_with_transactions(txvec![create_account(alice, "alice")]);
+    testkit
+}
+
+fn open_session(alice: &KeyPair, session: &PublicKey, duration: u64, seed: u64) -> TxOpenSession {
+    TxOpenSession::new(&alice.0, &alice.0, session, duration, seed, &alice.1)
+}
+
+/// Renames the account of `alice` with a transaction signed by the session key.
+fn edit_by_session(alice: &PublicKey, session: &KeyPair, username: &str) -> TxEditAccount {
+    TxEditAccount::new(&session.0, alice, username, "", 0, &session.1)
+}
+
+/// Moves the mock time forward and waits until the validators agree on it.
+fn advance_time(testkit: &mut TestKit, time: &MockTimeProvider, seconds: i64) {
+    time.add_time(Duration::seconds(seconds));
+    let height = testkit.height().next().next();
+    testkit.create_blocks_until(height);
+
+    let snapshot = testkit.snapshot();
+    assert_eq!(TimeSchema::new(&snapshot).time().get(), Some(time.time()));
+}
+
+#[test]
+fn test_session_expiry() {
+    let time = MockTimeProvider::new(Utc.timestamp(10, 0));
+    let (alice, session) = (crypto::gen_keypair(), crypto::gen_keypair());
+    let mut testkit = create_testkit(&time, &alice);
+
+    let opening = open_session(&alice, &session.0, DURATION, 0);
+    testkit.create_block_with_transactions(txvec![opening.clone()]);
+    assert_eq!(error_code(&testkit, &opening.hash()), None);
+    {
+        let snapshot = testkit.snapshot();
+        let schema = RoleSystemSchema::new(&snapshot);
+        let opened = schema.session(&session.0).unwrap();
+        assert_eq!(opened.account(), &alice.0);
+        assert_eq!(opened.expires_at(), time.time() + Duration::seconds(DURATION as i64));
+        assert!(schema.account_sessions(&alice.0).contains(&session.0));
+    }
+
+    let edit = edit_by_session(&alice.0, &session, "alice_session");
+    testkit.create_block_with_transactions(txvec![edit.clone()]);
+    assert_eq!(error_code(&testkit, &edit.hash()), None);
+
+    // The same key can't be opened again while the session is active
+    let reopening = open_session(&alice, &session.0, DURATION, 1);
+    testkit.create_block_with_transactions(txvec![reopening.clone()]);
+    assert_eq!(
+        error_code(&testkit, &reopening.hash()),
+        Some(ErrorKind::SessionAlreadyExists.into_code())
+    );
+
+    advance_time(&mut testkit, &time, DURATION as i64);
+
+    let expired = edit_by_session(&alice.0, &session, "alice_expired");
+    testkit.create_block_with_transactions(txvec![expired.clone()]);
+    assert_eq!(
+        error_code(&testkit, &expired.hash()),
+        Some(ErrorKind::PermissionDenied.into_code())
+    );
+    {
+        let snapshot = testkit.snapshot();
+        let schema = RoleSystemSchema::new(&snapshot);
+        assert_eq!(schema.account(&alice.0).unwrap().username(), "alice_session");
+        assert_eq!(schema.session_account(&session.0, time.time()), None);
+    }
+
+    // An expired session key can be opened anew
+    let renewal = open_session(&alice, &session.0, DURATION, 2);
+    testkit.create_block_with_transactions(txvec![renewal.clone()]);
+    assert_eq!(error_code(&testkit, &renewal.hash()), None);
+}
+
+#[test]
+fn test_invalid_session_duration() {
+    let time = MockTimeProvider::new(Utc.timestamp(10, 0));
+    let (alice, session) = (crypto::gen_keypair(), crypto::gen_keypair());
+    let mut testkit = create_testkit(&time, &alice);
+
+    let empty = open_session(&alice, &session.0, 0, 0);
+    let endless = open_session(&alice, &session.0, u64::max_value(), 1);
+    testkit.create_block_with_transactions(txvec![empty.clone(), endless.clone()]);
+    for tx_hash in &[empty.hash(), endless.hash()] {
+        assert_eq!(
+            error_code(&testkit, tx_hash),
+            Some(ErrorKind::InvalidSession.into_code())
+        );
+    }
+}
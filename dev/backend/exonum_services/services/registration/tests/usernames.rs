@@ -0,0 +1,100 @@
+//! Tests of the username uniqueness.
+
+extern crate exonum;
+#[macro_use]
+extern crate exonum_testkit;
+extern crate zen_rolesystem;
+
+mod common;
+
+use common::{create_account, create_testkit, error_code};
+use exonum::crypto::{self, PublicKey};
+use exonum::messages::Message;
+use exonum_testkit::TestKit;
+use zen_rolesystem::contracts::ErrorKind;
+use zen_rolesystem::schema::RoleSystemSchema;
+use zen_rolesystem::transactions::{TxChangeUsername, TxDeleteAccount, TxEditAccount};
+
+fn owner(testkit: &TestKit, username: &str) -> Option<PublicKey> {
+    let snapshot = testkit.snapshot();
+    RoleSystemSchema::new(&snapshot).pub_key_by_username(username)
+}
+
+#[test]
+fn test_username_taken_on_create() {
+    let (alice, bob) = (crypto::gen_keypair(), crypto::gen_keypair());
+    let mut testkit = create_testkit();
+
+    let first = create_account(&alice, "alice");
+    let second = create_account(&bob, "alice");
+    testkit.create_block_with_transactions(txvec![first.clone(), second.clone()]);
+    assert_eq!(error_code(&testkit, &first.hash()), None);
+    assert_eq!(
+        error_code(&testkit, &second.hash()),
+        Some(ErrorKind::UsernameAlreadyExists.into_code())
+    );
+
+    assert_eq!(owner(&testkit, "alice"), Some(alice.0));
+    let snapshot = testkit.snapshot();
+    assert!(RoleSystemSchema::new(&snapshot).account(&bob.0).is_none());
+}
+
+#[test]
+fn test_username_taken_on_rename() {
+    let (alice, bob) = (crypto::gen_keypair(), crypto::gen_keypair());
+    let mut testkit = create_testkit();
+    testkit.create_block_with_transactions(txvec![
+        create_account(&alice, "alice"),
+        create_account(&bob, "bob"),
+    ]);
+
+    let change = TxChangeUsername::new(&bob.0, &bob.0, "alice", 0, &bob.1);
+    let edit = TxEditAccount::new(&bob.0, &bob.0, "alice", "", 1, &bob.1);
+    testkit.create_block_with_transactions(txvec![change.clone(), edit.clone()]);
+    for tx_hash in &[change.hash(), edit.hash()] {
+        assert_eq!(
+            error_code(&testkit, tx_hash),
+            Some(ErrorKind::UsernameAlreadyExists.into_code())
+        );
+    }
+
+    // A successful rename releases the old username
+    let change = TxChangeUsername::new(&bob.0, &bob.0, "robert", 2, &bob.1);
+    testkit.create_block_with_transactions(txvec![change.clone()]);
+    assert_eq!(error_code(&testkit, &change.hash()), None);
+    assert_eq!(owner(&testkit, "alice"), Some(alice.0));
+    assert_eq!(owner(&testkit, "robert"), Some(bob.0));
+    assert_eq!(owner(&testkit, "bob"), None);
+
+    let taken = TxChangeUsername::new(&alice.0, &alice.0, "bob", 0, &alice.1);
+    testkit.create_block_with_transactions(txvec![taken.clone()]);
+    assert_eq!(error_code(&testkit, &taken.hash()), None);
+    assert_eq!(owner(&testkit, "bob"), Some(alice.0));
+}
+
+#[test]
+fn test_username_freed_by_deletion() {
+    let (alice, bob) = (crypto::gen_keypair(), crypto::gen_keypair());
+    let mut testkit = create_testkit();
+    testkit.create_block_with_transactions(txvec![create_account(&alice, "alice")]);
+
+    let deletion = TxDeleteAccount::new(&alice.0, &alice.0, 0, &alice.1);
+    testkit.create_block_with_transactions(txvec![deletion.clone()]);
+    assert_eq!(error_code(&testkit, &deletion.hash()), None);
+    assert_eq!(owner(&testkit, "alice"), None);
+
+    let creation = create_account(&bob, "alice");
+    testkit.create_block_with_transactions(txvec![creation.clone()]);
+    assert_eq!(error_code(&testkit, &creation.hash()), None);
+    assert_eq!(owner(&testkit, "alice"), Some(bob.0));
+
+    // The index is a part of the state hash
+    let snapshot = testkit.snapshot();
+    let schema = RoleSystemSchema::new(&snapshot);
+    let usernames = schema
+        .state_hash_components()
+        .into_iter()
+        .find(|&(name, _)| name == "rolesystem.usernames")
+        .map(|(_, hash)| hash);
+    assert_eq!(usernames, Some(schema.accounts_by_username().merkle_root()));
+}
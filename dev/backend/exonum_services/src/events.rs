@@ -0,0 +1,668 @@
+//! WebSocket subscriptions to the committed blocks, transaction statuses
+//! and account changes of `zen-rolesystem` and `zen-local-database`.
+//!
+//! A client connects to `ws://<events-address>/` and may pass the initial filter
+//! in the query string:
+//!
+//! ```text
+//! ws://127.0.0.1:8300/?events=transaction,account&pub_key=<hex>&tx_type=TxEditAccount
+//! ```
+//!
+//! The filter can be replaced later by sending it as a JSON text message:
+//! `{ "events": ["account"], "pub_keys": ["<hex>"], "tx_types": [] }`.
+
+use exonum::blockchain::{Schema, Service, ServiceContext, Transaction, TransactionErrorType,
+                         TransactionSet};
+use exonum::crypto::{Hash, PublicKey};
+use exonum::encoding::{self, serialize::FromHex};
+use exonum::helpers::fabric::{self, Argument, CommandExtension, CommandName, Context,
+                              ServiceFactory};
+use exonum::helpers::Height;
+use exonum::messages::RawTransaction;
+use exonum::storage::Snapshot;
+use failure;
+use serde_json::{self, Value};
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use ws;
+use zen_local_database::schema::EmployeesSchema;
+use zen_local_database::service::SERVICE_ID as EMPLOYEES_SERVICE_ID;
+use zen_local_database::transactions::EmployeesTransactions;
+use zen_rolesystem::schema::RoleSystemSchema;
+use zen_rolesystem::service::SERVICE_ID as ROLESYSTEM_SERVICE_ID;
+use zen_rolesystem::transactions::RoleSystemTransactions;
+
+/// Service ID for the `Service` trait.
+pub const SERVICE_ID: u16 = 130;
+
+/// Default listen address of the subscription endpoint.
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:8300";
+
+/// Name of the `run` argument with the listen address.
+pub const EVENTS_ADDRESS: &str = "EVENTS_ADDRESS";
+
+/// Kind of the pushed event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// Committed block.
+    Block,
+    /// Status of a committed transaction.
+    Transaction,
+    /// Change of an account made by a committed transaction.
+    Account,
+}
+
+/// Subscription filter. Empty lists match everything, except that account
+/// events are sent only for the listed public keys.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Filter {
+    /// Kinds of the events to send.
+    pub events: Vec<EventKind>,
+    /// Public keys mentioned in the transactions or owning the changed accounts.
+    pub pub_keys: Vec<PublicKey>,
+    /// Transaction type names, either bare (`TxEditAccount`) or qualified
+    /// by the service name (`rolesystem.TxEditAccount`).
+    pub tx_types: Vec<String>,
+}
+
+/// Execution status of a transaction.
+#[derive(Clone, Debug, Serialize)]
+pub struct TxStatus {
+    /// Whether the transaction has been executed successfully.
+    pub success: bool,
+    /// Error code returned by the service, `None` on success or panic.
+    pub code: Option<u8>,
+    /// Error description.
+    pub description: Option<String>,
+}
+
+/// Event pushed to the subscribers.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// Committed block.
+    Block {
+        /// Height of the block.
+        height: Height,
+        /// Hash of the block.
+        block_hash: Hash,
+        /// Number of transactions in the block.
+        tx_count: u32,
+    },
+    /// Committed transaction of a zen service.
+    Transaction {
+        /// Height of the block with the transaction.
+        height: Height,
+        /// Hash of the transaction.
+        tx_hash: Hash,
+        /// Name of the service.
+        service: &'static str,
+        /// Name of the transaction type.
+        tx_type: &'static str,
+        /// Public keys mentioned in the transaction.
+        pub_keys: Vec<PublicKey>,
+        /// Execution status.
+        status: TxStatus,
+    },
+    /// Account changed by a committed transaction.
+    Account {
+        /// Height of the block with the transaction.
+        height: Height,
+        /// Name of the service that stores the account.
+        service: &'static str,
+        /// Public key of the account.
+        pub_key: PublicKey,
+        /// Hash of the transaction.
+        tx_hash: Hash,
+        /// Name of the transaction type.
+        tx_type: &'static str,
+    },
+}
+
+impl Event {
+    fn kind(&self) -> EventKind {
+        match *self {
+            Event::Block { .. } => EventKind::Block,
+            Event::Transaction { .. } => EventKind::Transaction,
+            Event::Account { .. } => EventKind::Account,
+        }
+    }
+}
+
+impl Filter {
+    /// Parses the filter from a query string like
+    /// `events=block,account&pub_key=<hex>&tx_type=TxEditAccount`.
+    pub fn from_query(query: &str) -> Result<Self, String> {
+        let mut filter = Filter::default();
+
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let mut parts = pair.splitn(2, '=');
+            let key = parts.next().unwrap_or_default();
+            let value = parts.next().unwrap_or_default();
+
+            match key {
+                "events" => for kind in value.split(',').filter(|kind| !kind.is_empty()) {
+                    let kind = serde_json::from_value(Value::String(kind.to_owned()))
+                        .map_err(|_| format!("Unknown event kind {}", kind))?;
+                    filter.events.push(kind);
+                },
+                "pub_key" => {
+                    let pub_key = PublicKey::from_hex(value)
+                        .map_err(|_| format!("Invalid public key {}", value))?;
+                    filter.pub_keys.push(pub_key);
+                }
+                "tx_type" => filter.tx_types.push(value.to_owned()),
+                _ => return Err(format!("Unknown filter parameter {}", key)),
+            }
+        }
+
+        Ok(filter)
+    }
+
+    fn accepts_kind(&self, kind: EventKind) -> bool {
+        self.events.is_empty() || self.events.contains(&kind)
+    }
+
+    fn accepts_tx_type(&self, service: &str, tx_type: &str) -> bool {
+        self.tx_types.is_empty() || self.tx_types.iter().any(|name| {
+            name == tx_type || *name == format!("{}.{}", service, tx_type)
+        })
+    }
+
+    /// Checks that the event passes the filter.
+    pub fn matches(&self, event: &Event) -> bool {
+        if !self.accepts_kind(event.kind()) {
+            return false;
+        }
+
+        match *event {
+            Event::Block { .. } => true,
+            Event::Transaction {
+                service,
+                tx_type,
+                ref pub_keys,
+                ..
+            } => {
+                self.accepts_tx_type(service, tx_type)
+                    && (self.pub_keys.is_empty()
+                        || pub_keys.iter().any(|key| self.pub_keys.contains(key)))
+            }
+            Event::Account {
+                service,
+                tx_type,
+                ref pub_key,
+                ..
+            } => self.accepts_tx_type(service, tx_type) && self.pub_keys.contains(pub_key),
+        }
+    }
+}
+
+struct Subscriber {
+    sender: ws::Sender,
+    filter: Filter,
+}
+
+type Subscribers = Arc<Mutex<HashMap<u32, Subscriber>>>;
+
+/// WebSocket connection of a single subscriber.
+struct Connection {
+    sender: ws::Sender,
+    subscribers: Subscribers,
+    filter: Filter,
+}
+
+impl ws::Handler for Connection {
+    fn on_request(&mut self, request: &ws::Request) -> ws::Result<ws::Response> {
+        let query = request.resource().splitn(2, '?').nth(1).unwrap_or_default();
+
+        match Filter::from_query(query) {
+            Ok(filter) => {
+                self.filter = filter;
+                ws::Response::from_request(request)
+            }
+            Err(e) => Ok(ws::Response::new(400, "Bad Request", e.into_bytes())),
+        }
+    }
+
+    fn on_open(&mut self, _: ws::Handshake) -> ws::Result<()> {
+        let subscriber = Subscriber {
+            sender: self.sender.clone(),
+            filter: self.filter.clone(),
+        };
+        self.subscribers
+            .lock()
+            .unwrap()
+            .insert(self.sender.connection_id(), subscriber);
+        Ok(())
+    }
+
+    fn on_message(&mut self, message: ws::Message) -> ws::Result<()> {
+        let filter = message
+            .as_text()
+            .map_err(|e| e.to_string())
+            .and_then(|text| serde_json::from_str::<Filter>(text).map_err(|e| e.to_string()));
+
+        match filter {
+            Ok(filter) => {
+                if let Some(subscriber) = self.subscribers
+                    .lock()
+                    .unwrap()
+                    .get_mut(&self.sender.connection_id())
+                {
+                    subscriber.filter = filter;
+                }
+                Ok(())
+            }
+            Err(e) => self.sender
+                .send(json!({ "type": "error", "description": e }).to_string()),
+        }
+    }
+
+    fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .remove(&self.sender.connection_id());
+    }
+}
+
+/// Service that pushes events of the committed blocks to the WebSocket subscribers.
+/// It has no transactions and no state.
+pub struct EventsService {
+    subscribers: Subscribers,
+}
+
+impl EventsService {
+    /// Starts the subscription endpoint on the given address.
+    pub fn new(address: SocketAddr) -> EventsService {
+        let subscribers = Subscribers::default();
+
+        let connections = subscribers.clone();
+        thread::spawn(move || {
+            ws::listen(address, |sender| Connection {
+                sender,
+                subscribers: connections.clone(),
+                filter: Filter::default(),
+            }).expect("Could not start the events endpoint");
+        });
+
+        EventsService { subscribers }
+    }
+
+    /// Collects the events of the last committed block. Account events are
+    /// collected only for the given public keys.
+    pub fn block_events(snapshot: &Snapshot, pub_keys: &HashSet<PublicKey>) -> Vec<Event> {
+        let schema = Schema::new(snapshot);
+        let height = schema.height();
+        let block_hash = schema
+            .block_hash_by_height(height)
+            .expect("Committed block not found");
+        let tx_hashes: Vec<Hash> = schema.block_transactions(height).iter().collect();
+
+        let mut events = vec![Event::Block {
+            height,
+            block_hash,
+            tx_count: tx_hashes.len() as u32,
+        }];
+        let mut tx_types = HashMap::new();
+
+        for tx_hash in &tx_hashes {
+            let raw = match schema.transactions().get(tx_hash) {
+                Some(raw) => raw,
+                None => continue,
+            };
+
+            let (service, tx_type, json) = match decode(raw) {
+                Some(decoded) => decoded,
+                None => continue,
+            };
+            tx_types.insert(*tx_hash, (service, tx_type));
+
+            let mut keys = Vec::new();
+            collect_pub_keys(&json["body"], &mut keys);
+
+            let status = match schema.transaction_results().get(tx_hash) {
+                Some(Err(e)) => TxStatus {
+                    success: false,
+                    code: match e.error_type() {
+                        TransactionErrorType::Code(code) => Some(code),
+                        TransactionErrorType::Panic => None,
+                    },
+                    description: e.description().map(str::to_owned),
+                },
+                _ => TxStatus {
+                    success: true,
+                    code: None,
+                    description: None,
+                },
+            };
+
+            events.push(Event::Transaction {
+                height,
+                tx_hash: *tx_hash,
+                service,
+                tx_type,
+                pub_keys: keys,
+                status,
+            });
+        }
+
+        // An account is changed by the transaction if it has been appended
+        // to the account history in this block.
+        let block_txs: HashSet<Hash> = tx_hashes.iter().cloned().collect();
+        let rolesystem = RoleSystemSchema::new(snapshot);
+        let employees = EmployeesSchema::new(snapshot);
+
+        for pub_key in pub_keys {
+            let histories = vec![
+                ("rolesystem", rolesystem.account_history(pub_key)),
+                ("employees", employees.account_history(pub_key)),
+            ];
+
+            for (service, history) in histories {
+                let mut changes: Vec<Hash> = history
+                    .iter()
+                    .rev()
+                    .take_while(|tx_hash| block_txs.contains(tx_hash))
+                    .collect();
+                changes.reverse();
+
+                for tx_hash in changes {
+                    let tx_type = tx_types.get(&tx_hash).map_or("", |&(_, tx_type)| tx_type);
+                    events.push(Event::Account {
+                        height,
+                        service,
+                        pub_key: *pub_key,
+                        tx_hash,
+                        tx_type,
+                    });
+                }
+            }
+        }
+
+        events
+    }
+}
+
+/// Decodes a transaction of a zen service into its service name, type name and JSON.
+fn decode(raw: RawTransaction) -> Option<(&'static str, &'static str, Value)> {
+    match raw.service_id() {
+        ROLESYSTEM_SERVICE_ID => RoleSystemTransactions::tx_from_raw(raw)
+            .ok()
+            .map(|tx| ("rolesystem", tx.name(), serde_json::to_value(&tx).unwrap())),
+        EMPLOYEES_SERVICE_ID => EmployeesTransactions::tx_from_raw(raw)
+            .ok()
+            .map(|tx| ("employees", tx.name(), serde_json::to_value(&tx).unwrap())),
+        _ => None,
+    }
+}
+
+/// Collects all public keys from the transaction body, including lists of keys.
+fn collect_pub_keys(value: &Value, keys: &mut Vec<PublicKey>) {
+    match *value {
+        Value::String(ref s) if s.len() == 2 * ::exonum::crypto::PUBLIC_KEY_LENGTH => {
+            if let Ok(pub_key) = PublicKey::from_hex(s) {
+                if !keys.contains(&pub_key) {
+                    keys.push(pub_key);
+                }
+            }
+        }
+        Value::Array(ref values) => for value in values {
+            collect_pub_keys(value, keys);
+        },
+        Value::Object(ref fields) => for value in fields.values() {
+            collect_pub_keys(value, keys);
+        },
+        _ => {}
+    }
+}
+
+impl Service for EventsService {
+    fn service_id(&self) -> u16 {
+        SERVICE_ID
+    }
+
+    fn service_name(&self) -> &'static str {
+        "zen_events"
+    }
+
+    fn state_hash(&self, _: &Snapshot) -> Vec<Hash> {
+        Vec::new()
+    }
+
+    fn tx_from_raw(&self, raw: RawTransaction) -> Result<Box<Transaction>, encoding::Error> {
+        Err(encoding::Error::IncorrectMessageType {
+            message_type: raw.message_type(),
+        })
+    }
+
+    /// Pushes the events of the committed block to the subscribers.
+    fn after_commit(&self, context: &ServiceContext) {
+        let pub_keys: HashSet<PublicKey> = {
+            let subscribers = self.subscribers.lock().unwrap();
+            if subscribers.is_empty() {
+                return;
+            }
+
+            subscribers
+                .values()
+                .filter(|subscriber| subscriber.filter.accepts_kind(EventKind::Account))
+                .flat_map(|subscriber| subscriber.filter.pub_keys.iter().cloned())
+                .collect()
+        };
+
+        // Collecting the events reads the whole block, so it is done without
+        // holding the lock to not stall the connections.
+        let events = Self::block_events(context.snapshot(), &pub_keys);
+
+        let messages: Vec<(ws::Sender, Vec<String>)> = self.subscribers
+            .lock()
+            .unwrap()
+            .values()
+            .map(|subscriber| {
+                let messages = events
+                    .iter()
+                    .filter(|event| subscriber.filter.matches(event))
+                    .map(|event| serde_json::to_string(event).unwrap())
+                    .collect();
+                (subscriber.sender.clone(), messages)
+            })
+            .collect();
+
+        for (sender, messages) in messages {
+            for message in messages {
+                if let Err(e) = sender.send(message) {
+                    warn!("Could not push an event to the subscriber: {}", e);
+                }
+            }
+        }
+    }
+}
+
+/// An events service creator for the `NodeBuilder`.
+#[derive(Debug)]
+pub struct EventsServiceFactory;
+
+struct RunEvents;
+
+impl CommandExtension for RunEvents {
+    fn args(&self) -> Vec<Argument> {
+        vec![
+            Argument::new_named(
+                EVENTS_ADDRESS,
+                false,
+                "Listen address of the WebSocket subscriptions.",
+                None,
+                "events-address",
+                false,
+            ),
+        ]
+    }
+
+    fn execute(&self, context: Context) -> Result<Context, failure::Error> {
+        Ok(context)
+    }
+}
+
+impl ServiceFactory for EventsServiceFactory {
+    fn service_name(&self) -> &str {
+        "zen_events"
+    }
+
+    fn command(&mut self, command: CommandName) -> Option<Box<CommandExtension>> {
+        if command == fabric::Run::name() {
+            Some(Box::new(RunEvents))
+        } else {
+            None
+        }
+    }
+
+    fn make_service(&mut self, run_context: &Context) -> Box<Service> {
+        let address = run_context
+            .arg::<SocketAddr>(EVENTS_ADDRESS)
+            .unwrap_or_else(|_| DEFAULT_ADDRESS.parse().unwrap());
+
+        Box::new(EventsService::new(address))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exonum::crypto;
+    use exonum::encoding::serialize::ToHex;
+
+    fn block() -> Event {
+        Event::Block {
+            height: Height(1),
+            block_hash: Hash::zero(),
+            tx_count: 1,
+        }
+    }
+
+    fn transaction(tx_type: &'static str, pub_keys: Vec<PublicKey>) -> Event {
+        Event::Transaction {
+            height: Height(1),
+            tx_hash: Hash::zero(),
+            service: "rolesystem",
+            tx_type,
+            pub_keys,
+            status: TxStatus {
+                success: true,
+                code: None,
+                description: None,
+            },
+        }
+    }
+
+    fn account(tx_type: &'static str, pub_key: PublicKey) -> Event {
+        Event::Account {
+            height: Height(1),
+            service: "rolesystem",
+            pub_key,
+            tx_hash: Hash::zero(),
+            tx_type,
+        }
+    }
+
+    #[test]
+    fn test_from_query() {
+        let (pub_key, _) = crypto::gen_keypair();
+        let query = format!(
+            "events=block,account&pub_key={}&tx_type=TxEditAccount&tx_type=employees.TxAddEmployee",
+            pub_key.to_hex()
+        );
+
+        assert_eq!(
+            Filter::from_query(&query),
+            Ok(Filter {
+                events: vec![EventKind::Block, EventKind::Account],
+                pub_keys: vec![pub_key],
+                tx_types: vec![
+                    "TxEditAccount".to_owned(),
+                    "employees.TxAddEmployee".to_owned(),
+                ],
+            })
+        );
+        assert_eq!(Filter::from_query(""), Ok(Filter::default()));
+        assert_eq!(Filter::from_query("events=&"), Ok(Filter::default()));
+    }
+
+    #[test]
+    fn test_from_query_errors() {
+        assert!(Filter::from_query("events=blocks").is_err());
+        assert!(Filter::from_query("pub_key=00ff").is_err());
+        assert!(Filter::from_query("pub_key").is_err());
+        assert!(Filter::from_query("height=1").is_err());
+    }
+
+    #[test]
+    fn test_matches_everything_by_default() {
+        let (pub_key, _) = crypto::gen_keypair();
+        let filter = Filter::default();
+
+        assert!(filter.matches(&block()));
+        assert!(filter.matches(&transaction("TxEditAccount", vec![pub_key])));
+        assert!(filter.matches(&transaction("TxEditAccount", Vec::new())));
+        // Account events are sent only for the listed keys
+        assert!(!filter.matches(&account("TxEditAccount", pub_key)));
+    }
+
+    #[test]
+    fn test_matches_kinds() {
+        let (pub_key, _) = crypto::gen_keypair();
+        let filter = Filter {
+            events: vec![EventKind::Account],
+            pub_keys: vec![pub_key],
+            tx_types: Vec::new(),
+        };
+
+        assert!(!filter.matches(&block()));
+        assert!(!filter.matches(&transaction("TxEditAccount", vec![pub_key])));
+        assert!(filter.matches(&account("TxEditAccount", pub_key)));
+    }
+
+    #[test]
+    fn test_matches_pub_keys() {
+        let (pub_key, _) = crypto::gen_keypair();
+        let (other, _) = crypto::gen_keypair();
+        let filter = Filter {
+            events: Vec::new(),
+            pub_keys: vec![pub_key],
+            tx_types: Vec::new(),
+        };
+
+        assert!(filter.matches(&block()));
+        assert!(filter.matches(&transaction("TxEditAccount", vec![other, pub_key])));
+        assert!(!filter.matches(&transaction("TxEditAccount", vec![other])));
+        assert!(filter.matches(&account("TxEditAccount", pub_key)));
+        assert!(!filter.matches(&account("TxEditAccount", other)));
+    }
+
+    #[test]
+    fn test_matches_tx_types() {
+        let (pub_key, _) = crypto::gen_keypair();
+        let filter = Filter {
+            events: Vec::new(),
+            pub_keys: vec![pub_key],
+            tx_types: vec!["TxEditAccount".to_owned(), "rolesystem.TxSetGuardians".to_owned()],
+        };
+
+        assert!(filter.matches(&block()));
+        assert!(filter.matches(&transaction("TxEditAccount", vec![pub_key])));
+        assert!(filter.matches(&transaction("TxSetGuardians", vec![pub_key])));
+        assert!(!filter.matches(&transaction("TxCreateAccount", vec![pub_key])));
+        assert!(filter.matches(&account("TxEditAccount", pub_key)));
+        assert!(!filter.matches(&account("TxCreateAccount", pub_key)));
+
+        let filter = Filter {
+            tx_types: vec!["employees.TxEditAccount".to_owned()],
+            ..filter
+        };
+        assert!(!filter.matches(&transaction("TxEditAccount", vec![pub_key])));
+    }
+}
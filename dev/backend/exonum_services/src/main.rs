@@ -1,26 +1,56 @@
 extern crate exonum;
 extern crate exonum_configuration;
+extern crate exonum_time;
+#[macro_use]
+extern crate failure;
+#[macro_use]
+extern crate log;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+extern crate toml;
+extern crate ws;
 extern crate zen_local_database;
 extern crate zen_rolesystem;
 
 use exonum::helpers;
-use exonum::helpers::fabric::NodeBuilder;
-use exonum_configuration as configuration;
-use zen_local_database::cmd::ZenLocalDBServiceFactory;
-use zen_rolesystem::cmd::RoleSystemServiceFactory;
 
 use std::env;
 
+/// WebSocket subscriptions to block and account events.
+mod events;
+
+/// Node profiles.
+mod profile;
+
+/// Local testnet bootstrap.
+mod testnet;
+
+use profile::Profile;
+use std::process;
+use testnet::TestnetOptions;
+
 fn main() {
     env::set_var("RUST_LOG", "info");
 
     exonum::crypto::init();
     helpers::init_logger().unwrap();
 
-    let node = NodeBuilder::new()
-        .with_service(Box::new(configuration::ServiceFactory))
-        .with_service(Box::new(ZenLocalDBServiceFactory))
-        .with_service(Box::new(RoleSystemServiceFactory));
+    if env::args().nth(1).as_ref().map(String::as_str) == Some(testnet::COMMAND) {
+        let result = TestnetOptions::from_args(env::args().skip(2))
+            .and_then(|options| testnet::run(&options));
+
+        if let Err(e) = result {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    let profile = Profile::from_args(env::args());
+    let node = profile.node_builder();
 
     node.run();
 }
@@ -0,0 +1,252 @@
+//! Node profiles.
+//!
+//! A profile selects the services run by the node and how the node is exposed
+//! to the network. The profile is taken from the `--profile` argument or from
+//! the `profile` key of the file given by `--profile-config`:
+//!
+//! ```toml
+//! profile = "local"
+//! ```
+
+use events::EventsServiceFactory;
+use exonum::blockchain::Service;
+use exonum::helpers::fabric::{self, keys, Argument, CommandExtension, CommandName, Context,
+                              NodeBuilder, ServiceFactory};
+use exonum::node::NodeConfig;
+use exonum_configuration as configuration;
+use exonum_time::TimeServiceFactory;
+use failure;
+use std::fs::File;
+use std::io::Read;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::str::FromStr;
+use toml;
+use zen_local_database::cmd::ZenLocalDBServiceFactory;
+use zen_rolesystem::cmd::RoleSystemServiceFactory;
+
+const PROFILE: &str = "PROFILE";
+const PROFILE_CONFIG: &str = "PROFILE_CONFIG";
+const DATABASE_PATH: &str = "DATABASE_PATH";
+
+/// Set of services run by the node. Both profiles push events of the
+/// committed blocks to the WebSocket subscribers.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Profile {
+    /// Single validator bound to loopback that serves `zen-local-database`.
+    /// `zen-rolesystem` is included as the source of the permissions, together with
+    /// the time service its sessions depend on.
+    Local,
+    /// Validator of the zen network with `zen-rolesystem`, the configuration
+    /// and the time services.
+    Network,
+}
+
+/// Profile settings file.
+#[derive(Debug, Deserialize)]
+pub struct ProfileConfig {
+    /// Selected profile.
+    pub profile: Profile,
+}
+
+impl Profile {
+    /// Returns the profile name used on the command line.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Profile::Local => "local",
+            Profile::Network => "network",
+        }
+    }
+
+    /// Selects the profile from the command line arguments. `--profile` takes
+    /// precedence over `--profile-config`; defaults to `network`.
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Profile {
+        let mut profile = None;
+        let mut config_path = None;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--profile" => profile = args.next(),
+                "--profile-config" => config_path = args.next(),
+                _ if arg.starts_with("--profile=") => {
+                    profile = Some(arg["--profile=".len()..].to_owned())
+                }
+                _ if arg.starts_with("--profile-config=") => {
+                    config_path = Some(arg["--profile-config=".len()..].to_owned())
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(profile) = profile {
+            return profile.parse().unwrap_or_else(|e| panic!("{}", e));
+        }
+
+        config_path.map_or(Profile::Network, |path| {
+            ProfileConfig::load(&path)
+                .unwrap_or_else(|e| panic!("Can't load profile config {}: {}", path, e))
+                .profile
+        })
+    }
+
+    /// Returns factories of the services run in the profile.
+    pub fn service_factories(self) -> Vec<Box<ServiceFactory>> {
+        match self {
+            Profile::Local => vec![
+                Box::new(ZenLocalDBServiceFactory),
+                Box::new(TimeServiceFactory),
+                Box::new(RoleSystemServiceFactory),
+                Box::new(EventsServiceFactory),
+            ],
+            Profile::Network => vec![
+                Box::new(configuration::ServiceFactory),
+                Box::new(TimeServiceFactory),
+                Box::new(RoleSystemServiceFactory),
+                Box::new(EventsServiceFactory),
+            ],
+        }
+    }
+
+    /// Creates a node builder with the services of the profile.
+    pub fn node_builder(self) -> NodeBuilder {
+        let mut factories = self.service_factories().into_iter();
+        let first = factories.next().expect("Profile without services");
+        let builder = NodeBuilder::new().with_service(Box::new(ProfileServiceFactory {
+            profile: self,
+            inner: first,
+        }));
+
+        factories.fold(builder, |builder, factory| builder.with_service(factory))
+    }
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "local" => Ok(Profile::Local),
+            "network" => Ok(Profile::Network),
+            _ => Err(format!("Unknown profile {}, expected local or network", s)),
+        }
+    }
+}
+
+impl ProfileConfig {
+    /// Loads profile settings from a TOML file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, failure::Error> {
+        let mut content = String::new();
+        File::open(path)?.read_to_string(&mut content)?;
+        Ok(toml::from_str(&content)?)
+    }
+}
+
+/// Service factory wrapper that adds the profile arguments to every command
+/// and applies the profile in the `run` command.
+struct ProfileServiceFactory {
+    profile: Profile,
+    inner: Box<ServiceFactory>,
+}
+
+impl ServiceFactory for ProfileServiceFactory {
+    fn service_name(&self) -> &str {
+        self.inner.service_name()
+    }
+
+    fn command(&mut self, command: CommandName) -> Option<Box<CommandExtension>> {
+        Some(Box::new(ProfileExtension {
+            profile: self.profile,
+            is_run: command == fabric::Run::name(),
+            inner: self.inner.command(command),
+        }))
+    }
+
+    fn make_service(&mut self, run_context: &Context) -> Box<Service> {
+        self.inner.make_service(run_context)
+    }
+}
+
+/// Command extension that accepts the profile arguments. In the `run` command
+/// it moves the database into a per-profile directory and, for the `local`
+/// profile, binds the node to loopback without peers.
+struct ProfileExtension {
+    profile: Profile,
+    is_run: bool,
+    inner: Option<Box<CommandExtension>>,
+}
+
+impl ProfileExtension {
+    fn apply_local(config: &mut NodeConfig) -> Result<(), failure::Error> {
+        if config.genesis.validator_keys.len() != 1 {
+            bail!(
+                "Local profile requires a single validator, found {}",
+                config.genesis.validator_keys.len()
+            );
+        }
+
+        let loopback = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+        let to_loopback = |address: SocketAddr| SocketAddr::new(loopback, address.port());
+
+        config.listen_address = to_loopback(config.listen_address);
+        config.external_address = to_loopback(config.external_address);
+        config.connect_list.peers.clear();
+        config.api.public_api_address = config.api.public_api_address.map(to_loopback);
+        config.api.private_api_address = config.api.private_api_address.map(to_loopback);
+
+        Ok(())
+    }
+}
+
+impl CommandExtension for ProfileExtension {
+    fn args(&self) -> Vec<Argument> {
+        let mut args = vec![
+            Argument::new_named(
+                PROFILE,
+                false,
+                "Node profile: local or network.",
+                None,
+                "profile",
+                false,
+            ),
+            Argument::new_named(
+                PROFILE_CONFIG,
+                false,
+                "Path to the profile settings file.",
+                None,
+                "profile-config",
+                false,
+            ),
+        ];
+
+        if let Some(ref inner) = self.inner {
+            args.extend(inner.args());
+        }
+
+        args
+    }
+
+    fn execute(&self, context: Context) -> Result<Context, failure::Error> {
+        let mut context = match self.inner {
+            Some(ref inner) => inner.execute(context)?,
+            None => context,
+        };
+
+        if !self.is_run {
+            return Ok(context);
+        }
+
+        let db_path = context.arg::<String>(DATABASE_PATH)?;
+        let db_path = Path::new(&db_path).join(self.profile.name());
+        context.set_arg(DATABASE_PATH, db_path.to_string_lossy().into_owned());
+
+        if self.profile == Profile::Local {
+            let mut node_config: NodeConfig = context.get(keys::NODE_CONFIG)?;
+            Self::apply_local(&mut node_config)?;
+            context.set(keys::NODE_CONFIG, node_config);
+        }
+
+        Ok(context)
+    }
+}
@@ -0,0 +1,276 @@
+//! Local testnet bootstrap.
+//!
+//! `exonumcore testnet --validators N` replaces the `generate-template`,
+//! `generate-config`, `finalize` and `run` sequence: it generates validator
+//! and admin keys, writes the common and per-node configs and runs all
+//! validators of the `network` profile in one process on loopback.
+
+use events;
+use exonum::crypto::{gen_keypair, PublicKey, SecretKey};
+use exonum::encoding::serialize::ToHex;
+use exonum::helpers::config::ConfigFile;
+use exonum::helpers::fabric::{keys, CommonConfigTemplate, Context};
+use exonum::helpers::generate_testnet_config;
+use exonum::node::{Node, NodeConfig};
+use exonum::storage::{Database, RocksDB};
+use failure;
+use profile::Profile;
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::thread;
+use toml::Value;
+use zen_rolesystem::cmd::RoleSystemServiceConfig;
+
+/// Name of the subcommand.
+pub const COMMAND: &str = "testnet";
+
+/// Testnet settings taken from the command line.
+#[derive(Debug)]
+pub struct TestnetOptions {
+    /// Number of validators.
+    pub validators: u8,
+    /// Peer port of the first validator, the others use the following ports.
+    pub start_port: u16,
+    /// Public API port of the first validator. Validator `i` serves the public API
+    /// on `api_port + 2 * i` and the private API on the next port. Event subscriptions
+    /// are served on the ports following the APIs of all validators.
+    pub api_port: u16,
+    /// Directory for configs, keys and databases.
+    pub output_dir: PathBuf,
+}
+
+/// Admin keypair written for the frontend to sign admin actions.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdminKeys {
+    /// Public key from the admin set.
+    pub public_key: PublicKey,
+    /// Secret key of the admin.
+    pub secret_key: SecretKey,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AdminKeysFile {
+    admins: Vec<AdminKeys>,
+}
+
+impl TestnetOptions {
+    /// Parses `--validators`, `--start-port`, `--api-port` and `--output-dir`
+    /// arguments following the subcommand.
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Self, failure::Error> {
+        let mut options = TestnetOptions {
+            validators: 0,
+            start_port: 6330,
+            api_port: 8200,
+            output_dir: PathBuf::from(".zen-testnet"),
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let value = match args.next() {
+                Some(value) => value,
+                None => bail!("Missing value of {}", arg),
+            };
+
+            match arg.as_str() {
+                "--validators" | "-n" => options.validators = value.parse()?,
+                "--start-port" => options.start_port = value.parse()?,
+                "--api-port" => options.api_port = value.parse()?,
+                "--output-dir" | "-o" => options.output_dir = PathBuf::from(value),
+                _ => bail!(
+                    "Unknown argument {}. Usage: testnet --validators N [--start-port PORT] \
+                     [--api-port PORT] [--output-dir DIR]",
+                    arg
+                ),
+            }
+        }
+
+        options.validate()?;
+        Ok(options)
+    }
+
+    /// Checks that there are validators and their peer, API and event ports fit into `u16`.
+    fn validate(&self) -> Result<(), failure::Error> {
+        if self.validators == 0 {
+            bail!("Number of validators should be positive");
+        }
+
+        let validators = u16::from(self.validators);
+        if self.start_port.checked_add(validators - 1).is_none() {
+            bail!(
+                "Peer ports of {} validators starting from {} exceed {}",
+                validators,
+                self.start_port,
+                u16::max_value()
+            );
+        }
+
+        // Two API ports per validator followed by one events port per validator.
+        let last_api_port = validators
+            .checked_mul(3)
+            .and_then(|count| self.api_port.checked_add(count - 1));
+        if last_api_port.is_none() {
+            bail!(
+                "API ports of {} validators starting from {} exceed {}",
+                validators,
+                self.api_port,
+                u16::max_value()
+            );
+        }
+
+        Ok(())
+    }
+
+    fn node_dir(&self, index: usize) -> PathBuf {
+        self.output_dir.join(format!("validator_{}", index))
+    }
+
+    /// Returns the API address of the validator. The ports must be checked
+    /// by `validate` first.
+    fn api_address(&self, index: usize, offset: u16) -> SocketAddr {
+        let port = self.api_port + 2 * index as u16 + offset;
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    /// Returns the events address of the validator. The ports must be checked
+    /// by `validate` first.
+    fn events_address(&self, index: usize) -> SocketAddr {
+        let port = self.api_port + 2 * u16::from(self.validators) + index as u16;
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+}
+
+/// Generates the testnet configs and runs the validators until they stop.
+pub fn run(options: &TestnetOptions) -> Result<(), failure::Error> {
+    let configs = generate(options)?;
+
+    let nodes: Vec<_> = configs
+        .into_iter()
+        .enumerate()
+        .map(|(index, config)| {
+            let db_path = options.node_dir(index).join("db");
+            let events_address = options.events_address(index);
+            thread::spawn(move || run_node(config, &db_path, events_address))
+        })
+        .collect();
+
+    for node in nodes {
+        node.join().expect("Validator thread panicked");
+    }
+
+    Ok(())
+}
+
+/// Writes `common.toml`, `admins.toml` and `validator_<i>/node.toml` into the output
+/// directory and returns the node configs.
+pub fn generate(options: &TestnetOptions) -> Result<Vec<NodeConfig>, failure::Error> {
+    options.validate()?;
+
+    let count = options.validators as usize;
+    let mut configs = generate_testnet_config(options.validators, options.start_port);
+
+    let admins: Vec<AdminKeys> = (0..count)
+        .map(|_| {
+            let (public_key, secret_key) = gen_keypair();
+            AdminKeys {
+                public_key,
+                secret_key,
+            }
+        })
+        .collect();
+    let admin_pkeys: Vec<String> = admins.iter().map(|keys| keys.public_key.to_hex()).collect();
+    let admin_threshold = count as u16 / 2 + 1;
+
+    fs::create_dir_all(&options.output_dir)?;
+
+    let mut services_config = BTreeMap::new();
+    services_config.insert("admin_pub_keys".to_owned(), Value::try_from(&admin_pkeys)?);
+    services_config.insert("admin_threshold".to_owned(), Value::try_from(admin_threshold)?);
+
+    let mut general_config = BTreeMap::new();
+    general_config.insert("validators_count".to_owned(), Value::try_from(count)?);
+
+    let common = CommonConfigTemplate {
+        consensus_config: configs[0].genesis.consensus.clone(),
+        services_config,
+        general_config,
+    };
+    ConfigFile::save(&common, options.output_dir.join("common.toml"))?;
+    ConfigFile::save(&AdminKeysFile { admins }, options.output_dir.join("admins.toml"))?;
+
+    for (index, config) in configs.iter_mut().enumerate() {
+        let node_dir = options.node_dir(index);
+        fs::create_dir_all(&node_dir)?;
+
+        config.api.public_api_address = Some(options.api_address(index, 0));
+        config.api.private_api_address = Some(options.api_address(index, 1));
+        config.services_configs.insert(
+            "rolesystem_service".to_owned(),
+            Value::try_from(RoleSystemServiceConfig {
+                admin_pkeys: admin_pkeys.clone(),
+                admin_threshold,
+                blockstore_path: Some(node_dir.join("photos").to_string_lossy().into_owned()),
+            })?,
+        );
+
+        ConfigFile::save(config, node_dir.join("node.toml"))?;
+
+        println!(
+            "validator {}: peer {}, public api {}, private api {}, events ws://{}",
+            index,
+            config.listen_address,
+            options.api_address(index, 0),
+            options.api_address(index, 1),
+            options.events_address(index)
+        );
+    }
+
+    println!(
+        "admin keys: {} (threshold {} of {})",
+        options.output_dir.join("admins.toml").display(),
+        admin_threshold,
+        count
+    );
+
+    Ok(configs)
+}
+
+/// Runs a single validator with the services of the `network` profile.
+fn run_node(config: NodeConfig, db_path: &Path, events_address: SocketAddr) {
+    let db = RocksDB::open(db_path, &config.database).expect("Can't load database file");
+
+    let mut context = Context::default();
+    context.set(keys::NODE_CONFIG, config.clone());
+    context.set_arg(events::EVENTS_ADDRESS, events_address.to_string());
+
+    let services = Profile::Network
+        .service_factories()
+        .into_iter()
+        .map(|mut factory| factory.make_service(&context))
+        .collect();
+
+    let node = Node::new(Box::new(db) as Box<Database>, services, config, None);
+    node.run().expect("Node return error");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(args: &[&str]) -> Result<TestnetOptions, failure::Error> {
+        TestnetOptions::from_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_ports_overflow() {
+        let max = options(&["-n", "3", "--start-port", "65533", "--api-port", "65527"]).unwrap();
+        assert_eq!(max.api_address(2, 1).port(), 65532);
+        assert_eq!(max.events_address(2).port(), 65535);
+
+        assert!(options(&["-n", "4", "--start-port", "65533"]).is_err());
+        assert!(options(&["-n", "3", "--api-port", "65528"]).is_err());
+        assert!(options(&["-n", "255", "--api-port", "65000"]).is_err());
+        assert!(options(&["-n", "0"]).is_err());
+    }
+}
@@ -16,17 +16,15 @@ use tokio::net::TcpListener;
 use tokio::prelude::*;
 use bitcoin::blockdata::transaction::Transaction;
 
-use types::PaymentAddress;
-
 // https://github.com/libbitcoin/libbitcoin/blob/fdb5558e5fa03c0afbb0e447c69757869c7f148a/include/bitcoin/bitcoin/chain/transaction.hpp
 use bitcoin::blockdata::transaction;
 
 use bitcoin::network::serialize::*;
 
-// Use to pass an ec point as either ec_compressed or ec_uncompressed.
-// ec_public doesn't carry a version for address creation or base58 encoding.
-// https://github.com/libbitcoin/libbitcoin/blob/fdb5558e5fa03c0afbb0e447c69757869c7f148a/include/bitcoin/bitcoin/wallet/ec_public.hpp
-pub type ec_public = u64;
+use ln::chan_utils::{self, CommitmentTransactionInfo, HTLCOutputInCommitment, TxCreationKeys};
+use ln::funding::{self, FundingError, FundingTransaction, WalletSource};
+use secp256k1::Secp256k1;
+use secp256k1::key::PublicKey;
 
 // type ec_compressed: byte_array<ec_compressed_size>
 // static BC_CONSTEXPR size_t ec_compressed_size = 33;
@@ -36,14 +34,9 @@ pub type ec_compressed = Vec<usize>;
 // Raw bytes. A chunk of data. This type is equivalent to std::vector<uint8_t>.
 pub type data_chunk = Vec<u8>;
 
-// static BC_CONSTEXPR size_t ec_secret_size = 32;
-// typedef byte_array<ec_secret_size> ec_secret;
-pub type ec_secret = Vec<usize>;
-
 const CLOSING_CHANNEL_FEES: usize = 10000;
 const DELAY: usize = 600;
 const EC_COMPRESSED_SIZE: usize = 33;
-const EC_SECRET_SIZE: usize = 32;
 
 // изучить побитовый сдвиг вправо - зачем он нужен
 fn u32_to_data_chunk(value: u32) -> data_chunk {
@@ -51,8 +44,6 @@ fn u32_to_data_chunk(value: u32) -> data_chunk {
     for x in (0..3).rev() {
         return_value.push((value >> (8 * x)) as u8);
     }
-    println!("return_value in u32_to_data_chunk: {:?}\n", &return_value);
-
     return return_value;
 }
 
@@ -60,19 +51,16 @@ fn u32_to_data_chunk(value: u32) -> data_chunk {
 fn xor (point1: ec_compressed, point2: ec_compressed) -> ec_compressed {
     let mut return_value: ec_compressed = Vec::new();
 
-    for x in (0..EC_COMPRESSED_SIZE) {
-        return_value[x] = point1[x] ^ point2[x];
+    for x in 0..EC_COMPRESSED_SIZE {
+        return_value.push(point1[x] ^ point2[x]);
     }
 
-    println!("return_value in XOR: {:?}\n", &return_value);
-
     return return_value;
 }
 
 // отправка транзакции
 fn send_transaction(tx: Transaction, socket: TcpStream) {
     let sender_data: data_chunk = bitcoin::network::serialize::serialize(&tx).unwrap(); // обработать ошибку
-    println!("sender_data: {:?}", &sender_data);
     let size_data = sender_data.len();
 
     // socket->send(boost::asio::buffer(&SizeData,sizeof(uint64_t)));
@@ -122,215 +110,55 @@ fn receive_transaction(
 }
 
 // Создание открывающей канал транзакции
-// Параметры канала - ServerPublicKey: ec_public, client_public_key: ec_public, user_secret: ec_secret
-fn create_opening_tx(server_public_key: ec_public, client_public_key: ec_public, user_secret: ec_secret) {
-
-    // Создаем тип payment_address в /src/lib/engine/types.rs
-    // преобразуем публичный ключ в адрес для платежей
-    let client_address: PaymentAddress = client_public_key.to_payment_address();
-
-    //создаваемая транзакция
-    let tx: Transaction = true;
-
-    // // old - tx.set_version(1u);
-    // tx.set_version(1);
-
-    // //входы транзакции(в идеале лучше было бы, еслиб программа сама находила и строила входы, как например с помощью функции getUTXO, суть ее в том, чтобы обраться к серверу(в данном случае libbitcoin серверу) и отправить запрос на получение данных из блокчейна, из которые уже можно бы было получить UTXO для конкретного адреса,но сервер возвращает пустое множество UTXO, для любого адреса. долгое время не смогя разобраться с этой проблемой, было решено пока возложить на пользователя задачу найти и вписать свой UTXO)
-    // //выходы можно самостоятельно посмотреть на сайте
-    // //https://live.blockcypher.com/btc-testnet/tx/62408b1b14ce9eea82b73b543cfb0bdfc4ec118b9d50c07e6c6d75ba3c6a7b59/
-    // //тут необходимо ввести хэш транзакции в которой есть непотраченный выход
-
-    // let prev_tx_hash_str: String = String::new();
-
-    // println!("\n write your prev UTXO hash in base16, once output from this will input for channel's creation's transaction:\n");
-    // // Ввод данных в консоль
-    // // std::cin>>prev_tx_hash_str;
-    // io::stdin().read_to_string(&mut prev_tx_hash_str);
-
-    // // Неизвестный тип hash_digest
-    // let prev_tx_hash: hash_digest;
-
-    // decode_hash(prev_tx_hash, prev_tx_hash_str);
-
-    // //тут нужно ввести индекс непотраченного выхода, индексация начинается с нуля, выходы например на сайте
-    // //https://live.blockcypher.com/btc-testnet/tx/62408b1b14ce9eea82b73b543cfb0bdfc4ec118b9d50c07e6c6d75ba3c6a7b59/
-    // //расположены сверху внизу в порядке увеличения их индекса
-    // let mut prev_tx_index: u32;
-    // println!("\n write index unspended output of your UTXO, its output will be input:\n");
-    // // Ввод данных в консоль
-    // // std::cin>>prev_tx_index;
-    // io::stdin().read_to_string(&mut prev_tx_index);
-
-    // //желательно реализовать поиска непотраченных выходов на определенную сумму автоматически,
-    // //то есть просить пользователя ввести только адрес и уже пробегая по блокчейну искать выходы
-    // //в предположение, что машина пользователя не является полной биткоин нодой,
-    // //нужно отправлять запрос серверу для нахождения непотраченных выходов.
-    // //но так как программа писалась для демонстрации работы Lighing канала, было решено поиск непотраченных выходов временно оставить на пользователе
-
-    // let mut weight_of_channel_btc: String = String::new();;
-    // let weight_of_channel_satoshi: u64 = 0;
-
-    // println!("\n write weight of channel in BTC:\n");
-    // // Ввод данных в консоль
-    // // std::cin>>weight_of_channel_btc;
-    // io::stdin().read_to_string(&mut weight_of_channel_btc);
-    // decode_base10(weight_of_channel_satoshi, weight_of_channel_btc, btc_decimal_places);
-
-    // //сдача пользователю, то есть открывающая транзакция (opening_tx) имеет 2 выхода - первый, на счет с мультподписью размер отправляеных на него биткоинов равен ширине канала, второй - сдача пользователю, остаток средств которые он хочет вернуть на свой адрес, он наберет их сам, учитывая какую сумму он хочет потратить на fees
-    // let mut odd_money_btc: String = String::new();;
-    // let odd_money_satoshi: u64 = 0;
-    // println!("\n write odd money (in BTC), for creating p2pkh output on your address. Dont forgot about transaction's fees:\n");
-    // // Ввод данных в консоль
-    // // std::cin>>odd_money_btc;
-    // io::stdin().read_to_string(&mut odd_money_btc);
-
-    // decode_base10(odd_money_satoshi, odd_money_btc, btc_decimal_places);
-
-    // output_point UTXO(prev_tx_hash, prev_tx_index);
-
-    // input input0;
-    // input0.set_previous_output(UTXO);
-    // input0.set_sequence(0xffffffff);
-
-    // //добавим вход без подписи
-    // tx.inputs().push_back(input0);
-
-    // //создадим выходы транзакции (2 выхода:
-    // //первый - на адрес с мультиподписью 2из2)
-    // //второй - сдача самому себе, выход со скриптом вида p2pkh
-
-    // point_list PubKeys({server_public_key.point(), client_public_key.point()});
-    // //выход с мультиподписью
-    // operation::list MultisigLockingScript=script::to_pay_multisig_pattern(2u,PubKeys);
-    // /*
-    // MultisigLockingScript.push_back(operation(opcode::push_positive_1));
-    // MultisigLockingScript.push_back(operation(to_chunk(server_public_key.point() )));
-    // MultisigLockingScript.push_back(operation(to_chunk(client_public_key.point() )));
-    // MultisigLockingScript.push_back(operation(opcode::push_positive_2));
-    // MultisigLockingScript.push_back(operation(opcode::checkmultisig));
-    // */
-    // //вызываем конструктор класса output, в который передаем 1 - количество коинов, 2 - скрипт
-    // output Output0(weight_of_channel_satoshi, MultisigLockingScript);
-
-    // //скрипт для возвращает сдачу себе
-    // let p2pkhScript: operation::list = script::to_pay_key_hash_pattern(client_address.hash());
-
-    // //создаем второй выход
-    // output Output1(odd_money_satoshi, p2pkhScript);
-
-    // tx.outputs().push_back(Output0);
-    // tx.outputs().push_back(Output1);
-
-    // //после добавления всех входов и выходов подпишем транзакцию
-
-    // let sig_0: endorsement;
-
-    // // считаем скрипт выхода соответствующнго входу0 равен p2pkhScript
-    // script::create_endorsement(sig_0, user_secret, p2pkhScript, tx, 0, 0x01);
-
-    // let sig_script0: operation::list;
-    // sig_script0.push_back(operation(sig_0));
-    // sig_script0.push_back(operation(to_chunk(client_public_key.point() )));
-    // script InputScript0(sig_script0);
-    // tx.inputs()[0].set_script(InputScript0);
-
-    return tx;
+// Выход с мультиподписью 2 из 2 на ключи сервера и клиента, входы выбираются из кошелька,
+// сдача возвращается в кошелёк, комиссия считается по feerate_per_kw
+pub fn create_opening_tx<W: WalletSource>(
+    wallet: &W,
+    server_funding_key: &PublicKey,
+    client_funding_key: &PublicKey,
+    channel_value_satoshis: u64,
+    feerate_per_kw: u64,
+) -> Result<FundingTransaction, FundingError> {
+    let secp_ctx = Secp256k1::new();
+    funding::build_funding_transaction(
+        &secp_ctx,
+        wallet,
+        client_funding_key,
+        server_funding_key,
+        channel_value_satoshis,
+        feerate_per_kw,
+    )
 }
 
-fn create_commitment_tx() -> bool {
-    let commit_tx: bool = true;
-
-    // ec_private UserPrivate(user_secret, ec_private::testnet);
-    // let user_pub_key: ec_public = UserPrivate.to_public();
-
-    // commit_tx.set_version(2u);
-
-    // //вычислим n_sequence(необходим для задержки валюты), как описано на:
-    // //https://github.com/bitcoin/bips/blob/master/bip-0068.mediawiki
-
-    // let n_time: u32 = DELAY; //неделя в секундах
-    // let n_sequence: u32 =(1<<22) | (n_time>>9);
-
-    // //вход
-    // //вход транзакции обязательства, это первый(с нулевым индексом) выход созданной ранее funding транзакции
-    // output_point UTXO(funding_tx.hash(), 0u);
-
-    // let input_0: input;
-    // input_0.set_previous_output(UTXO);
-    // input_0.set_sequence(0xffffffff);
-
-    //  //добавим вход без подписи
-    // commit_tx.inputs().push_back(input_0);
-
-    // //выходы
-    // let output_0: output;  //выход на свой адрес с p2pkh выходом
-    // output_0.set_value(my_balance - (my_balance * CLOSING_CHANNEL_FEES / (my_balance + server_balance)));
-    // let p2pkhScript: operation::list = script::to_pay_key_hash_pattern(user_pub_key.to_payment_address().hash());
-    // output_0.set_script(p2pkhScript);
-
-    // //RSMC выход, выход со следуюшим скриптом:
-    // /*
-    // OP_IF
-    // # Penalty transaction
-    // <revocationpubkey>
-    // OP_ELSE
-    // `to_self_delay`
-    // OP_CSV
-    // OP_DROP
-    // <local_delayedpubkey>
-    // OP_ENDIF
-    // OP_CHECKSIG
-
-    // более подробно можно почитать:
-    // https://github.com/lightningnetwork/lightning-rfc/blob/master/03-transactions.md
-    // разделы KEYS, revocationpubkey Derivation
-
-    // составим revocationpubkey по формуле
-    // revocationpubkey = revocation_basepoint * SHA256(revocation_basepoint || per_commitment_point) + per_commitment_point * SHA256(per_commitment_point || revocation_basepoint)
-    // , где || операция XOR(исключающее или)
-    // */
-
-    // let revocation_pub_key: ec_compressed;
-    // let commitment_point_list: point_list = {remote_per_commitment_basepoint,revocation_basepoint};
-
-    // //revocation_pub_key= remote_per_commitment_basepoint + revocation_basepoint. в поле эллиптических точек.
-    // ec_sum(revocation_pub_key, commitment_point_list);
-    // ec_multiply( revocation_pub_key, to_array<ec_secret_size>( sha256_hash_chunk( XOR(revocation_basepoint, remote_per_commitment_basepoint) )) );
-
-    // let output1: output;
-    // output1.set_value(server_balance - (server_balance * CLOSING_CHANNEL_FEES / (my_balance + server_balance)));
-
-    // let output_script_1: operation::list;
-    // output_script_1.push_back(operation(opcode::if_));
-    // output_script_1.push_back(operation(to_chunk(revocation_pub_key)));
-    // output_script_1.push_back(operation(opcode::else_));
-
-    // //добавить to_self_delay
-    // output_script_1.push_back(operation(uint32_to_data_chunk(n_sequence)));
-
-    // output_script_1.push_back(operation(opcode::checksequenceverify));
-    // output_script_1.push_back(operation(opcode::drop));
-    // output_script_1.push_back(operation(to_chunk(server_public_key.point())));
-    // output_script_1.push_back(operation(opcode::endif));
-    // output_script_1.push_back(operation(opcode::checksig));
-
-    // output1.set_script(output_script_1);
-
-    // commit_tx.outputs().push_back(output_0);
-    // commit_tx.outputs().push_back(output1);
-
-    // //подпишем транзакцию своим приватным ключом
-    // let sig_0: endorsement;
-
-    // //считаем скрипт выхода соответствующнго входу0 равен p2pkhScript
-    // script::create_endorsement(sig_0, user_secret, funding_tx.outputs()[0].script(), commit_tx, 0, 0x01);
-
-    // let sig_script0: operation::list;
-    // sig_script0.push_back(operation(sig_0));
-    // script InputScript0(sig_script0);
-    // commit_tx.inputs()[0].set_script(InputScript0);
-
-    return commit_tx;
+// Создание транзакции обязательства клиента (BOLT #3)
+// Канал открывал клиент, поэтому комиссию платит он. Выход to_local закрыт скриптом с
+// revocationpubkey и задержкой to_self_delay (OP_CSV), выход to_remote - P2WPKH сервера.
+// Выходы меньше dust_limit отбрасываются, оставшиеся сортируются по BIP69
+pub fn create_commitment_tx(
+    funding_tx: &FundingTransaction,
+    commitment_number: u64,
+    obscure_factor: u64,
+    keys: &TxCreationKeys,
+    my_balance_msat: u64,
+    server_balance_msat: u64,
+    htlcs: &[HTLCOutputInCommitment],
+    feerate_per_kw: u64,
+    to_self_delay: u16,
+) -> (Transaction, Vec<HTLCOutputInCommitment>) {
+    chan_utils::build_commitment_transaction(&CommitmentTransactionInfo {
+        funding_txid: funding_tx.transaction.bitcoin_hash(),
+        funding_output_index: funding_tx.output_index,
+        commitment_number: commitment_number,
+        obscure_factor: obscure_factor,
+        a_is_funder: true,
+        to_a_msat: my_balance_msat,
+        to_b_msat: server_balance_msat,
+        feerate_per_kw: feerate_per_kw,
+        dust_limit_satoshis: funding::DUST_LIMIT_SATOSHIS,
+        to_self_delay: to_self_delay,
+        keys: keys,
+        htlcs: htlcs,
+    })
 }
 
 // TcpStream - boost::asio::ip::tcp::socket
@@ -13,6 +13,9 @@ use std::net::{AddrParseError, IpAddr, Ipv4Addr, SocketAddr, TcpStream};
 use tokio::net::TcpListener;
 use tokio::prelude::*;
 
+use ln::chan_utils::{self, CommitmentTransactionInfo, HTLCOutputInCommitment, TxCreationKeys};
+use ln::funding;
+
 const CLOSING_CHANNEL_FEES: u64 = 10000;
 const DELAY: u64 = 600;
 
@@ -353,102 +356,36 @@ fn broadcast_tx(tx: Transaction) -> bool {
     return timeunlock_tx;
 }
 
-fn create_commitment_tx() -> bool {
-    // fn create_commitment_tx() -> Transaction {
-
-    // let CommitTX: Transaction;
-    // CommitTX.set_version(2u);
-
-    // ec_private UserPrivate(UserSecret, ec_private::testnet);
-    // ec_public UserPubKey = UserPrivate.to_public();
-
-    // transaction CommitTX;
-    // CommitTX.set_version(2u);
-
-    // //вычислим nSequence(необходим для задержки валюты), как описано на:
-    // //https://github.com/bitcoin/bips/blob/master/bip-0068.mediawiki
-    // uint32_t nTime = DELAY; //неделя в секундах
-    // uint32_t nSequence = (1<<22) | (nTime>>9);
-
-    // //вход
-    // output_point UTXO(opening_tx.hash(), 0u); //вход транзакции обязательства, это первый(с нулевым индексом) выход созданной ранее транзакции
-
-    // input input0;
-    // input0.set_previous_output(UTXO);
-    // input0.set_sequence(0xffffffff);
-
-    // CommitTX.inputs().push_back(input0); //добавим вход без подписи
-
-    // //выходы
-    // output output0;  //выход на свой адрес с p2pkh выходом
-    // output0.set_value(MyBalance- (MyBalance * CLOSING_CHANNEL_FEES/(MyBalance+EitherBalance)));
-    // operation::list p2pkhScript=script::to_pay_key_hash_pattern(UserPubKey.to_payment_address().hash());
-    // output0.set_script(p2pkhScript);
-    // CommitTX.outputs().push_back(output0);
-
-    // //RSMC выход, выход со следуюшим скриптом:
-    // /*
-
-    // OP_IF
-    // # Penalty transaction
-    // <revocationpubkey>
-    // OP_ELSE
-    // `to_self_delay`
-    // OP_CSV
-    // OP_DROP
-    // <local_delayedpubkey>
-    // OP_ENDIF
-    // OP_CHECKSIG
-
-    // более подробно можно почитать:
-    // https://github.com/lightningnetwork/lightning-rfc/blob/master/03-transactions.md
-    // разделы KEYS, revocationpubkey Derivation
-
-    // составим revocationpubkey по формуле
-    // revocationpubkey = revocation_basepoint * SHA256(revocation_basepoint || per_commitment_point) + per_commitment_point * SHA256(per_commitment_point || revocation_basepoint)
-    // , где || операция XOR(исключающее или)
-    // */
-
-    // let revocation_pub_key: ec_compressed;
-    // point_list commitment_point_list = {remote_per_commitment_basepoint, revocation_basepoint};
-    // //revocation_pub_key= remote_per_commitment_basepoint + revocation_basepoint. в поле эллиптических точек.
-    // ec_sum(revocation_pub_key, commitment_point_list);
-    // ec_multiply( revocation_pub_key, to_array<ec_secret_size>( sha256_hash_chunk( XOR(revocation_basepoint, remote_per_commitment_basepoint) )) );
-
-    // output output1;
-    // output1.set_value(EitherBalance- (EitherBalance * CLOSING_CHANNEL_FEES/(MyBalance+EitherBalance)));
-
-    // operation::list outputScript1;
-    // outputScript1.push_back(operation(opcode::if_));
-    // outputScript1.push_back(operation(to_chunk(revocation_pub_key)));
-    // outputScript1.push_back(operation(opcode::else_));
-
-    // //добавить to_self_delay
-    // outputScript1.push_back(operation(uint32_to_data_chunk(nSequence)));
-
-    // outputScript1.push_back(operation(opcode::checksequenceverify));
-    // outputScript1.push_back(operation(opcode::drop));
-    // outputScript1.push_back(operation(to_chunk(EitherPublicKey.point())));
-    // outputScript1.push_back(operation(opcode::endif));
-    // outputScript1.push_back(operation(opcode::checksig));
-
-    // output1.set_script(outputScript1);
-
-    // CommitTX.outputs().push_back(output1);
-    // //подпишем транзакцию своим приватным ключом
-
-    // let sig_0: endorsement;
-    // // считаем скрипт выхода соответствующнго входу0 равен p2pkhScript
-    // script::create_endorsement(sig_0, UserSecret, opening_tx.outputs()[0].script(), CommitTX, 0, 0x01);
-
-    // let sig_script0: operation::list;
-    // sig_script0.push_back(operation(sig_0));
-    // script InputScript0(sig_script0);
-    // CommitTX.inputs()[0].set_script(InputScript0);
-
-    // return CommitTX;
-        return true;
-
+// Создание транзакции обязательства сервера (BOLT #3)
+// Канал открывал клиент, поэтому комиссию платит он. Выход to_local закрыт скриптом с
+// revocationpubkey и задержкой to_self_delay (OP_CSV), выход to_remote - P2WPKH клиента.
+// Выходы меньше dust_limit отбрасываются, оставшиеся сортируются по BIP69
+pub fn create_commitment_tx(
+    opening_tx: &Transaction,
+    funding_output_index: u16,
+    commitment_number: u64,
+    obscure_factor: u64,
+    keys: &TxCreationKeys,
+    my_balance_msat: u64,
+    client_balance_msat: u64,
+    htlcs: &[HTLCOutputInCommitment],
+    feerate_per_kw: u64,
+    to_self_delay: u16,
+) -> (Transaction, Vec<HTLCOutputInCommitment>) {
+    chan_utils::build_commitment_transaction(&CommitmentTransactionInfo {
+        funding_txid: opening_tx.bitcoin_hash(),
+        funding_output_index: funding_output_index,
+        commitment_number: commitment_number,
+        obscure_factor: obscure_factor,
+        a_is_funder: false,
+        to_a_msat: my_balance_msat,
+        to_b_msat: client_balance_msat,
+        feerate_per_kw: feerate_per_kw,
+        dust_limit_satoshis: funding::DUST_LIMIT_SATOSHIS,
+        to_self_delay: to_self_delay,
+        keys: keys,
+        htlcs: htlcs,
+    })
 }
 
 fn server_handler() {
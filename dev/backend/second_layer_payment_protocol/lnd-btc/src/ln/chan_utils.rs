@@ -0,0 +1,773 @@
+//! BOLT #3 building blocks: per-commitment secrets and keys, the output scripts and the
+//! commitment and HTLC transactions built from them.
+
+use bitcoin::blockdata::opcodes;
+use bitcoin::blockdata::script::{Script,Builder};
+use bitcoin::blockdata::transaction::{TxIn,TxOut,Transaction,SigHashType};
+use bitcoin::util::hash::{Sha256dHash,Hash160};
+
+use secp256k1::{Secp256k1,Signature};
+use secp256k1::key::{SecretKey,PublicKey};
+use secp256k1;
+
+use crypto::digest::Digest;
+use crypto::ripemd160::Ripemd160;
+
+use ln::funding::{get_funding_redeemscript,get_p2wpkh_script};
+use ln::msgs::HandleError;
+use util::sha2::Sha256;
+
+pub const HTLC_SUCCESS_TX_WEIGHT: u64 = 703;
+pub const HTLC_TIMEOUT_TX_WEIGHT: u64 = 663;
+pub const COMMITMENT_TX_BASE_WEIGHT: u64 = 724;
+pub const COMMITMENT_TX_WEIGHT_PER_HTLC: u64 = 172;
+
+/// Commitment numbers (and per-commitment secret indexes) are 48 bits long.
+pub const COMMITMENT_NUMBER_BITS: u8 = 48;
+
+/// Builds the per-commitment secret with the given index from the commitment seed. Secrets are
+/// handed out starting at index 2^48 - 1, going down.
+pub fn build_commitment_secret(commitment_seed: [u8; 32], idx: u64) -> [u8; 32] {
+	derive_secret(commitment_seed, COMMITMENT_NUMBER_BITS, idx)
+}
+
+/// Flips and hashes the lowest `bits` bits of idx into the given secret, so that a secret can
+/// derive all the secrets below it which only differ from it in those bits.
+fn derive_secret(secret: [u8; 32], bits: u8, idx: u64) -> [u8; 32] {
+	let mut res = secret;
+	for i in 0..bits {
+		let bitpos = bits - 1 - i;
+		if idx & (1 << bitpos) == (1 << bitpos) {
+			res[(bitpos / 8) as usize] ^= 1 << (bitpos & 7);
+			let mut sha = Sha256::new();
+			sha.input(&res);
+			sha.result(&mut res);
+		}
+	}
+	res
+}
+
+/// Stores the per-commitment secrets revealed by the remote side in the compact form of
+/// BOLT #3: 49 slots are enough to derive every secret received so far.
+pub struct CounterpartyCommitmentSecrets {
+	old_secrets: [([u8; 32], u64); 49],
+}
+
+/// Marks an unused slot, no 48-bit index can match it.
+const UNSET_SECRET_INDEX: u64 = 1 << 48;
+
+impl CounterpartyCommitmentSecrets {
+	pub fn new() -> CounterpartyCommitmentSecrets {
+		CounterpartyCommitmentSecrets { old_secrets: [([0; 32], UNSET_SECRET_INDEX); 49] }
+	}
+
+	fn place_secret(idx: u64) -> u8 {
+		for i in 0..COMMITMENT_NUMBER_BITS {
+			if idx & (1 << i) == (1 << i) {
+				return i;
+			}
+		}
+		COMMITMENT_NUMBER_BITS
+	}
+
+	/// Adds the secret with the given index, checking that it derives every secret it replaces.
+	/// Secrets must be provided in decreasing index order, as they are revealed.
+	pub fn provide_secret(&mut self, idx: u64, secret: [u8; 32]) -> Result<(), HandleError> {
+		let pos = CounterpartyCommitmentSecrets::place_secret(idx);
+		for i in 0..pos {
+			let (old_secret, old_idx) = self.old_secrets[i as usize];
+			if old_idx == UNSET_SECRET_INDEX {
+				continue;
+			}
+			if derive_secret(secret, pos, old_idx) != old_secret {
+				return Err(HandleError{err: "Previous secret did not match new one", action: None})
+			}
+		}
+		self.old_secrets[pos as usize] = (secret, idx);
+		Ok(())
+	}
+
+	/// Gets the secret with the given index, if it was provided or can be derived.
+	pub fn get_secret(&self, idx: u64) -> Option<[u8; 32]> {
+		for i in 0..self.old_secrets.len() {
+			let (secret, secret_idx) = self.old_secrets[i];
+			if secret_idx == UNSET_SECRET_INDEX {
+				continue;
+			}
+			if (idx & (!0 << i)) == (secret_idx & (!0 << i)) {
+				return Some(derive_secret(secret, i as u8, idx));
+			}
+		}
+		None
+	}
+}
+
+fn sha256_of_points(a: &PublicKey, b: &PublicKey) -> [u8; 32] {
+	let mut sha = Sha256::new();
+	sha.input(&a.serialize());
+	sha.input(&b.serialize());
+	let mut res = [0; 32];
+	sha.result(&mut res);
+	res
+}
+
+/// Derives a per-commitment private key (eg localprivkey) from its base secret:
+/// base_secret + SHA256(per_commitment_point || base_point)
+pub fn derive_private_key(secp_ctx: &Secp256k1, per_commitment_point: &PublicKey, base_secret: &SecretKey) -> Result<SecretKey, secp256k1::Error> {
+	let base_point = PublicKey::from_secret_key(&secp_ctx, &base_secret)?;
+	let res = sha256_of_points(per_commitment_point, &base_point);
+
+	let mut key = base_secret.clone();
+	key.add_assign(&secp_ctx, &SecretKey::from_slice(&secp_ctx, &res)?)?;
+	Ok(key)
+}
+
+/// Derives a per-commitment public key (eg localpubkey, remotepubkey or local_delayedpubkey)
+/// from its basepoint: base_point + SHA256(per_commitment_point || base_point) * G
+pub fn derive_public_key(secp_ctx: &Secp256k1, per_commitment_point: &PublicKey, base_point: &PublicKey) -> Result<PublicKey, secp256k1::Error> {
+	let res = sha256_of_points(per_commitment_point, base_point);
+
+	let hashkey = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&secp_ctx, &res)?)?;
+	base_point.combine(&secp_ctx, &hashkey)
+}
+
+/// Derives the revocation private key, which the remote side can only build once we gave them
+/// the per-commitment secret:
+/// revocation_base_secret * SHA256(revocation_basepoint || per_commitment_point) +
+/// per_commitment_secret * SHA256(per_commitment_point || revocation_basepoint)
+pub fn derive_private_revocation_key(secp_ctx: &Secp256k1, per_commitment_secret: &SecretKey, revocation_base_secret: &SecretKey) -> Result<SecretKey, secp256k1::Error> {
+	let revocation_base_point = PublicKey::from_secret_key(&secp_ctx, &revocation_base_secret)?;
+	let per_commitment_point = PublicKey::from_secret_key(&secp_ctx, &per_commitment_secret)?;
+
+	let rev_append_commit_hash_key = sha256_of_points(&revocation_base_point, &per_commitment_point);
+	let commit_append_rev_hash_key = sha256_of_points(&per_commitment_point, &revocation_base_point);
+
+	let mut part_a = revocation_base_secret.clone();
+	part_a.mul_assign(&secp_ctx, &SecretKey::from_slice(&secp_ctx, &rev_append_commit_hash_key)?)?;
+	let mut part_b = per_commitment_secret.clone();
+	part_b.mul_assign(&secp_ctx, &SecretKey::from_slice(&secp_ctx, &commit_append_rev_hash_key)?)?;
+	part_a.add_assign(&secp_ctx, &part_b)?;
+	Ok(part_a)
+}
+
+/// Derives the revocation public key:
+/// revocation_basepoint * SHA256(revocation_basepoint || per_commitment_point) +
+/// per_commitment_point * SHA256(per_commitment_point || revocation_basepoint)
+pub fn derive_public_revocation_key(secp_ctx: &Secp256k1, per_commitment_point: &PublicKey, revocation_base_point: &PublicKey) -> Result<PublicKey, secp256k1::Error> {
+	let rev_append_commit_hash_key = sha256_of_points(revocation_base_point, per_commitment_point);
+	let commit_append_rev_hash_key = sha256_of_points(per_commitment_point, revocation_base_point);
+
+	let mut part_a = revocation_base_point.clone();
+	part_a.mul_assign(&secp_ctx, &SecretKey::from_slice(&secp_ctx, &rev_append_commit_hash_key)?)?;
+	let mut part_b = per_commitment_point.clone();
+	part_b.mul_assign(&secp_ctx, &SecretKey::from_slice(&secp_ctx, &commit_append_rev_hash_key)?)?;
+	part_a.combine(&secp_ctx, &part_b)
+}
+
+/// The set of public keys which are used in the creation of one commitment transaction.
+/// These are derived from the channel base keys and per-commitment data.
+/// A is the side which broadcasts the transaction, B the other one.
+pub struct TxCreationKeys {
+	pub per_commitment_point: PublicKey,
+	pub revocation_key: PublicKey,
+	pub a_htlc_key: PublicKey,
+	pub b_htlc_key: PublicKey,
+	pub a_delayed_payment_key: PublicKey,
+	pub b_payment_key: PublicKey,
+}
+
+impl TxCreationKeys {
+	pub fn new(secp_ctx: &Secp256k1, per_commitment_point: &PublicKey, a_delayed_payment_base: &PublicKey, a_htlc_base: &PublicKey, b_revocation_base: &PublicKey, b_payment_base: &PublicKey, b_htlc_base: &PublicKey) -> Result<TxCreationKeys, secp256k1::Error> {
+		Ok(TxCreationKeys {
+			per_commitment_point: per_commitment_point.clone(),
+			revocation_key: derive_public_revocation_key(&secp_ctx, &per_commitment_point, &b_revocation_base)?,
+			a_htlc_key: derive_public_key(&secp_ctx, &per_commitment_point, &a_htlc_base)?,
+			b_htlc_key: derive_public_key(&secp_ctx, &per_commitment_point, &b_htlc_base)?,
+			a_delayed_payment_key: derive_public_key(&secp_ctx, &per_commitment_point, &a_delayed_payment_base)?,
+			b_payment_key: derive_public_key(&secp_ctx, &per_commitment_point, &b_payment_base)?,
+		})
+	}
+}
+
+/// Gets the "to_local" output redeemscript, ie the script which is time-locked or spendable by
+/// the revocation key
+pub fn get_revokeable_redeemscript(revocation_key: &PublicKey, to_self_delay: u16, delayed_payment_key: &PublicKey) -> Script {
+	Builder::new().push_opcode(opcodes::All::OP_IF)
+	              .push_slice(&revocation_key.serialize())
+	              .push_opcode(opcodes::All::OP_ELSE)
+	              .push_int(to_self_delay as i64)
+	              .push_opcode(opcodes::OP_CSV)
+	              .push_opcode(opcodes::All::OP_DROP)
+	              .push_slice(&delayed_payment_key.serialize())
+	              .push_opcode(opcodes::All::OP_ENDIF)
+	              .push_opcode(opcodes::All::OP_CHECKSIG)
+	              .into_script()
+}
+
+#[derive(Clone, PartialEq)]
+pub struct HTLCOutputInCommitment {
+	/// Whether the HTLC was offered by the side which broadcasts the commitment transaction
+	pub offered: bool,
+	pub amount_msat: u64,
+	pub cltv_expiry: u32,
+	pub payment_hash: [u8; 32],
+	/// Index of the HTLC output, only meaningful once the commitment transaction was built
+	pub transaction_output_index: u32,
+}
+
+#[inline]
+pub fn get_htlc_redeemscript_with_explicit_keys(htlc: &HTLCOutputInCommitment, a_htlc_key: &PublicKey, b_htlc_key: &PublicKey, revocation_key: &PublicKey) -> Script {
+	let payment_hash160 = {
+		let mut ripemd = Ripemd160::new();
+		ripemd.input(&htlc.payment_hash);
+		let mut payment_hash160 = [0; 20];
+		ripemd.result(&mut payment_hash160);
+		payment_hash160
+	};
+	let revocation_key_hash160 = Hash160::from_data(&revocation_key.serialize()[..]);
+	if htlc.offered {
+		Builder::new().push_opcode(opcodes::All::OP_DUP)
+		              .push_opcode(opcodes::All::OP_HASH160)
+		              .push_slice(&revocation_key_hash160[..])
+		              .push_opcode(opcodes::All::OP_EQUAL)
+		              .push_opcode(opcodes::All::OP_IF)
+		              .push_opcode(opcodes::All::OP_CHECKSIG)
+		              .push_opcode(opcodes::All::OP_ELSE)
+		              .push_slice(&b_htlc_key.serialize()[..])
+		              .push_opcode(opcodes::All::OP_SWAP)
+		              .push_opcode(opcodes::All::OP_SIZE)
+		              .push_int(32)
+		              .push_opcode(opcodes::All::OP_EQUAL)
+		              .push_opcode(opcodes::All::OP_NOTIF)
+		              .push_opcode(opcodes::All::OP_DROP)
+		              .push_int(2)
+		              .push_opcode(opcodes::All::OP_SWAP)
+		              .push_slice(&a_htlc_key.serialize()[..])
+		              .push_int(2)
+		              .push_opcode(opcodes::All::OP_CHECKMULTISIG)
+		              .push_opcode(opcodes::All::OP_ELSE)
+		              .push_opcode(opcodes::All::OP_HASH160)
+		              .push_slice(&payment_hash160)
+		              .push_opcode(opcodes::All::OP_EQUALVERIFY)
+		              .push_opcode(opcodes::All::OP_CHECKSIG)
+		              .push_opcode(opcodes::All::OP_ENDIF)
+		              .push_opcode(opcodes::All::OP_ENDIF)
+		              .into_script()
+	} else {
+		Builder::new().push_opcode(opcodes::All::OP_DUP)
+		              .push_opcode(opcodes::All::OP_HASH160)
+		              .push_slice(&revocation_key_hash160[..])
+		              .push_opcode(opcodes::All::OP_EQUAL)
+		              .push_opcode(opcodes::All::OP_IF)
+		              .push_opcode(opcodes::All::OP_CHECKSIG)
+		              .push_opcode(opcodes::All::OP_ELSE)
+		              .push_slice(&b_htlc_key.serialize()[..])
+		              .push_opcode(opcodes::All::OP_SWAP)
+		              .push_opcode(opcodes::All::OP_SIZE)
+		              .push_int(32)
+		              .push_opcode(opcodes::All::OP_EQUAL)
+		              .push_opcode(opcodes::All::OP_IF)
+		              .push_opcode(opcodes::All::OP_HASH160)
+		              .push_slice(&payment_hash160)
+		              .push_opcode(opcodes::All::OP_EQUALVERIFY)
+		              .push_int(2)
+		              .push_opcode(opcodes::All::OP_SWAP)
+		              .push_slice(&a_htlc_key.serialize()[..])
+		              .push_int(2)
+		              .push_opcode(opcodes::All::OP_CHECKMULTISIG)
+		              .push_opcode(opcodes::All::OP_ELSE)
+		              .push_opcode(opcodes::All::OP_DROP)
+		              .push_int(htlc.cltv_expiry as i64)
+		              .push_opcode(opcodes::OP_CLTV)
+		              .push_opcode(opcodes::All::OP_DROP)
+		              .push_opcode(opcodes::All::OP_CHECKSIG)
+		              .push_opcode(opcodes::All::OP_ENDIF)
+		              .push_opcode(opcodes::All::OP_ENDIF)
+		              .into_script()
+	}
+}
+
+/// note here that 'a_revocation_key' is generated using b_revocation_basepoint and a's
+/// commitment secret. 'htlc' does *not* need to have its previous_output_index filled.
+#[inline]
+pub fn get_htlc_redeemscript(htlc: &HTLCOutputInCommitment, keys: &TxCreationKeys) -> Script {
+	get_htlc_redeemscript_with_explicit_keys(htlc, &keys.a_htlc_key, &keys.b_htlc_key, &keys.revocation_key)
+}
+
+/// Gets the lower 48 bits of SHA256(funder's payment_basepoint || fundee's payment_basepoint),
+/// which is XORed into the commitment numbers to hide them from third parties.
+pub fn get_commitment_transaction_number_obscure_factor(funder_payment_basepoint: &PublicKey, fundee_payment_basepoint: &PublicKey) -> u64 {
+	let res = sha256_of_points(funder_payment_basepoint, fundee_payment_basepoint);
+	((res[26] as u64) << 5*8) |
+	((res[27] as u64) << 4*8) |
+	((res[28] as u64) << 3*8) |
+	((res[29] as u64) << 2*8) |
+	((res[30] as u64) << 1*8) |
+	((res[31] as u64) << 0*8)
+}
+
+/// The channel state which one commitment transaction pays out.
+pub struct CommitmentTransactionInfo<'a> {
+	pub funding_txid: Sha256dHash,
+	pub funding_output_index: u16,
+	/// The BOLT #3 commitment number, starting at 0 for the first commitment transaction
+	pub commitment_number: u64,
+	pub obscure_factor: u64,
+	/// Whether the side which broadcasts the transaction opened the channel and pays the fee
+	pub a_is_funder: bool,
+	pub to_a_msat: u64,
+	pub to_b_msat: u64,
+	pub feerate_per_kw: u64,
+	/// The dust limit of the side which broadcasts the transaction
+	pub dust_limit_satoshis: u64,
+	/// The to_self_delay the other side asked for
+	pub to_self_delay: u16,
+	pub keys: &'a TxCreationKeys,
+	pub htlcs: &'a [HTLCOutputInCommitment],
+}
+
+/// Builds the unsigned commitment transaction: HTLCs which aren't worth their HTLC transaction
+/// fee are trimmed, the funder pays the fee, dust balances are dropped and the outputs are
+/// sorted per BIP69. Returns the transaction along with the HTLCs which got an output, in output
+/// order and with their transaction_output_index set.
+pub fn build_commitment_transaction(info: &CommitmentTransactionInfo) -> (Transaction, Vec<HTLCOutputInCommitment>) {
+	let obscured_commitment_transaction_number = info.obscure_factor ^ info.commitment_number;
+
+	let txins = vec![TxIn {
+		prev_hash: info.funding_txid.clone(),
+		prev_index: info.funding_output_index as u32,
+		script_sig: Script::new(),
+		sequence: ((0x80 as u32) << 8*3) | ((obscured_commitment_transaction_number >> 3*8) as u32),
+		witness: Vec::new(),
+	}];
+
+	let mut txouts: Vec<(TxOut, Option<HTLCOutputInCommitment>)> = Vec::new();
+
+	for htlc in info.htlcs.iter() {
+		let htlc_tx_weight = if htlc.offered { HTLC_TIMEOUT_TX_WEIGHT } else { HTLC_SUCCESS_TX_WEIGHT };
+		if htlc.amount_msat / 1000 >= info.dust_limit_satoshis + info.feerate_per_kw * htlc_tx_weight / 1000 {
+			txouts.push((TxOut {
+				script_pubkey: get_htlc_redeemscript(htlc, info.keys).to_v0_p2wsh(),
+				value: htlc.amount_msat / 1000,
+			}, Some(htlc.clone())));
+		}
+	}
+
+	let total_fee = info.feerate_per_kw * (COMMITMENT_TX_BASE_WEIGHT + (txouts.len() as u64) * COMMITMENT_TX_WEIGHT_PER_HTLC) / 1000;
+	let (value_to_a, value_to_b) = if info.a_is_funder {
+		((info.to_a_msat / 1000).saturating_sub(total_fee), info.to_b_msat / 1000)
+	} else {
+		(info.to_a_msat / 1000, (info.to_b_msat / 1000).saturating_sub(total_fee))
+	};
+
+	if value_to_a >= info.dust_limit_satoshis {
+		txouts.push((TxOut {
+			script_pubkey: get_revokeable_redeemscript(&info.keys.revocation_key, info.to_self_delay, &info.keys.a_delayed_payment_key).to_v0_p2wsh(),
+			value: value_to_a,
+		}, None));
+	}
+
+	if value_to_b >= info.dust_limit_satoshis {
+		txouts.push((TxOut {
+			script_pubkey: get_p2wpkh_script(&info.keys.b_payment_key),
+			value: value_to_b,
+		}, None));
+	}
+
+	// BIP69: by value, then by script_pubkey
+	txouts.sort_by(|a, b| {
+		a.0.value.cmp(&b.0.value).then_with(|| a.0.script_pubkey[..].cmp(&b.0.script_pubkey[..]))
+	});
+
+	let mut outputs = Vec::with_capacity(txouts.len());
+	let mut htlcs_included = Vec::new();
+	for (idx, (txout, htlc)) in txouts.drain(..).enumerate() {
+		outputs.push(txout);
+		if let Some(mut htlc) = htlc {
+			htlc.transaction_output_index = idx as u32;
+			htlcs_included.push(htlc);
+		}
+	}
+
+	(Transaction {
+		version: 2,
+		lock_time: ((0x20 as u32) << 8*3) | ((obscured_commitment_transaction_number & 0xffffff) as u32),
+		input: txins,
+		output: outputs,
+	}, htlcs_included)
+}
+
+/// Builds the unsigned HTLC-timeout (for offered HTLCs) or HTLC-success (for received HTLCs)
+/// transaction spending the given HTLC output of the commitment transaction.
+pub fn build_htlc_transaction(prev_hash: &Sha256dHash, feerate_per_kw: u64, to_self_delay: u16, htlc: &HTLCOutputInCommitment, a_delayed_payment_key: &PublicKey, revocation_key: &PublicKey) -> Transaction {
+	let txins = vec![TxIn {
+		prev_hash: prev_hash.clone(),
+		prev_index: htlc.transaction_output_index,
+		script_sig: Script::new(),
+		sequence: 0,
+		witness: Vec::new(),
+	}];
+
+	let total_fee = if htlc.offered {
+			feerate_per_kw * HTLC_TIMEOUT_TX_WEIGHT / 1000
+		} else {
+			feerate_per_kw * HTLC_SUCCESS_TX_WEIGHT / 1000
+		};
+
+	let txouts = vec![TxOut {
+		script_pubkey: get_revokeable_redeemscript(revocation_key, to_self_delay, a_delayed_payment_key).to_v0_p2wsh(),
+		value: htlc.amount_msat / 1000 - total_fee,
+	}];
+
+	Transaction {
+		version: 2,
+		lock_time: if htlc.offered { htlc.cltv_expiry } else { 0 },
+		input: txins,
+		output: txouts,
+	}
+}
+
+fn signature_with_sighash_type(secp_ctx: &Secp256k1, sig: &Signature) -> Vec<u8> {
+	let mut res = sig.serialize_der(secp_ctx);
+	res.push(SigHashType::All as u8);
+	res
+}
+
+/// Fills in the witness spending the 2-of-2 funding output, with the signatures in the order of
+/// the keys in the funding redeemscript.
+pub fn add_funding_witness(secp_ctx: &Secp256k1, tx: &mut Transaction, local_sig: &Signature, remote_sig: &Signature, local_funding_key: &PublicKey, remote_funding_key: &PublicKey) {
+	let local_sig = signature_with_sighash_type(secp_ctx, local_sig);
+	let remote_sig = signature_with_sighash_type(secp_ctx, remote_sig);
+
+	tx.input[0].witness.push(Vec::new()); // First is the multisig dummy
+	if local_funding_key.serialize()[..] < remote_funding_key.serialize()[..] {
+		tx.input[0].witness.push(local_sig);
+		tx.input[0].witness.push(remote_sig);
+	} else {
+		tx.input[0].witness.push(remote_sig);
+		tx.input[0].witness.push(local_sig);
+	}
+	tx.input[0].witness.push(get_funding_redeemscript(local_funding_key, remote_funding_key).into_vec());
+}
+
+/// Fills in the witness of an HTLC-success (given the payment preimage) or HTLC-timeout
+/// transaction. The b signature is the one of the side which doesn't broadcast.
+pub fn add_htlc_witness(secp_ctx: &Secp256k1, tx: &mut Transaction, htlc: &HTLCOutputInCommitment, a_sig: &Signature, b_sig: &Signature, payment_preimage: Option<[u8; 32]>, htlc_redeemscript: &Script) {
+	tx.input[0].witness.push(Vec::new()); // First is the multisig dummy
+	tx.input[0].witness.push(signature_with_sighash_type(secp_ctx, b_sig));
+	tx.input[0].witness.push(signature_with_sighash_type(secp_ctx, a_sig));
+	match payment_preimage {
+		Some(preimage) => {
+			assert!(!htlc.offered);
+			tx.input[0].witness.push(preimage.to_vec());
+		},
+		None => tx.input[0].witness.push(Vec::new()),
+	}
+	tx.input[0].witness.push(htlc_redeemscript.clone().into_vec());
+}
+
+#[cfg(test)]
+mod tests {
+	use bitcoin::blockdata::script::Script;
+	use bitcoin::blockdata::transaction::Transaction;
+	use bitcoin::network::serialize::{serialize,BitcoinHash};
+	use bitcoin::util::bip143;
+	use bitcoin::util::hash::Sha256dHash;
+
+	use crypto::digest::Digest;
+
+	use hex;
+	use ln::chan_utils::*;
+	use ln::funding::get_funding_redeemscript;
+	use util::sha2::Sha256;
+
+	use secp256k1::{Secp256k1,Message,Signature};
+	use secp256k1::key::{SecretKey,PublicKey};
+
+	fn secret_from_hex(secp_ctx: &Secp256k1, hex: &str) -> SecretKey {
+		SecretKey::from_slice(secp_ctx, &hex::decode(hex).unwrap()[..]).unwrap()
+	}
+
+	fn pubkey_from_hex(secp_ctx: &Secp256k1, hex: &str) -> PublicKey {
+		PublicKey::from_slice(secp_ctx, &hex::decode(hex).unwrap()[..]).unwrap()
+	}
+
+	fn array_from_hex(hex: &str) -> [u8; 32] {
+		let mut res = [0; 32];
+		res.copy_from_slice(&hex::decode(hex).unwrap()[..]);
+		res
+	}
+
+	#[test]
+	fn test_key_derivation() {
+		// BOLT #3 Appendix E
+		let secp_ctx = Secp256k1::new();
+		let base_secret = secret_from_hex(&secp_ctx, "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f");
+		let per_commitment_secret = secret_from_hex(&secp_ctx, "1f1e1d1c1b1a191817161514131211100f0e0d0c0b0a09080706050403020100");
+		let base_point = PublicKey::from_secret_key(&secp_ctx, &base_secret).unwrap();
+		assert_eq!(base_point, pubkey_from_hex(&secp_ctx, "036d6caac248af96f6afa7f904f550253a0f3ef3f5aa2fe6838a95b216691468e2"));
+		let per_commitment_point = PublicKey::from_secret_key(&secp_ctx, &per_commitment_secret).unwrap();
+		assert_eq!(per_commitment_point, pubkey_from_hex(&secp_ctx, "025f7117a78150fe2ef97db7cfc83bd57b2e2c0d0dd25eaf467a4a1c2a45ce1486"));
+
+		assert_eq!(derive_public_key(&secp_ctx, &per_commitment_point, &base_point).unwrap(),
+			pubkey_from_hex(&secp_ctx, "0235f2dbfaa89b57ec7b055afe29849ef7ddfeb1cefdb9ebdc43f5494984db29e5"));
+		assert_eq!(derive_private_key(&secp_ctx, &per_commitment_point, &base_secret).unwrap(),
+			secret_from_hex(&secp_ctx, "cbced912d3b21bf196a766651e436aff192362621ce317704ea2f75d87e7be0f"));
+
+		assert_eq!(derive_public_revocation_key(&secp_ctx, &per_commitment_point, &base_point).unwrap(),
+			pubkey_from_hex(&secp_ctx, "02916e326636d19c33f13e8c0c3a03dd157f332f3e99c317c141dd865eb01f8ff0"));
+		assert_eq!(derive_private_revocation_key(&secp_ctx, &per_commitment_secret, &base_secret).unwrap(),
+			secret_from_hex(&secp_ctx, "d09ffff62ddb2297ab000cc85bcb4283fdeb6aa052affbc9dddcf33b61078110"));
+	}
+
+	#[test]
+	fn test_per_commitment_secret_gen() {
+		// BOLT #3 Appendix D, generation tests
+		assert_eq!(build_commitment_secret([0; 32], 281474976710655),
+			array_from_hex("02a40c85b6f28da08dfdbe0926c53fab2de6d28c10301f8f7c4073d5e42e3148"));
+		assert_eq!(build_commitment_secret([0xff; 32], 281474976710655),
+			array_from_hex("7cc854b54e3e0dcdb010d7a3fee464a9687be6e8db3be6854c475621e007a5dc"));
+		assert_eq!(build_commitment_secret([0xff; 32], 0xaaaaaaaaaaa),
+			array_from_hex("56f4008fb007ca9acf0e15b054d5c9fd12ee06cea347914ddbaed70d1c13a528"));
+		assert_eq!(build_commitment_secret([0xff; 32], 0x555555555555),
+			array_from_hex("9015daaeb06dba4ccc05b91b2f73bd54405f2be9f217fbacd3c5ac2e62327d31"));
+		assert_eq!(build_commitment_secret(array_from_hex("0101010101010101010101010101010101010101010101010101010101010101"), 1),
+			array_from_hex("915c75942a26bb3a433a8ce2cb0427c29ec6c1775cfc78328b57f6ba7bfeaa9c"));
+	}
+
+	#[test]
+	fn test_per_commitment_storage() {
+		// BOLT #3 Appendix D, storage tests: the secrets of seed 0xff are correct, replacing any one
+		// of them with the secret of seed 0 must be caught as soon as a later secret derives it.
+		let start = (1 << COMMITMENT_NUMBER_BITS) - 1;
+		let correct = |idx: u64| build_commitment_secret([0xff; 32], idx);
+		let corrupt = |idx: u64| build_commitment_secret([0; 32], idx);
+
+		let mut secrets = CounterpartyCommitmentSecrets::new();
+		for i in 0..8 {
+			secrets.provide_secret(start - i, correct(start - i)).unwrap();
+			for j in 0..i + 1 {
+				assert_eq!(secrets.get_secret(start - j), Some(correct(start - j)));
+			}
+		}
+		assert!(secrets.get_secret(start - 8).is_none());
+
+		for &(corrupted, fails_at) in [(0, 1), (1, 1), (2, 3), (3, 3), (4, 5), (5, 5), (6, 7)].iter() {
+			let mut secrets = CounterpartyCommitmentSecrets::new();
+			for i in 0..fails_at + 1 {
+				let idx = start - i;
+				let secret = if i == corrupted { corrupt(idx) } else { correct(idx) };
+				let res = secrets.provide_secret(idx, secret);
+				assert_eq!(res.is_err(), i == fails_at);
+			}
+		}
+	}
+
+	#[test]
+	fn test_obscure_factor() {
+		let secp_ctx = Secp256k1::new();
+		let local_payment_basepoint = pubkey_from_hex(&secp_ctx, "034f355bdcb7cc0af728ef3cceb9615d90684bb5b2ca5f859ab0f0b704075871aa");
+		let remote_payment_basepoint = pubkey_from_hex(&secp_ctx, "032c0b7cf95324a07d05398b240174dc0c2be444d96b159aa6c7f7b1e668680991");
+		assert_eq!(get_commitment_transaction_number_obscure_factor(&local_payment_basepoint, &remote_payment_basepoint), 0x2bb038521914);
+	}
+
+	/// The channel of BOLT #3 Appendix C, seen from the local side, which is the funder.
+	struct CommitmentTestContext {
+		secp_ctx: Secp256k1,
+		funding_txid: Sha256dHash,
+		obscure_factor: u64,
+		keys: TxCreationKeys,
+		local_funding_key: SecretKey,
+		remote_funding_key: SecretKey,
+		local_htlc_key: SecretKey,
+		remote_htlc_key: SecretKey,
+		preimages: Vec<[u8; 32]>,
+	}
+
+	impl CommitmentTestContext {
+		fn new() -> CommitmentTestContext {
+			let secp_ctx = Secp256k1::new();
+			let local_payment_basepoint = pubkey_from_hex(&secp_ctx, "034f355bdcb7cc0af728ef3cceb9615d90684bb5b2ca5f859ab0f0b704075871aa");
+			let remote_payment_basepoint = pubkey_from_hex(&secp_ctx, "032c0b7cf95324a07d05398b240174dc0c2be444d96b159aa6c7f7b1e668680991");
+			let local_htlc_key = secret_from_hex(&secp_ctx, "bb13b121cdc357cd2e608b0aea294afca36e2b34cf958e2e6451a2f274694491");
+			let remote_htlc_key = secret_from_hex(&secp_ctx, "8deba327a7cc6d638ab0eb025770400a6184afcba6713c210d8d10e199ff2fda");
+			let keys = TxCreationKeys {
+				per_commitment_point: pubkey_from_hex(&secp_ctx, "025f7117a78150fe2ef97db7cfc83bd57b2e2c0d0dd25eaf467a4a1c2a45ce1486"),
+				revocation_key: pubkey_from_hex(&secp_ctx, "0212a140cd0c6539d07cd08dfe09984dec3251ea808b892efeac3ede9402bf2b19"),
+				a_htlc_key: PublicKey::from_secret_key(&secp_ctx, &local_htlc_key).unwrap(),
+				b_htlc_key: PublicKey::from_secret_key(&secp_ctx, &remote_htlc_key).unwrap(),
+				a_delayed_payment_key: pubkey_from_hex(&secp_ctx, "03fd5960528dc152014952efdb702a88f71e3c1653b2314431701ec77e57fde83c"),
+				b_payment_key: pubkey_from_hex(&secp_ctx, "0394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b"),
+			};
+			assert_eq!(keys.a_htlc_key, pubkey_from_hex(&secp_ctx, "030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e7"));
+			CommitmentTestContext {
+				funding_txid: Sha256dHash::from_hex("8984484a580b825b9972d7adb15050b3ab624ccd731946b3eeddb92f4e7ef6be").unwrap(),
+				obscure_factor: get_commitment_transaction_number_obscure_factor(&local_payment_basepoint, &remote_payment_basepoint),
+				keys: keys,
+				local_funding_key: secret_from_hex(&secp_ctx, "30ff4956bbdd3222d44cc5e8a1261dab1e07957bdac5ae88fe3261ef321f3749"),
+				remote_funding_key: secret_from_hex(&secp_ctx, "1552dfba4f6cf29a62a0af13c8d6981d36d0ef8d61ba10fb0fe90da7634d7e13"),
+				local_htlc_key: local_htlc_key,
+				remote_htlc_key: remote_htlc_key,
+				preimages: (0..5).map(|i| [i as u8; 32]).collect(),
+				secp_ctx: secp_ctx,
+			}
+		}
+
+		fn htlcs(&self) -> Vec<HTLCOutputInCommitment> {
+			let offered = [false, false, true, true, false];
+			self.preimages.iter().enumerate().map(|(i, preimage)| {
+				let mut payment_hash = [0; 32];
+				let mut sha = Sha256::new();
+				sha.input(preimage);
+				sha.result(&mut payment_hash);
+				HTLCOutputInCommitment {
+					offered: offered[i],
+					amount_msat: [1000000, 2000000, 2000000, 3000000, 4000000][i],
+					cltv_expiry: 500 + i as u32,
+					payment_hash: payment_hash,
+					transaction_output_index: 0,
+				}
+			}).collect()
+		}
+
+		fn sign(&self, tx: &Transaction, script: &Script, value: u64, key: &SecretKey) -> Signature {
+			let sighash = bip143::SighashComponents::new(tx).sighash_all(tx, 0, script, value);
+			self.secp_ctx.sign(&Message::from_slice(&sighash[..]).unwrap(), key).unwrap()
+		}
+
+		fn check(&self, to_local_msat: u64, htlcs: &[HTLCOutputInCommitment], feerate_per_kw: u64, expected_tx: &str, expected_htlc_txs: &[&str]) {
+			let secp_ctx = &self.secp_ctx;
+			let (mut tx, htlcs_included) = build_commitment_transaction(&CommitmentTransactionInfo {
+				funding_txid: self.funding_txid.clone(),
+				funding_output_index: 0,
+				commitment_number: 42,
+				obscure_factor: self.obscure_factor,
+				a_is_funder: true,
+				to_a_msat: to_local_msat,
+				to_b_msat: 3000000000,
+				feerate_per_kw: feerate_per_kw,
+				dust_limit_satoshis: 546,
+				to_self_delay: 144,
+				keys: &self.keys,
+				htlcs: htlcs,
+			});
+
+			let local_funding_pubkey = PublicKey::from_secret_key(secp_ctx, &self.local_funding_key).unwrap();
+			let remote_funding_pubkey = PublicKey::from_secret_key(secp_ctx, &self.remote_funding_key).unwrap();
+			let funding_script = get_funding_redeemscript(&local_funding_pubkey, &remote_funding_pubkey);
+			let local_sig = self.sign(&tx, &funding_script, 10000000, &self.local_funding_key);
+			let remote_sig = self.sign(&tx, &funding_script, 10000000, &self.remote_funding_key);
+			add_funding_witness(secp_ctx, &mut tx, &local_sig, &remote_sig, &local_funding_pubkey, &remote_funding_pubkey);
+			assert_eq!(hex::encode(serialize(&tx).unwrap()), expected_tx);
+
+			assert_eq!(htlcs_included.len(), expected_htlc_txs.len());
+			for (htlc, expected_htlc_tx) in htlcs_included.iter().zip(expected_htlc_txs.iter()) {
+				assert_eq!(tx.output[htlc.transaction_output_index as usize].value, htlc.amount_msat / 1000);
+				let mut htlc_tx = build_htlc_transaction(&tx.bitcoin_hash(), feerate_per_kw, 144, htlc, &self.keys.a_delayed_payment_key, &self.keys.revocation_key);
+				let htlc_script = get_htlc_redeemscript(htlc, &self.keys);
+				let local_sig = self.sign(&htlc_tx, &htlc_script, htlc.amount_msat / 1000, &self.local_htlc_key);
+				let remote_sig = self.sign(&htlc_tx, &htlc_script, htlc.amount_msat / 1000, &self.remote_htlc_key);
+				// HTLC i expires at 500 + i
+				let preimage = if htlc.offered { None } else { Some(self.preimages[(htlc.cltv_expiry - 500) as usize]) };
+				add_htlc_witness(secp_ctx, &mut htlc_tx, htlc, &local_sig, &remote_sig, preimage, &htlc_script);
+				assert_eq!(hex::encode(serialize(&htlc_tx).unwrap()), *expected_htlc_tx);
+			}
+		}
+	}
+
+	#[test]
+	fn test_bolt3_commitment_vectors() {
+		let ctx = CommitmentTestContext::new();
+		let htlcs = ctx.htlcs();
+
+		// simple commitment tx with no HTLCs
+		ctx.check(7000000000, &[], 15000, "02000000000101bef67e4e2fb9ddeeb3461973cd4c62abb35050b1add772995b820b584a488489000000000038b02b8002c0c62d0000000000160014ccf1af2f2aabee14bb40fa3851ab2301de84311054a56a00000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0400473044022051b75c73198c6deee1a875871c3961832909acd297c6b908d59e3319e5185a46022055c419379c5051a78d00dbbce11b5b664a0c22815fbcc6fcef6b1937c383693901483045022100f51d2e566a70ba740fc5d8c0f07b9b93d2ed741c3c0860c613173de7d39e7968022041376d520e9c0e1ad52248ddf4b22e12be8763007df977253ef45a4ca3bdb7c001475221023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb21030e9f7b623d2ccc7c9bd44d66d5ce21ce504c0acf6385a132cec6d3c39fa711c152ae3e195220", &[
+		]);
+
+		// commitment tx with all five HTLCs untrimmed (minimum feerate)
+		ctx.check(6988000000, &htlcs, 0, "02000000000101bef67e4e2fb9ddeeb3461973cd4c62abb35050b1add772995b820b584a488489000000000038b02b8007e80300000000000022002052bfef0479d7b293c27e0f1eb294bea154c63a3294ef092c19af51409bce0e2ad007000000000000220020403d394747cae42e98ff01734ad5c08f82ba123d3d9a620abda88989651e2ab5d007000000000000220020748eba944fedc8827f6b06bc44678f93c0f9e6078b35c6331ed31e75f8ce0c2db80b000000000000220020c20b5d1f8584fd90443e7b7b720136174fa4b9333c261d04dbbd012635c0f419a00f0000000000002200208c48d15160397c9731df9bc3b236656efb6665fbfe92b4a6878e88a499f741c4c0c62d0000000000160014ccf1af2f2aabee14bb40fa3851ab2301de843110e0a06a00000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e04004730440220275b0c325a5e9355650dc30c0eccfbc7efb23987c24b556b9dfdd40effca18d202206caceb2c067836c51f296740c7ae807ffcbfbf1dd3a0d56b6de9a5b247985f060147304402204fd4928835db1ccdfc40f5c78ce9bd65249b16348df81f0c44328dcdefc97d630220194d3869c38bc732dd87d13d2958015e2fc16829e74cd4377f84d215c0b7060601475221023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb21030e9f7b623d2ccc7c9bd44d66d5ce21ce504c0acf6385a132cec6d3c39fa711c152ae3e195220", &[
+			"020000000001018154ecccf11a5fb56c39654c4deb4d2296f83c69268280b94d021370c94e219700000000000000000001e8030000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e050047304402206a6e59f18764a5bf8d4fa45eebc591566689441229c918b480fb2af8cc6a4aeb02205248f273be447684b33e3c8d1d85a8e0ca9fa0bae9ae33f0527ada9c162919a60147304402207cb324fa0de88f452ffa9389678127ebcf4cabe1dd848b8e076c1a1962bf34720220116ed922b12311bd602d67e60d2529917f21c5b82f25ff6506c0f87886b4dfd5012000000000000000000000000000000000000000000000000000000000000000008a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a914b8bcb07f6344b42ab04250c86a6e8b75d3fdbbc688527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f401b175ac686800000000",
+			"020000000001018154ecccf11a5fb56c39654c4deb4d2296f83c69268280b94d021370c94e219701000000000000000001d0070000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100d5275b3619953cb0c3b5aa577f04bc512380e60fa551762ce3d7a1bb7401cff9022037237ab0dac3fe100cde094e82e2bed9ba0ed1bb40154b48e56aa70f259e608b01483045022100c89172099507ff50f4c925e6c5150e871fb6e83dd73ff9fbb72f6ce829a9633f02203a63821d9162e99f9be712a68f9e589483994feae2661e4546cd5b6cec007be501008576a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c820120876475527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae67a914b43e1b38138a41b37f7cd9a1d274bc63e3a9b5d188ac6868f6010000",
+			"020000000001018154ecccf11a5fb56c39654c4deb4d2296f83c69268280b94d021370c94e219702000000000000000001d0070000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e050047304402201b63ec807771baf4fdff523c644080de17f1da478989308ad13a58b51db91d360220568939d38c9ce295adba15665fa68f51d967e8ed14a007b751540a80b325f20201483045022100def389deab09cee69eaa1ec14d9428770e45bcbe9feb46468ecf481371165c2f022015d2e3c46600b2ebba8dcc899768874cc6851fd1ecb3fffd15db1cc3de7e10da012001010101010101010101010101010101010101010101010101010101010101018a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a9144b6b2e5444c2639cc0fb7bcea5afba3f3cdce23988527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f501b175ac686800000000",
+			"020000000001018154ecccf11a5fb56c39654c4deb4d2296f83c69268280b94d021370c94e219703000000000000000001b80b0000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100daee1808f9861b6c3ecd14f7b707eca02dd6bdfc714ba2f33bc8cdba507bb182022026654bf8863af77d74f51f4e0b62d461a019561bb12acb120d3f7195d148a554014730440220643aacb19bbb72bd2b635bc3f7375481f5981bace78cdd8319b2988ffcc6704202203d27784ec8ad51ed3bd517a05525a5139bb0b755dd719e0054332d186ac0872701008576a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c820120876475527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae67a9148a486ff2e31d6158bf39e2608864d63fefd09d5b88ac6868f7010000",
+			"020000000001018154ecccf11a5fb56c39654c4deb4d2296f83c69268280b94d021370c94e219704000000000000000001a00f0000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e050047304402207e0410e45454b0978a623f36a10626ef17b27d9ad44e2760f98cfa3efb37924f0220220bd8acd43ecaa916a80bd4f919c495a2c58982ce7c8625153f8596692a801d014730440220549e80b4496803cbc4a1d09d46df50109f546d43fbbf86cd90b174b1484acd5402205f12a4f995cb9bded597eabfee195a285986aa6d93ae5bb72507ebc6a4e2349e012004040404040404040404040404040404040404040404040404040404040404048a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a91418bc1a114ccf9c052d3d23e28d3b0a9d1227434288527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f801b175ac686800000000",
+		]);
+
+		// commitment tx with seven outputs untrimmed (maximum feerate)
+		ctx.check(6988000000, &htlcs, 647, "02000000000101bef67e4e2fb9ddeeb3461973cd4c62abb35050b1add772995b820b584a488489000000000038b02b8007e80300000000000022002052bfef0479d7b293c27e0f1eb294bea154c63a3294ef092c19af51409bce0e2ad007000000000000220020403d394747cae42e98ff01734ad5c08f82ba123d3d9a620abda88989651e2ab5d007000000000000220020748eba944fedc8827f6b06bc44678f93c0f9e6078b35c6331ed31e75f8ce0c2db80b000000000000220020c20b5d1f8584fd90443e7b7b720136174fa4b9333c261d04dbbd012635c0f419a00f0000000000002200208c48d15160397c9731df9bc3b236656efb6665fbfe92b4a6878e88a499f741c4c0c62d0000000000160014ccf1af2f2aabee14bb40fa3851ab2301de843110e09c6a00000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e040048304502210094bfd8f5572ac0157ec76a9551b6c5216a4538c07cd13a51af4a54cb26fa14320220768efce8ce6f4a5efac875142ff19237c011343670adf9c7ac69704a120d116301483045022100a5c01383d3ec646d97e40f44318d49def817fcd61a0ef18008a665b3e151785502203e648efddd5838981ef55ec954be69c4a652d021e6081a100d034de366815e9b01475221023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb21030e9f7b623d2ccc7c9bd44d66d5ce21ce504c0acf6385a132cec6d3c39fa711c152ae3e195220", &[
+			"020000000001018323148ce2419f21ca3d6780053747715832e18ac780931a514b187768882bb60000000000000000000122020000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e05004730440220385a5afe75632f50128cbb029ee95c80156b5b4744beddc729ad339c9ca432c802202ba5f48550cad3379ac75b9b4fedb86a35baa6947f16ba5037fb8b11ab3437400147304402205999590b8a79fa346e003a68fd40366397119b2b0cdf37b149968d6bc6fbcc4702202b1e1fb5ab7864931caed4e732c359e0fe3d86a548b557be2246efb1708d579a012000000000000000000000000000000000000000000000000000000000000000008a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a914b8bcb07f6344b42ab04250c86a6e8b75d3fdbbc688527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f401b175ac686800000000",
+			"020000000001018323148ce2419f21ca3d6780053747715832e18ac780931a514b187768882bb60100000000000000000124060000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e050047304402207ceb6678d4db33d2401fdc409959e57c16a6cb97a30261d9c61f29b8c58d34b90220084b4a17b4ca0e86f2d798b3698ca52de5621f2ce86f80bed79afa66874511b00147304402207ff03eb0127fc7c6cae49cc29e2a586b98d1e8969cf4a17dfa50b9c2647720b902205e2ecfda2252956c0ca32f175080e75e4e390e433feb1f8ce9f2ba55648a1dac01008576a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c820120876475527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae67a914b43e1b38138a41b37f7cd9a1d274bc63e3a9b5d188ac6868f6010000",
+			"020000000001018323148ce2419f21ca3d6780053747715832e18ac780931a514b187768882bb6020000000000000000010a060000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e050047304402206a401b29a0dff0d18ec903502c13d83e7ec019450113f4a7655a4ce40d1f65ba0220217723a084e727b6ca0cc8b6c69c014a7e4a01fcdcba3e3993f462a3c574d83301483045022100d50d067ca625d54e62df533a8f9291736678d0b86c28a61bb2a80cf42e702d6e02202373dde7e00218eacdafb9415fe0e1071beec1857d1af3c6a201a44cbc47c877012001010101010101010101010101010101010101010101010101010101010101018a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a9144b6b2e5444c2639cc0fb7bcea5afba3f3cdce23988527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f501b175ac686800000000",
+			"020000000001018323148ce2419f21ca3d6780053747715832e18ac780931a514b187768882bb6030000000000000000010c0a0000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e05004830450221009b1c987ba599ee3bde1dbca776b85481d70a78b681a8d84206723e2795c7cac002207aac84ad910f8598c4d1c0ea2e3399cf6627a4e3e90131315bc9f038451ce39d01483045022100db9dc65291077a52728c622987e9895b7241d4394d6dcb916d7600a3e8728c22022036ee3ee717ba0bb5c45ee84bc7bbf85c0f90f26ae4e4a25a6b4241afa8a3f1cb01008576a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c820120876475527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae67a9148a486ff2e31d6158bf39e2608864d63fefd09d5b88ac6868f7010000",
+			"020000000001018323148ce2419f21ca3d6780053747715832e18ac780931a514b187768882bb604000000000000000001da0d0000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100cc28030b59f0914f45b84caa983b6f8effa900c952310708c2b5b00781117022022027ba2ccdf94d03c6d48b327f183f6e28c8a214d089b9227f94ac4f85315274f00147304402202d1a3c0d31200265d2a2def2753ead4959ae20b4083e19553acfffa5dfab60bf022020ede134149504e15b88ab261a066de49848411e15e70f9e6a5462aec2949f8f012004040404040404040404040404040404040404040404040404040404040404048a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a91418bc1a114ccf9c052d3d23e28d3b0a9d1227434288527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f801b175ac686800000000",
+		]);
+
+		// commitment tx with six outputs untrimmed (minimum feerate)
+		ctx.check(6988000000, &htlcs, 648, "02000000000101bef67e4e2fb9ddeeb3461973cd4c62abb35050b1add772995b820b584a488489000000000038b02b8006d007000000000000220020403d394747cae42e98ff01734ad5c08f82ba123d3d9a620abda88989651e2ab5d007000000000000220020748eba944fedc8827f6b06bc44678f93c0f9e6078b35c6331ed31e75f8ce0c2db80b000000000000220020c20b5d1f8584fd90443e7b7b720136174fa4b9333c261d04dbbd012635c0f419a00f0000000000002200208c48d15160397c9731df9bc3b236656efb6665fbfe92b4a6878e88a499f741c4c0c62d0000000000160014ccf1af2f2aabee14bb40fa3851ab2301de8431104e9d6a00000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0400483045022100a2270d5950c89ae0841233f6efea9c951898b301b2e89e0adbd2c687b9f32efa02207943d90f95b9610458e7c65a576e149750ff3accaacad004cd85e70b235e27de01473044022072714e2fbb93cdd1c42eb0828b4f2eff143f717d8f26e79d6ada4f0dcb681bbe02200911be4e5161dd6ebe59ff1c58e1997c4aea804f81db6b698821db6093d7b05701475221023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb21030e9f7b623d2ccc7c9bd44d66d5ce21ce504c0acf6385a132cec6d3c39fa711c152ae3e195220", &[
+			"02000000000101579c183eca9e8236a5d7f5dcd79cfec32c497fdc0ec61533cde99ecd436cadd10000000000000000000123060000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500473044022062ef2e77591409d60d7817d9bb1e71d3c4a2931d1a6c7c8307422c84f001a251022022dad9726b0ae3fe92bda745a06f2c00f92342a186d84518588cf65f4dfaada801483045022100a4c574f00411dd2f978ca5cdc1b848c311cd7849c087ad2f21a5bce5e8cc5ae90220090ae39a9bce2fb8bc879d7e9f9022df249f41e25e51f1a9bf6447a9eeffc09801008576a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c820120876475527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae67a914b43e1b38138a41b37f7cd9a1d274bc63e3a9b5d188ac6868f6010000",
+			"02000000000101579c183eca9e8236a5d7f5dcd79cfec32c497fdc0ec61533cde99ecd436cadd10100000000000000000109060000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100e968cbbb5f402ed389fdc7f6cd2a80ed650bb42c79aeb2a5678444af94f6c78502204b47a1cb24ab5b0b6fe69fe9cfc7dba07b9dd0d8b95f372c1d9435146a88f8d40147304402207679cf19790bea76a733d2fa0672bd43ab455687a068f815a3d237581f57139a0220683a1a799e102071c206b207735ca80f627ab83d6616b4bcd017c5d79ef3e7d0012001010101010101010101010101010101010101010101010101010101010101018a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a9144b6b2e5444c2639cc0fb7bcea5afba3f3cdce23988527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f501b175ac686800000000",
+			"02000000000101579c183eca9e8236a5d7f5dcd79cfec32c497fdc0ec61533cde99ecd436cadd1020000000000000000010b0a0000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100aa91932e305292cf9969cc23502bbf6cef83a5df39c95ad04a707c4f4fed5c7702207099fc0f3a9bfe1e7683c0e9aa5e76c5432eb20693bf4cb182f04d383dc9c8c20147304402200df76fea718745f3c529bac7fd37923e7309ce38b25c0781e4cf514dd9ef8dc802204172295739dbae9fe0474dcee3608e3433b4b2af3a2e6787108b02f894dcdda301008576a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c820120876475527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae67a9148a486ff2e31d6158bf39e2608864d63fefd09d5b88ac6868f7010000",
+			"02000000000101579c183eca9e8236a5d7f5dcd79cfec32c497fdc0ec61533cde99ecd436cadd103000000000000000001d90d0000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500473044022035cac88040a5bba420b1c4257235d5015309113460bc33f2853cd81ca36e632402202fc94fd3e81e9d34a9d01782a0284f3044370d03d60f3fc041e2da088d2de58f0147304402200daf2eb7afd355b4caf6fb08387b5f031940ea29d1a9f35071288a839c9039e4022067201b562456e7948616c13acb876b386b511599b58ac1d94d127f91c50463a6012004040404040404040404040404040404040404040404040404040404040404048a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a91418bc1a114ccf9c052d3d23e28d3b0a9d1227434288527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f801b175ac686800000000",
+		]);
+
+		// commitment tx with six outputs untrimmed (maximum feerate)
+		ctx.check(6988000000, &htlcs, 2069, "02000000000101bef67e4e2fb9ddeeb3461973cd4c62abb35050b1add772995b820b584a488489000000000038b02b8006d007000000000000220020403d394747cae42e98ff01734ad5c08f82ba123d3d9a620abda88989651e2ab5d007000000000000220020748eba944fedc8827f6b06bc44678f93c0f9e6078b35c6331ed31e75f8ce0c2db80b000000000000220020c20b5d1f8584fd90443e7b7b720136174fa4b9333c261d04dbbd012635c0f419a00f0000000000002200208c48d15160397c9731df9bc3b236656efb6665fbfe92b4a6878e88a499f741c4c0c62d0000000000160014ccf1af2f2aabee14bb40fa3851ab2301de84311077956a00000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e040047304402203ca8f31c6a47519f83255dc69f1894d9a6d7476a19f498d31eaf0cd3a85eeb63022026fd92dc752b33905c4c838c528b692a8ad4ced959990b5d5ee2ff940fa90eea01473044022001d55e488b8b035b2dd29d50b65b530923a416d47f377284145bc8767b1b6a75022019bb53ddfe1cefaf156f924777eaaf8fdca1810695a7d0a247ad2afba8232eb401475221023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb21030e9f7b623d2ccc7c9bd44d66d5ce21ce504c0acf6385a132cec6d3c39fa711c152ae3e195220", &[
+			"02000000000101ca94a9ad516ebc0c4bdd7b6254871babfa978d5accafb554214137d398bfcf6a0000000000000000000175020000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100d1cf354de41c1369336cf85b225ed033f1f8982a01be503668df756a7e668b66022001254144fb4d0eecc61908fccc3388891ba17c5d7a1a8c62bdd307e5a513f99201473044022056eb1af429660e45a1b0b66568cb8c4a3aa7e4c9c292d5d6c47f86ebf2c8838f022065c3ac4ebe980ca7a41148569be4ad8751b0a724a41405697ec55035dae6640201008576a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c820120876475527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae67a914b43e1b38138a41b37f7cd9a1d274bc63e3a9b5d188ac6868f6010000",
+			"02000000000101ca94a9ad516ebc0c4bdd7b6254871babfa978d5accafb554214137d398bfcf6a0100000000000000000122020000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100d065569dcb94f090345402736385efeb8ea265131804beac06dd84d15dd2d6880220664feb0b4b2eb985fadb6ec7dc58c9334ea88ce599a9be760554a2d4b3b5d9f401483045022100914bb232cd4b2690ee3d6cb8c3713c4ac9c4fb925323068d8b07f67c8541f8d9022057152f5f1615b793d2d45aac7518989ae4fe970f28b9b5c77504799d25433f7f012001010101010101010101010101010101010101010101010101010101010101018a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a9144b6b2e5444c2639cc0fb7bcea5afba3f3cdce23988527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f501b175ac686800000000",
+			"02000000000101ca94a9ad516ebc0c4bdd7b6254871babfa978d5accafb554214137d398bfcf6a020000000000000000015d060000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100d4e69d363de993684eae7b37853c40722a4c1b4a7b588ad7b5d8a9b5006137a102207a069c628170ee34be5612747051bdcc087466dbaa68d5756ea81c10155aef180147304402200e362443f7af830b419771e8e1614fc391db3a4eb799989abfc5ab26d6fcd032022039ab0cad1c14dfbe9446bf847965e56fe016e0cbcf719fd18c1bfbf53ecbd9f901008576a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c820120876475527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae67a9148a486ff2e31d6158bf39e2608864d63fefd09d5b88ac6868f7010000",
+			"02000000000101ca94a9ad516ebc0c4bdd7b6254871babfa978d5accafb554214137d398bfcf6a03000000000000000001f2090000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e05004830450221008ec888e36e4a4b3dc2ed6b823319855b2ae03006ca6ae0d9aa7e24bfc1d6f07102203b0f78885472a67ff4fe5916c0bb669487d659527509516fc3a08e87a2cc0a7c0147304402202c3e14282b84b02705dfd00a6da396c9fe8a8bcb1d3fdb4b20a4feba09440e8b02202b058b39aa9b0c865b22095edcd9ff1f71bbfe20aa4993755e54d042755ed0d5012004040404040404040404040404040404040404040404040404040404040404048a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a91418bc1a114ccf9c052d3d23e28d3b0a9d1227434288527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f801b175ac686800000000",
+		]);
+
+		// commitment tx with five outputs untrimmed (minimum feerate)
+		ctx.check(6988000000, &htlcs, 2070, "02000000000101bef67e4e2fb9ddeeb3461973cd4c62abb35050b1add772995b820b584a488489000000000038b02b8005d007000000000000220020403d394747cae42e98ff01734ad5c08f82ba123d3d9a620abda88989651e2ab5b80b000000000000220020c20b5d1f8584fd90443e7b7b720136174fa4b9333c261d04dbbd012635c0f419a00f0000000000002200208c48d15160397c9731df9bc3b236656efb6665fbfe92b4a6878e88a499f741c4c0c62d0000000000160014ccf1af2f2aabee14bb40fa3851ab2301de843110da966a00000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e04004730440220443cb07f650aebbba14b8bc8d81e096712590f524c5991ac0ed3bbc8fd3bd0c7022028a635f548e3ca64b19b69b1ea00f05b22752f91daf0b6dab78e62ba52eb7fd001483045022100f2377f7a67b7fc7f4e2c0c9e3a7de935c32417f5668eda31ea1db401b7dc53030220415fdbc8e91d0f735e70c21952342742e25249b0d062d43efbfc564499f3752601475221023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb21030e9f7b623d2ccc7c9bd44d66d5ce21ce504c0acf6385a132cec6d3c39fa711c152ae3e195220", &[
+			"0200000000010140a83ce364747ff277f4d7595d8d15f708418798922c40bc2b056aca5485a2180000000000000000000174020000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100eed143b1ee4bed5dc3cde40afa5db3e7354cbf9c44054b5f713f729356f08cf7022077161d171c2bbd9badf3c9934de65a4918de03bbac1450f715275f75b103f89101483045022100a0d043ed533e7fb1911e0553d31a8e2f3e6de19dbc035257f29d747c5e02f1f5022030cd38d8e84282175d49c1ebe0470db3ebd59768cf40780a784e248a43904fb801008576a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c820120876475527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae67a914b43e1b38138a41b37f7cd9a1d274bc63e3a9b5d188ac6868f6010000",
+			"0200000000010140a83ce364747ff277f4d7595d8d15f708418798922c40bc2b056aca5485a218010000000000000000015c060000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500473044022071e9357619fd8d29a411dc053b326a5224c5d11268070e88ecb981b174747c7a02202b763ae29a9d0732fa8836dd8597439460b50472183f420021b768981b4f7cf601483045022100adb1d679f65f96178b59f23ed37d3b70443118f345224a07ecb043eee2acc157022034d24524fe857144a3bcfff3065a9994d0a6ec5f11c681e49431d573e242612d01008576a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c820120876475527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae67a9148a486ff2e31d6158bf39e2608864d63fefd09d5b88ac6868f7010000",
+			"0200000000010140a83ce364747ff277f4d7595d8d15f708418798922c40bc2b056aca5485a21802000000000000000001f1090000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100c9458a4d2cbb741705577deb0a890e5cb90ee141be0400d3162e533727c9cb2102206edcf765c5dc5e5f9b976ea8149bf8607b5a0efb30691138e1231302b640d2a40147304402200831422aa4e1ee6d55e0b894201770a8f8817a189356f2d70be76633ffa6a6f602200dd1b84a4855dc6727dd46c98daae43dfc70889d1ba7ef0087529a57c06e5e04012004040404040404040404040404040404040404040404040404040404040404048a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a91418bc1a114ccf9c052d3d23e28d3b0a9d1227434288527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f801b175ac686800000000",
+		]);
+
+		// commitment tx with five outputs untrimmed (maximum feerate)
+		ctx.check(6988000000, &htlcs, 2194, "02000000000101bef67e4e2fb9ddeeb3461973cd4c62abb35050b1add772995b820b584a488489000000000038b02b8005d007000000000000220020403d394747cae42e98ff01734ad5c08f82ba123d3d9a620abda88989651e2ab5b80b000000000000220020c20b5d1f8584fd90443e7b7b720136174fa4b9333c261d04dbbd012635c0f419a00f0000000000002200208c48d15160397c9731df9bc3b236656efb6665fbfe92b4a6878e88a499f741c4c0c62d0000000000160014ccf1af2f2aabee14bb40fa3851ab2301de84311040966a00000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e040047304402203b1b010c109c2ecbe7feb2d259b9c4126bd5dc99ee693c422ec0a5781fe161ba0220571fe4e2c649dea9c7aaf7e49b382962f6a3494963c97d80fef9a430ca3f706101483045022100d33c4e541aa1d255d41ea9a3b443b3b822ad8f7f86862638aac1f69f8f760577022007e2a18e6931ce3d3a804b1c78eda1de17dbe1fb7a95488c9a4ec8620395334801475221023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb21030e9f7b623d2ccc7c9bd44d66d5ce21ce504c0acf6385a132cec6d3c39fa711c152ae3e195220", &[
+			"02000000000101fb824d4e4dafc0f567789dee3a6bce8d411fe80f5563d8cdfdcc7d7e4447d43a0000000000000000000122020000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e05004830450221009ed2f0a67f99e29c3c8cf45c08207b765980697781bb727fe0b1416de0e7622902206052684229bc171419ed290f4b615c943f819c0262414e43c5b91dcf72ddcf4401473044022004ad5f04ae69c71b3b141d4db9d0d4c38d84009fb3cfeeae6efdad414487a9a0022042d3fe1388c1ff517d1da7fb4025663d372c14728ed52dc88608363450ff6a2f01008576a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c820120876475527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae67a914b43e1b38138a41b37f7cd9a1d274bc63e3a9b5d188ac6868f6010000",
+			"02000000000101fb824d4e4dafc0f567789dee3a6bce8d411fe80f5563d8cdfdcc7d7e4447d43a010000000000000000010a060000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e05004730440220155d3b90c67c33a8321996a9be5b82431b0c126613be751d400669da9d5c696702204318448bcd48824439d2c6a70be6e5747446be47ff45977cf41672bdc9b6b12d0147304402201707050c870c1f77cc3ed58d6d71bf281de239e9eabd8ef0955bad0d7fe38dcc02204d36d80d0019b3a71e646a08fa4a5607761d341ae8be371946ebe437c289c91501008576a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c820120876475527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae67a9148a486ff2e31d6158bf39e2608864d63fefd09d5b88ac6868f7010000",
+			"02000000000101fb824d4e4dafc0f567789dee3a6bce8d411fe80f5563d8cdfdcc7d7e4447d43a020000000000000000019a090000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100a12a9a473ece548584aabdd051779025a5ed4077c4b7aa376ec7a0b1645e5a48022039490b333f53b5b3e2ddde1d809e492cba2b3e5fc3a436cd3ffb4cd3d500fa5a01483045022100ff200bc934ab26ce9a559e998ceb0aee53bc40368e114ab9d3054d9960546e2802202496856ca163ac12c143110b6b3ac9d598df7254f2e17b3b94c3ab5301f4c3b0012004040404040404040404040404040404040404040404040404040404040404048a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a91418bc1a114ccf9c052d3d23e28d3b0a9d1227434288527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f801b175ac686800000000",
+		]);
+
+		// commitment tx with four outputs untrimmed (minimum feerate)
+		ctx.check(6988000000, &htlcs, 2195, "02000000000101bef67e4e2fb9ddeeb3461973cd4c62abb35050b1add772995b820b584a488489000000000038b02b8004b80b000000000000220020c20b5d1f8584fd90443e7b7b720136174fa4b9333c261d04dbbd012635c0f419a00f0000000000002200208c48d15160397c9731df9bc3b236656efb6665fbfe92b4a6878e88a499f741c4c0c62d0000000000160014ccf1af2f2aabee14bb40fa3851ab2301de843110b8976a00000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e040047304402203b12d44254244b8ff3bb4129b0920fd45120ab42f553d9976394b099d500c99e02205e95bb7a3164852ef0c48f9e0eaf145218f8e2c41251b231f03cbdc4f29a54290147304402205e2f76d4657fb732c0dfc820a18a7301e368f5799e06b7828007633741bda6df0220458009ae59d0c6246065c419359e05eb2a4b4ef4a1b310cc912db44eb792429801475221023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb21030e9f7b623d2ccc7c9bd44d66d5ce21ce504c0acf6385a132cec6d3c39fa711c152ae3e195220", &[
+			"020000000001014e16c488fa158431c1a82e8f661240ec0a71ba0ce92f2721a6538c510226ad5c0000000000000000000109060000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100a8a78fa1016a5c5c3704f2e8908715a3cef66723fb95f3132ec4d2d05cd84fb4022025ac49287b0861ec21932405f5600cbce94313dbde0e6c5d5af1b3366d8afbfc01483045022100be6ae1977fd7b630a53623f3f25c542317ccfc2b971782802a4f1ef538eb22b402207edc4d0408f8f38fd3c7365d1cfc26511b7cd2d4fecd8b005fba3cd5bc70439001008576a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c820120876475527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae67a9148a486ff2e31d6158bf39e2608864d63fefd09d5b88ac6868f7010000",
+			"020000000001014e16c488fa158431c1a82e8f661240ec0a71ba0ce92f2721a6538c510226ad5c0100000000000000000199090000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100e769cb156aa2f7515d126cef7a69968629620ce82afcaa9e210969de6850df4602200b16b3f3486a229a48aadde520dbee31ae340dbadaffae74fbb56681fef27b92014730440220665b9cb4a978c09d1ca8977a534999bc8a49da624d0c5439451dd69cde1a003d022070eae0620f01f3c1bd029cc1488da13fb40fdab76f396ccd335479a11c5276d8012004040404040404040404040404040404040404040404040404040404040404048a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a91418bc1a114ccf9c052d3d23e28d3b0a9d1227434288527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f801b175ac686800000000",
+		]);
+
+		// commitment tx with four outputs untrimmed (maximum feerate)
+		ctx.check(6988000000, &htlcs, 3702, "02000000000101bef67e4e2fb9ddeeb3461973cd4c62abb35050b1add772995b820b584a488489000000000038b02b8004b80b000000000000220020c20b5d1f8584fd90443e7b7b720136174fa4b9333c261d04dbbd012635c0f419a00f0000000000002200208c48d15160397c9731df9bc3b236656efb6665fbfe92b4a6878e88a499f741c4c0c62d0000000000160014ccf1af2f2aabee14bb40fa3851ab2301de8431106f916a00000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e040047304402200e930a43c7951162dc15a2b7344f48091c74c70f7024e7116e900d8bcfba861c022066fa6cbda3929e21daa2e7e16a4b948db7e8919ef978402360d1095ffdaff7b001483045022100c1a3b0b60ca092ed5080121f26a74a20cec6bdee3f8e47bae973fcdceb3eda5502207d467a9873c939bf3aa758014ae67295fedbca52412633f7e5b2670fc7c381c101475221023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb21030e9f7b623d2ccc7c9bd44d66d5ce21ce504c0acf6385a132cec6d3c39fa711c152ae3e195220", &[
+			"02000000000101b8de11eb51c22498fe39722c7227b6e55ff1a94146cf638458cb9bc6a060d3a30000000000000000000122020000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100dfb73b4fe961b31a859b2bb1f4f15cabab9265016dd0272323dc6a9e85885c54022059a7b87c02861ee70662907f25ce11597d7b68d3399443a831ae40e777b76bdb0147304402202765b9c9ece4f127fa5407faf66da4c5ce2719cdbe47cd3175fc7d48b482e43d02205605125925e07bad1e41c618a4b434d72c88a164981c4b8af5eaf4ee9142ec3a01008576a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c820120876475527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae67a9148a486ff2e31d6158bf39e2608864d63fefd09d5b88ac6868f7010000",
+			"02000000000101b8de11eb51c22498fe39722c7227b6e55ff1a94146cf638458cb9bc6a060d3a30100000000000000000176050000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100ea9dc2a7c3c3640334dab733bb4e036e32a3106dc707b24227874fa4f7da746802204d672f7ac0fe765931a8df10b81e53a3242dd32bd9dc9331eb4a596da87954e9014730440220048a41c660c4841693de037d00a407810389f4574b3286afb7bc392a438fa3f802200401d71fa87c64fe621b49ac07e3bf85157ac680acb977124da28652cc7f1a5c012004040404040404040404040404040404040404040404040404040404040404048a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a91418bc1a114ccf9c052d3d23e28d3b0a9d1227434288527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f801b175ac686800000000",
+		]);
+
+		// commitment tx with three outputs untrimmed (minimum feerate)
+		ctx.check(6988000000, &htlcs, 3703, "02000000000101bef67e4e2fb9ddeeb3461973cd4c62abb35050b1add772995b820b584a488489000000000038b02b8003a00f0000000000002200208c48d15160397c9731df9bc3b236656efb6665fbfe92b4a6878e88a499f741c4c0c62d0000000000160014ccf1af2f2aabee14bb40fa3851ab2301de843110eb936a00000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0400473044022047305531dd44391dce03ae20f8735005c615eb077a974edb0059ea1a311857d602202e0ed6972fbdd1e8cb542b06e0929bc41b2ddf236e04cb75edd56151f4197506014830450221008b7c191dd46893b67b628e618d2dc8e81169d38bade310181ab77d7c94c6675e02203b4dd131fd7c9deb299560983dcdc485545c98f989f7ae8180c28289f9e6bdb001475221023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb21030e9f7b623d2ccc7c9bd44d66d5ce21ce504c0acf6385a132cec6d3c39fa711c152ae3e195220", &[
+			"020000000001011c076aa7fb3d7460d10df69432c904227ea84bbf3134d4ceee5fb0f135ef206d0000000000000000000175050000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500473044022044f65cf833afdcb9d18795ca93f7230005777662539815b8a601eeb3e57129a902206a4bf3e53392affbba52640627defa8dc8af61c958c9e827b2798ab45828abdd01483045022100b94d931a811b32eeb885c28ddcf999ae1981893b21dd1329929543fe87ce793002206370107fdd151c5f2384f9ceb71b3107c69c74c8ed5a28a94a4ab2d27d3b0724012004040404040404040404040404040404040404040404040404040404040404048a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a91418bc1a114ccf9c052d3d23e28d3b0a9d1227434288527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f801b175ac686800000000",
+		]);
+
+		// commitment tx with three outputs untrimmed (maximum feerate)
+		ctx.check(6988000000, &htlcs, 4914, "02000000000101bef67e4e2fb9ddeeb3461973cd4c62abb35050b1add772995b820b584a488489000000000038b02b8003a00f0000000000002200208c48d15160397c9731df9bc3b236656efb6665fbfe92b4a6878e88a499f741c4c0c62d0000000000160014ccf1af2f2aabee14bb40fa3851ab2301de843110ae8f6a00000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e040047304402206a2679efa3c7aaffd2a447fd0df7aba8792858b589750f6a1203f9259173198a022008d52a0e77a99ab533c36206cb15ad7aeb2aa72b93d4b571e728cb5ec2f6fe260147304402206d6cb93969d39177a09d5d45b583f34966195b77c7e585cf47ac5cce0c90cefb022031d71ae4e33a4e80df7f981d696fbdee517337806a3c7138b7491e2cbb077a0e01475221023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb21030e9f7b623d2ccc7c9bd44d66d5ce21ce504c0acf6385a132cec6d3c39fa711c152ae3e195220", &[
+			"0200000000010110a3fdcbcd5db477cd3ad465e7f501ffa8c437e8301f00a6061138590add757f0000000000000000000122020000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0500483045022100fcb38506bfa11c02874092a843d0cc0a8613c23b639832564a5f69020cb0f6ba02206508b9e91eaa001425c190c68ee5f887e1ad5b1b314002e74db9dbd9e42dbecf0148304502210086e76b460ddd3cea10525fba298405d3fe11383e56966a5091811368362f689a02200f72ee75657915e0ede89c28709acd113ede9e1b7be520e3bc5cda425ecd6e68012004040404040404040404040404040404040404040404040404040404040404048a76a91414011f7254d96b819c76986c277d115efce6f7b58763ac67210394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b7c8201208763a91418bc1a114ccf9c052d3d23e28d3b0a9d1227434288527c21030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e752ae677502f801b175ac686800000000",
+		]);
+
+		// commitment tx with two outputs untrimmed (minimum feerate)
+		ctx.check(6988000000, &htlcs, 4915, "02000000000101bef67e4e2fb9ddeeb3461973cd4c62abb35050b1add772995b820b584a488489000000000038b02b8002c0c62d0000000000160014ccf1af2f2aabee14bb40fa3851ab2301de843110fa926a00000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80e0400483045022100a012691ba6cea2f73fa8bac37750477e66363c6d28813b0bb6da77c8eb3fb0270220365e99c51304b0b1a6ab9ea1c8500db186693e39ec1ad5743ee231b0138384b90147304402200769ba89c7330dfa4feba447b6e322305f12ac7dac70ec6ba997ed7c1b598d0802204fe8d337e7fee781f9b7b1a06e580b22f4f79d740059560191d7db53f876555201475221023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb21030e9f7b623d2ccc7c9bd44d66d5ce21ce504c0acf6385a132cec6d3c39fa711c152ae3e195220", &[
+		]);
+
+		// commitment tx with two outputs untrimmed (maximum feerate)
+		ctx.check(6988000000, &htlcs, 9651180, "02000000000101bef67e4e2fb9ddeeb3461973cd4c62abb35050b1add772995b820b584a488489000000000038b02b800222020000000000002200204adb4e2f00643db396dd120d4e7dc17625f5f2c11a40d857accc862d6b7dd80ec0c62d0000000000160014ccf1af2f2aabee14bb40fa3851ab2301de84311004004730440220514f977bf7edc442de8ce43ace9686e5ebdc0f893033f13e40fb46c8b8c6e1f90220188006227d175f5c35da0b092c57bea82537aed89f7778204dc5bacf4f29f2b901473044022037f83ff00c8e5fb18ae1f918ffc24e54581775a20ff1ae719297ef066c71caa9022039c529cccd89ff6c5ed1db799614533844bd6d101da503761c45c713996e3bbd01475221023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb21030e9f7b623d2ccc7c9bd44d66d5ce21ce504c0acf6385a132cec6d3c39fa711c152ae3e195220", &[
+		]);
+
+		// commitment tx with one output untrimmed (minimum feerate)
+		ctx.check(6988000000, &htlcs, 9651181, "02000000000101bef67e4e2fb9ddeeb3461973cd4c62abb35050b1add772995b820b584a488489000000000038b02b8001c0c62d0000000000160014ccf1af2f2aabee14bb40fa3851ab2301de8431100400473044022031a82b51bd014915fe68928d1abf4b9885353fb896cac10c3fdd88d7f9c7f2e00220716bda819641d2c63e65d3549b6120112e1aeaf1742eed94a471488e79e206b101473044022064901950be922e62cbe3f2ab93de2b99f37cff9fc473e73e394b27f88ef0731d02206d1dfa227527b4df44a07599289e207d6fd9cca60c0365682dcd3deaf739567e01475221023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb21030e9f7b623d2ccc7c9bd44d66d5ce21ce504c0acf6385a132cec6d3c39fa711c152ae3e195220", &[
+		]);
+
+		// commitment tx with fee greater than funder amount
+		ctx.check(6988000000, &htlcs, 9651936, "02000000000101bef67e4e2fb9ddeeb3461973cd4c62abb35050b1add772995b820b584a488489000000000038b02b8001c0c62d0000000000160014ccf1af2f2aabee14bb40fa3851ab2301de8431100400473044022031a82b51bd014915fe68928d1abf4b9885353fb896cac10c3fdd88d7f9c7f2e00220716bda819641d2c63e65d3549b6120112e1aeaf1742eed94a471488e79e206b101473044022064901950be922e62cbe3f2ab93de2b99f37cff9fc473e73e394b27f88ef0731d02206d1dfa227527b4df44a07599289e207d6fd9cca60c0365682dcd3deaf739567e01475221023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb21030e9f7b623d2ccc7c9bd44d66d5ce21ce504c0acf6385a132cec6d3c39fa711c152ae3e195220", &[
+		]);
+	}
+}
@@ -0,0 +1,1680 @@
+//! The state machine of a single channel, following BOLT #2: establishment, HTLC updates with
+//! their commitment_signed/revoke_and_ack dance, and mutual close.
+//!
+//! HTLCs are only ever exchanged with the direct counterparty: onions are neither built nor read.
+//! The feerate is fixed when the channel is opened, update_fee isn't supported.
+
+use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::{TxIn,TxOut,Transaction};
+use bitcoin::network::serialize::BitcoinHash;
+use bitcoin::util::bip143;
+use bitcoin::util::hash::Sha256dHash;
+
+use secp256k1::key::{SecretKey,PublicKey};
+use secp256k1::{Secp256k1,Message,Signature};
+
+use crypto::digest::Digest;
+
+use ln::chan_utils;
+use ln::chan_utils::{TxCreationKeys,HTLCOutputInCommitment,CommitmentTransactionInfo,CounterpartyCommitmentSecrets,COMMITMENT_TX_BASE_WEIGHT,COMMITMENT_TX_WEIGHT_PER_HTLC};
+use ln::funding;
+use ln::msgs;
+use ln::msgs::{ErrorAction,HandleError};
+use util::rng;
+use util::sha2::Sha256;
+
+use std::{cmp,mem};
+
+/// The secret keys of our side of a channel.
+pub struct ChannelKeys {
+	pub funding_key: SecretKey,
+	pub revocation_base_key: SecretKey,
+	pub payment_base_key: SecretKey,
+	pub delayed_payment_base_key: SecretKey,
+	pub htlc_base_key: SecretKey,
+	pub commitment_seed: [u8; 32],
+}
+
+impl ChannelKeys {
+	/// Generates a fresh set of random keys.
+	pub fn new_random(secp_ctx: &Secp256k1) -> ChannelKeys {
+		let mut commitment_seed = [0; 32];
+		rng::fill_bytes(&mut commitment_seed);
+		ChannelKeys {
+			funding_key: random_secret_key(secp_ctx),
+			revocation_base_key: random_secret_key(secp_ctx),
+			payment_base_key: random_secret_key(secp_ctx),
+			delayed_payment_base_key: random_secret_key(secp_ctx),
+			htlc_base_key: random_secret_key(secp_ctx),
+			commitment_seed: commitment_seed,
+		}
+	}
+}
+
+pub(crate) fn random_secret_key(secp_ctx: &Secp256k1) -> SecretKey {
+	loop {
+		let mut key = [0; 32];
+		rng::fill_bytes(&mut key);
+		if let Ok(key) = SecretKey::from_slice(secp_ctx, &key) {
+			return key;
+		}
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum HTLCState {
+	/// Added by remote, to be included in next local commitment tx.
+	/// Implies HTLCOutput::outbound: false
+	RemoteAnnounced,
+	/// Included in a received commitment_signed message (implying we've revoke_and_ack'ed it), but
+	/// we were waiting on a revoke_and_ack for a previous commitment_signed of ours, so we haven't
+	/// included it in a commitment_signed yet.
+	/// Implies HTLCOutput::outbound: false
+	AwaitingRemoteRevokeToAnnounce,
+	/// Included in a received commitment_signed message and in our latest commitment_signed. Once
+	/// they revoke_and_ack the HTLC is irrevocably committed.
+	/// Implies HTLCOutput::outbound: false
+	AwaitingAnnouncedRemoteRevoke,
+	/// Added by us and included in a commitment_signed (if we were AwaitingRemoteRevoke when we
+	/// created it we would have put it in the holding cell instead). When they next
+	/// revoke_and_ack we will promote to Committed.
+	/// Implies HTLCOutput::outbound: true
+	LocalAnnounced,
+	Committed,
+	/// Remote removed this (outbound) HTLC. We're waiting on their commitment_signed to finalize
+	/// the change.
+	/// Implies HTLCOutput::outbound: true
+	RemoteRemoved,
+	/// Remote removed this and sent a commitment_signed (implying we've revoke_and_ack'ed it), but
+	/// we were waiting on a revoke_and_ack for a previous commitment_signed of ours, so we haven't
+	/// removed it in a commitment_signed yet.
+	/// Implies HTLCOutput::outbound: true
+	AwaitingRemoteRevokeToRemove,
+	/// Remote removed this and sent a commitment_signed, we removed it in our latest
+	/// commitment_signed and are now just waiting on a revoke_and_ack to drop it completely.
+	/// Implies HTLCOutput::outbound: true
+	AwaitingRemovedRemoteRevoke,
+	/// Removed by us and a new commitment_signed was sent (if we were AwaitingRemoteRevoke when we
+	/// created it we would have put it in the holding cell instead). When they next
+	/// revoke_and_ack we'll promote to LocalRemovedAwaitingCommitment.
+	/// Implies HTLCOutput::outbound: false
+	LocalRemoved,
+	/// Removed by us, sent a new commitment_signed and got a revoke_and_ack. Just waiting on an
+	/// updated local commitment transaction to drop it.
+	/// Implies HTLCOutput::outbound: false
+	LocalRemovedAwaitingCommitment,
+}
+
+impl HTLCState {
+	/// Whether an HTLC in this state has an output in our (local) or their (remote) next
+	/// commitment transaction.
+	fn is_included_in(&self, local: bool) -> bool {
+		match *self {
+			HTLCState::RemoteAnnounced => local,
+			HTLCState::AwaitingRemoteRevokeToAnnounce => true,
+			HTLCState::AwaitingAnnouncedRemoteRevoke => true,
+			HTLCState::LocalAnnounced => !local,
+			HTLCState::Committed => true,
+			HTLCState::RemoteRemoved => !local,
+			HTLCState::AwaitingRemoteRevokeToRemove => false,
+			HTLCState::AwaitingRemovedRemoteRevoke => false,
+			HTLCState::LocalRemoved => local,
+			HTLCState::LocalRemovedAwaitingCommitment => false,
+		}
+	}
+
+	fn is_removed(&self) -> bool {
+		match *self {
+			HTLCState::RemoteRemoved | HTLCState::AwaitingRemoteRevokeToRemove | HTLCState::AwaitingRemovedRemoteRevoke |
+			HTLCState::LocalRemoved | HTLCState::LocalRemovedAwaitingCommitment => true,
+			_ => false,
+		}
+	}
+}
+
+struct HTLCOutput {
+	outbound: bool, // ie to an HTLC-Timeout transaction
+	htlc_id: u64,
+	amount_msat: u64,
+	cltv_expiry: u32,
+	payment_hash: [u8; 32],
+	state: HTLCState,
+	/// Once removed, whether the HTLC was fulfilled (rather than failed)
+	fulfilled: bool,
+}
+
+impl HTLCOutput {
+	/// The HTLC as seen by the commitment transaction of the given side.
+	fn in_commitment(&self, local: bool) -> HTLCOutputInCommitment {
+		HTLCOutputInCommitment {
+			offered: self.outbound == local,
+			amount_msat: self.amount_msat,
+			cltv_expiry: self.cltv_expiry,
+			payment_hash: self.payment_hash,
+			transaction_output_index: 0,
+		}
+	}
+}
+
+/// Updates we generated while waiting on a revoke_and_ack, sent once it arrives.
+enum HTLCUpdateAwaitingACK {
+	AddHTLC {
+		amount_msat: u64,
+		cltv_expiry: u32,
+		payment_hash: [u8; 32],
+		onion_routing_packet: msgs::OnionPacket,
+	},
+	ClaimHTLC {
+		payment_preimage: [u8; 32],
+		htlc_id: u64,
+	},
+	FailHTLC {
+		htlc_id: u64,
+		err_packet: msgs::OnionErrorPacket,
+	},
+}
+
+enum ChannelState {
+	/// Implies we have (or are prepared to) send our open_channel/accept_channel message
+	OurInitSent = (1 << 0),
+	/// Implies we have received their open_channel/accept_channel message
+	TheirInitSent = (1 << 1),
+	/// We have sent funding_created and are awaiting a funding_signed to advance to FundingSent.
+	/// Note that this is nonsense for an inbound channel as we immediately generate funding_signed
+	/// upon receipt of funding_created, so simply skip this state.
+	FundingCreated = 4,
+	/// Set when we have received/sent funding_created and funding_signed and are thus now waiting
+	/// on the funding transaction to confirm. The FundingLocked flags are set to indicate when we
+	/// and our counterparty consider the funding transaction confirmed.
+	FundingSent = 8,
+	/// Flag which can be set on FundingSent to indicate they sent us a funding_locked message.
+	/// Once both TheirFundingLocked and OurFundingLocked are set, state moves on to ChannelFunded.
+	TheirFundingLocked = (1 << 4),
+	/// Flag which can be set on FundingSent to indicate we sent them a funding_locked message.
+	/// Once both TheirFundingLocked and OurFundingLocked are set, state moves on to ChannelFunded.
+	OurFundingLocked = (1 << 5),
+	ChannelFunded = 64,
+	/// Flag which implies that we have sent a commitment_signed but are awaiting the responding
+	/// revoke_and_ack message. During this time period, we can't generate new commitment_signed
+	/// messages as then we will be unable to determine which HTLCs they included in their
+	/// revoke_and_ack implicit ACK, so instead we have to hold them away temporarily to be sent
+	/// later.
+	/// Flag is set on ChannelFunded.
+	AwaitingRemoteRevoke = (1 << 7),
+	/// Flag which is set on ChannelFunded to indicate they sent us a shutdown message and no new
+	/// HTLCs may be added by them.
+	RemoteShutdownSent = (1 << 8),
+	/// Flag which is set on ChannelFunded to indicate we sent them a shutdown message and we may
+	/// not add new HTLCs.
+	LocalShutdownSent = (1 << 9),
+	/// We've successfully negotiated a closing_signed dance. At this point ChannelManager is about
+	/// to drop us, but we store this anyway.
+	ShutdownComplete = (1 << 10),
+}
+const BOTH_SIDES_SHUTDOWN_MASK: u32 = (ChannelState::LocalShutdownSent as u32 | ChannelState::RemoteShutdownSent as u32);
+
+/// The number of the first commitment transaction, numbers count down from there, in the same
+/// order as the per-commitment secrets are derived.
+pub const INITIAL_COMMITMENT_NUMBER: u64 = (1 << 48) - 1;
+
+/// The largest channel BOLT #2 allows.
+const MAX_FUNDING_SATOSHIS: u64 = (1 << 24);
+
+/// The delay we ask the remote side to wait before spending its own outputs of its commitment
+/// transactions, so that we have time to punish revoked states.
+const OUR_TO_SELF_DELAY: u16 = 144;
+/// The longest delay we accept on our own outputs (two weeks).
+const MAX_LOCAL_BREAKDOWN_TIMEOUT: u16 = 2016;
+/// The confirmations we ask for on the funding transaction of inbound channels.
+const OUR_MINIMUM_DEPTH: u32 = 3;
+
+/// The most HTLCs we accept from the counterparty at once. Each of them adds an output to the
+/// commitment transactions and a signature to every commitment_signed, so we stay well below the
+/// BOLT #2 limit to keep a force-closing commitment transaction small and cheap to confirm.
+const OUR_MAX_HTLCS: u16 = 50;
+const OUR_HTLC_MINIMUM_MSAT: u64 = 1000;
+/// BOLT #2 limit on max_accepted_htlcs
+const MAX_HTLCS: u16 = 483;
+
+/// BOLT #4 incorrect_payment_details (PERM|15), the only failure we send back
+const INCORRECT_PAYMENT_DETAILS: u16 = 0x4000 | 15;
+
+fn channel_error(channel_id: [u8; 32], err: &'static str) -> HandleError {
+	HandleError {
+		err: err,
+		action: Some(ErrorAction::SendErrorMessage {
+			msg: msgs::ErrorMessage {
+				channel_id: channel_id,
+				data: err.to_string(),
+			},
+		}),
+	}
+}
+
+macro_rules! secp_call {
+	( $res: expr, $channel_id: expr, $err_msg: expr ) => {
+		match $res {
+			Ok(key) => key,
+			Err(_) => return Err(channel_error($channel_id, $err_msg)),
+		}
+	};
+}
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+	let mut sha = Sha256::new();
+	sha.input(data);
+	let mut res = [0; 32];
+	sha.result(&mut res);
+	res
+}
+
+/// Only outputs which are standard for relay may receive the closing transaction's funds.
+fn is_standard_shutdown_script(script: &Script) -> bool {
+	let script = &script[..];
+	(script.len() == 25 && script[0] == 0x76 && script[1] == 0xa9 && script[2] == 20 && script[23] == 0x88 && script[24] == 0xac) || // P2PKH
+	(script.len() == 23 && script[0] == 0xa9 && script[1] == 20 && script[22] == 0x87) || // P2SH
+	(script.len() == 22 && script[0] == 0 && script[1] == 20) || // P2WPKH
+	(script.len() == 34 && script[0] == 0 && script[1] == 32) // P2WSH
+}
+
+// TODO: We should refactor this to be a Inbound/OutboundChannel until initial setup handshaking
+// has been completed, and then turn into a Channel to get compiler-time enforcement of things like
+// calling get_channel_id() before we're set up or things like get_outbound_funding_signed on an
+// inbound channel.
+pub struct Channel {
+	channel_id: [u8; 32],
+	channel_state: u32,
+	channel_outbound: bool,
+	secp_ctx: Secp256k1,
+	channel_value_satoshis: u64,
+	their_node_id: PublicKey,
+
+	local_keys: ChannelKeys,
+
+	cur_local_commitment_transaction_number: u64,
+	cur_remote_commitment_transaction_number: u64,
+	/// Our balance, excluding every HTLC still in pending_htlcs and the commitment fee
+	value_to_self_msat: u64,
+	pending_htlcs: Vec<HTLCOutput>,
+	holding_cell_htlc_updates: Vec<HTLCUpdateAwaitingACK>,
+	next_local_htlc_id: u64,
+	next_remote_htlc_id: u64,
+	feerate_per_kw: u64,
+	/// Our own feerate estimate, which the closing fee we propose is based on
+	our_feerate_per_kw: u64,
+
+	/// Our latest commitment transaction, with both signatures, so that we can close the channel
+	/// on our own if the counterparty goes wrong
+	last_local_commitment_txn: Option<Transaction>,
+	/// The funding transaction of an outbound channel, until they signed our first commitment
+	funding_tx: Option<Transaction>,
+	funding_txo: Option<(Sha256dHash, u16)>,
+	last_sent_closing_fee: Option<u64>,
+	last_received_closing_fee: Option<u64>,
+
+	their_dust_limit_satoshis: u64,
+	our_dust_limit_satoshis: u64,
+	their_max_htlc_value_in_flight_msat: u64,
+	their_channel_reserve_satoshis: u64,
+	their_htlc_minimum_msat: u64,
+	their_to_self_delay: u16,
+	their_max_accepted_htlcs: u16,
+
+	their_funding_pubkey: Option<PublicKey>,
+	their_revocation_basepoint: Option<PublicKey>,
+	their_payment_basepoint: Option<PublicKey>,
+	their_delayed_payment_basepoint: Option<PublicKey>,
+	their_htlc_basepoint: Option<PublicKey>,
+	/// The point of the next remote commitment transaction we'll sign
+	their_cur_commitment_point: Option<PublicKey>,
+	/// The point of the remote commitment transaction they'll revoke next
+	their_prev_commitment_point: Option<PublicKey>,
+	their_commitment_secrets: CounterpartyCommitmentSecrets,
+
+	their_shutdown_scriptpubkey: Option<Script>,
+	our_shutdown_scriptpubkey: Script,
+}
+
+impl Channel {
+	/// The reserve we ask the counterparty to keep in the channel: 1% of the channel value, but no
+	/// less than the dust limit.
+	fn get_our_channel_reserve_satoshis(channel_value_satoshis: u64) -> u64 {
+		cmp::max(channel_value_satoshis / 100, funding::DUST_LIMIT_SATOSHIS)
+	}
+
+	/// The most we let the counterparty have in flight towards us: the whole channel, as the
+	/// reserve and the balance checks already bound the HTLCs we accept.
+	fn get_our_max_htlc_value_in_flight_msat(channel_value_satoshis: u64) -> u64 {
+		channel_value_satoshis * 1000
+	}
+
+	fn commitment_tx_fee_msat(&self, num_htlcs: usize) -> u64 {
+		self.feerate_per_kw * (COMMITMENT_TX_BASE_WEIGHT + num_htlcs as u64 * COMMITMENT_TX_WEIGHT_PER_HTLC)
+	}
+
+	// Constructors:
+	pub fn new_outbound(their_node_id: PublicKey, channel_value_satoshis: u64, push_msat: u64, feerate_per_kw: u64, shutdown_scriptpubkey: Script) -> Result<Channel, HandleError> {
+		let mut temporary_channel_id = [0; 32];
+		rng::fill_bytes(&mut temporary_channel_id);
+		if channel_value_satoshis >= MAX_FUNDING_SATOSHIS {
+			return Err(HandleError{err: "funding value > 2^24", action: None});
+		}
+		if push_msat > channel_value_satoshis * 1000 {
+			return Err(HandleError{err: "push value > channel value", action: None});
+		}
+		let secp_ctx = Secp256k1::new();
+		let local_keys = ChannelKeys::new_random(&secp_ctx);
+
+		Ok(Channel {
+			channel_id: temporary_channel_id,
+			channel_state: ChannelState::OurInitSent as u32,
+			channel_outbound: true,
+			secp_ctx: secp_ctx,
+			channel_value_satoshis: channel_value_satoshis,
+			their_node_id: their_node_id,
+
+			local_keys: local_keys,
+
+			cur_local_commitment_transaction_number: INITIAL_COMMITMENT_NUMBER,
+			cur_remote_commitment_transaction_number: INITIAL_COMMITMENT_NUMBER,
+			value_to_self_msat: channel_value_satoshis * 1000 - push_msat,
+			pending_htlcs: Vec::new(),
+			holding_cell_htlc_updates: Vec::new(),
+			next_local_htlc_id: 0,
+			next_remote_htlc_id: 0,
+			feerate_per_kw: feerate_per_kw,
+			our_feerate_per_kw: feerate_per_kw,
+
+			last_local_commitment_txn: None,
+			funding_tx: None,
+			funding_txo: None,
+			last_sent_closing_fee: None,
+			last_received_closing_fee: None,
+
+			their_dust_limit_satoshis: 0,
+			our_dust_limit_satoshis: funding::DUST_LIMIT_SATOSHIS,
+			their_max_htlc_value_in_flight_msat: 0,
+			their_channel_reserve_satoshis: 0,
+			their_htlc_minimum_msat: 0,
+			their_to_self_delay: 0,
+			their_max_accepted_htlcs: 0,
+
+			their_funding_pubkey: None,
+			their_revocation_basepoint: None,
+			their_payment_basepoint: None,
+			their_delayed_payment_basepoint: None,
+			their_htlc_basepoint: None,
+			their_cur_commitment_point: None,
+			their_prev_commitment_point: None,
+			their_commitment_secrets: CounterpartyCommitmentSecrets::new(),
+
+			their_shutdown_scriptpubkey: None,
+			our_shutdown_scriptpubkey: shutdown_scriptpubkey,
+		})
+	}
+
+	/// Creates a new channel from a remote side's request for one.
+	/// Assumes chain_hash has already been checked and corresponds with what we expect!
+	pub fn new_from_req(their_node_id: PublicKey, msg: &msgs::OpenChannel, feerate_per_kw: u64, shutdown_scriptpubkey: Script) -> Result<Channel, HandleError> {
+		let channel_id = msg.temporary_channel_id;
+
+		// Check sanity of message fields:
+		if msg.funding_satoshis >= MAX_FUNDING_SATOSHIS {
+			return Err(channel_error(channel_id, "funding value > 2^24"));
+		}
+		if msg.channel_reserve_satoshis > msg.funding_satoshis {
+			return Err(channel_error(channel_id, "Bogus channel_reserve_satoshis"));
+		}
+		if msg.push_msat > (msg.funding_satoshis - msg.channel_reserve_satoshis) * 1000 {
+			return Err(channel_error(channel_id, "push_msat more than highest possible value"));
+		}
+		if msg.dust_limit_satoshis > msg.funding_satoshis {
+			return Err(channel_error(channel_id, "Peer never wants payout outputs?"));
+		}
+		if msg.dust_limit_satoshis > msg.channel_reserve_satoshis {
+			return Err(channel_error(channel_id, "Bogus; channel reserve is less than dust limit"));
+		}
+		if msg.htlc_minimum_msat >= (msg.funding_satoshis - msg.channel_reserve_satoshis) * 1000 {
+			return Err(channel_error(channel_id, "Minimum htlc value is full channel value"));
+		}
+		if (msg.feerate_per_kw as u64) * 2 < feerate_per_kw || (msg.feerate_per_kw as u64) > feerate_per_kw * 2 {
+			return Err(channel_error(channel_id, "Peer's feerate is too far from ours"));
+		}
+		if msg.to_self_delay > MAX_LOCAL_BREAKDOWN_TIMEOUT {
+			return Err(channel_error(channel_id, "They wanted our payments to be delayed by a needlessly long period"));
+		}
+		if msg.max_accepted_htlcs < 1 {
+			return Err(channel_error(channel_id, "0 max_accpted_htlcs makes for a useless channel"));
+		}
+		if msg.max_accepted_htlcs > MAX_HTLCS {
+			return Err(channel_error(channel_id, "max_accpted_htlcs > 483"));
+		}
+
+		let secp_ctx = Secp256k1::new();
+		let local_keys = ChannelKeys::new_random(&secp_ctx);
+
+		Ok(Channel {
+			channel_id: channel_id,
+			channel_state: (ChannelState::OurInitSent as u32) | (ChannelState::TheirInitSent as u32),
+			channel_outbound: false,
+			secp_ctx: secp_ctx,
+			channel_value_satoshis: msg.funding_satoshis,
+			their_node_id: their_node_id,
+
+			local_keys: local_keys,
+
+			cur_local_commitment_transaction_number: INITIAL_COMMITMENT_NUMBER,
+			cur_remote_commitment_transaction_number: INITIAL_COMMITMENT_NUMBER,
+			value_to_self_msat: msg.push_msat,
+			pending_htlcs: Vec::new(),
+			holding_cell_htlc_updates: Vec::new(),
+			next_local_htlc_id: 0,
+			next_remote_htlc_id: 0,
+			feerate_per_kw: msg.feerate_per_kw as u64,
+			our_feerate_per_kw: feerate_per_kw,
+
+			last_local_commitment_txn: None,
+			funding_tx: None,
+			funding_txo: None,
+			last_sent_closing_fee: None,
+			last_received_closing_fee: None,
+
+			their_dust_limit_satoshis: msg.dust_limit_satoshis,
+			our_dust_limit_satoshis: funding::DUST_LIMIT_SATOSHIS,
+			their_max_htlc_value_in_flight_msat: cmp::min(msg.max_htlc_value_in_flight_msat, msg.funding_satoshis * 1000),
+			their_channel_reserve_satoshis: msg.channel_reserve_satoshis,
+			their_htlc_minimum_msat: msg.htlc_minimum_msat,
+			their_to_self_delay: msg.to_self_delay,
+			their_max_accepted_htlcs: msg.max_accepted_htlcs,
+
+			their_funding_pubkey: Some(msg.funding_pubkey),
+			their_revocation_basepoint: Some(msg.revocation_basepoint),
+			their_payment_basepoint: Some(msg.payment_basepoint),
+			their_delayed_payment_basepoint: Some(msg.delayed_payment_basepoint),
+			their_htlc_basepoint: Some(msg.htlc_basepoint),
+			their_cur_commitment_point: Some(msg.first_per_commitment_point),
+			their_prev_commitment_point: None,
+			their_commitment_secrets: CounterpartyCommitmentSecrets::new(),
+
+			their_shutdown_scriptpubkey: None,
+			our_shutdown_scriptpubkey: shutdown_scriptpubkey,
+		})
+	}
+
+	// Utilities to build transactions:
+
+	fn get_commitment_transaction_number_obscure_factor(&self) -> u64 {
+		let our_payment_basepoint = PublicKey::from_secret_key(&self.secp_ctx, &self.local_keys.payment_base_key).unwrap();
+		if self.channel_outbound {
+			chan_utils::get_commitment_transaction_number_obscure_factor(&our_payment_basepoint, self.their_payment_basepoint.as_ref().unwrap())
+		} else {
+			chan_utils::get_commitment_transaction_number_obscure_factor(self.their_payment_basepoint.as_ref().unwrap(), &our_payment_basepoint)
+		}
+	}
+
+	/// Builds the commitment transaction with the given number, either ours (local) or theirs,
+	/// with the HTLCs the given side includes at this point, see HTLCState::is_included_in.
+	fn build_commitment_transaction(&self, commitment_number: u64, keys: &TxCreationKeys, local: bool) -> (Transaction, Vec<HTLCOutputInCommitment>) {
+		let mut htlcs = Vec::with_capacity(self.pending_htlcs.len());
+		let mut value_to_self_msat = self.value_to_self_msat;
+		let mut value_to_remote_msat = self.channel_value_satoshis * 1000 - self.value_to_self_msat;
+		for htlc in self.pending_htlcs.iter() {
+			value_to_remote_msat -= htlc.amount_msat;
+			if htlc.state.is_included_in(local) {
+				htlcs.push(htlc.in_commitment(local));
+			} else {
+				// Either not added yet, so the value is still the offerer's, or already removed, so
+				// the value went to the receiver if the HTLC was fulfilled
+				let to_receiver = htlc.state.is_removed() && htlc.fulfilled;
+				if htlc.outbound != to_receiver {
+					value_to_self_msat += htlc.amount_msat;
+				} else {
+					value_to_remote_msat += htlc.amount_msat;
+				}
+			}
+		}
+
+		let funding_txo = self.funding_txo.as_ref().unwrap();
+		chan_utils::build_commitment_transaction(&CommitmentTransactionInfo {
+			funding_txid: funding_txo.0.clone(),
+			funding_output_index: funding_txo.1,
+			commitment_number: INITIAL_COMMITMENT_NUMBER - commitment_number,
+			obscure_factor: self.get_commitment_transaction_number_obscure_factor(),
+			a_is_funder: self.channel_outbound == local,
+			to_a_msat: if local { value_to_self_msat } else { value_to_remote_msat },
+			to_b_msat: if local { value_to_remote_msat } else { value_to_self_msat },
+			feerate_per_kw: self.feerate_per_kw,
+			dust_limit_satoshis: if local { self.our_dust_limit_satoshis } else { self.their_dust_limit_satoshis },
+			to_self_delay: if local { self.their_to_self_delay } else { OUR_TO_SELF_DELAY },
+			keys: keys,
+			htlcs: &htlcs,
+		})
+	}
+
+	fn build_local_commitment_secret(&self, idx: u64) -> SecretKey {
+		let res = chan_utils::build_commitment_secret(self.local_keys.commitment_seed, idx);
+		SecretKey::from_slice(&self.secp_ctx, &res).unwrap()
+	}
+
+	fn get_local_commitment_point(&self, idx: u64) -> PublicKey {
+		PublicKey::from_secret_key(&self.secp_ctx, &self.build_local_commitment_secret(idx)).unwrap()
+	}
+
+	/// Gets the keys of our commitment transaction with the given number.
+	fn build_local_transaction_keys(&self, commitment_number: u64) -> Result<TxCreationKeys, HandleError> {
+		let per_commitment_point = self.get_local_commitment_point(commitment_number);
+		let delayed_payment_base = PublicKey::from_secret_key(&self.secp_ctx, &self.local_keys.delayed_payment_base_key).unwrap();
+		let htlc_basepoint = PublicKey::from_secret_key(&self.secp_ctx, &self.local_keys.htlc_base_key).unwrap();
+
+		Ok(secp_call!(TxCreationKeys::new(&self.secp_ctx, &per_commitment_point, &delayed_payment_base, &htlc_basepoint, self.their_revocation_basepoint.as_ref().unwrap(), self.their_payment_basepoint.as_ref().unwrap(), self.their_htlc_basepoint.as_ref().unwrap()), self.channel_id, "Local tx keys generation got bogus keys"))
+	}
+
+	/// Gets the keys of the next commitment transaction of theirs which we'll sign.
+	fn build_remote_transaction_keys(&self) -> Result<TxCreationKeys, HandleError> {
+		let revocation_basepoint = PublicKey::from_secret_key(&self.secp_ctx, &self.local_keys.revocation_base_key).unwrap();
+		let payment_basepoint = PublicKey::from_secret_key(&self.secp_ctx, &self.local_keys.payment_base_key).unwrap();
+		let htlc_basepoint = PublicKey::from_secret_key(&self.secp_ctx, &self.local_keys.htlc_base_key).unwrap();
+
+		Ok(secp_call!(TxCreationKeys::new(&self.secp_ctx, self.their_cur_commitment_point.as_ref().unwrap(), self.their_delayed_payment_basepoint.as_ref().unwrap(), self.their_htlc_basepoint.as_ref().unwrap(), &revocation_basepoint, &payment_basepoint, &htlc_basepoint), self.channel_id, "Remote tx keys generation got bogus keys"))
+	}
+
+	fn get_funding_redeemscript(&self) -> Script {
+		funding::get_funding_redeemscript(&self.get_local_funding_pubkey(), self.their_funding_pubkey.as_ref().unwrap())
+	}
+
+	fn get_funding_sighash(&self, tx: &Transaction) -> Message {
+		let sighash = bip143::SighashComponents::new(tx).sighash_all(tx, 0, &self.get_funding_redeemscript(), self.channel_value_satoshis);
+		Message::from_slice(&sighash[..]).unwrap()
+	}
+
+	fn get_htlc_sighash(tx: &Transaction, htlc_redeemscript: &Script, htlc: &HTLCOutputInCommitment) -> Message {
+		let sighash = bip143::SighashComponents::new(tx).sighash_all(tx, 0, htlc_redeemscript, htlc.amount_msat / 1000);
+		Message::from_slice(&sighash[..]).unwrap()
+	}
+
+	/// Checks their signature of our commitment transaction and adds the witness to it, so that
+	/// it can be broadcast.
+	fn sign_local_commitment_transaction(&self, tx: &mut Transaction, their_sig: &Signature) -> Result<(), HandleError> {
+		let sighash = self.get_funding_sighash(tx);
+		secp_call!(self.secp_ctx.verify(&sighash, their_sig, self.their_funding_pubkey.as_ref().unwrap()), self.channel_id, "Invalid commitment tx signature from peer");
+		let our_sig = self.secp_ctx.sign(&sighash, &self.local_keys.funding_key).unwrap();
+		chan_utils::add_funding_witness(&self.secp_ctx, tx, &our_sig, their_sig, &self.get_local_funding_pubkey(), self.their_funding_pubkey.as_ref().unwrap());
+		Ok(())
+	}
+
+	/// Builds their commitment transaction with the given number and signs it and its HTLC
+	/// transactions.
+	fn sign_remote_commitment_transaction(&self, commitment_number: u64) -> Result<(Signature, Vec<Signature>), HandleError> {
+		let keys = self.build_remote_transaction_keys()?;
+		let (tx, htlcs) = self.build_commitment_transaction(commitment_number, &keys, false);
+		let signature = self.secp_ctx.sign(&self.get_funding_sighash(&tx), &self.local_keys.funding_key).unwrap();
+
+		let htlc_key = secp_call!(chan_utils::derive_private_key(&self.secp_ctx, &keys.per_commitment_point, &self.local_keys.htlc_base_key), self.channel_id, "Derived invalid key, peer is maliciously selecting parameters");
+		let txid = tx.bitcoin_hash();
+		let mut htlc_signatures = Vec::with_capacity(htlcs.len());
+		for htlc in htlcs.iter() {
+			let htlc_tx = chan_utils::build_htlc_transaction(&txid, self.feerate_per_kw, OUR_TO_SELF_DELAY, htlc, &keys.a_delayed_payment_key, &keys.revocation_key);
+			let htlc_redeemscript = chan_utils::get_htlc_redeemscript(htlc, &keys);
+			htlc_signatures.push(self.secp_ctx.sign(&Channel::get_htlc_sighash(&htlc_tx, &htlc_redeemscript, htlc), &htlc_key).unwrap());
+		}
+		Ok((signature, htlc_signatures))
+	}
+
+	// Message handlers:
+
+	pub fn accept_channel(&mut self, msg: &msgs::AcceptChannel) -> Result<(), HandleError> {
+		// Check sanity of message fields:
+		if !self.channel_outbound {
+			return Err(HandleError{err: "Got an accept_channel message from an inbound peer", action: None});
+		}
+		if self.channel_state != ChannelState::OurInitSent as u32 {
+			return Err(HandleError{err: "Got an accept_channel message at a strange time", action: None});
+		}
+		if msg.dust_limit_satoshis > MAX_FUNDING_SATOSHIS {
+			return Err(channel_error(self.channel_id, "Peer never wants payout outputs?"));
+		}
+		if msg.channel_reserve_satoshis > self.channel_value_satoshis {
+			return Err(channel_error(self.channel_id, "Bogus channel_reserve_satoshis"));
+		}
+		if msg.dust_limit_satoshis > msg.channel_reserve_satoshis {
+			return Err(channel_error(self.channel_id, "Bogus channel_reserve and dust_limit"));
+		}
+		if msg.htlc_minimum_msat >= (self.channel_value_satoshis - msg.channel_reserve_satoshis) * 1000 {
+			return Err(channel_error(self.channel_id, "Minimum htlc value is full channel value"));
+		}
+		if msg.to_self_delay > MAX_LOCAL_BREAKDOWN_TIMEOUT {
+			return Err(channel_error(self.channel_id, "They wanted our payments to be delayed by a needlessly long period"));
+		}
+		if msg.max_accepted_htlcs < 1 {
+			return Err(channel_error(self.channel_id, "0 max_accpted_htlcs makes for a useless channel"));
+		}
+		if msg.max_accepted_htlcs > MAX_HTLCS {
+			return Err(channel_error(self.channel_id, "max_accpted_htlcs > 483"));
+		}
+
+		self.their_dust_limit_satoshis = msg.dust_limit_satoshis;
+		self.their_max_htlc_value_in_flight_msat = cmp::min(msg.max_htlc_value_in_flight_msat, self.channel_value_satoshis * 1000);
+		self.their_channel_reserve_satoshis = msg.channel_reserve_satoshis;
+		self.their_htlc_minimum_msat = msg.htlc_minimum_msat;
+		self.their_to_self_delay = msg.to_self_delay;
+		self.their_max_accepted_htlcs = msg.max_accepted_htlcs;
+		self.their_funding_pubkey = Some(msg.funding_pubkey);
+		self.their_revocation_basepoint = Some(msg.revocation_basepoint);
+		self.their_payment_basepoint = Some(msg.payment_basepoint);
+		self.their_delayed_payment_basepoint = Some(msg.delayed_payment_basepoint);
+		self.their_htlc_basepoint = Some(msg.htlc_basepoint);
+		self.their_cur_commitment_point = Some(msg.first_per_commitment_point);
+
+		self.channel_state = ChannelState::OurInitSent as u32 | ChannelState::TheirInitSent as u32;
+
+		Ok(())
+	}
+
+	/// The channel_id of a funded channel: the funding txid with the output index XORed into its
+	/// last two bytes.
+	fn funding_channel_id(funding_txid: &Sha256dHash, funding_output_index: u16) -> [u8; 32] {
+		let mut res = [0; 32];
+		res[..].copy_from_slice(&funding_txid[..]);
+		res[30] ^= ((funding_output_index >> 8) & 0xff) as u8;
+		res[31] ^= ((funding_output_index >> 0) & 0xff) as u8;
+		res
+	}
+
+	pub fn funding_created(&mut self, msg: &msgs::FundingCreated) -> Result<msgs::FundingSigned, HandleError> {
+		if self.channel_outbound {
+			return Err(HandleError{err: "Received funding_created for an outbound channel?", action: None});
+		}
+		if self.channel_state != (ChannelState::OurInitSent as u32 | ChannelState::TheirInitSent as u32) {
+			return Err(HandleError{err: "Received funding_created after we got the channel!", action: None});
+		}
+
+		self.funding_txo = Some((msg.funding_txid.clone(), msg.funding_output_index));
+
+		let local_keys = match self.build_local_transaction_keys(self.cur_local_commitment_transaction_number) {
+			Ok(keys) => keys,
+			Err(e) => {
+				self.funding_txo = None;
+				return Err(e);
+			}
+		};
+		let (mut local_initial_commitment_tx, _) = self.build_commitment_transaction(self.cur_local_commitment_transaction_number, &local_keys, true);
+		if let Err(e) = self.sign_local_commitment_transaction(&mut local_initial_commitment_tx, &msg.signature) {
+			self.funding_txo = None;
+			return Err(e);
+		}
+		let (our_signature, _) = match self.sign_remote_commitment_transaction(self.cur_remote_commitment_transaction_number) {
+			Ok(sigs) => sigs,
+			Err(e) => {
+				self.funding_txo = None;
+				return Err(e);
+			}
+		};
+
+		// Now that we're past error-generating stuff, update our local state:
+		self.last_local_commitment_txn = Some(local_initial_commitment_tx);
+		self.channel_id = Channel::funding_channel_id(&msg.funding_txid, msg.funding_output_index);
+		self.channel_state = ChannelState::FundingSent as u32;
+
+		Ok(msgs::FundingSigned {
+			channel_id: self.channel_id,
+			signature: our_signature,
+		})
+	}
+
+	/// Handles a funding_signed message from the remote end.
+	/// If this call is successful, the returned funding transaction should be broadcast.
+	pub fn funding_signed(&mut self, msg: &msgs::FundingSigned) -> Result<Transaction, HandleError> {
+		if !self.channel_outbound {
+			return Err(HandleError{err: "Received funding_signed for an inbound channel?", action: None});
+		}
+		if self.channel_state != ChannelState::FundingCreated as u32 {
+			return Err(HandleError{err: "Received funding_signed in strange state!", action: None});
+		}
+
+		let local_keys = self.build_local_transaction_keys(self.cur_local_commitment_transaction_number)?;
+		let (mut local_initial_commitment_tx, _) = self.build_commitment_transaction(self.cur_local_commitment_transaction_number, &local_keys, true);
+		self.sign_local_commitment_transaction(&mut local_initial_commitment_tx, &msg.signature)?;
+
+		self.last_local_commitment_txn = Some(local_initial_commitment_tx);
+		self.channel_state = ChannelState::FundingSent as u32;
+
+		Ok(self.funding_tx.take().unwrap())
+	}
+
+	/// Called once the funding transaction has enough confirmations. Returns the funding_locked
+	/// to send, unless we already did.
+	pub fn funding_confirmed(&mut self) -> Option<msgs::FundingLocked> {
+		let non_shutdown_state = self.channel_state & (!BOTH_SIDES_SHUTDOWN_MASK);
+		if non_shutdown_state & !(ChannelState::TheirFundingLocked as u32) != ChannelState::FundingSent as u32 {
+			return None;
+		}
+		if non_shutdown_state & (ChannelState::TheirFundingLocked as u32) != 0 {
+			self.channel_state = ChannelState::ChannelFunded as u32 | (self.channel_state & BOTH_SIDES_SHUTDOWN_MASK);
+		} else {
+			self.channel_state |= ChannelState::OurFundingLocked as u32;
+		}
+
+		Some(msgs::FundingLocked {
+			channel_id: self.channel_id,
+			next_per_commitment_point: self.get_local_commitment_point(self.cur_local_commitment_transaction_number - 1),
+		})
+	}
+
+	pub fn funding_locked(&mut self, msg: &msgs::FundingLocked) -> Result<(), HandleError> {
+		let non_shutdown_state = self.channel_state & (!BOTH_SIDES_SHUTDOWN_MASK);
+		if non_shutdown_state == ChannelState::FundingSent as u32 {
+			self.channel_state |= ChannelState::TheirFundingLocked as u32;
+		} else if non_shutdown_state == (ChannelState::FundingSent as u32 | ChannelState::OurFundingLocked as u32) {
+			self.channel_state = ChannelState::ChannelFunded as u32 | (self.channel_state & BOTH_SIDES_SHUTDOWN_MASK);
+		} else {
+			return Err(channel_error(self.channel_id, "Peer sent a funding_locked at a strange time"));
+		}
+
+		self.their_prev_commitment_point = self.their_cur_commitment_point;
+		self.their_cur_commitment_point = Some(msg.next_per_commitment_point);
+		Ok(())
+	}
+
+	fn is_funded(&self) -> bool {
+		self.channel_state & (ChannelState::ChannelFunded as u32) != 0 && self.channel_state & (ChannelState::ShutdownComplete as u32) == 0
+	}
+
+	/// The value of the HTLCs we've sent which are still pending, including the ones in the holding
+	/// cell
+	fn get_outbound_pending_htlc_stats(&self) -> (usize, u64) {
+		let mut htlc_outbound_count = 0;
+		let mut htlc_outbound_value_msat = 0;
+		for htlc in self.pending_htlcs.iter() {
+			if htlc.outbound {
+				htlc_outbound_count += 1;
+				htlc_outbound_value_msat += htlc.amount_msat;
+			}
+		}
+		for update in self.holding_cell_htlc_updates.iter() {
+			if let &HTLCUpdateAwaitingACK::AddHTLC { amount_msat, .. } = update {
+				htlc_outbound_count += 1;
+				htlc_outbound_value_msat += amount_msat;
+			}
+		}
+		(htlc_outbound_count, htlc_outbound_value_msat)
+	}
+
+	pub fn update_add_htlc(&mut self, msg: &msgs::UpdateAddHTLC) -> Result<(), HandleError> {
+		if !self.is_funded() || (self.channel_state & (ChannelState::RemoteShutdownSent as u32)) != 0 {
+			return Err(channel_error(self.channel_id, "Got add HTLC message when channel was not in an operational state"));
+		}
+		if msg.amount_msat < OUR_HTLC_MINIMUM_MSAT {
+			return Err(channel_error(self.channel_id, "Remote side tried to send less than our minimum HTLC value"));
+		}
+		if msg.htlc_id != self.next_remote_htlc_id {
+			return Err(channel_error(self.channel_id, "Remote skipped HTLC ID"));
+		}
+		if msg.cltv_expiry >= 500000000 {
+			return Err(channel_error(self.channel_id, "Remote provided CLTV expiry in seconds instead of block height"));
+		}
+
+		// All of the amounts below come from the remote side, so any overflow fails the channel
+		let mut inbound_htlc_count = 0;
+		let mut inbound_htlcs_value_msat: u64 = 0;
+		let mut pending_htlcs_value_msat: u64 = 0;
+		for htlc in self.pending_htlcs.iter() {
+			if !htlc.outbound {
+				inbound_htlc_count += 1;
+				inbound_htlcs_value_msat = match inbound_htlcs_value_msat.checked_add(htlc.amount_msat) {
+					Some(value) => value,
+					None => return Err(channel_error(self.channel_id, "Remote HTLCs overflow the in-flight value")),
+				};
+			}
+			pending_htlcs_value_msat = match pending_htlcs_value_msat.checked_add(htlc.amount_msat) {
+				Some(value) => value,
+				None => return Err(channel_error(self.channel_id, "Pending HTLCs overflow the channel value")),
+			};
+		}
+		if inbound_htlc_count + 1 > OUR_MAX_HTLCS as u32 {
+			return Err(channel_error(self.channel_id, "Remote tried to push more than our max accepted HTLCs"));
+		}
+		match inbound_htlcs_value_msat.checked_add(msg.amount_msat) {
+			Some(value) if value <= Channel::get_our_max_htlc_value_in_flight_msat(self.channel_value_satoshis) => {},
+			_ => return Err(channel_error(self.channel_id, "Remote HTLC add would put them over our max HTLC value in flight")),
+		}
+
+		// Their balance must pay for the HTLC, and for the commitment fee if they're the funder,
+		// while keeping the reserve we asked them for
+		let their_balance_msat = match (self.channel_value_satoshis * 1000).checked_sub(self.value_to_self_msat).and_then(|value| value.checked_sub(pending_htlcs_value_msat)) {
+			Some(value) => value,
+			None => return Err(channel_error(self.channel_id, "Pending HTLCs exceed the remote balance")),
+		};
+		let their_fee_msat = if self.channel_outbound { 0 } else { self.commitment_tx_fee_msat(self.pending_htlcs.len() + 1) };
+		let their_reserve_msat = Channel::get_our_channel_reserve_satoshis(self.channel_value_satoshis) * 1000;
+		match msg.amount_msat.checked_add(their_fee_msat).and_then(|value| value.checked_add(their_reserve_msat)) {
+			Some(value) if value <= their_balance_msat => {},
+			_ => return Err(channel_error(self.channel_id, "Remote HTLC add would overdraw remaining funds or their channel reserve")),
+		}
+
+		self.next_remote_htlc_id += 1;
+		self.pending_htlcs.push(HTLCOutput {
+			outbound: false,
+			htlc_id: msg.htlc_id,
+			amount_msat: msg.amount_msat,
+			cltv_expiry: msg.cltv_expiry,
+			payment_hash: msg.payment_hash,
+			state: HTLCState::RemoteAnnounced,
+			fulfilled: false,
+		});
+
+		Ok(())
+	}
+
+	/// Marks the outbound HTLC with the given id as removed by the remote side. Returns its
+	/// payment hash.
+	fn mark_outbound_htlc_removed(&mut self, htlc_id: u64, payment_preimage: Option<[u8; 32]>) -> Result<[u8; 32], HandleError> {
+		if !self.is_funded() {
+			return Err(channel_error(self.channel_id, "Got HTLC removal message when channel was not in an operational state"));
+		}
+		for htlc in self.pending_htlcs.iter_mut() {
+			if htlc.outbound && htlc.htlc_id == htlc_id {
+				if htlc.state != HTLCState::Committed {
+					return Err(channel_error(self.channel_id, "Remote tried to remove an HTLC which wasn't irrevocably committed"));
+				}
+				if let Some(payment_preimage) = payment_preimage {
+					if sha256(&payment_preimage) != htlc.payment_hash {
+						return Err(channel_error(self.channel_id, "Remote tried to fulfill an HTLC with an incorrect preimage"));
+					}
+					htlc.fulfilled = true;
+				}
+				htlc.state = HTLCState::RemoteRemoved;
+				return Ok(htlc.payment_hash);
+			}
+		}
+		Err(channel_error(self.channel_id, "Remote tried to remove an HTLC we couldn't find"))
+	}
+
+	pub fn update_fulfill_htlc(&mut self, msg: &msgs::UpdateFulfillHTLC) -> Result<(), HandleError> {
+		self.mark_outbound_htlc_removed(msg.htlc_id, Some(msg.payment_preimage))?;
+		Ok(())
+	}
+
+	/// Returns the payment hash of the failed HTLC.
+	pub fn update_fail_htlc(&mut self, msg: &msgs::UpdateFailHTLC) -> Result<[u8; 32], HandleError> {
+		self.mark_outbound_htlc_removed(msg.htlc_id, None)
+	}
+
+	/// Returns the payment hash of the failed HTLC.
+	pub fn update_fail_malformed_htlc(&mut self, msg: &msgs::UpdateFailMalformedHTLC) -> Result<[u8; 32], HandleError> {
+		self.mark_outbound_htlc_removed(msg.htlc_id, None)
+	}
+
+	pub fn commitment_signed(&mut self, msg: &msgs::CommitmentSigned) -> Result<(msgs::RevokeAndACK, Option<msgs::CommitmentSigned>), HandleError> {
+		if !self.is_funded() {
+			return Err(channel_error(self.channel_id, "Got commitment signed message when channel was not in an operational state"));
+		}
+
+		let local_keys = self.build_local_transaction_keys(self.cur_local_commitment_transaction_number - 1)?;
+		let (mut local_commitment_tx, htlcs) = self.build_commitment_transaction(self.cur_local_commitment_transaction_number - 1, &local_keys, true);
+		self.sign_local_commitment_transaction(&mut local_commitment_tx, &msg.signature)?;
+
+		if msg.htlc_signatures.len() != htlcs.len() {
+			return Err(channel_error(self.channel_id, "Got wrong number of HTLC signatures from remote"));
+		}
+		let txid = local_commitment_tx.bitcoin_hash();
+		for (htlc, their_sig) in htlcs.iter().zip(msg.htlc_signatures.iter()) {
+			let htlc_tx = chan_utils::build_htlc_transaction(&txid, self.feerate_per_kw, self.their_to_self_delay, htlc, &local_keys.a_delayed_payment_key, &local_keys.revocation_key);
+			let htlc_redeemscript = chan_utils::get_htlc_redeemscript(htlc, &local_keys);
+			secp_call!(self.secp_ctx.verify(&Channel::get_htlc_sighash(&htlc_tx, &htlc_redeemscript, htlc), their_sig, &local_keys.b_htlc_key), self.channel_id, "Invalid HTLC tx signature from peer");
+		}
+
+		// Revoke our previous commitment transaction:
+		let per_commitment_secret = chan_utils::build_commitment_secret(self.local_keys.commitment_seed, self.cur_local_commitment_transaction_number);
+		let next_per_commitment_point = self.get_local_commitment_point(self.cur_local_commitment_transaction_number - 2);
+
+		// Now that we're past error-generating stuff, update our local state:
+		self.last_local_commitment_txn = Some(local_commitment_tx);
+		self.cur_local_commitment_transaction_number -= 1;
+
+		let awaiting_remote_revoke = (self.channel_state & ChannelState::AwaitingRemoteRevoke as u32) != 0;
+		let mut need_our_commitment = false;
+		let mut value_to_self_msat = self.value_to_self_msat;
+		self.pending_htlcs.retain(|htlc| {
+			if htlc.state == HTLCState::LocalRemovedAwaitingCommitment {
+				if htlc.fulfilled {
+					value_to_self_msat += htlc.amount_msat;
+				}
+				false
+			} else { true }
+		});
+		self.value_to_self_msat = value_to_self_msat;
+		for htlc in self.pending_htlcs.iter_mut() {
+			if htlc.state == HTLCState::RemoteAnnounced {
+				htlc.state = if awaiting_remote_revoke { HTLCState::AwaitingRemoteRevokeToAnnounce } else { HTLCState::AwaitingAnnouncedRemoteRevoke };
+				need_our_commitment = true;
+			} else if htlc.state == HTLCState::RemoteRemoved {
+				htlc.state = if awaiting_remote_revoke { HTLCState::AwaitingRemoteRevokeToRemove } else { HTLCState::AwaitingRemovedRemoteRevoke };
+				need_our_commitment = true;
+			}
+		}
+
+		let our_commitment_signed = if need_our_commitment && !awaiting_remote_revoke {
+			Some(self.send_commitment()?)
+		} else { None };
+
+		Ok((msgs::RevokeAndACK {
+			channel_id: self.channel_id,
+			per_commitment_secret: per_commitment_secret,
+			next_per_commitment_point: next_per_commitment_point,
+		}, our_commitment_signed))
+	}
+
+	/// Handles a revoke_and_ack, returning the updates which were waiting in the holding cell (and
+	/// the commitment_signed including them) if there were any, the (payment_hash, amount_msat) of
+	/// the inbound HTLCs which are now irrevocably committed and the payment hashes of the
+	/// outbound HTLCs which were failed for good.
+	pub fn revoke_and_ack(&mut self, msg: &msgs::RevokeAndACK) -> Result<(Option<msgs::CommitmentUpdate>, Vec<([u8; 32], u64)>, Vec<[u8; 32]>), HandleError> {
+		if !self.is_funded() {
+			return Err(channel_error(self.channel_id, "Got revoke/ACK message when channel was not in an operational state"));
+		}
+		if (self.channel_state & ChannelState::AwaitingRemoteRevoke as u32) == 0 {
+			return Err(channel_error(self.channel_id, "Got a revoke_and_ack when we weren't expecting one"));
+		}
+		let per_commitment_key = secp_call!(SecretKey::from_slice(&self.secp_ctx, &msg.per_commitment_secret), self.channel_id, "Peer provided an invalid per_commitment_secret");
+		if PublicKey::from_secret_key(&self.secp_ctx, &per_commitment_key).ok() != self.their_prev_commitment_point {
+			return Err(channel_error(self.channel_id, "Got a revoke commitment secret which didn't correspond to their current pubkey"));
+		}
+		if let Err(e) = self.their_commitment_secrets.provide_secret(self.cur_remote_commitment_transaction_number + 1, msg.per_commitment_secret) {
+			return Err(channel_error(self.channel_id, e.err));
+		}
+
+		// Update state now that we've passed all the can-fail calls...
+		self.their_prev_commitment_point = self.their_cur_commitment_point;
+		self.their_cur_commitment_point = Some(msg.next_per_commitment_point);
+		self.channel_state &= !(ChannelState::AwaitingRemoteRevoke as u32);
+
+		let mut payments_received = Vec::new();
+		let mut payments_failed = Vec::new();
+		let mut need_our_commitment = false;
+		let mut value_to_self_msat = self.value_to_self_msat;
+		self.pending_htlcs.retain(|htlc| {
+			if htlc.state == HTLCState::AwaitingRemovedRemoteRevoke {
+				if !htlc.fulfilled {
+					value_to_self_msat += htlc.amount_msat;
+					payments_failed.push(htlc.payment_hash);
+				}
+				false
+			} else { true }
+		});
+		self.value_to_self_msat = value_to_self_msat;
+		for htlc in self.pending_htlcs.iter_mut() {
+			match htlc.state {
+				HTLCState::LocalAnnounced => {
+					htlc.state = HTLCState::Committed;
+				},
+				HTLCState::AwaitingAnnouncedRemoteRevoke => {
+					htlc.state = HTLCState::Committed;
+					payments_received.push((htlc.payment_hash, htlc.amount_msat));
+				},
+				HTLCState::LocalRemoved => {
+					htlc.state = HTLCState::LocalRemovedAwaitingCommitment;
+				},
+				HTLCState::AwaitingRemoteRevokeToAnnounce | HTLCState::AwaitingRemoteRevokeToRemove => {
+					need_our_commitment = true;
+				},
+				_ => {},
+			}
+		}
+
+		let (update_add_htlcs, update_fulfill_htlcs, update_fail_htlcs) = self.free_holding_cell_htlcs();
+		let commitment_update = if need_our_commitment || !update_add_htlcs.is_empty() || !update_fulfill_htlcs.is_empty() || !update_fail_htlcs.is_empty() {
+			Some(msgs::CommitmentUpdate {
+				update_add_htlcs: update_add_htlcs,
+				update_fulfill_htlcs: update_fulfill_htlcs,
+				update_fail_htlcs: update_fail_htlcs,
+				commitment_signed: self.send_commitment()?,
+			})
+		} else { None };
+
+		Ok((commitment_update, payments_received, payments_failed))
+	}
+
+	/// Applies the updates which were waiting on a revoke_and_ack, returning the messages to send
+	/// ahead of the next commitment_signed.
+	fn free_holding_cell_htlcs(&mut self) -> (Vec<msgs::UpdateAddHTLC>, Vec<msgs::UpdateFulfillHTLC>, Vec<msgs::UpdateFailHTLC>) {
+		let mut update_add_htlcs = Vec::new();
+		let mut update_fulfill_htlcs = Vec::new();
+		let mut update_fail_htlcs = Vec::new();
+		for update in mem::replace(&mut self.holding_cell_htlc_updates, Vec::new()) {
+			match update {
+				HTLCUpdateAwaitingACK::AddHTLC { amount_msat, cltv_expiry, payment_hash, onion_routing_packet } => {
+					update_add_htlcs.push(self.add_local_htlc(amount_msat, payment_hash, cltv_expiry, onion_routing_packet));
+				},
+				HTLCUpdateAwaitingACK::ClaimHTLC { payment_preimage, htlc_id } => {
+					update_fulfill_htlcs.push(msgs::UpdateFulfillHTLC {
+						channel_id: self.channel_id,
+						htlc_id: self.mark_inbound_htlc_removed(htlc_id, true),
+						payment_preimage: payment_preimage,
+					});
+				},
+				HTLCUpdateAwaitingACK::FailHTLC { htlc_id, err_packet } => {
+					update_fail_htlcs.push(msgs::UpdateFailHTLC {
+						channel_id: self.channel_id,
+						htlc_id: self.mark_inbound_htlc_removed(htlc_id, false),
+						reason: err_packet,
+					});
+				},
+			}
+		}
+		(update_add_htlcs, update_fulfill_htlcs, update_fail_htlcs)
+	}
+
+	// Methods to update the channel from our side:
+
+	fn add_local_htlc(&mut self, amount_msat: u64, payment_hash: [u8; 32], cltv_expiry: u32, onion_routing_packet: msgs::OnionPacket) -> msgs::UpdateAddHTLC {
+		self.pending_htlcs.push(HTLCOutput {
+			outbound: true,
+			htlc_id: self.next_local_htlc_id,
+			amount_msat: amount_msat,
+			cltv_expiry: cltv_expiry,
+			payment_hash: payment_hash,
+			state: HTLCState::LocalAnnounced,
+			fulfilled: false,
+		});
+		self.value_to_self_msat -= amount_msat;
+
+		let res = msgs::UpdateAddHTLC {
+			channel_id: self.channel_id,
+			htlc_id: self.next_local_htlc_id,
+			amount_msat: amount_msat,
+			payment_hash: payment_hash,
+			cltv_expiry: cltv_expiry,
+			onion_routing_packet: onion_routing_packet,
+		};
+		self.next_local_htlc_id += 1;
+		res
+	}
+
+	/// Marks the committed inbound HTLC with the given id as removed by us, returning the id.
+	fn mark_inbound_htlc_removed(&mut self, htlc_id: u64, fulfilled: bool) -> u64 {
+		for htlc in self.pending_htlcs.iter_mut() {
+			if !htlc.outbound && htlc.htlc_id == htlc_id && htlc.state == HTLCState::Committed {
+				htlc.state = HTLCState::LocalRemoved;
+				htlc.fulfilled = fulfilled;
+			}
+		}
+		htlc_id
+	}
+
+	/// Adds a pending outbound HTLC to this channel, note that you probably want
+	/// send_htlc_and_commit instead cause you'll want both messages at once.
+	/// This returns an option instead of a pure UpdateAddHTLC as we may be in a state where we are
+	/// waiting on a remote revoke_and_ack. In that case the HTLC sits in the holding cell, to be
+	/// sent along with the commitment_signed which follows the revoke_and_ack.
+	pub fn send_htlc(&mut self, amount_msat: u64, payment_hash: [u8; 32], cltv_expiry: u32, onion_routing_packet: msgs::OnionPacket) -> Result<Option<msgs::UpdateAddHTLC>, HandleError> {
+		if !self.is_funded() || (self.channel_state & BOTH_SIDES_SHUTDOWN_MASK) != 0 {
+			return Err(HandleError{err: "Cannot send HTLC until channel is fully established and we haven't started shutting down", action: None});
+		}
+		if amount_msat > self.channel_value_satoshis * 1000 {
+			return Err(HandleError{err: "Cannot send more than the total value of the channel", action: None});
+		}
+		if amount_msat < self.their_htlc_minimum_msat {
+			return Err(HandleError{err: "Cannot send less than their minimum HTLC value", action: None});
+		}
+
+		let (outbound_htlc_count, htlc_outbound_value_msat) = self.get_outbound_pending_htlc_stats();
+		if outbound_htlc_count + 1 > self.their_max_accepted_htlcs as usize {
+			return Err(HandleError{err: "Cannot push more than their max accepted HTLCs", action: None});
+		}
+		if htlc_outbound_value_msat + amount_msat > self.their_max_htlc_value_in_flight_msat {
+			return Err(HandleError{err: "Cannot send value that would put us over our max HTLC value in flight", action: None});
+		}
+		// HTLCs in the holding cell aren't taken out of value_to_self_msat yet
+		let mut holding_cell_htlc_count = 0;
+		let mut holding_cell_value_msat = 0;
+		for update in self.holding_cell_htlc_updates.iter() {
+			if let &HTLCUpdateAwaitingACK::AddHTLC { amount_msat, .. } = update {
+				holding_cell_htlc_count += 1;
+				holding_cell_value_msat += amount_msat;
+			}
+		}
+		let our_fee_msat = if self.channel_outbound { self.commitment_tx_fee_msat(self.pending_htlcs.len() + holding_cell_htlc_count + 1) } else { 0 };
+		if self.value_to_self_msat < holding_cell_value_msat + amount_msat + our_fee_msat + self.their_channel_reserve_satoshis * 1000 {
+			return Err(HandleError{err: "Cannot send value that would put us under their reserve value", action: None});
+		}
+
+		if (self.channel_state & (ChannelState::AwaitingRemoteRevoke as u32)) != 0 {
+			self.holding_cell_htlc_updates.push(HTLCUpdateAwaitingACK::AddHTLC {
+				amount_msat: amount_msat,
+				payment_hash: payment_hash,
+				cltv_expiry: cltv_expiry,
+				onion_routing_packet: onion_routing_packet,
+			});
+			return Ok(None);
+		}
+
+		Ok(Some(self.add_local_htlc(amount_msat, payment_hash, cltv_expiry, onion_routing_packet)))
+	}
+
+	/// Creates a signed commitment transaction to send to the remote peer.
+	/// Always accepts the current state: callers must check we aren't waiting on a revoke_and_ack
+	/// first.
+	fn send_commitment(&mut self) -> Result<msgs::CommitmentSigned, HandleError> {
+		let (signature, htlc_signatures) = self.sign_remote_commitment_transaction(self.cur_remote_commitment_transaction_number - 1)?;
+
+		// Update state now that we've passed all the can-fail calls...
+		for htlc in self.pending_htlcs.iter_mut() {
+			if htlc.state == HTLCState::AwaitingRemoteRevokeToAnnounce {
+				htlc.state = HTLCState::AwaitingAnnouncedRemoteRevoke;
+			} else if htlc.state == HTLCState::AwaitingRemoteRevokeToRemove {
+				htlc.state = HTLCState::AwaitingRemovedRemoteRevoke;
+			}
+		}
+		self.cur_remote_commitment_transaction_number -= 1;
+		self.channel_state |= ChannelState::AwaitingRemoteRevoke as u32;
+
+		Ok(msgs::CommitmentSigned {
+			channel_id: self.channel_id,
+			signature: signature,
+			htlc_signatures: htlc_signatures,
+		})
+	}
+
+	/// Adds a pending outbound HTLC to this channel, and creates a signed commitment transaction
+	/// to send to the remote peer in one go.
+	/// Shorthand for calling send_htlc() followed by send_commitment(), see docs on those for
+	/// more info.
+	pub fn send_htlc_and_commit(&mut self, amount_msat: u64, payment_hash: [u8; 32], cltv_expiry: u32, onion_routing_packet: msgs::OnionPacket) -> Result<Option<(msgs::UpdateAddHTLC, msgs::CommitmentSigned)>, HandleError> {
+		match self.send_htlc(amount_msat, payment_hash, cltv_expiry, onion_routing_packet)? {
+			Some(update_add_htlc) => Ok(Some((update_add_htlc, self.send_commitment()?))),
+			None => Ok(None),
+		}
+	}
+
+	/// Finds the committed inbound HTLC paying to the given hash, skipping the ones we already
+	/// queued a removal for.
+	fn find_committed_inbound_htlc(&self, payment_hash: &[u8; 32]) -> Option<u64> {
+		for htlc in self.pending_htlcs.iter() {
+			if !htlc.outbound && htlc.state == HTLCState::Committed && htlc.payment_hash == *payment_hash {
+				let queued = self.holding_cell_htlc_updates.iter().any(|update| {
+					match update {
+						&HTLCUpdateAwaitingACK::ClaimHTLC { htlc_id, .. } => htlc_id == htlc.htlc_id,
+						&HTLCUpdateAwaitingACK::FailHTLC { htlc_id, .. } => htlc_id == htlc.htlc_id,
+						_ => false,
+					}
+				});
+				if !queued {
+					return Some(htlc.htlc_id);
+				}
+			}
+		}
+		None
+	}
+
+	/// Claims an inbound HTLC paying to the hash of the given preimage, returning the
+	/// update_fulfill_htlc and commitment_signed to send, or None if we're waiting on a
+	/// revoke_and_ack and the claim went to the holding cell.
+	pub fn claim_htlc_and_commit(&mut self, payment_preimage: [u8; 32]) -> Result<Option<(msgs::UpdateFulfillHTLC, msgs::CommitmentSigned)>, HandleError> {
+		if !self.is_funded() {
+			return Err(HandleError{err: "Was asked to claim an HTLC when channel was not in an operational state", action: None});
+		}
+		let htlc_id = match self.find_committed_inbound_htlc(&sha256(&payment_preimage)) {
+			Some(htlc_id) => htlc_id,
+			None => return Err(HandleError{err: "Unable to find a pending HTLC which matched the given payment preimage", action: None}),
+		};
+
+		if (self.channel_state & (ChannelState::AwaitingRemoteRevoke as u32)) != 0 {
+			self.holding_cell_htlc_updates.push(HTLCUpdateAwaitingACK::ClaimHTLC {
+				payment_preimage: payment_preimage,
+				htlc_id: htlc_id,
+			});
+			return Ok(None);
+		}
+
+		let update_fulfill_htlc = msgs::UpdateFulfillHTLC {
+			channel_id: self.channel_id,
+			htlc_id: self.mark_inbound_htlc_removed(htlc_id, true),
+			payment_preimage: payment_preimage,
+		};
+		Ok(Some((update_fulfill_htlc, self.send_commitment()?)))
+	}
+
+	/// Fails an inbound HTLC paying to the given hash back to the remote side, returning the
+	/// update_fail_htlc and commitment_signed to send, or None if the failure went to the holding
+	/// cell.
+	pub fn fail_htlc_and_commit(&mut self, payment_hash: &[u8; 32]) -> Result<Option<(msgs::UpdateFailHTLC, msgs::CommitmentSigned)>, HandleError> {
+		if !self.is_funded() {
+			return Err(HandleError{err: "Was asked to fail an HTLC when channel was not in an operational state", action: None});
+		}
+		let htlc_id = match self.find_committed_inbound_htlc(payment_hash) {
+			Some(htlc_id) => htlc_id,
+			None => return Err(HandleError{err: "Unable to find a pending HTLC which matched the given payment hash", action: None}),
+		};
+
+		// Without onions there's nothing to encrypt the failure to, so the failure code goes as is
+		let err_packet = msgs::OnionErrorPacket {
+			data: vec![(INCORRECT_PAYMENT_DETAILS >> 8) as u8, INCORRECT_PAYMENT_DETAILS as u8],
+		};
+
+		if (self.channel_state & (ChannelState::AwaitingRemoteRevoke as u32)) != 0 {
+			self.holding_cell_htlc_updates.push(HTLCUpdateAwaitingACK::FailHTLC {
+				htlc_id: htlc_id,
+				err_packet: err_packet,
+			});
+			return Ok(None);
+		}
+
+		let update_fail_htlc = msgs::UpdateFailHTLC {
+			channel_id: self.channel_id,
+			htlc_id: self.mark_inbound_htlc_removed(htlc_id, false),
+			reason: err_packet,
+		};
+		Ok(Some((update_fail_htlc, self.send_commitment()?)))
+	}
+
+	// Closing:
+
+	/// Begins the shutdown process, getting the message to send to the other peer.
+	pub fn get_shutdown(&mut self) -> Result<msgs::Shutdown, HandleError> {
+		if !self.is_funded() {
+			return Err(HandleError{err: "Cannot begin shutdown before the channel is funded", action: None});
+		}
+		if (self.channel_state & (ChannelState::LocalShutdownSent as u32)) != 0 {
+			return Err(HandleError{err: "Shutdown already in progress", action: None});
+		}
+		if !self.holding_cell_htlc_updates.is_empty() {
+			return Err(HandleError{err: "Cannot begin shutdown while HTLC updates wait for a revoke_and_ack", action: None});
+		}
+
+		self.channel_state |= ChannelState::LocalShutdownSent as u32;
+		Ok(msgs::Shutdown {
+			channel_id: self.channel_id,
+			scriptpubkey: self.our_shutdown_scriptpubkey.clone(),
+		})
+	}
+
+	/// Handles their shutdown, returning our own shutdown if we didn't send it yet, our first
+	/// closing_signed if we can already propose a fee, and the payment hashes of the HTLCs we
+	/// hadn't sent yet and now never will.
+	pub fn shutdown(&mut self, msg: &msgs::Shutdown) -> Result<(Option<msgs::Shutdown>, Option<msgs::ClosingSigned>, Vec<[u8; 32]>), HandleError> {
+		if !self.is_funded() {
+			return Err(channel_error(self.channel_id, "Peer sent shutdown pre-funding"));
+		}
+		if (self.channel_state & (ChannelState::RemoteShutdownSent as u32)) != 0 {
+			return Err(channel_error(self.channel_id, "Remote peer sent duplicate shutdown message"));
+		}
+		if !is_standard_shutdown_script(&msg.scriptpubkey) {
+			return Err(channel_error(self.channel_id, "Got a nonstandard scriptpubkey from remote peer"));
+		}
+
+		self.their_shutdown_scriptpubkey = Some(msg.scriptpubkey.clone());
+		self.channel_state |= ChannelState::RemoteShutdownSent as u32;
+
+		// No new HTLCs may be added once shutdown was received, so fail back those we hadn't sent
+		let mut dropped_payments = Vec::new();
+		self.holding_cell_htlc_updates.retain(|update| {
+			match update {
+				&HTLCUpdateAwaitingACK::AddHTLC { ref payment_hash, .. } => {
+					dropped_payments.push(*payment_hash);
+					false
+				},
+				_ => true,
+			}
+		});
+
+		let our_shutdown = if (self.channel_state & (ChannelState::LocalShutdownSent as u32)) == 0 {
+			self.channel_state |= ChannelState::LocalShutdownSent as u32;
+			Some(msgs::Shutdown {
+				channel_id: self.channel_id,
+				scriptpubkey: self.our_shutdown_scriptpubkey.clone(),
+			})
+		} else { None };
+
+		Ok((our_shutdown, self.maybe_propose_first_closing_signed(), dropped_payments))
+	}
+
+	fn build_closing_transaction(&self, total_fee_satoshis: u64) -> Transaction {
+		let txins = vec![TxIn {
+			prev_hash: self.funding_txo.as_ref().unwrap().0.clone(),
+			prev_index: self.funding_txo.as_ref().unwrap().1 as u32,
+			script_sig: Script::new(),
+			sequence: 0xffffffff,
+			witness: Vec::new(),
+		}];
+
+		// No HTLCs are left, so the balances are final. The funder pays the fee.
+		let value_to_self = self.value_to_self_msat / 1000;
+		let value_to_remote = (self.channel_value_satoshis * 1000 - self.value_to_self_msat) / 1000;
+		let (value_to_self, value_to_remote) = if self.channel_outbound {
+			(value_to_self.saturating_sub(total_fee_satoshis), value_to_remote)
+		} else {
+			(value_to_self, value_to_remote.saturating_sub(total_fee_satoshis))
+		};
+
+		let dust_limit_satoshis = cmp::max(self.our_dust_limit_satoshis, self.their_dust_limit_satoshis);
+		let mut txouts = Vec::new();
+		if value_to_self >= dust_limit_satoshis {
+			txouts.push(TxOut {
+				script_pubkey: self.our_shutdown_scriptpubkey.clone(),
+				value: value_to_self,
+			});
+		}
+		if value_to_remote >= dust_limit_satoshis {
+			txouts.push(TxOut {
+				script_pubkey: self.their_shutdown_scriptpubkey.clone().unwrap(),
+				value: value_to_remote,
+			});
+		}
+		// BIP69: by value, then by script_pubkey
+		txouts.sort_by(|a, b| {
+			a.value.cmp(&b.value).then_with(|| a.script_pubkey[..].cmp(&b.script_pubkey[..]))
+		});
+
+		Transaction {
+			version: 2,
+			lock_time: 0,
+			input: txins,
+			output: txouts,
+		}
+	}
+
+	/// Gets the funder's first closing_signed once both sides sent shutdown and all HTLCs are
+	/// resolved. Should be called again after each commitment update while shutting down.
+	pub fn maybe_propose_first_closing_signed(&mut self) -> Option<msgs::ClosingSigned> {
+		if !self.channel_outbound || !self.pending_htlcs.is_empty() || self.last_sent_closing_fee.is_some() {
+			return None;
+		}
+		if (self.channel_state & BOTH_SIDES_SHUTDOWN_MASK) != BOTH_SIDES_SHUTDOWN_MASK ||
+				(self.channel_state & (ChannelState::AwaitingRemoteRevoke as u32 | ChannelState::ShutdownComplete as u32)) != 0 {
+			return None;
+		}
+
+		let total_fee_satoshis = cmp::min(self.get_closing_fee_target(), self.get_funder_value_satoshis());
+		Some(self.sign_closing_fee(total_fee_satoshis))
+	}
+
+	/// The fee we'd like the closing transaction to pay at our own feerate. A closing transaction
+	/// has the shape of a commitment transaction without HTLCs.
+	fn get_closing_fee_target(&self) -> u64 {
+		self.our_feerate_per_kw * COMMITMENT_TX_BASE_WEIGHT / 1000
+	}
+
+	fn get_funder_value_satoshis(&self) -> u64 {
+		if self.channel_outbound { self.value_to_self_msat / 1000 } else { self.channel_value_satoshis - self.value_to_self_msat / 1000 }
+	}
+
+	fn sign_closing_fee(&mut self, total_fee_satoshis: u64) -> msgs::ClosingSigned {
+		let closing_tx = self.build_closing_transaction(total_fee_satoshis);
+		let our_sig = self.secp_ctx.sign(&self.get_funding_sighash(&closing_tx), &self.local_keys.funding_key).unwrap();
+
+		self.last_sent_closing_fee = Some(total_fee_satoshis);
+		msgs::ClosingSigned {
+			channel_id: self.channel_id,
+			fee_satoshis: total_fee_satoshis,
+			signature: our_sig,
+		}
+	}
+
+	/// Handles their closing_signed, negotiating the fee as in BOLT #2. The funder pays the fee, so
+	/// it takes any fee up to twice its own estimate (the feerate slack allowed in open_channel),
+	/// while the fundee takes any fee at least as high as its own estimate. Otherwise we counter
+	/// with our estimate first and then with a fee strictly between our last one and theirs.
+	/// They have to do the same, or we drop the connection: as we don't support reconnection,
+	/// the negotiation never starts over.
+	/// Returns our closing_signed, if we have to answer, and the signed closing transaction to
+	/// broadcast once both sides agreed on the fee.
+	pub fn closing_signed(&mut self, msg: &msgs::ClosingSigned) -> Result<(Option<msgs::ClosingSigned>, Option<Transaction>), HandleError> {
+		if (self.channel_state & BOTH_SIDES_SHUTDOWN_MASK) != BOTH_SIDES_SHUTDOWN_MASK {
+			return Err(channel_error(self.channel_id, "Remote end sent us a closing_signed before both sides provided a shutdown"));
+		}
+		if !self.pending_htlcs.is_empty() || (self.channel_state & (ChannelState::AwaitingRemoteRevoke as u32)) != 0 {
+			return Err(channel_error(self.channel_id, "Remote end sent us a closing_signed while there were still pending HTLCs"));
+		}
+		if self.channel_outbound && self.last_sent_closing_fee.is_none() {
+			return Err(channel_error(self.channel_id, "Remote end sent us a closing_signed before the funder proposed a fee"));
+		}
+		let funder_value_satoshis = self.get_funder_value_satoshis();
+		if msg.fee_satoshis > funder_value_satoshis {
+			return Err(channel_error(self.channel_id, "Remote proposed a closing fee the funder can't pay"));
+		}
+		if let (Some(last_sent), Some(last_received)) = (self.last_sent_closing_fee, self.last_received_closing_fee) {
+			let (low, high) = (cmp::min(last_sent, last_received), cmp::max(last_sent, last_received));
+			if msg.fee_satoshis != last_sent && (msg.fee_satoshis <= low || msg.fee_satoshis >= high) {
+				let err = "Remote closing fee isn't strictly between our last proposal and theirs";
+				return Err(HandleError {
+					err: err,
+					action: Some(ErrorAction::DisconnectPeer {
+						msg: Some(msgs::ErrorMessage {
+							channel_id: self.channel_id,
+							data: err.to_string(),
+						}),
+					}),
+				});
+			}
+		}
+
+		let mut closing_tx = self.build_closing_transaction(msg.fee_satoshis);
+		let sighash = self.get_funding_sighash(&closing_tx);
+		secp_call!(self.secp_ctx.verify(&sighash, &msg.signature, self.their_funding_pubkey.as_ref().unwrap()), self.channel_id, "Invalid closing tx signature from peer");
+		self.last_received_closing_fee = Some(msg.fee_satoshis);
+
+		if self.last_sent_closing_fee != Some(msg.fee_satoshis) {
+			let target = cmp::min(self.get_closing_fee_target(), funder_value_satoshis);
+			let acceptable = if self.channel_outbound { msg.fee_satoshis <= target * 2 } else { msg.fee_satoshis >= target };
+			let counter_fee = match self.last_sent_closing_fee {
+				None => target,
+				Some(last_fee) => (last_fee + msg.fee_satoshis) / 2,
+			};
+			// Once no fee is left strictly between the two proposals we take theirs
+			if !acceptable && counter_fee != msg.fee_satoshis && Some(counter_fee) != self.last_sent_closing_fee {
+				return Ok((Some(self.sign_closing_fee(counter_fee)), None));
+			}
+		}
+
+		let our_sig = self.secp_ctx.sign(&sighash, &self.local_keys.funding_key).unwrap();
+		chan_utils::add_funding_witness(&self.secp_ctx, &mut closing_tx, &our_sig, &msg.signature, &self.get_local_funding_pubkey(), self.their_funding_pubkey.as_ref().unwrap());
+
+		self.channel_state = ChannelState::ShutdownComplete as u32;
+		let our_closing_signed = if self.last_sent_closing_fee == Some(msg.fee_satoshis) {
+			None
+		} else {
+			self.last_sent_closing_fee = Some(msg.fee_satoshis);
+			Some(msgs::ClosingSigned {
+				channel_id: self.channel_id,
+				fee_satoshis: msg.fee_satoshis,
+				signature: our_sig,
+			})
+		};
+		Ok((our_closing_signed, Some(closing_tx)))
+	}
+
+	/// Gets our latest commitment transaction, signed and ready for broadcast, if the channel got
+	/// that far. Called when the channel has to be closed without the other side's cooperation.
+	pub fn force_shutdown(&mut self) -> Option<Transaction> {
+		self.channel_state = ChannelState::ShutdownComplete as u32;
+		self.last_local_commitment_txn.take()
+	}
+
+	// Public utilities:
+
+	pub fn channel_id(&self) -> [u8; 32] {
+		self.channel_id
+	}
+
+	pub fn get_their_node_id(&self) -> PublicKey {
+		self.their_node_id
+	}
+
+	pub fn get_channel_value_satoshis(&self) -> u64 {
+		self.channel_value_satoshis
+	}
+
+	/// Our balance, not counting HTLCs which are still pending.
+	pub fn get_value_to_self_msat(&self) -> u64 {
+		self.value_to_self_msat
+	}
+
+	pub fn get_funding_txo(&self) -> Option<(Sha256dHash, u16)> {
+		self.funding_txo.clone()
+	}
+
+	/// Our funding transaction, until they signed our first commitment and it was broadcast.
+	pub fn get_unbroadcast_funding_tx(&self) -> Option<&Transaction> {
+		self.funding_tx.as_ref()
+	}
+
+	pub fn get_local_funding_pubkey(&self) -> PublicKey {
+		PublicKey::from_secret_key(&self.secp_ctx, &self.local_keys.funding_key).unwrap()
+	}
+
+	pub fn get_their_funding_pubkey(&self) -> Option<PublicKey> {
+		self.their_funding_pubkey
+	}
+
+	/// Whether HTLCs can be sent over the channel
+	pub fn is_live(&self) -> bool {
+		self.is_funded() && (self.channel_state & BOTH_SIDES_SHUTDOWN_MASK) == 0
+	}
+
+	/// Whether the funding transaction is broadcastable, ie both sides signed their first
+	/// commitment transactions
+	pub fn is_funding_signed(&self) -> bool {
+		self.channel_state >= ChannelState::FundingSent as u32
+	}
+
+	pub fn is_shutdown(&self) -> bool {
+		self.channel_state == ChannelState::ShutdownComplete as u32
+	}
+
+	pub fn get_open_channel(&self, chain_hash: Sha256dHash) -> msgs::OpenChannel {
+		if !self.channel_outbound {
+			panic!("Tried to open a channel for an inbound channel?");
+		}
+		if self.channel_state != ChannelState::OurInitSent as u32 {
+			panic!("Cannot generate an open_channel after we've moved forward");
+		}
+
+		msgs::OpenChannel {
+			chain_hash: chain_hash,
+			temporary_channel_id: self.channel_id,
+			funding_satoshis: self.channel_value_satoshis,
+			push_msat: self.channel_value_satoshis * 1000 - self.value_to_self_msat,
+			dust_limit_satoshis: self.our_dust_limit_satoshis,
+			max_htlc_value_in_flight_msat: Channel::get_our_max_htlc_value_in_flight_msat(self.channel_value_satoshis),
+			channel_reserve_satoshis: Channel::get_our_channel_reserve_satoshis(self.channel_value_satoshis),
+			htlc_minimum_msat: OUR_HTLC_MINIMUM_MSAT,
+			feerate_per_kw: self.feerate_per_kw as u32,
+			to_self_delay: OUR_TO_SELF_DELAY,
+			max_accepted_htlcs: OUR_MAX_HTLCS,
+			funding_pubkey: self.get_local_funding_pubkey(),
+			revocation_basepoint: PublicKey::from_secret_key(&self.secp_ctx, &self.local_keys.revocation_base_key).unwrap(),
+			payment_basepoint: PublicKey::from_secret_key(&self.secp_ctx, &self.local_keys.payment_base_key).unwrap(),
+			delayed_payment_basepoint: PublicKey::from_secret_key(&self.secp_ctx, &self.local_keys.delayed_payment_base_key).unwrap(),
+			htlc_basepoint: PublicKey::from_secret_key(&self.secp_ctx, &self.local_keys.htlc_base_key).unwrap(),
+			first_per_commitment_point: self.get_local_commitment_point(self.cur_local_commitment_transaction_number),
+			channel_flags: 0, // We don't announce channels
+			shutdown_scriptpubkey: None,
+		}
+	}
+
+	pub fn get_accept_channel(&self) -> msgs::AcceptChannel {
+		if self.channel_outbound {
+			panic!("Tried to send accept_channel for an outbound channel?");
+		}
+		if self.channel_state != (ChannelState::OurInitSent as u32) | (ChannelState::TheirInitSent as u32) {
+			panic!("Tried to send accept_channel after channel had moved forward");
+		}
+
+		msgs::AcceptChannel {
+			temporary_channel_id: self.channel_id,
+			dust_limit_satoshis: self.our_dust_limit_satoshis,
+			max_htlc_value_in_flight_msat: Channel::get_our_max_htlc_value_in_flight_msat(self.channel_value_satoshis),
+			channel_reserve_satoshis: Channel::get_our_channel_reserve_satoshis(self.channel_value_satoshis),
+			htlc_minimum_msat: OUR_HTLC_MINIMUM_MSAT,
+			minimum_depth: OUR_MINIMUM_DEPTH,
+			to_self_delay: OUR_TO_SELF_DELAY,
+			max_accepted_htlcs: OUR_MAX_HTLCS,
+			funding_pubkey: self.get_local_funding_pubkey(),
+			revocation_basepoint: PublicKey::from_secret_key(&self.secp_ctx, &self.local_keys.revocation_base_key).unwrap(),
+			payment_basepoint: PublicKey::from_secret_key(&self.secp_ctx, &self.local_keys.payment_base_key).unwrap(),
+			delayed_payment_basepoint: PublicKey::from_secret_key(&self.secp_ctx, &self.local_keys.delayed_payment_base_key).unwrap(),
+			htlc_basepoint: PublicKey::from_secret_key(&self.secp_ctx, &self.local_keys.htlc_base_key).unwrap(),
+			first_per_commitment_point: self.get_local_commitment_point(self.cur_local_commitment_transaction_number),
+			shutdown_scriptpubkey: None,
+		}
+	}
+
+	/// Takes the funding transaction paying to our and their funding keys and signs their first
+	/// commitment transaction, getting the funding_created message to send. The channel gets its
+	/// final channel_id here.
+	pub fn get_outbound_funding_created(&mut self, funding_tx: Transaction, funding_output_index: u16) -> Result<msgs::FundingCreated, HandleError> {
+		if !self.channel_outbound {
+			panic!("Tried to create outbound funding_created message on an inbound channel!");
+		}
+		if self.channel_state != (ChannelState::OurInitSent as u32 | ChannelState::TheirInitSent as u32) {
+			panic!("Tried to get a funding_created messsage at a time other than immediately after initial handshake completion (or tried to get funding_created twice)");
+		}
+
+		let funding_txid = funding_tx.bitcoin_hash();
+		self.funding_txo = Some((funding_txid.clone(), funding_output_index));
+		let (our_signature, _) = match self.sign_remote_commitment_transaction(self.cur_remote_commitment_transaction_number) {
+			Ok(sigs) => sigs,
+			Err(e) => {
+				self.funding_txo = None;
+				return Err(e);
+			}
+		};
+
+		let temporary_channel_id = self.channel_id;
+
+		// Now that we're past error-generating stuff, update our local state:
+		self.funding_tx = Some(funding_tx);
+		self.channel_id = Channel::funding_channel_id(&funding_txid, funding_output_index);
+		self.channel_state = ChannelState::FundingCreated as u32;
+
+		Ok(msgs::FundingCreated {
+			temporary_channel_id: temporary_channel_id,
+			funding_txid: funding_txid,
+			funding_output_index: funding_output_index,
+			signature: our_signature,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use bitcoin::blockdata::script::Script;
+
+	use ln::channel::{HTLCState, is_standard_shutdown_script};
+
+	use hex;
+
+	#[test]
+	fn test_htlc_state_inclusion() {
+		// Each side's updates reach the other side's commitment first, ours only once they
+		// revoked the commitment which didn't have them.
+		assert!(HTLCState::RemoteAnnounced.is_included_in(true));
+		assert!(!HTLCState::RemoteAnnounced.is_included_in(false));
+		assert!(!HTLCState::LocalAnnounced.is_included_in(true));
+		assert!(HTLCState::LocalAnnounced.is_included_in(false));
+		assert!(HTLCState::LocalRemoved.is_included_in(true));
+		assert!(!HTLCState::LocalRemoved.is_included_in(false));
+		assert!(!HTLCState::RemoteRemoved.is_included_in(true));
+		assert!(HTLCState::RemoteRemoved.is_included_in(false));
+		for state in [HTLCState::AwaitingRemoteRevokeToAnnounce, HTLCState::AwaitingAnnouncedRemoteRevoke, HTLCState::Committed].iter() {
+			assert!(state.is_included_in(true) && state.is_included_in(false));
+			assert!(!state.is_removed());
+		}
+		for state in [HTLCState::AwaitingRemoteRevokeToRemove, HTLCState::AwaitingRemovedRemoteRevoke, HTLCState::LocalRemovedAwaitingCommitment].iter() {
+			assert!(!state.is_included_in(true) && !state.is_included_in(false));
+			assert!(state.is_removed());
+		}
+	}
+
+	#[test]
+	fn test_standard_shutdown_scripts() {
+		let script = |script_hex: &str| Script::from(hex::decode(script_hex).unwrap());
+		assert!(is_standard_shutdown_script(&script("76a9143ca33c2e4446f4a305f23c80df8ad1afdcf652f988ac")));
+		assert!(is_standard_shutdown_script(&script("a9143ca33c2e4446f4a305f23c80df8ad1afdcf652f987")));
+		assert!(is_standard_shutdown_script(&script("00143ca33c2e4446f4a305f23c80df8ad1afdcf652f9")));
+		assert!(is_standard_shutdown_script(&script("0020c015c4a6be010e21657068fc2e6a9d02b27ebe4d490a25846f7237f104d1a3cd")));
+		// OP_RETURN and a truncated P2WPKH
+		assert!(!is_standard_shutdown_script(&script("6a0400000000")));
+		assert!(!is_standard_shutdown_script(&script("00133ca33c2e4446f4a305f23c80df8ad1afdcf652")));
+	}
+}
@@ -0,0 +1,1163 @@
+//! The ChannelManager keeps every Channel of the node, routes the messages of peers to them and
+//! provides the calls to open, pay over and close channels.
+//!
+//! Messages to send and things the user has to act on come out as Events, see
+//! PeerManager::process_events.
+
+use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::network::constants::Network;
+use bitcoin::network::serialize::BitcoinHash;
+use bitcoin::util::hash::Sha256dHash;
+
+use secp256k1::key::PublicKey;
+use secp256k1::Secp256k1;
+
+use ln::channel;
+use ln::channel::Channel;
+use ln::funding;
+use ln::funding::WalletSource;
+use ln::msgs;
+use ln::msgs::{ErrorAction,HandleError};
+use util::events;
+use util::events::Event;
+
+use std::collections::HashMap;
+use std::sync::{Arc,Mutex};
+use std::mem;
+
+/// Details of a channel, as returned by ChannelManager::list_channels.
+pub struct ChannelDetails {
+	/// The channel's ID (prior to funding transaction generation, this is a random 32 bytes,
+	/// thereafter this is the txid of the funding transaction xor the funding transaction output).
+	pub channel_id: [u8; 32],
+	pub remote_network_id: PublicKey,
+	pub channel_value_satoshis: u64,
+	/// Our balance, not counting HTLCs which are still pending
+	pub value_to_self_msat: u64,
+	/// Whether payments can be sent over the channel
+	pub is_live: bool,
+}
+
+/// Manages the channels of a node, opening and closing them and sending payments over them.
+/// Payments only go to the direct counterparty of a channel: onions are neither built nor read.
+///
+/// Channels are failed (ie our latest commitment transaction is broadcast) when the counterparty
+/// misbehaves, or when the PeerManager tells us no connection to it is possible any more. There
+/// is no channel_reestablish support yet, so a disconnection in the middle of an HTLC update
+/// leaves its channel stuck until it is closed.
+pub struct ChannelManager {
+	genesis_hash: Sha256dHash,
+	feerate_per_kw: u64,
+	wallet: Arc<WalletSource>,
+
+	secp_ctx: Secp256k1,
+
+	channels: Mutex<HashMap<[u8; 32], Channel>>,
+	pending_events: Mutex<Vec<Event>>,
+}
+
+macro_rules! get_channel {
+	( $channels: expr, $their_node_id: expr, $channel_id: expr ) => {
+		match $channels.get_mut(&$channel_id) {
+			Some(chan) => {
+				if chan.get_their_node_id() != *$their_node_id {
+					return Err(HandleError{err: "Got a message for a channel from the wrong node!", action: None});
+				}
+				chan
+			},
+			None => return Err(HandleError{err: "Failed to find corresponding channel", action: None}),
+		}
+	};
+}
+
+impl ChannelManager {
+	/// Constructs a new ChannelManager for the given network. Every channel is opened and closed
+	/// at feerate_per_kw, funding transactions are paid for by the wallet, which also receives
+	/// our share when channels are closed.
+	pub fn new(network: Network, feerate_per_kw: u64, wallet: Arc<WalletSource>) -> Arc<ChannelManager> {
+		Arc::new(ChannelManager {
+			genesis_hash: genesis_block(network).header.bitcoin_hash(),
+			feerate_per_kw: feerate_per_kw,
+			wallet: wallet,
+
+			secp_ctx: Secp256k1::new(),
+
+			channels: Mutex::new(HashMap::new()),
+			pending_events: Mutex::new(Vec::new()),
+		})
+	}
+
+	/// Creates a new outbound channel to the given remote node and with the given value.
+	/// push_msat is the value which goes to the remote side right away.
+	/// Returns the temporary channel_id, the channel gets its final one along with its funding
+	/// transaction.
+	pub fn create_channel(&self, their_network_key: PublicKey, channel_value_satoshis: u64, push_msat: u64) -> Result<[u8; 32], HandleError> {
+		let chan = Channel::new_outbound(their_network_key, channel_value_satoshis, push_msat, self.feerate_per_kw, self.wallet.get_change_script())?;
+		let res = chan.get_open_channel(self.genesis_hash.clone());
+		let channel_id = chan.channel_id();
+
+		let mut channels = self.channels.lock().unwrap();
+		match channels.insert(channel_id, chan) {
+			Some(_) => panic!("RNG is bad???"),
+			None => {}
+		}
+
+		let mut events = self.pending_events.lock().unwrap();
+		events.push(Event::SendOpenChannel {
+			node_id: their_network_key,
+			msg: res,
+		});
+		Ok(channel_id)
+	}
+
+	/// Gets the list of open channels, in random order.
+	pub fn list_channels(&self) -> Vec<ChannelDetails> {
+		let channels = self.channels.lock().unwrap();
+		let mut res = Vec::with_capacity(channels.len());
+		for chan in channels.values() {
+			res.push(ChannelDetails {
+				channel_id: chan.channel_id(),
+				remote_network_id: chan.get_their_node_id(),
+				channel_value_satoshis: chan.get_channel_value_satoshis(),
+				value_to_self_msat: chan.get_value_to_self_msat(),
+				is_live: chan.is_live(),
+			});
+		}
+		res
+	}
+
+	/// Begins the closing of the given channel. The closing transaction comes out as a
+	/// BroadcastTransaction event once both sides agreed on it.
+	pub fn close_channel(&self, channel_id: &[u8; 32]) -> Result<(), HandleError> {
+		let mut channels = self.channels.lock().unwrap();
+		let (node_id, res) = match channels.get_mut(channel_id) {
+			Some(chan) => (chan.get_their_node_id(), chan.get_shutdown()?),
+			None => return Err(HandleError{err: "No such channel", action: None}),
+		};
+
+		let mut events = self.pending_events.lock().unwrap();
+		events.push(Event::SendShutdown {
+			node_id: node_id,
+			msg: res,
+		});
+		Ok(())
+	}
+
+	/// Sends a payment over the given channel to its counterparty, which has to know the preimage
+	/// of payment_hash to claim it. A PaymentSent or PaymentFailed event tells how it went.
+	pub fn send_payment(&self, channel_id: &[u8; 32], amount_msat: u64, payment_hash: [u8; 32], cltv_expiry: u32) -> Result<(), HandleError> {
+		let onion_packet = self.dummy_onion_packet();
+
+		let mut channels = self.channels.lock().unwrap();
+		let res = match channels.get_mut(channel_id) {
+			Some(chan) => chan.send_htlc_and_commit(amount_msat, payment_hash, cltv_expiry, onion_packet).map(|res| (chan.get_their_node_id(), res)),
+			None => return Err(HandleError{err: "No such channel", action: None}),
+		};
+		let (node_id, res) = self.close_on_channel_error(&mut channels, channel_id, res)?;
+
+		if let Some((update_add_htlc, commitment_signed)) = res {
+			let mut events = self.pending_events.lock().unwrap();
+			events.push(Event::UpdateHTLCs {
+				node_id: node_id,
+				updates: msgs::CommitmentUpdate {
+					update_add_htlcs: vec![update_add_htlc],
+					update_fulfill_htlcs: Vec::new(),
+					update_fail_htlcs: Vec::new(),
+					commitment_signed: commitment_signed,
+				},
+			});
+		}
+		Ok(())
+	}
+
+	/// Provides a payment preimage in response to a PaymentReceived event, returning true and
+	/// claiming every pending HTLC paying to its hash, if any. A channel whose new commitment we
+	/// fail to sign can't go on, so it is failed.
+	pub fn claim_funds(&self, payment_preimage: [u8; 32]) -> bool {
+		let mut claimed = false;
+		let mut new_events = Vec::new();
+		let mut failed_channels = Vec::new();
+		let mut channels = self.channels.lock().unwrap();
+		for (channel_id, chan) in channels.iter_mut() {
+			loop {
+				match chan.claim_htlc_and_commit(payment_preimage) {
+					Ok(Some((update_fulfill_htlc, commitment_signed))) => {
+						new_events.push(Event::UpdateHTLCs {
+							node_id: chan.get_their_node_id(),
+							updates: msgs::CommitmentUpdate {
+								update_add_htlcs: Vec::new(),
+								update_fulfill_htlcs: vec![update_fulfill_htlc],
+								update_fail_htlcs: Vec::new(),
+								commitment_signed: commitment_signed,
+							},
+						});
+					},
+					Ok(None) => {},
+					// No (more) HTLCs paying to the hash in this channel
+					Err(HandleError{action: None, ..}) => break,
+					Err(_) => {
+						failed_channels.push(*channel_id);
+						break;
+					},
+				}
+				claimed = true;
+			}
+		}
+		for channel_id in failed_channels {
+			let chan = channels.remove(&channel_id).unwrap();
+			self.force_close_channel(chan);
+		}
+		self.pending_events.lock().unwrap().append(&mut new_events);
+		claimed
+	}
+
+	/// Indicates that the preimage for payment_hash is unknown after a PaymentReceived event,
+	/// returning true and failing every pending HTLC paying to it back to its sender, if any. A
+	/// channel whose new commitment we fail to sign can't go on, so it is failed.
+	pub fn fail_htlc_backwards(&self, payment_hash: &[u8; 32]) -> bool {
+		let mut failed = false;
+		let mut new_events = Vec::new();
+		let mut failed_channels = Vec::new();
+		let mut channels = self.channels.lock().unwrap();
+		for (channel_id, chan) in channels.iter_mut() {
+			loop {
+				match chan.fail_htlc_and_commit(payment_hash) {
+					Ok(Some((update_fail_htlc, commitment_signed))) => {
+						new_events.push(Event::UpdateHTLCs {
+							node_id: chan.get_their_node_id(),
+							updates: msgs::CommitmentUpdate {
+								update_add_htlcs: Vec::new(),
+								update_fulfill_htlcs: Vec::new(),
+								update_fail_htlcs: vec![update_fail_htlc],
+								commitment_signed: commitment_signed,
+							},
+						});
+					},
+					Ok(None) => {},
+					// No (more) HTLCs paying to the hash in this channel
+					Err(HandleError{action: None, ..}) => break,
+					Err(_) => {
+						failed_channels.push(*channel_id);
+						break;
+					},
+				}
+				failed = true;
+			}
+		}
+		for channel_id in failed_channels {
+			let chan = channels.remove(&channel_id).unwrap();
+			self.force_close_channel(chan);
+		}
+		self.pending_events.lock().unwrap().append(&mut new_events);
+		failed
+	}
+
+	/// Called once the funding transaction with the given txid has enough confirmations, sending
+	/// funding_locked for the channels it funds.
+	pub fn funding_transaction_confirmed(&self, funding_txid: &Sha256dHash) {
+		let mut channels = self.channels.lock().unwrap();
+		let mut events = self.pending_events.lock().unwrap();
+		for chan in channels.values_mut() {
+			let funds_channel = match chan.get_funding_txo() {
+				Some((txid, _)) => txid == *funding_txid,
+				None => false,
+			};
+			if funds_channel && chan.is_funding_signed() {
+				if let Some(funding_locked) = chan.funding_confirmed() {
+					events.push(Event::SendFundingLocked {
+						node_id: chan.get_their_node_id(),
+						msg: funding_locked,
+						announcement_sigs: None,
+					});
+				}
+			}
+		}
+	}
+
+	/// Without routing there's nothing to put in the onion, but update_add_htlc needs one.
+	fn dummy_onion_packet(&self) -> msgs::OnionPacket {
+		let session_key = channel::random_secret_key(&self.secp_ctx);
+		msgs::OnionPacket {
+			version: 0,
+			public_key: PublicKey::from_secret_key(&self.secp_ctx, &session_key).unwrap(),
+			hop_data: [0; 20*65],
+			hmac: [0; 32],
+		}
+	}
+
+	/// Broadcasts our latest commitment transaction of a channel we're dropping, and gives the
+	/// coins of its funding transaction back to the wallet if it was never broadcast.
+	fn force_close_channel(&self, mut chan: Channel) {
+		if let Some(funding_tx) = chan.get_unbroadcast_funding_tx() {
+			self.wallet.unlock_unspent(&funding::get_spent_outpoints(funding_tx));
+		}
+		if let Some(tx) = chan.force_shutdown() {
+			let mut events = self.pending_events.lock().unwrap();
+			events.push(Event::BroadcastTransaction {
+				tx: tx,
+			});
+		}
+	}
+
+	/// Fails the channel when the error is to be reported to the counterparty, ie when it can't
+	/// go on.
+	fn close_on_channel_error<T>(&self, channels: &mut HashMap<[u8; 32], Channel>, channel_id: &[u8; 32], res: Result<T, HandleError>) -> Result<T, HandleError> {
+		if let Err(HandleError{action: Some(ErrorAction::SendErrorMessage{..}), ..}) = res {
+			if let Some(chan) = channels.remove(channel_id) {
+				self.force_close_channel(chan);
+			}
+		}
+		res
+	}
+
+	/// Proposes a closing fee if the shutdown of the channel was only waiting on HTLCs.
+	fn maybe_send_closing_signed(&self, chan: &mut Channel) {
+		if let Some(closing_signed) = chan.maybe_propose_first_closing_signed() {
+			let mut events = self.pending_events.lock().unwrap();
+			events.push(Event::SendClosingSigned {
+				node_id: chan.get_their_node_id(),
+				msg: closing_signed,
+			});
+		}
+	}
+}
+
+impl events::EventsProvider for ChannelManager {
+	fn get_and_clear_pending_events(&self) -> Vec<Event> {
+		let mut pending_events = self.pending_events.lock().unwrap();
+		let mut ret = Vec::new();
+		mem::swap(&mut ret, &mut *pending_events);
+		ret
+	}
+}
+
+impl msgs::ChannelMessageHandler for ChannelManager {
+	fn handle_open_channel(&self, their_node_id: &PublicKey, msg: &msgs::OpenChannel) -> Result<msgs::AcceptChannel, HandleError> {
+		if msg.chain_hash != self.genesis_hash {
+			return Err(HandleError{err: "Unknown genesis block hash", action: None});
+		}
+		let mut channels = self.channels.lock().unwrap();
+		if channels.contains_key(&msg.temporary_channel_id) {
+			return Err(HandleError{err: "temporary_channel_id collision!", action: None});
+		}
+		let channel = Channel::new_from_req(their_node_id.clone(), msg, self.feerate_per_kw, self.wallet.get_change_script())?;
+		let accept_msg = channel.get_accept_channel();
+		channels.insert(msg.temporary_channel_id.clone(), channel);
+		Ok(accept_msg)
+	}
+
+	fn handle_accept_channel(&self, their_node_id: &PublicKey, msg: &msgs::AcceptChannel) -> Result<(), HandleError> {
+		let mut channels = self.channels.lock().unwrap();
+		let res = {
+			let chan = get_channel!(channels, their_node_id, msg.temporary_channel_id);
+			chan.accept_channel(msg)?;
+			match funding::build_funding_transaction(&self.secp_ctx, &*self.wallet, &chan.get_local_funding_pubkey(), &chan.get_their_funding_pubkey().unwrap(), chan.get_channel_value_satoshis(), self.feerate_per_kw) {
+				Ok(funding) => {
+					// Our channels lock is held, so no other open can pick the same coins before
+					// they're locked
+					let spent_outpoints = funding::get_spent_outpoints(&funding.transaction);
+					let res = chan.get_outbound_funding_created(funding.transaction, funding.output_index);
+					if res.is_ok() {
+						self.wallet.lock_unspent(&spent_outpoints);
+					}
+					res
+				},
+				Err(_) => Err(HandleError{err: "Our wallet can't pay for the funding transaction", action: Some(ErrorAction::SendErrorMessage {
+					msg: msgs::ErrorMessage {
+						channel_id: msg.temporary_channel_id,
+						data: "Funder can't pay for the funding transaction".to_string(),
+					},
+				})}),
+			}
+		};
+		let funding_created = self.close_on_channel_error(&mut channels, &msg.temporary_channel_id, res)?;
+
+		// The channel_id is now derived from the funding transaction
+		let chan = channels.remove(&msg.temporary_channel_id).unwrap();
+		channels.insert(chan.channel_id(), chan);
+
+		let mut events = self.pending_events.lock().unwrap();
+		events.push(Event::SendFundingCreated {
+			node_id: their_node_id.clone(),
+			msg: funding_created,
+		});
+		Ok(())
+	}
+
+	fn handle_funding_created(&self, their_node_id: &PublicKey, msg: &msgs::FundingCreated) -> Result<msgs::FundingSigned, HandleError> {
+		let mut channels = self.channels.lock().unwrap();
+		let res = {
+			let chan = get_channel!(channels, their_node_id, msg.temporary_channel_id);
+			chan.funding_created(msg)
+		};
+		let funding_signed = self.close_on_channel_error(&mut channels, &msg.temporary_channel_id, res)?;
+
+		let chan = channels.remove(&msg.temporary_channel_id).unwrap();
+		if channels.contains_key(&chan.channel_id()) {
+			return Err(HandleError{err: "Already had channel with the new channel_id", action: None});
+		}
+		channels.insert(chan.channel_id(), chan);
+		Ok(funding_signed)
+	}
+
+	fn handle_funding_signed(&self, their_node_id: &PublicKey, msg: &msgs::FundingSigned) -> Result<(), HandleError> {
+		let mut channels = self.channels.lock().unwrap();
+		let res = {
+			let chan = get_channel!(channels, their_node_id, msg.channel_id);
+			chan.funding_signed(msg)
+		};
+		let funding_tx = self.close_on_channel_error(&mut channels, &msg.channel_id, res)?;
+
+		let mut events = self.pending_events.lock().unwrap();
+		events.push(Event::BroadcastTransaction {
+			tx: funding_tx,
+		});
+		Ok(())
+	}
+
+	fn handle_funding_locked(&self, their_node_id: &PublicKey, msg: &msgs::FundingLocked) -> Result<Option<msgs::AnnouncementSignatures>, HandleError> {
+		let mut channels = self.channels.lock().unwrap();
+		let res = {
+			let chan = get_channel!(channels, their_node_id, msg.channel_id);
+			chan.funding_locked(msg)
+		};
+		self.close_on_channel_error(&mut channels, &msg.channel_id, res)?;
+		// We don't announce channels
+		Ok(None)
+	}
+
+	fn handle_shutdown(&self, their_node_id: &PublicKey, msg: &msgs::Shutdown) -> Result<(Option<msgs::Shutdown>, Option<msgs::ClosingSigned>), HandleError> {
+		let mut channels = self.channels.lock().unwrap();
+		let res = {
+			let chan = get_channel!(channels, their_node_id, msg.channel_id);
+			chan.shutdown(msg)
+		};
+		let (our_shutdown, closing_signed, dropped_payments) = self.close_on_channel_error(&mut channels, &msg.channel_id, res)?;
+
+		let mut events = self.pending_events.lock().unwrap();
+		for payment_hash in dropped_payments {
+			events.push(Event::PaymentFailed {
+				payment_hash: payment_hash,
+			});
+		}
+		Ok((our_shutdown, closing_signed))
+	}
+
+	fn handle_closing_signed(&self, their_node_id: &PublicKey, msg: &msgs::ClosingSigned) -> Result<Option<msgs::ClosingSigned>, HandleError> {
+		let mut channels = self.channels.lock().unwrap();
+		let res = {
+			let chan = get_channel!(channels, their_node_id, msg.channel_id);
+			chan.closing_signed(msg)
+		};
+		let (our_closing_signed, closing_tx) = self.close_on_channel_error(&mut channels, &msg.channel_id, res)?;
+
+		// The channel is done with once the closing transaction is signed by both sides
+		if let Some(closing_tx) = closing_tx {
+			channels.remove(&msg.channel_id);
+
+			let mut events = self.pending_events.lock().unwrap();
+			events.push(Event::BroadcastTransaction {
+				tx: closing_tx,
+			});
+		}
+		Ok(our_closing_signed)
+	}
+
+	fn handle_update_add_htlc(&self, their_node_id: &PublicKey, msg: &msgs::UpdateAddHTLC) -> Result<(), HandleError> {
+		let mut channels = self.channels.lock().unwrap();
+		let res = {
+			let chan = get_channel!(channels, their_node_id, msg.channel_id);
+			chan.update_add_htlc(msg)
+		};
+		self.close_on_channel_error(&mut channels, &msg.channel_id, res)
+	}
+
+	fn handle_update_fulfill_htlc(&self, their_node_id: &PublicKey, msg: &msgs::UpdateFulfillHTLC) -> Result<(), HandleError> {
+		let mut channels = self.channels.lock().unwrap();
+		let res = {
+			let chan = get_channel!(channels, their_node_id, msg.channel_id);
+			chan.update_fulfill_htlc(msg)
+		};
+		self.close_on_channel_error(&mut channels, &msg.channel_id, res)?;
+
+		// The preimage is ours to keep, whatever happens to the channel from now on
+		let mut events = self.pending_events.lock().unwrap();
+		events.push(Event::PaymentSent {
+			payment_preimage: msg.payment_preimage,
+		});
+		Ok(())
+	}
+
+	fn handle_update_fail_htlc(&self, their_node_id: &PublicKey, msg: &msgs::UpdateFailHTLC) -> Result<Option<msgs::HTLCFailChannelUpdate>, HandleError> {
+		let mut channels = self.channels.lock().unwrap();
+		let res = {
+			let chan = get_channel!(channels, their_node_id, msg.channel_id);
+			chan.update_fail_htlc(msg)
+		};
+		// PaymentFailed waits for the HTLC to be removed from both commitment transactions
+		self.close_on_channel_error(&mut channels, &msg.channel_id, res)?;
+		Ok(None)
+	}
+
+	fn handle_update_fail_malformed_htlc(&self, their_node_id: &PublicKey, msg: &msgs::UpdateFailMalformedHTLC) -> Result<(), HandleError> {
+		let mut channels = self.channels.lock().unwrap();
+		let res = {
+			let chan = get_channel!(channels, their_node_id, msg.channel_id);
+			chan.update_fail_malformed_htlc(msg)
+		};
+		self.close_on_channel_error(&mut channels, &msg.channel_id, res)?;
+		Ok(())
+	}
+
+	fn handle_commitment_signed(&self, their_node_id: &PublicKey, msg: &msgs::CommitmentSigned) -> Result<(msgs::RevokeAndACK, Option<msgs::CommitmentSigned>), HandleError> {
+		let mut channels = self.channels.lock().unwrap();
+		let res = {
+			let chan = get_channel!(channels, their_node_id, msg.channel_id);
+			chan.commitment_signed(msg)
+		};
+		let res = self.close_on_channel_error(&mut channels, &msg.channel_id, res)?;
+
+		if let Some(chan) = channels.get_mut(&msg.channel_id) {
+			self.maybe_send_closing_signed(chan);
+		}
+		Ok(res)
+	}
+
+	fn handle_revoke_and_ack(&self, their_node_id: &PublicKey, msg: &msgs::RevokeAndACK) -> Result<Option<msgs::CommitmentUpdate>, HandleError> {
+		let mut channels = self.channels.lock().unwrap();
+		let res = {
+			let chan = get_channel!(channels, their_node_id, msg.channel_id);
+			chan.revoke_and_ack(msg)
+		};
+		let (commitment_update, payments_received, payments_failed) = self.close_on_channel_error(&mut channels, &msg.channel_id, res)?;
+
+		if let Some(chan) = channels.get_mut(&msg.channel_id) {
+			self.maybe_send_closing_signed(chan);
+		}
+
+		let mut events = self.pending_events.lock().unwrap();
+		for (payment_hash, amount_msat) in payments_received {
+			events.push(Event::PaymentReceived {
+				payment_hash: payment_hash,
+				amt: amount_msat,
+			});
+		}
+		for payment_hash in payments_failed {
+			events.push(Event::PaymentFailed {
+				payment_hash: payment_hash,
+			});
+		}
+		Ok(commitment_update)
+	}
+
+	fn handle_update_fee(&self, their_node_id: &PublicKey, msg: &msgs::UpdateFee) -> Result<(), HandleError> {
+		let mut channels = self.channels.lock().unwrap();
+		{
+			get_channel!(channels, their_node_id, msg.channel_id);
+		}
+		// The feerate is fixed when the channel is opened
+		let res = Err(HandleError{err: "Peer tried to update the channel feerate", action: Some(ErrorAction::SendErrorMessage {
+			msg: msgs::ErrorMessage {
+				channel_id: msg.channel_id,
+				data: "update_fee isn't supported".to_string(),
+			},
+		})});
+		self.close_on_channel_error(&mut channels, &msg.channel_id, res)
+	}
+
+	fn handle_announcement_signatures(&self, _their_node_id: &PublicKey, _msg: &msgs::AnnouncementSignatures) -> Result<(), HandleError> {
+		// We don't announce channels, so there's nothing to do with theirs
+		Ok(())
+	}
+
+	fn peer_disconnected(&self, their_node_id: &PublicKey, no_connection_possible: bool) {
+		let mut channels = self.channels.lock().unwrap();
+		let mut failed_channels = Vec::new();
+		for (channel_id, chan) in channels.iter() {
+			if chan.get_their_node_id() == *their_node_id && (no_connection_possible || !chan.is_funding_signed()) {
+				failed_channels.push(*channel_id);
+			}
+		}
+		for channel_id in failed_channels {
+			// Channels which were never signed have nothing to broadcast
+			let chan = channels.remove(&channel_id).unwrap();
+			self.force_close_channel(chan);
+		}
+	}
+
+	fn handle_error(&self, their_node_id: &PublicKey, msg: &msgs::ErrorMessage) {
+		let mut channels = self.channels.lock().unwrap();
+		let is_their_channel = match channels.get(&msg.channel_id) {
+			Some(chan) => chan.get_their_node_id() == *their_node_id,
+			None => false,
+		};
+		if is_their_channel {
+			let chan = channels.remove(&msg.channel_id).unwrap();
+			self.force_close_channel(chan);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use bitcoin::blockdata::script::Script;
+	use bitcoin::blockdata::transaction::Transaction;
+	use bitcoin::network::constants::Network;
+	use bitcoin::network::serialize::BitcoinHash;
+	use bitcoin::util::hash::Sha256dHash;
+
+	use crypto::digest::Digest;
+
+	use ln::channelmanager::ChannelManager;
+	use ln::funding::{get_p2wpkh_script, SpendableKind, Utxo, WalletSource};
+	use ln::msgs;
+	use ln::msgs::{ChannelMessageHandler,ErrorAction,HandleError};
+	use util::events::{Event, EventsProvider};
+	use util::sha2::Sha256;
+
+	use secp256k1::Secp256k1;
+	use secp256k1::key::{SecretKey,PublicKey};
+
+	use std::collections::HashSet;
+	use std::sync::{Arc, Mutex};
+
+	const FEERATE_PER_KW: u64 = 253;
+
+	struct TestWallet {
+		utxos: Vec<Utxo>,
+		change_script: Script,
+		locked: Mutex<HashSet<(Sha256dHash, u32)>>,
+	}
+	impl WalletSource for TestWallet {
+		fn list_unspent(&self) -> Vec<Utxo> {
+			let locked = self.locked.lock().unwrap();
+			self.utxos.iter().filter(|utxo| !locked.contains(&(utxo.prev_hash.clone(), utxo.prev_index))).cloned().collect()
+		}
+		fn get_change_script(&self) -> Script {
+			self.change_script.clone()
+		}
+		fn lock_unspent(&self, outpoints: &[(Sha256dHash, u32)]) {
+			self.locked.lock().unwrap().extend(outpoints.iter().cloned());
+		}
+		fn unlock_unspent(&self, outpoints: &[(Sha256dHash, u32)]) {
+			let mut locked = self.locked.lock().unwrap();
+			for outpoint in outpoints {
+				locked.remove(outpoint);
+			}
+		}
+	}
+
+	struct Node {
+		node_id: PublicKey,
+		manager: Arc<ChannelManager>,
+		shutdown_script: Script,
+	}
+
+	fn create_node(secp_ctx: &Secp256k1, seed: u8, feerate_per_kw: u64) -> Node {
+		let node_id = PublicKey::from_secret_key(secp_ctx, &SecretKey::from_slice(secp_ctx, &[seed; 32]).unwrap()).unwrap();
+		let wallet_key = SecretKey::from_slice(secp_ctx, &[seed + 100; 32]).unwrap();
+		let shutdown_script = get_p2wpkh_script(&PublicKey::from_secret_key(secp_ctx, &wallet_key).unwrap());
+		let wallet = TestWallet {
+			utxos: vec![Utxo {
+				prev_hash: Sha256dHash::from_data(&[seed]),
+				prev_index: 0,
+				value: 10000000,
+				kind: SpendableKind::P2WPKH,
+				secret_key: wallet_key,
+			}],
+			change_script: shutdown_script.clone(),
+			locked: Mutex::new(HashSet::new()),
+		};
+		Node {
+			node_id: node_id,
+			manager: ChannelManager::new(Network::Testnet, feerate_per_kw, Arc::new(wallet)),
+			shutdown_script: shutdown_script,
+		}
+	}
+
+	fn create_network() -> (Node, Node) {
+		let secp_ctx = Secp256k1::new();
+		(create_node(&secp_ctx, 1, FEERATE_PER_KW), create_node(&secp_ctx, 2, FEERATE_PER_KW))
+	}
+
+	fn sha256(data: &[u8]) -> [u8; 32] {
+		let mut sha = Sha256::new();
+		sha.input(data);
+		let mut res = [0; 32];
+		sha.result(&mut res);
+		res
+	}
+
+	fn get_single_event(node: &Node) -> Event {
+		let mut events = node.manager.get_and_clear_pending_events();
+		assert_eq!(events.len(), 1);
+		events.pop().unwrap()
+	}
+
+	fn get_funding_locked(node: &Node, counterparty: &Node) -> msgs::FundingLocked {
+		match get_single_event(node) {
+			Event::SendFundingLocked { node_id, msg, announcement_sigs } => {
+				assert_eq!(node_id, counterparty.node_id);
+				assert!(announcement_sigs.is_none());
+				msg
+			},
+			_ => panic!("Unexpected event"),
+		}
+	}
+
+	fn get_commitment_update(node: &Node, counterparty: &Node) -> msgs::CommitmentUpdate {
+		match get_single_event(node) {
+			Event::UpdateHTLCs { node_id, updates } => {
+				assert_eq!(node_id, counterparty.node_id);
+				updates
+			},
+			_ => panic!("Unexpected event"),
+		}
+	}
+
+	fn get_broadcast_transaction(node: &Node) -> Transaction {
+		match get_single_event(node) {
+			Event::BroadcastTransaction { tx } => tx,
+			_ => panic!("Unexpected event"),
+		}
+	}
+
+	/// Opens a funded channel from a to b, returning its channel_id.
+	fn create_chan_between_nodes(a: &Node, b: &Node, channel_value_satoshis: u64, push_msat: u64) -> [u8; 32] {
+		let temporary_channel_id = a.manager.create_channel(b.node_id, channel_value_satoshis, push_msat).unwrap();
+		let open_channel = match get_single_event(a) {
+			Event::SendOpenChannel { node_id, msg } => {
+				assert_eq!(node_id, b.node_id);
+				msg
+			},
+			_ => panic!("Unexpected event"),
+		};
+		assert_eq!(open_channel.temporary_channel_id, temporary_channel_id);
+
+		let accept_channel = b.manager.handle_open_channel(&a.node_id, &open_channel).unwrap();
+		a.manager.handle_accept_channel(&b.node_id, &accept_channel).unwrap();
+		let funding_created = match get_single_event(a) {
+			Event::SendFundingCreated { node_id, msg } => {
+				assert_eq!(node_id, b.node_id);
+				msg
+			},
+			_ => panic!("Unexpected event"),
+		};
+
+		let funding_signed = b.manager.handle_funding_created(&a.node_id, &funding_created).unwrap();
+		assert!(b.manager.get_and_clear_pending_events().is_empty());
+		a.manager.handle_funding_signed(&b.node_id, &funding_signed).unwrap();
+
+		// The funding transaction is only handed out once b signed our first commitment
+		let funding_tx = get_broadcast_transaction(a);
+		assert_eq!(funding_tx.bitcoin_hash(), funding_created.funding_txid);
+		assert_eq!(funding_tx.output[funding_created.funding_output_index as usize].value, channel_value_satoshis);
+
+		a.manager.funding_transaction_confirmed(&funding_tx.bitcoin_hash());
+		b.manager.funding_transaction_confirmed(&funding_tx.bitcoin_hash());
+		let a_funding_locked = get_funding_locked(a, b);
+		let b_funding_locked = get_funding_locked(b, a);
+		assert!(a.manager.handle_funding_locked(&b.node_id, &b_funding_locked).unwrap().is_none());
+		assert!(b.manager.handle_funding_locked(&a.node_id, &a_funding_locked).unwrap().is_none());
+
+		let channel_id = funding_signed.channel_id;
+		for &(node, counterparty) in [(a, b), (b, a)].iter() {
+			let channels = node.manager.list_channels();
+			assert_eq!(channels.len(), 1);
+			assert_eq!(channels[0].channel_id, channel_id);
+			assert_eq!(channels[0].remote_network_id, counterparty.node_id);
+			assert_eq!(channels[0].channel_value_satoshis, channel_value_satoshis);
+			assert!(channels[0].is_live);
+		}
+		channel_id
+	}
+
+	/// Delivers the commitment_signed of origin to dest and plays the revoke_and_ack and
+	/// commitment_signed which follow, until both sides revoked their previous commitment.
+	fn commitment_signed_dance(origin: &Node, dest: &Node, commitment_signed: &msgs::CommitmentSigned) {
+		let (revoke_and_ack, dest_commitment_signed) = dest.manager.handle_commitment_signed(&origin.node_id, commitment_signed).unwrap();
+		assert!(origin.manager.handle_revoke_and_ack(&dest.node_id, &revoke_and_ack).unwrap().is_none());
+		let (revoke_and_ack, origin_commitment_signed) = origin.manager.handle_commitment_signed(&dest.node_id, &dest_commitment_signed.unwrap()).unwrap();
+		assert!(origin_commitment_signed.is_none());
+		assert!(dest.manager.handle_revoke_and_ack(&origin.node_id, &revoke_and_ack).unwrap().is_none());
+	}
+
+	/// Sends a payment from a to b and delivers it, up to b's PaymentReceived.
+	fn send_payment(a: &Node, b: &Node, channel_id: &[u8; 32], amount_msat: u64, payment_hash: [u8; 32]) {
+		a.manager.send_payment(channel_id, amount_msat, payment_hash, 500).unwrap();
+		let updates = get_commitment_update(a, b);
+		assert_eq!(updates.update_add_htlcs.len(), 1);
+		assert!(updates.update_fulfill_htlcs.is_empty() && updates.update_fail_htlcs.is_empty());
+		b.manager.handle_update_add_htlc(&a.node_id, &updates.update_add_htlcs[0]).unwrap();
+		commitment_signed_dance(a, b, &updates.commitment_signed);
+
+		match get_single_event(b) {
+			Event::PaymentReceived { payment_hash: received_hash, amt } => {
+				assert_eq!(received_hash, payment_hash);
+				assert_eq!(amt, amount_msat);
+			},
+			_ => panic!("Unexpected event"),
+		}
+		assert!(a.manager.get_and_clear_pending_events().is_empty());
+	}
+
+	/// Claims a payment from a received by b.
+	fn claim_payment(a: &Node, b: &Node, payment_preimage: [u8; 32]) {
+		assert!(b.manager.claim_funds(payment_preimage));
+		let updates = get_commitment_update(b, a);
+		assert_eq!(updates.update_fulfill_htlcs.len(), 1);
+		assert!(updates.update_add_htlcs.is_empty() && updates.update_fail_htlcs.is_empty());
+		a.manager.handle_update_fulfill_htlc(&b.node_id, &updates.update_fulfill_htlcs[0]).unwrap();
+		match get_single_event(a) {
+			Event::PaymentSent { payment_preimage: sent_preimage } => assert_eq!(sent_preimage, payment_preimage),
+			_ => panic!("Unexpected event"),
+		}
+		commitment_signed_dance(b, a, &updates.commitment_signed);
+		assert!(a.manager.get_and_clear_pending_events().is_empty());
+		assert!(b.manager.get_and_clear_pending_events().is_empty());
+	}
+
+	fn get_value_to_self_msat(node: &Node) -> u64 {
+		let channels = node.manager.list_channels();
+		assert_eq!(channels.len(), 1);
+		channels[0].value_to_self_msat
+	}
+
+	#[test]
+	fn test_channel_payment() {
+		let (a, b) = create_network();
+		let channel_id = create_chan_between_nodes(&a, &b, 100000, 0);
+
+		let payment_preimage = [1; 32];
+		send_payment(&a, &b, &channel_id, 10000000, sha256(&payment_preimage));
+		// The preimage is checked against the payment hash
+		assert!(!b.manager.claim_funds([2; 32]));
+		assert!(b.manager.get_and_clear_pending_events().is_empty());
+		claim_payment(&a, &b, payment_preimage);
+		assert_eq!(get_value_to_self_msat(&a), 90000000);
+		assert_eq!(get_value_to_self_msat(&b), 10000000);
+
+		// And back the other way
+		let payment_preimage = [3; 32];
+		send_payment(&b, &a, &channel_id, 4000000, sha256(&payment_preimage));
+		claim_payment(&b, &a, payment_preimage);
+		assert_eq!(get_value_to_self_msat(&a), 94000000);
+		assert_eq!(get_value_to_self_msat(&b), 6000000);
+	}
+
+	#[test]
+	fn test_failed_payment() {
+		let (a, b) = create_network();
+		let channel_id = create_chan_between_nodes(&a, &b, 100000, 0);
+
+		let payment_hash = sha256(&[2; 32]);
+		send_payment(&a, &b, &channel_id, 10000000, payment_hash);
+		assert_eq!(get_value_to_self_msat(&a), 90000000);
+
+		assert!(b.manager.fail_htlc_backwards(&payment_hash));
+		assert!(!b.manager.fail_htlc_backwards(&payment_hash));
+		let updates = get_commitment_update(&b, &a);
+		assert_eq!(updates.update_fail_htlcs.len(), 1);
+		assert!(a.manager.handle_update_fail_htlc(&b.node_id, &updates.update_fail_htlcs[0]).unwrap().is_none());
+		commitment_signed_dance(&b, &a, &updates.commitment_signed);
+
+		match get_single_event(&a) {
+			Event::PaymentFailed { payment_hash: failed_hash } => assert_eq!(failed_hash, payment_hash),
+			_ => panic!("Unexpected event"),
+		}
+		assert_eq!(get_value_to_self_msat(&a), 100000000);
+		assert_eq!(get_value_to_self_msat(&b), 0);
+	}
+
+	#[test]
+	fn test_holding_cell_payment() {
+		let (a, b) = create_network();
+		let channel_id = create_chan_between_nodes(&a, &b, 100000, 0);
+
+		let payment_preimage_1 = [1; 32];
+		let payment_preimage_2 = [2; 32];
+		a.manager.send_payment(&channel_id, 10000000, sha256(&payment_preimage_1), 500).unwrap();
+		let updates_1 = get_commitment_update(&a, &b);
+		// a waits on b's revoke_and_ack before it can commit to the second payment
+		a.manager.send_payment(&channel_id, 20000000, sha256(&payment_preimage_2), 500).unwrap();
+		assert!(a.manager.get_and_clear_pending_events().is_empty());
+
+		b.manager.handle_update_add_htlc(&a.node_id, &updates_1.update_add_htlcs[0]).unwrap();
+		let (b_revoke_and_ack, b_commitment_signed) = b.manager.handle_commitment_signed(&a.node_id, &updates_1.commitment_signed).unwrap();
+		let updates_2 = a.manager.handle_revoke_and_ack(&b.node_id, &b_revoke_and_ack).unwrap().unwrap();
+		assert_eq!(updates_2.update_add_htlcs.len(), 1);
+		assert_eq!(updates_2.update_add_htlcs[0].amount_msat, 20000000);
+		let (a_revoke_and_ack, a_commitment_signed) = a.manager.handle_commitment_signed(&b.node_id, &b_commitment_signed.unwrap()).unwrap();
+		assert!(a_commitment_signed.is_none());
+
+		// The second payment and a's revoke_and_ack cross b's state in that order
+		b.manager.handle_update_add_htlc(&a.node_id, &updates_2.update_add_htlcs[0]).unwrap();
+		let (b_revoke_and_ack, b_commitment_signed) = b.manager.handle_commitment_signed(&a.node_id, &updates_2.commitment_signed).unwrap();
+		assert!(b_commitment_signed.is_none());
+		let b_updates = b.manager.handle_revoke_and_ack(&a.node_id, &a_revoke_and_ack).unwrap().unwrap();
+		assert!(b_updates.update_add_htlcs.is_empty() && b_updates.update_fulfill_htlcs.is_empty() && b_updates.update_fail_htlcs.is_empty());
+		match get_single_event(&b) {
+			Event::PaymentReceived { payment_hash, amt } => {
+				assert_eq!(payment_hash, sha256(&payment_preimage_1));
+				assert_eq!(amt, 10000000);
+			},
+			_ => panic!("Unexpected event"),
+		}
+
+		assert!(a.manager.handle_revoke_and_ack(&b.node_id, &b_revoke_and_ack).unwrap().is_none());
+		let (a_revoke_and_ack, a_commitment_signed) = a.manager.handle_commitment_signed(&b.node_id, &b_updates.commitment_signed).unwrap();
+		assert!(a_commitment_signed.is_none());
+		assert!(b.manager.handle_revoke_and_ack(&a.node_id, &a_revoke_and_ack).unwrap().is_none());
+		match get_single_event(&b) {
+			Event::PaymentReceived { payment_hash, amt } => {
+				assert_eq!(payment_hash, sha256(&payment_preimage_2));
+				assert_eq!(amt, 20000000);
+			},
+			_ => panic!("Unexpected event"),
+		}
+
+		claim_payment(&a, &b, payment_preimage_2);
+		claim_payment(&a, &b, payment_preimage_1);
+		assert_eq!(get_value_to_self_msat(&a), 70000000);
+		assert_eq!(get_value_to_self_msat(&b), 30000000);
+	}
+
+	#[test]
+	fn test_update_add_htlc_limits() {
+		// Below our htlc_minimum_msat, overflowing, and eating into a's reserve (1000 sat) once
+		// a's commitment fee is paid
+		for &amount_msat in [999, u64::max_value(), 99500000].iter() {
+			let (a, b) = create_network();
+			let channel_id = create_chan_between_nodes(&a, &b, 100000, 0);
+			a.manager.send_payment(&channel_id, 1000000, sha256(&[1; 32]), 500).unwrap();
+			let mut update_add_htlc = get_commitment_update(&a, &b).update_add_htlcs[0].clone();
+			update_add_htlc.amount_msat = amount_msat;
+
+			assert!(b.manager.handle_update_add_htlc(&a.node_id, &update_add_htlc).is_err());
+			assert!(b.manager.list_channels().is_empty());
+			get_broadcast_transaction(&b);
+		}
+
+		// Leaving exactly the fee and the reserve to a is fine
+		let (a, b) = create_network();
+		let channel_id = create_chan_between_nodes(&a, &b, 100000, 0);
+		a.manager.send_payment(&channel_id, 1000000, sha256(&[1; 32]), 500).unwrap();
+		let mut update_add_htlc = get_commitment_update(&a, &b).update_add_htlcs[0].clone();
+		update_add_htlc.amount_msat = 100000000 - 1000000 - FEERATE_PER_KW * (724 + 172);
+		b.manager.handle_update_add_htlc(&a.node_id, &update_add_htlc).unwrap();
+		assert_eq!(b.manager.list_channels().len(), 1);
+	}
+
+	#[test]
+	fn test_channel_close() {
+		let (a, b) = create_network();
+		let channel_id = create_chan_between_nodes(&a, &b, 100000, 0);
+		let payment_preimage = [1; 32];
+		send_payment(&a, &b, &channel_id, 10000000, sha256(&payment_preimage));
+		claim_payment(&a, &b, payment_preimage);
+
+		a.manager.close_channel(&channel_id).unwrap();
+		let a_shutdown = match get_single_event(&a) {
+			Event::SendShutdown { node_id, msg } => {
+				assert_eq!(node_id, b.node_id);
+				msg
+			},
+			_ => panic!("Unexpected event"),
+		};
+		// No more payments once the shutdown started
+		assert!(a.manager.send_payment(&channel_id, 1000000, sha256(&[2; 32]), 500).is_err());
+
+		let (b_shutdown, b_closing_signed) = b.manager.handle_shutdown(&a.node_id, &a_shutdown).unwrap();
+		// Only the funder proposes a fee
+		assert!(b_closing_signed.is_none());
+		let (a_shutdown, a_closing_signed) = a.manager.handle_shutdown(&b.node_id, &b_shutdown.unwrap()).unwrap();
+		assert!(a_shutdown.is_none());
+
+		let b_closing_signed = b.manager.handle_closing_signed(&a.node_id, &a_closing_signed.unwrap()).unwrap();
+		let b_closing_tx = get_broadcast_transaction(&b);
+		assert!(a.manager.handle_closing_signed(&b.node_id, &b_closing_signed.unwrap()).unwrap().is_none());
+		let a_closing_tx = get_broadcast_transaction(&a);
+
+		assert!(a_closing_tx == b_closing_tx);
+		assert_eq!(a_closing_tx.output.len(), 2);
+		let total_fee = FEERATE_PER_KW * 724 / 1000;
+		for output in a_closing_tx.output.iter() {
+			if output.script_pubkey == a.shutdown_script {
+				assert_eq!(output.value, 90000 - total_fee);
+			} else {
+				assert!(output.script_pubkey == b.shutdown_script);
+				assert_eq!(output.value, 10000);
+			}
+		}
+		assert!(a.manager.list_channels().is_empty());
+		assert!(b.manager.list_channels().is_empty());
+	}
+
+	#[test]
+	fn test_channel_close_fee_negotiation() {
+		// b estimates a higher fee than a, the funder
+		let secp_ctx = Secp256k1::new();
+		let (a, b) = (create_node(&secp_ctx, 1, FEERATE_PER_KW), create_node(&secp_ctx, 2, FEERATE_PER_KW * 2 - 50));
+		let channel_id = create_chan_between_nodes(&a, &b, 100000, 0);
+
+		a.manager.close_channel(&channel_id).unwrap();
+		let a_shutdown = match get_single_event(&a) {
+			Event::SendShutdown { msg, .. } => msg,
+			_ => panic!("Unexpected event"),
+		};
+		let (b_shutdown, _) = b.manager.handle_shutdown(&a.node_id, &a_shutdown).unwrap();
+		let (_, a_closing_signed) = a.manager.handle_shutdown(&b.node_id, &b_shutdown.unwrap()).unwrap();
+		let a_closing_signed = a_closing_signed.unwrap();
+		assert_eq!(a_closing_signed.fee_satoshis, FEERATE_PER_KW * 724 / 1000);
+
+		// b counters with its own fee instead of closing
+		let b_closing_signed = b.manager.handle_closing_signed(&a.node_id, &a_closing_signed).unwrap().unwrap();
+		let b_fee = (FEERATE_PER_KW * 2 - 50) * 724 / 1000;
+		assert_eq!(b_closing_signed.fee_satoshis, b_fee);
+		assert!(b.manager.get_and_clear_pending_events().is_empty());
+		assert_eq!(b.manager.list_channels().len(), 1);
+
+		// It's within twice a's fee, so a takes it and b closes on a's matching closing_signed
+		let a_closing_signed = a.manager.handle_closing_signed(&b.node_id, &b_closing_signed).unwrap().unwrap();
+		assert_eq!(a_closing_signed.fee_satoshis, b_fee);
+		let a_closing_tx = get_broadcast_transaction(&a);
+		assert!(b.manager.handle_closing_signed(&a.node_id, &a_closing_signed).unwrap().is_none());
+		let b_closing_tx = get_broadcast_transaction(&b);
+
+		assert!(a_closing_tx == b_closing_tx);
+		for output in a_closing_tx.output.iter() {
+			assert!(output.script_pubkey == a.shutdown_script);
+			assert_eq!(output.value, 100000 - b_fee);
+		}
+		assert!(a.manager.list_channels().is_empty());
+		assert!(b.manager.list_channels().is_empty());
+	}
+
+	#[test]
+	fn test_channel_close_fee_out_of_range() {
+		// b estimates a higher fee than a, the funder
+		let secp_ctx = Secp256k1::new();
+		let (a, b) = (create_node(&secp_ctx, 1, FEERATE_PER_KW), create_node(&secp_ctx, 2, FEERATE_PER_KW * 2 - 50));
+		let channel_id = create_chan_between_nodes(&a, &b, 100000, 0);
+
+		a.manager.close_channel(&channel_id).unwrap();
+		let a_shutdown = match get_single_event(&a) {
+			Event::SendShutdown { msg, .. } => msg,
+			_ => panic!("Unexpected event"),
+		};
+		let (b_shutdown, _) = b.manager.handle_shutdown(&a.node_id, &a_shutdown).unwrap();
+		let (_, a_closing_signed) = a.manager.handle_shutdown(&b.node_id, &b_shutdown.unwrap()).unwrap();
+		let a_closing_signed = a_closing_signed.unwrap();
+		let a_fee = FEERATE_PER_KW * 724 / 1000;
+		let b_fee = (FEERATE_PER_KW * 2 - 50) * 724 / 1000;
+		let b_closing_signed = b.manager.handle_closing_signed(&a.node_id, &a_closing_signed).unwrap().unwrap();
+		assert_eq!(b_closing_signed.fee_satoshis, b_fee);
+
+		// Any counter-offer of a has to be strictly between its first fee and b's one
+		for &fee_satoshis in [a_fee, a_fee - 1, b_fee + 1].iter() {
+			let closing_signed = msgs::ClosingSigned {
+				channel_id: channel_id,
+				fee_satoshis: fee_satoshis,
+				signature: a_closing_signed.signature.clone(),
+			};
+			match b.manager.handle_closing_signed(&a.node_id, &closing_signed) {
+				Err(HandleError { action: Some(ErrorAction::DisconnectPeer { msg: Some(_) }), .. }) => {},
+				_ => panic!("Out of range closing fee wasn't rejected"),
+			}
+		}
+		// The connection is dropped, but the channel isn't failed
+		assert!(b.manager.get_and_clear_pending_events().is_empty());
+		assert_eq!(b.manager.list_channels().len(), 1);
+
+		// Agreeing on b's fee still closes the channel
+		let a_closing_signed = a.manager.handle_closing_signed(&b.node_id, &b_closing_signed).unwrap().unwrap();
+		assert_eq!(a_closing_signed.fee_satoshis, b_fee);
+		get_broadcast_transaction(&a);
+		assert!(b.manager.handle_closing_signed(&a.node_id, &a_closing_signed).unwrap().is_none());
+		get_broadcast_transaction(&b);
+		assert!(b.manager.list_channels().is_empty());
+	}
+
+	#[test]
+	fn test_funding_coins_locked() {
+		let (a, b) = create_network();
+
+		// a's wallet has a single coin, so only one of two channels opened at once gets funded
+		let mut accept_channels = Vec::new();
+		for _ in 0..2 {
+			a.manager.create_channel(b.node_id, 100000, 0).unwrap();
+			match get_single_event(&a) {
+				Event::SendOpenChannel { msg, .. } => accept_channels.push(b.manager.handle_open_channel(&a.node_id, &msg).unwrap()),
+				_ => panic!("Unexpected event"),
+			}
+		}
+		a.manager.handle_accept_channel(&b.node_id, &accept_channels[0]).unwrap();
+		match get_single_event(&a) {
+			Event::SendFundingCreated { .. } => {},
+			_ => panic!("Unexpected event"),
+		}
+		assert!(a.manager.handle_accept_channel(&b.node_id, &accept_channels[1]).is_err());
+		assert!(a.manager.get_and_clear_pending_events().is_empty());
+		assert_eq!(a.manager.list_channels().len(), 1);
+
+		// The coin is unlocked once the funded channel is dropped before its funding went out
+		a.manager.peer_disconnected(&b.node_id, false);
+		b.manager.peer_disconnected(&a.node_id, false);
+		assert!(a.manager.list_channels().is_empty());
+		assert!(a.manager.get_and_clear_pending_events().is_empty());
+		create_chan_between_nodes(&a, &b, 100000, 0);
+	}
+
+	#[test]
+	fn test_channel_error_message() {
+		let (a, b) = create_network();
+		let channel_id = create_chan_between_nodes(&a, &b, 100000, 0);
+
+		// Errors about unknown channels or from another node are ignored
+		let error = msgs::ErrorMessage { channel_id: channel_id, data: "Channel failed".to_string() };
+		b.manager.handle_error(&b.node_id, &error);
+		b.manager.handle_error(&a.node_id, &msgs::ErrorMessage { channel_id: [0; 32], data: String::new() });
+		assert_eq!(b.manager.list_channels().len(), 1);
+		assert!(b.manager.get_and_clear_pending_events().is_empty());
+
+		// Otherwise the channel is failed and our commitment transaction goes out
+		b.manager.handle_error(&a.node_id, &error);
+		assert!(b.manager.list_channels().is_empty());
+		let commitment_tx = get_broadcast_transaction(&b);
+		assert_eq!(commitment_tx.input.len(), 1);
+		assert_eq!(commitment_tx.input[0].witness.len(), 4);
+		assert_eq!(a.manager.list_channels().len(), 1);
+	}
+
+	#[test]
+	fn test_peer_disconnected() {
+		let (a, b) = create_network();
+		let channel_id = create_chan_between_nodes(&a, &b, 100000, 0);
+
+		// Messages for the channel must come from its counterparty
+		assert!(a.manager.close_channel(&[0; 32]).is_err());
+		let update_fee = msgs::UpdateFee { channel_id: channel_id, feerate_per_kw: 1000 };
+		assert!(b.manager.handle_update_fee(&b.node_id, &update_fee).is_err());
+		assert_eq!(b.manager.list_channels().len(), 1);
+
+		// Unfunded channels are dropped on disconnection, funded ones are kept
+		a.manager.create_channel(b.node_id, 100000, 0).unwrap();
+		a.manager.get_and_clear_pending_events();
+		assert_eq!(a.manager.list_channels().len(), 2);
+		a.manager.peer_disconnected(&b.node_id, false);
+		assert_eq!(a.manager.list_channels().len(), 1);
+		assert!(a.manager.get_and_clear_pending_events().is_empty());
+
+		// Unless no connection is possible, then our commitment transaction goes out
+		a.manager.peer_disconnected(&b.node_id, true);
+		assert!(a.manager.list_channels().is_empty());
+		let commitment_tx = get_broadcast_transaction(&a);
+		assert_eq!(commitment_tx.input.len(), 1);
+		assert_eq!(commitment_tx.input[0].witness.len(), 4);
+
+		// A feerate update is a protocol violation for us, so it fails the channel too
+		assert!(b.manager.handle_update_fee(&a.node_id, &update_fee).is_err());
+		assert!(b.manager.list_channels().is_empty());
+		get_broadcast_transaction(&b);
+	}
+}
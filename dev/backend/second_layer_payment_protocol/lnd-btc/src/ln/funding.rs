@@ -0,0 +1,364 @@
+//! Construction of the funding transaction which opens a channel.
+//!
+//! The funding output is the BOLT #3 2-of-2 P2WSH multisig of both funding pubkeys. Inputs are
+//! selected from a WalletSource largest first, the change goes back to the wallet and everything
+//! is signed with RFC6979 signatures, so the same wallet state always gives the same transaction.
+
+use bitcoin::blockdata::opcodes;
+use bitcoin::blockdata::script::{Script,Builder};
+use bitcoin::blockdata::transaction::{TxIn,TxOut,Transaction,SigHashType};
+use bitcoin::util::bip143;
+use bitcoin::util::hash::{Sha256dHash,Hash160};
+
+use secp256k1::{Secp256k1,Message};
+use secp256k1::key::{SecretKey,PublicKey};
+
+use std::error::Error;
+use std::fmt;
+
+/// Outputs below this value are non-standard and wouldn't be relayed.
+pub const DUST_LIMIT_SATOSHIS: u64 = 546;
+
+// Weights used for fee estimation. Input and output counts are assumed to fit in one byte, and
+// signatures are counted as 72 bytes with the sighash type, as ECDSA signatures almost always are.
+const TX_BASE_WEIGHT: u64 = 4 * (4 + 1 + 1 + 4); // version, input count, output count, lock_time
+const SEGWIT_MARKER_WEIGHT: u64 = 2;
+const P2PKH_INPUT_WEIGHT: u64 = 4 * (32 + 4 + 1 + 1 + 72 + 1 + 33 + 4);
+const P2WPKH_INPUT_WEIGHT: u64 = 4 * (32 + 4 + 1 + 4) + (1 + 1 + 72 + 1 + 33);
+const FUNDING_OUTPUT_WEIGHT: u64 = 4 * (8 + 1 + 34);
+
+/// Script types of the wallet outputs which can be spent into a funding transaction.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SpendableKind {
+	P2PKH,
+	P2WPKH,
+}
+
+/// An unspent wallet output.
+#[derive(Clone)]
+pub struct Utxo {
+	pub prev_hash: Sha256dHash,
+	pub prev_index: u32,
+	pub value: u64,
+	pub kind: SpendableKind,
+	/// Key which the output pays to
+	pub secret_key: SecretKey,
+}
+
+/// A wallet which provides the coins for funding transactions.
+pub trait WalletSource : Sync + Send {
+	/// Returns the outputs which may be spent, leaving out the locked ones.
+	fn list_unspent(&self) -> Vec<Utxo>;
+	/// Returns the script which receives the change of a funding transaction.
+	fn get_change_script(&self) -> Script;
+	/// Locks the outputs spent by a funding transaction which isn't broadcast yet, so that they
+	/// aren't selected again by another channel opened in the meantime. The locks may be kept
+	/// once the transaction is broadcast, as the outputs are spent then anyway.
+	fn lock_unspent(&self, outpoints: &[(Sha256dHash, u32)]);
+	/// Unlocks the outputs of a funding transaction which will never be broadcast.
+	fn unlock_unspent(&self, outpoints: &[(Sha256dHash, u32)]);
+}
+
+/// A signed funding transaction, ready for funding_created and broadcast once funding_signed
+/// arrives.
+pub struct FundingTransaction {
+	pub transaction: Transaction,
+	/// Index of the 2-of-2 output in the transaction
+	pub output_index: u16,
+	/// Fee paid by the transaction, including change which was too small to be worth an output
+	pub fee: u64,
+}
+
+#[derive(Debug)]
+pub enum FundingError {
+	/// The wallet can't pay for the funding output and the fee
+	InsufficientFunds,
+	/// The funding output would be below the dust limit
+	DustFundingOutput,
+}
+
+impl Error for FundingError {
+	fn description(&self) -> &str {
+		match *self {
+			FundingError::InsufficientFunds => "Wallet can't pay for the funding output and the fee",
+			FundingError::DustFundingOutput => "Funding output is below the dust limit",
+		}
+	}
+}
+impl fmt::Display for FundingError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(self.description())
+	}
+}
+
+/// Gets the witness script of the funding output. The keys are sorted as BOLT #3 requires, so the
+/// argument order doesn't matter.
+pub fn get_funding_redeemscript(local_funding_pubkey: &PublicKey, remote_funding_pubkey: &PublicKey) -> Script {
+	let local_funding_key = local_funding_pubkey.serialize();
+	let remote_funding_key = remote_funding_pubkey.serialize();
+	let builder = Builder::new().push_opcode(opcodes::All::OP_PUSHNUM_2);
+	let builder = if local_funding_key[..] < remote_funding_key[..] {
+		builder.push_slice(&local_funding_key).push_slice(&remote_funding_key)
+	} else {
+		builder.push_slice(&remote_funding_key).push_slice(&local_funding_key)
+	};
+	builder.push_opcode(opcodes::All::OP_PUSHNUM_2).push_opcode(opcodes::All::OP_CHECKMULTISIG).into_script()
+}
+
+/// Gets the P2PKH script paying to the given key.
+pub fn get_p2pkh_script(pubkey: &PublicKey) -> Script {
+	Builder::new().push_opcode(opcodes::All::OP_DUP)
+	              .push_opcode(opcodes::All::OP_HASH160)
+	              .push_slice(&Hash160::from_data(&pubkey.serialize()[..])[..])
+	              .push_opcode(opcodes::All::OP_EQUALVERIFY)
+	              .push_opcode(opcodes::All::OP_CHECKSIG)
+	              .into_script()
+}
+
+/// Gets the P2WPKH script paying to the given key.
+pub fn get_p2wpkh_script(pubkey: &PublicKey) -> Script {
+	Builder::new().push_opcode(opcodes::All::OP_PUSHBYTES_0)
+	              .push_slice(&Hash160::from_data(&pubkey.serialize()[..])[..])
+	              .into_script()
+}
+
+/// Gets the outputs spent by the transaction, to lock or unlock them in the wallet.
+pub fn get_spent_outpoints(tx: &Transaction) -> Vec<(Sha256dHash, u32)> {
+	tx.input.iter().map(|input| (input.prev_hash.clone(), input.prev_index)).collect()
+}
+
+fn fee_for_weight(feerate_per_kw: u64, weight: u64) -> u64 {
+	feerate_per_kw * weight / 1000
+}
+
+fn sign_input(secp_ctx: &Secp256k1, sighash: &Sha256dHash, secret_key: &SecretKey) -> Vec<u8> {
+	let msg = Message::from_slice(&sighash[..]).unwrap();
+	let mut sig = secp_ctx.sign(&msg, secret_key).unwrap().serialize_der(secp_ctx);
+	sig.push(SigHashType::All as u8);
+	sig
+}
+
+/// Builds and signs a transaction paying funding_satoshis into the 2-of-2 output of the given
+/// funding pubkeys, paying feerate_per_kw from the wallet's coins.
+/// The funding output is the first output, the change (if it isn't dust) is the second one.
+pub fn build_funding_transaction<W: WalletSource + ?Sized>(secp_ctx: &Secp256k1, wallet: &W, local_funding_pubkey: &PublicKey, remote_funding_pubkey: &PublicKey, funding_satoshis: u64, feerate_per_kw: u64) -> Result<FundingTransaction, FundingError> {
+	if funding_satoshis < DUST_LIMIT_SATOSHIS {
+		return Err(FundingError::DustFundingOutput);
+	}
+
+	let change_script = wallet.get_change_script();
+	let change_output_weight = 4 * (8 + 1 + change_script.len() as u64);
+
+	let mut utxos = wallet.list_unspent();
+	utxos.sort_by(|a, b| {
+		b.value.cmp(&a.value)
+			.then_with(|| a.prev_hash[..].cmp(&b.prev_hash[..]))
+			.then_with(|| a.prev_index.cmp(&b.prev_index))
+	});
+
+	let mut selected = Vec::new();
+	let mut total_in = 0;
+	let mut weight = TX_BASE_WEIGHT + FUNDING_OUTPUT_WEIGHT;
+	let mut has_witness = false;
+	let mut with_change = None;
+	for utxo in utxos {
+		total_in += utxo.value;
+		weight += match utxo.kind {
+			SpendableKind::P2PKH => P2PKH_INPUT_WEIGHT,
+			SpendableKind::P2WPKH => P2WPKH_INPUT_WEIGHT,
+		};
+		if utxo.kind == SpendableKind::P2WPKH && !has_witness {
+			has_witness = true;
+			weight += SEGWIT_MARKER_WEIGHT;
+		}
+		selected.push(utxo);
+
+		// Stop as soon as the coins pay for the funding output and the fee. The change gets an
+		// output only if it isn't dust, otherwise it goes to the fee.
+		if total_in >= funding_satoshis + fee_for_weight(feerate_per_kw, weight) {
+			let fee_with_change = fee_for_weight(feerate_per_kw, weight + change_output_weight);
+			with_change = Some(total_in >= funding_satoshis + fee_with_change + DUST_LIMIT_SATOSHIS);
+			break;
+		}
+	}
+
+	let with_change = match with_change {
+		Some(with_change) => with_change,
+		None => return Err(FundingError::InsufficientFunds),
+	};
+
+	let mut output = vec![TxOut {
+		value: funding_satoshis,
+		script_pubkey: get_funding_redeemscript(local_funding_pubkey, remote_funding_pubkey).to_v0_p2wsh(),
+	}];
+	let fee = if with_change {
+		let fee = fee_for_weight(feerate_per_kw, weight + change_output_weight);
+		output.push(TxOut {
+			value: total_in - funding_satoshis - fee,
+			script_pubkey: change_script,
+		});
+		fee
+	} else {
+		total_in - funding_satoshis
+	};
+
+	let input = selected.iter().map(|utxo| {
+		TxIn {
+			prev_hash: utxo.prev_hash.clone(),
+			prev_index: utxo.prev_index,
+			script_sig: Script::new(),
+			sequence: 0xffffffff,
+			witness: Vec::new(),
+		}
+	}).collect();
+
+	let mut tx = Transaction {
+		version: 2,
+		lock_time: 0,
+		input: input,
+		output: output,
+	};
+
+	let sighash_components = bip143::SighashComponents::new(&tx);
+	for (idx, utxo) in selected.iter().enumerate() {
+		let pubkey = PublicKey::from_secret_key(secp_ctx, &utxo.secret_key).unwrap();
+		let script_code = get_p2pkh_script(&pubkey);
+		match utxo.kind {
+			SpendableKind::P2PKH => {
+				let sighash = tx.signature_hash(idx, &script_code, SigHashType::All as u32);
+				let sig = sign_input(secp_ctx, &sighash, &utxo.secret_key);
+				tx.input[idx].script_sig = Builder::new().push_slice(&sig[..]).push_slice(&pubkey.serialize()[..]).into_script();
+			},
+			SpendableKind::P2WPKH => {
+				let sighash = sighash_components.sighash_all(&tx, idx, &script_code, utxo.value);
+				let sig = sign_input(secp_ctx, &sighash, &utxo.secret_key);
+				tx.input[idx].witness = vec![sig, pubkey.serialize().to_vec()];
+			},
+		}
+	}
+
+	Ok(FundingTransaction {
+		transaction: tx,
+		output_index: 0,
+		fee: fee,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use bitcoin::blockdata::script::Script;
+	use bitcoin::blockdata::transaction::Transaction;
+	use bitcoin::network::serialize::{deserialize,serialize,BitcoinHash};
+	use bitcoin::util::hash::Sha256dHash;
+
+	use hex;
+	use ln::funding::{build_funding_transaction, get_funding_redeemscript, get_p2wpkh_script, FundingError, SpendableKind, Utxo, WalletSource};
+
+	use secp256k1::Secp256k1;
+	use secp256k1::key::{SecretKey,PublicKey};
+
+	struct TestWallet {
+		utxos: Vec<Utxo>,
+		change_script: Script,
+	}
+	impl WalletSource for TestWallet {
+		fn list_unspent(&self) -> Vec<Utxo> {
+			self.utxos.clone()
+		}
+		fn get_change_script(&self) -> Script {
+			self.change_script.clone()
+		}
+		fn lock_unspent(&self, _outpoints: &[(Sha256dHash, u32)]) {}
+		fn unlock_unspent(&self, _outpoints: &[(Sha256dHash, u32)]) {}
+	}
+
+	fn pubkey_from_hex(secp_ctx: &Secp256k1, hex: &str) -> PublicKey {
+		PublicKey::from_slice(secp_ctx, &hex::decode(hex).unwrap()[..]).unwrap()
+	}
+
+	/// The wallet of the BOLT #3 funding test vectors: the block 1 coinbase output, with the
+	/// change going to the P2WPKH of the same key.
+	fn bolt3_wallet(secp_ctx: &Secp256k1) -> TestWallet {
+		let coinbase: Transaction = deserialize(&hex::decode("01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff03510101ffffffff0100f2052a010000001976a9143ca33c2e4446f4a305f23c80df8ad1afdcf652f988ac00000000").unwrap()[..]).unwrap();
+		let secret_key = SecretKey::from_slice(secp_ctx, &hex::decode("6bd078650fcee8444e4e09825227b801a1ca928debb750eb36e6d56124bb20e8").unwrap()[..]).unwrap();
+		let pubkey = PublicKey::from_secret_key(secp_ctx, &secret_key).unwrap();
+		TestWallet {
+			utxos: vec![Utxo {
+				prev_hash: coinbase.bitcoin_hash(),
+				prev_index: 0,
+				value: coinbase.output[0].value,
+				kind: SpendableKind::P2PKH,
+				secret_key: secret_key,
+			}],
+			change_script: get_p2wpkh_script(&pubkey),
+		}
+	}
+
+	#[test]
+	fn test_bolt3_funding_vectors() {
+		let secp_ctx = Secp256k1::new();
+		let local_funding_pubkey = pubkey_from_hex(&secp_ctx, "023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb");
+		let remote_funding_pubkey = pubkey_from_hex(&secp_ctx, "030e9f7b623d2ccc7c9bd44d66d5ce21ce504c0acf6385a132cec6d3c39fa711c1");
+
+		let witness_script = "5221023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb21030e9f7b623d2ccc7c9bd44d66d5ce21ce504c0acf6385a132cec6d3c39fa711c152ae";
+		assert_eq!(hex::encode(&get_funding_redeemscript(&local_funding_pubkey, &remote_funding_pubkey)[..]), witness_script);
+		assert_eq!(hex::encode(&get_funding_redeemscript(&remote_funding_pubkey, &local_funding_pubkey)[..]), witness_script);
+
+		let wallet = bolt3_wallet(&secp_ctx);
+		let funding = build_funding_transaction(&secp_ctx, &wallet, &local_funding_pubkey, &remote_funding_pubkey, 10000000, 15000).unwrap();
+		assert_eq!(funding.output_index, 0);
+		assert_eq!(funding.fee, 13920);
+		assert_eq!(funding.transaction.output[1].value, 4989986080);
+		assert_eq!(hex::encode(serialize(&funding.transaction).unwrap()), "0200000001adbb20ea41a8423ea937e76e8151636bf6093b70eaff942930d20576600521fd000000006b48304502210090587b6201e166ad6af0227d3036a9454223d49a1f11839c1a362184340ef0240220577f7cd5cca78719405cbf1de7414ac027f0239ef6e214c90fcaab0454d84b3b012103535b32d5eb0a6ed0982a0479bbadc9868d9836f6ba94dd5a63be16d875069184ffffffff028096980000000000220020c015c4a6be010e21657068fc2e6a9d02b27ebe4d490a25846f7237f104d1a3cd20256d29010000001600143ca33c2e4446f4a305f23c80df8ad1afdcf652f900000000");
+		assert_eq!(funding.transaction.bitcoin_hash().be_hex_string(), "8984484a580b825b9972d7adb15050b3ab624ccd731946b3eeddb92f4e7ef6be");
+
+		// Same wallet state, same transaction
+		let again = build_funding_transaction(&secp_ctx, &wallet, &local_funding_pubkey, &remote_funding_pubkey, 10000000, 15000).unwrap();
+		assert_eq!(serialize(&again.transaction).unwrap(), serialize(&funding.transaction).unwrap());
+	}
+
+	#[test]
+	fn test_coin_selection() {
+		let secp_ctx = Secp256k1::new();
+		let local_funding_pubkey = pubkey_from_hex(&secp_ctx, "023da092f6980e58d2c037173180e9a465476026ee50f96695963e8efe436f54eb");
+		let remote_funding_pubkey = pubkey_from_hex(&secp_ctx, "030e9f7b623d2ccc7c9bd44d66d5ce21ce504c0acf6385a132cec6d3c39fa711c1");
+		let secret_key = SecretKey::from_slice(&secp_ctx, &[0x11; 32]).unwrap();
+		let pubkey = PublicKey::from_secret_key(&secp_ctx, &secret_key).unwrap();
+		let utxo = |n: u8, value: u64| Utxo {
+			prev_hash: Sha256dHash::from_data(&[n]),
+			prev_index: n as u32,
+			value: value,
+			kind: SpendableKind::P2WPKH,
+			secret_key: secret_key.clone(),
+		};
+		let wallet = TestWallet {
+			utxos: vec![utxo(1, 50000), utxo(2, 2000000), utxo(3, 1000000)],
+			change_script: get_p2wpkh_script(&pubkey),
+		};
+
+		// Largest coins first, only as many as needed
+		let funding = build_funding_transaction(&secp_ctx, &wallet, &local_funding_pubkey, &remote_funding_pubkey, 2500000, 1000).unwrap();
+		let tx = &funding.transaction;
+		assert_eq!(tx.input.len(), 2);
+		assert_eq!(tx.input[0].prev_index, 2);
+		assert_eq!(tx.input[1].prev_index, 3);
+		assert!(tx.input.iter().all(|input| input.script_sig[..].is_empty() && input.witness.len() == 2));
+		assert_eq!(tx.output.len(), 2);
+		assert_eq!(tx.output[1].value + funding.fee + 2500000, 3000000);
+
+		// Leftover below the dust limit goes to the fee
+		let fee = funding.fee - 4 * (8 + 1 + 22); // the same inputs without the change output
+		let funding = build_funding_transaction(&secp_ctx, &wallet, &local_funding_pubkey, &remote_funding_pubkey, 3000000 - fee - 100, 1000).unwrap();
+		assert_eq!(funding.transaction.output.len(), 1);
+		assert_eq!(funding.fee, fee + 100);
+
+		match build_funding_transaction(&secp_ctx, &wallet, &local_funding_pubkey, &remote_funding_pubkey, 3050000, 1000) {
+			Err(FundingError::InsufficientFunds) => {},
+			_ => panic!("Funding transaction built without enough coins"),
+		}
+		match build_funding_transaction(&secp_ctx, &wallet, &local_funding_pubkey, &remote_funding_pubkey, 500, 1000) {
+			Err(FundingError::DustFundingOutput) => {},
+			_ => panic!("Funding transaction built with a dust output"),
+		}
+	}
+}
@@ -0,0 +1,9 @@
+pub mod chan_utils;
+pub mod channel;
+pub mod channelmanager;
+pub mod funding;
+pub mod msgs;
+pub mod peer_channel_encryptor;
+pub mod peer_handler;
+pub mod peer_socket;
+pub mod wire;
@@ -41,6 +41,8 @@ pub enum DecodeError {
 	/// A length descriptor in the packet didn't describe the later data correctly
 	/// (currently only generated in node_announcement)
 	BadLengthDescriptor,
+	/// Message type is even and unknown to us, so the peer requires something we don't support
+	UnknownRequiredMessage,
 }
 
 pub trait MsgDecodable: Sized {
@@ -452,6 +454,9 @@ pub trait ChannelMessageHandler : events::EventsProvider + Send + Sync {
 	/// understand or indicate they require unknown feature bits), no_connection_possible is set
 	/// and any outstanding channels should be failed.
 	fn peer_disconnected(&self, their_node_id: &PublicKey, no_connection_possible: bool);
+	/// Handles an error message the peer sent about one of our channels with it, which must be
+	/// failed. Errors about all channels (an all-zero channel_id) go through peer_disconnected.
+	fn handle_error(&self, their_node_id: &PublicKey, msg: &ErrorMessage);
 }
 
 pub trait RoutingMessageHandler : Send + Sync {
@@ -508,6 +513,7 @@ impl Error for DecodeError {
 			DecodeError::ShortRead => "Packet extended beyond the provided bytes",
 			DecodeError::ExtraAddressesPerType => "More than one address of a single type",
 			DecodeError::BadLengthDescriptor => "A length descriptor in the packet didn't describe the later data correctly",
+			DecodeError::UnknownRequiredMessage => "Unknown message type with an even type number",
 		}
 	}
 }
@@ -629,9 +635,10 @@ impl MsgDecodable for Ping {
 
 impl MsgEncodable for Ping {
 	fn encode(&self) -> Vec<u8> {
-		let mut res = Vec::with_capacity(self.byteslen as usize + 2);
+		let mut res = Vec::with_capacity(self.byteslen as usize + 4);
+		res.extend_from_slice(&byte_utils::be16_to_array(self.ponglen));
 		res.extend_from_slice(&byte_utils::be16_to_array(self.byteslen));
-		res.resize(2 + self.byteslen as usize, 0);
+		res.resize(4 + self.byteslen as usize, 0);
 		res
 	}
 }
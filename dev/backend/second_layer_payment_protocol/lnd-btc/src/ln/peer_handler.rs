@@ -0,0 +1,922 @@
+use secp256k1::key::{SecretKey,PublicKey};
+
+use ln::msgs;
+use ln::peer_channel_encryptor::{PeerChannelEncryptor,NextNoiseStep};
+use ln::wire;
+use util::events::{EventsProvider,Event};
+
+use std::collections::{HashMap,LinkedList};
+use std::sync::{Arc, Mutex};
+use std::{cmp,error,hash,fmt,mem};
+
+/// Provides references to trait impls which handle different types of messages.
+pub struct MessageHandler {
+	pub chan_handler: Arc<msgs::ChannelMessageHandler>,
+	pub route_handler: Arc<msgs::RoutingMessageHandler>,
+}
+
+/// Provides an object which can be used to send data to and which uniquely identifies a connection
+/// to a remote host. You will need to be able to generate multiple of these which meet Eq and
+/// implement Hash to meet the PeerManager API.
+/// For efficiency, Clone should be relatively cheap for this type.
+pub trait SocketDescriptor : cmp::Eq + hash::Hash + Clone {
+	/// Attempts to send some data from the given Vec starting at the given offset to the peer.
+	/// Returns the amount of data which was sent, possibly 0 if the socket has since disconnected.
+	/// Note that in the disconnected case, a disconnect_event must still fire and further write
+	/// attempts may occur until that time.
+	/// If the returned size is smaller than data.len() - write_offset, a write_event must trigger
+	/// the next time more data can be written.
+	/// If a read_event on this descriptor had previously returned true (indicating that read
+	/// events should be paused to prevent DoS in the send buffer), resume_read may be set
+	/// indicating that read events on this descriptor should resume. A resume_read of false does
+	/// *not* imply that further read events should be paused.
+	fn send_data(&mut self, data: &Vec<u8>, write_offset: usize, resume_read: bool) -> usize;
+	/// Disconnect the socket pointed to by this SocketDescriptor. Once this function returns, no
+	/// more calls to write_event, read_event or disconnect_event may be made with this descriptor.
+	/// No disconnect_event should be generated as a result of this call, though races may occur
+	/// whereby disconnect_socket is called after a call to disconnect_event but prior to that
+	/// event completing.
+	fn disconnect_socket(&mut self);
+}
+
+/// Error for PeerManager errors. If you get one of these, you must disconnect the socket and
+/// generate no further read/write_events for the descriptor, only triggering a single
+/// disconnect_event (unless it was provided in response to a new_*_connection event, in which case
+/// no such disconnect_event must be generated and the socket be silently disconencted).
+pub struct PeerHandleError {
+	no_connection_possible: bool,
+}
+impl fmt::Debug for PeerHandleError {
+	fn fmt(&self, formatter: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		formatter.write_str("Peer Sent Invalid Data")
+	}
+}
+impl fmt::Display for PeerHandleError {
+	fn fmt(&self, formatter: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		formatter.write_str("Peer Sent Invalid Data")
+	}
+}
+impl error::Error for PeerHandleError {
+	fn description(&self) -> &str {
+		"Peer Sent Invalid Data"
+	}
+}
+
+struct Peer {
+	channel_encryptor: PeerChannelEncryptor,
+	outbound: bool,
+	their_node_id: Option<PublicKey>,
+	their_global_features: Option<msgs::GlobalFeatures>,
+	their_local_features: Option<msgs::LocalFeatures>,
+
+	pending_outbound_buffer: LinkedList<Vec<u8>>,
+	pending_outbound_buffer_first_msg_offset: usize,
+	awaiting_write_event: bool,
+
+	pending_read_buffer: Vec<u8>,
+	pending_read_buffer_pos: usize,
+	pending_read_is_header: bool,
+
+	awaiting_pong: bool,
+}
+
+struct PeerHolder<Descriptor: SocketDescriptor> {
+	peers: HashMap<Descriptor, Peer>,
+	/// Only add to this set when noise completes:
+	node_id_to_descriptor: HashMap<PublicKey, Descriptor>,
+}
+struct MutPeerHolder<'a, Descriptor: SocketDescriptor + 'a> {
+	peers: &'a mut HashMap<Descriptor, Peer>,
+	node_id_to_descriptor: &'a mut HashMap<PublicKey, Descriptor>,
+}
+impl<Descriptor: SocketDescriptor> PeerHolder<Descriptor> {
+	fn borrow_parts(&mut self) -> MutPeerHolder<Descriptor> {
+		MutPeerHolder {
+			peers: &mut self.peers,
+			node_id_to_descriptor: &mut self.node_id_to_descriptor,
+		}
+	}
+}
+
+/// A PeerManager manages a set of peers, described by their SocketDescriptor and marshalls socket
+/// events into messages which it passes on to its MessageHandlers.
+pub struct PeerManager<Descriptor: SocketDescriptor> {
+	message_handler: MessageHandler,
+	peers: Mutex<PeerHolder<Descriptor>>,
+	pending_events: Mutex<Vec<Event>>,
+	our_node_secret: SecretKey,
+}
+
+/// Manages and reacts to connection events. You probably want to use file descriptors as PeerIds.
+/// PeerIds may repeat, but only after disconnect_event() has been called.
+impl<Descriptor: SocketDescriptor> PeerManager<Descriptor> {
+	/// Constructs a new PeerManager with the given message handlers and node_id secret key
+	pub fn new(message_handler: MessageHandler, our_node_secret: SecretKey) -> PeerManager<Descriptor> {
+		PeerManager {
+			message_handler: message_handler,
+			peers: Mutex::new(PeerHolder { peers: HashMap::new(), node_id_to_descriptor: HashMap::new() }),
+			pending_events: Mutex::new(Vec::new()),
+			our_node_secret: our_node_secret,
+		}
+	}
+
+	/// Get the list of node ids for peers which have completed the initial handshake.
+	/// For outbound connections, this will be the same as the their_node_id parameter passed in to
+	/// new_outbound_connection, however entries will only appear once the initial handshake has
+	/// completed and we are sure the remote peer has the private key for the given node_id.
+	pub fn get_peer_node_ids(&self) -> Vec<PublicKey> {
+		let peers = self.peers.lock().unwrap();
+		peers.peers.values().filter_map(|p| {
+			if p.their_global_features.is_none() {
+				return None;
+			}
+			p.their_node_id
+		}).collect()
+	}
+
+	/// Indicates a new outbound connection has been established to a node with the given node_id.
+	/// Note that if an Err is returned here you MUST NOT call disconnect_event for the new
+	/// descriptor but must disconnect the connection immediately.
+	/// Returns some bytes to send to the remote node.
+	/// Panics if descriptor is duplicative with some other descriptor which has not yet has a
+	/// disconnect_event.
+	pub fn new_outbound_connection(&self, their_node_id: PublicKey, descriptor: Descriptor) -> Result<Vec<u8>, PeerHandleError> {
+		let mut peer_encryptor = PeerChannelEncryptor::new_outbound(their_node_id.clone());
+		let res = peer_encryptor.get_act_one().to_vec();
+		let pending_read_buffer = [0; 50].to_vec(); // Noise act two is 50 bytes
+
+		let mut peers = self.peers.lock().unwrap();
+		if peers.peers.insert(descriptor, Peer {
+			channel_encryptor: peer_encryptor,
+			outbound: true,
+			their_node_id: Some(their_node_id),
+			their_global_features: None,
+			their_local_features: None,
+
+			pending_outbound_buffer: LinkedList::new(),
+			pending_outbound_buffer_first_msg_offset: 0,
+			awaiting_write_event: false,
+
+			pending_read_buffer: pending_read_buffer,
+			pending_read_buffer_pos: 0,
+			pending_read_is_header: false,
+
+			awaiting_pong: false,
+		}).is_some() {
+			panic!("PeerManager driver duplicated descriptors!");
+		};
+		Ok(res)
+	}
+
+	/// Indicates a new inbound connection has been established.
+	/// May refuse the connection by returning an Err, but will never write bytes to the remote end
+	/// (outbound connector always speaks first). Note that if an Err is returned here you MUST NOT
+	/// call disconnect_event for the new descriptor but must disconnect the connection
+	/// immediately.
+	/// Panics if descriptor is duplicative with some other descriptor which has not yet has a
+	/// disconnect_event.
+	pub fn new_inbound_connection(&self, descriptor: Descriptor) -> Result<(), PeerHandleError> {
+		let peer_encryptor = PeerChannelEncryptor::new_inbound(&self.our_node_secret);
+		let pending_read_buffer = [0; 50].to_vec(); // Noise act one is 50 bytes
+
+		let mut peers = self.peers.lock().unwrap();
+		if peers.peers.insert(descriptor, Peer {
+			channel_encryptor: peer_encryptor,
+			outbound: false,
+			their_node_id: None,
+			their_global_features: None,
+			their_local_features: None,
+
+			pending_outbound_buffer: LinkedList::new(),
+			pending_outbound_buffer_first_msg_offset: 0,
+			awaiting_write_event: false,
+
+			pending_read_buffer: pending_read_buffer,
+			pending_read_buffer_pos: 0,
+			pending_read_is_header: false,
+
+			awaiting_pong: false,
+		}).is_some() {
+			panic!("PeerManager driver duplicated descriptors!");
+		};
+		Ok(())
+	}
+
+	fn do_attempt_write_data(descriptor: &mut Descriptor, peer: &mut Peer) {
+		while !peer.awaiting_write_event {
+			if {
+				let next_buff = match peer.pending_outbound_buffer.front() {
+					None => return,
+					Some(buff) => buff,
+				};
+				let should_be_reading = peer.pending_outbound_buffer.len() < 10;
+
+				let data_sent = descriptor.send_data(next_buff, peer.pending_outbound_buffer_first_msg_offset, should_be_reading);
+				peer.pending_outbound_buffer_first_msg_offset += data_sent;
+				peer.pending_outbound_buffer_first_msg_offset == next_buff.len()
+			} {
+				peer.pending_outbound_buffer_first_msg_offset = 0;
+				peer.pending_outbound_buffer.pop_front();
+			} else {
+				peer.awaiting_write_event = true;
+			}
+		}
+	}
+
+	/// Indicates that there is room to write data to the given socket descriptor.
+	/// May return an Err to indicate that the connection should be closed.
+	/// Will most likely call send_data on the descriptor passed in (or the descriptor handed into
+	/// new_*_connection) before returning. Thus, be very careful with reentrancy issues! The
+	/// invariants around calling write_event in case a write did not fully complete must still
+	/// hold - be ready to call write_event again if a write call generated here isn't sufficient!
+	/// Panics if the descriptor was not previously registered in a new_*_connection event.
+	pub fn write_event(&self, descriptor: &mut Descriptor) -> Result<(), PeerHandleError> {
+		let mut peers = self.peers.lock().unwrap();
+		match peers.peers.get_mut(descriptor) {
+			None => panic!("Descriptor for write_event is not already known to PeerManager"),
+			Some(peer) => {
+				peer.awaiting_write_event = false;
+				Self::do_attempt_write_data(descriptor, peer);
+			}
+		};
+		Ok(())
+	}
+
+	/// Indicates that data was read from the given socket descriptor.
+	/// May return an Err to indicate that the connection should be closed.
+	/// Will very likely call send_data on the descriptor passed in (or a descriptor handed into
+	/// new_*_connection) before returning. Thus, be very careful with reentrancy issues! The
+	/// invariants around calling write_event in case a write did not fully complete must still
+	/// hold. Note that this function will often call send_data on many peers before returning, not
+	/// just this peer!
+	/// If Ok(true) is returned, further read_events should not be triggered until a write_event on
+	/// this file descriptor has resume_read set (preventing DoS issues in the send buffer). Note
+	/// that this must be true even if a send_data call with resume_read=true was made during the
+	/// course of this function!
+	/// Panics if the descriptor was not previously registered in a new_*_connection event.
+	pub fn read_event(&self, peer_descriptor: &mut Descriptor, data: Vec<u8>) -> Result<bool, PeerHandleError> {
+		match self.do_read_event(peer_descriptor, data) {
+			Ok(res) => Ok(res),
+			Err(e) => {
+				self.disconnect_event_internal(peer_descriptor, e.no_connection_possible);
+				Err(e)
+			}
+		}
+	}
+
+	fn do_read_event(&self, peer_descriptor: &mut Descriptor, data: Vec<u8>) -> Result<bool, PeerHandleError> {
+		let pause_read = {
+			let mut peers_lock = self.peers.lock().unwrap();
+			let peers = peers_lock.borrow_parts();
+			let (insert_node_id, pause_read) = match peers.peers.get_mut(peer_descriptor) {
+				None => panic!("Descriptor for read_event is not already known to PeerManager"),
+				Some(peer) => {
+					assert!(peer.pending_read_buffer.len() > 0);
+					assert!(peer.pending_read_buffer.len() > peer.pending_read_buffer_pos);
+
+					let mut insert_node_id = None;
+					let mut read_pos = 0;
+					while read_pos < data.len() {
+						{
+							let data_to_copy = cmp::min(peer.pending_read_buffer.len() - peer.pending_read_buffer_pos, data.len() - read_pos);
+							peer.pending_read_buffer[peer.pending_read_buffer_pos..peer.pending_read_buffer_pos + data_to_copy].copy_from_slice(&data[read_pos..read_pos + data_to_copy]);
+							read_pos += data_to_copy;
+							peer.pending_read_buffer_pos += data_to_copy;
+						}
+
+						if peer.pending_read_buffer_pos == peer.pending_read_buffer.len() {
+							peer.pending_read_buffer_pos = 0;
+
+							macro_rules! encode_and_send_msg {
+								($msg: expr) => {
+									peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&wire::encode_msg(&$msg)[..]));
+								}
+							}
+
+							macro_rules! try_potential_handleerror {
+								($thing: expr) => {
+									match $thing {
+										Ok(x) => x,
+										Err(e) => {
+											if let Some(action) = e.action {
+												match action {
+													msgs::ErrorAction::UpdateFailHTLC { msg } => {
+														encode_and_send_msg!(msg);
+														continue;
+													},
+													msgs::ErrorAction::DisconnectPeer { msg } => {
+														if let Some(msg) = msg {
+															if peer.channel_encryptor.is_ready_for_encryption() {
+																encode_and_send_msg!(msg);
+																Self::do_attempt_write_data(peer_descriptor, peer);
+															}
+														}
+														return Err(PeerHandleError{ no_connection_possible: false });
+													},
+													msgs::ErrorAction::IgnoreError => {
+														continue;
+													},
+													msgs::ErrorAction::SendErrorMessage { msg } => {
+														encode_and_send_msg!(msg);
+														continue;
+													},
+												}
+											} else {
+												return Err(PeerHandleError{ no_connection_possible: false });
+											}
+										}
+									};
+								}
+							}
+
+							macro_rules! try_potential_decodeerror {
+								($thing: expr) => {
+									match $thing {
+										Ok(x) => x,
+										Err(msgs::DecodeError::UnknownRequiredMessage) => {
+											// Unknown even messages are fatal ("it's OK to be odd")
+											return Err(PeerHandleError{ no_connection_possible: true });
+										},
+										Err(_e) => {
+											return Err(PeerHandleError{ no_connection_possible: false });
+										}
+									};
+								}
+							}
+
+							let next_step = peer.channel_encryptor.get_noise_step();
+							match next_step {
+								NextNoiseStep::ActOne => {
+									let act_two = try_potential_handleerror!(peer.channel_encryptor.process_act_one_with_key(&peer.pending_read_buffer[..], &self.our_node_secret)).to_vec();
+									peer.pending_outbound_buffer.push_back(act_two);
+									peer.pending_read_buffer = [0; 66].to_vec(); // act three is 66 bytes long
+								},
+								NextNoiseStep::ActTwo => {
+									let act_three = try_potential_handleerror!(peer.channel_encryptor.process_act_two(&peer.pending_read_buffer[..], &self.our_node_secret)).to_vec();
+									peer.pending_outbound_buffer.push_back(act_three);
+									peer.pending_read_buffer = [0; 18].to_vec(); // Message length header is 18 bytes
+									peer.pending_read_is_header = true;
+
+									insert_node_id = Some(peer.their_node_id.unwrap());
+									encode_and_send_msg!(msgs::Init {
+										global_features: msgs::GlobalFeatures::new(),
+										local_features: msgs::LocalFeatures::new(),
+									});
+								},
+								NextNoiseStep::ActThree => {
+									let their_node_id = try_potential_handleerror!(peer.channel_encryptor.process_act_three(&peer.pending_read_buffer[..]));
+									peer.pending_read_buffer = [0; 18].to_vec(); // Message length header is 18 bytes
+									peer.pending_read_is_header = true;
+									peer.their_node_id = Some(their_node_id);
+									insert_node_id = Some(their_node_id);
+								},
+								NextNoiseStep::NoiseComplete => {
+									if peer.pending_read_is_header {
+										let msg_len = try_potential_handleerror!(peer.channel_encryptor.decrypt_length_header(&peer.pending_read_buffer[..]));
+										if msg_len < 2 { // Need at least the message type tag
+											return Err(PeerHandleError{ no_connection_possible: false });
+										}
+										peer.pending_read_buffer = Vec::with_capacity(msg_len as usize + 16);
+										peer.pending_read_buffer.resize(msg_len as usize + 16, 0);
+										peer.pending_read_is_header = false;
+									} else {
+										let msg_data = try_potential_handleerror!(peer.channel_encryptor.decrypt_message(&peer.pending_read_buffer[..]));
+										assert!(msg_data.len() >= 2);
+
+										// Reset read buffer
+										peer.pending_read_buffer = [0; 18].to_vec();
+										peer.pending_read_is_header = true;
+
+										let message = try_potential_decodeerror!(wire::read_message(&msg_data[..]));
+										if peer.their_global_features.is_none() {
+											if let wire::Message::Init(_) = message {} else {
+												// Need an init message as first message
+												return Err(PeerHandleError{ no_connection_possible: false });
+											}
+										}
+										match message {
+											// Connection control:
+											wire::Message::Init(msg) => {
+												if msg.global_features.requires_unknown_bits() {
+													return Err(PeerHandleError{ no_connection_possible: true });
+												}
+												if msg.local_features.requires_unknown_bits() {
+													return Err(PeerHandleError{ no_connection_possible: true });
+												}
+												if peer.their_global_features.is_some() {
+													return Err(PeerHandleError{ no_connection_possible: false });
+												}
+												peer.their_global_features = Some(msg.global_features);
+												peer.their_local_features = Some(msg.local_features);
+
+												if !peer.outbound {
+													encode_and_send_msg!(msgs::Init {
+														global_features: msgs::GlobalFeatures::new(),
+														local_features: msgs::LocalFeatures::new(),
+													});
+												}
+											},
+											wire::Message::Error(msg) => {
+												if msg.channel_id == [0; 32] {
+													// All channels with the peer are failed, so there is nothing left to talk about
+													return Err(PeerHandleError{ no_connection_possible: true });
+												}
+												self.message_handler.chan_handler.handle_error(&peer.their_node_id.unwrap(), &msg);
+											},
+
+											wire::Message::Ping(msg) => {
+												if msg.ponglen < 65532 {
+													let resp = msgs::Pong { byteslen: msg.ponglen };
+													encode_and_send_msg!(resp);
+												}
+											},
+											wire::Message::Pong(_) => {
+												peer.awaiting_pong = false;
+											},
+
+											// Channel control:
+											wire::Message::OpenChannel(msg) => {
+												let resp = try_potential_handleerror!(self.message_handler.chan_handler.handle_open_channel(&peer.their_node_id.unwrap(), &msg));
+												encode_and_send_msg!(resp);
+											},
+											wire::Message::AcceptChannel(msg) => {
+												try_potential_handleerror!(self.message_handler.chan_handler.handle_accept_channel(&peer.their_node_id.unwrap(), &msg));
+											},
+
+											wire::Message::FundingCreated(msg) => {
+												let resp = try_potential_handleerror!(self.message_handler.chan_handler.handle_funding_created(&peer.their_node_id.unwrap(), &msg));
+												encode_and_send_msg!(resp);
+											},
+											wire::Message::FundingSigned(msg) => {
+												try_potential_handleerror!(self.message_handler.chan_handler.handle_funding_signed(&peer.their_node_id.unwrap(), &msg));
+											},
+											wire::Message::FundingLocked(msg) => {
+												let resp_option = try_potential_handleerror!(self.message_handler.chan_handler.handle_funding_locked(&peer.their_node_id.unwrap(), &msg));
+												match resp_option {
+													Some(resp) => encode_and_send_msg!(resp),
+													None => {},
+												}
+											},
+
+											wire::Message::Shutdown(msg) => {
+												let resp_options = try_potential_handleerror!(self.message_handler.chan_handler.handle_shutdown(&peer.their_node_id.unwrap(), &msg));
+												if let Some(resp) = resp_options.0 {
+													encode_and_send_msg!(resp);
+												}
+												if let Some(resp) = resp_options.1 {
+													encode_and_send_msg!(resp);
+												}
+											},
+											wire::Message::ClosingSigned(msg) => {
+												let resp_option = try_potential_handleerror!(self.message_handler.chan_handler.handle_closing_signed(&peer.their_node_id.unwrap(), &msg));
+												if let Some(resp) = resp_option {
+													encode_and_send_msg!(resp);
+												}
+											},
+
+											wire::Message::UpdateAddHTLC(msg) => {
+												try_potential_handleerror!(self.message_handler.chan_handler.handle_update_add_htlc(&peer.their_node_id.unwrap(), &msg));
+											},
+											wire::Message::UpdateFulfillHTLC(msg) => {
+												try_potential_handleerror!(self.message_handler.chan_handler.handle_update_fulfill_htlc(&peer.their_node_id.unwrap(), &msg));
+											},
+											wire::Message::UpdateFailHTLC(msg) => {
+												let chan_update = try_potential_handleerror!(self.message_handler.chan_handler.handle_update_fail_htlc(&peer.their_node_id.unwrap(), &msg));
+												if let Some(update) = chan_update {
+													self.message_handler.route_handler.handle_htlc_fail_channel_update(&update);
+												}
+											},
+											wire::Message::UpdateFailMalformedHTLC(msg) => {
+												try_potential_handleerror!(self.message_handler.chan_handler.handle_update_fail_malformed_htlc(&peer.their_node_id.unwrap(), &msg));
+											},
+
+											wire::Message::CommitmentSigned(msg) => {
+												let resps = try_potential_handleerror!(self.message_handler.chan_handler.handle_commitment_signed(&peer.their_node_id.unwrap(), &msg));
+												encode_and_send_msg!(resps.0);
+												if let Some(resp) = resps.1 {
+													encode_and_send_msg!(resp);
+												}
+											},
+											wire::Message::RevokeAndACK(msg) => {
+												let resp_option = try_potential_handleerror!(self.message_handler.chan_handler.handle_revoke_and_ack(&peer.their_node_id.unwrap(), &msg));
+												match resp_option {
+													Some(resps) => {
+														for resp in resps.update_add_htlcs {
+															encode_and_send_msg!(resp);
+														}
+														for resp in resps.update_fulfill_htlcs {
+															encode_and_send_msg!(resp);
+														}
+														for resp in resps.update_fail_htlcs {
+															encode_and_send_msg!(resp);
+														}
+														encode_and_send_msg!(resps.commitment_signed);
+													},
+													None => {},
+												}
+											},
+											wire::Message::UpdateFee(msg) => {
+												try_potential_handleerror!(self.message_handler.chan_handler.handle_update_fee(&peer.their_node_id.unwrap(), &msg));
+											},
+											wire::Message::ChannelReestablish(_) => { }, // TODO: channel_reestablish
+
+											// Routing control:
+											wire::Message::AnnouncementSignatures(msg) => {
+												try_potential_handleerror!(self.message_handler.chan_handler.handle_announcement_signatures(&peer.their_node_id.unwrap(), &msg));
+											},
+											wire::Message::ChannelAnnouncement(msg) => {
+												try_potential_handleerror!(self.message_handler.route_handler.handle_channel_announcement(&msg));
+											},
+											wire::Message::NodeAnnouncement(msg) => {
+												try_potential_handleerror!(self.message_handler.route_handler.handle_node_announcement(&msg));
+											},
+											wire::Message::ChannelUpdate(msg) => {
+												try_potential_handleerror!(self.message_handler.route_handler.handle_channel_update(&msg));
+											},
+											wire::Message::Unknown { .. } => { }, // It's OK to be odd
+										}
+									}
+								}
+							}
+						}
+					}
+
+					Self::do_attempt_write_data(peer_descriptor, peer);
+
+					(insert_node_id, peer.pending_outbound_buffer.len() > 10) // pause_read
+				}
+			};
+
+			if let Some(node_id) = insert_node_id {
+				if peers.node_id_to_descriptor.contains_key(&node_id) {
+					// We already have a connection to this node
+					return Err(PeerHandleError{ no_connection_possible: false });
+				}
+				peers.node_id_to_descriptor.insert(node_id, peer_descriptor.clone());
+			}
+
+			pause_read
+		};
+
+		Ok(pause_read)
+	}
+
+	/// Checks for any events generated by our handlers and processes them. May be needed after eg
+	/// calls to ChannelManager::send_payment. Events which aren't messages to send are handed up
+	/// and available through get_and_clear_pending_events.
+	pub fn process_events(&self) {
+		let mut peers = self.peers.lock().unwrap();
+		let events = self.message_handler.chan_handler.get_and_clear_pending_events();
+		for event in events {
+			macro_rules! get_peer_for_forwarding {
+				($node_id: expr, $handle_no_such_peer: block) => {
+					{
+						let descriptor = match peers.node_id_to_descriptor.get($node_id) {
+							Some(descriptor) => descriptor.clone(),
+							None => {
+								$handle_no_such_peer;
+								continue;
+							},
+						};
+						match peers.peers.get_mut(&descriptor) {
+							Some(peer) => {
+								if peer.their_global_features.is_none() {
+									$handle_no_such_peer;
+									continue;
+								}
+								(descriptor, peer)
+							},
+							None => panic!("Inconsistent peers set state!"),
+						}
+					}
+				}
+			}
+			match event {
+				Event::BroadcastTransaction {..} => {
+					/* Hand upstream */
+					self.pending_events.lock().unwrap().push(event);
+				},
+				Event::PaymentReceived {..} => {
+					/* Hand upstream */
+					self.pending_events.lock().unwrap().push(event);
+				},
+				Event::PaymentSent {..} => {
+					/* Hand upstream */
+					self.pending_events.lock().unwrap().push(event);
+				},
+				Event::PaymentFailed {..} => {
+					/* Hand upstream */
+					self.pending_events.lock().unwrap().push(event);
+				},
+
+				Event::SendOpenChannel { ref node_id, ref msg } => {
+					let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+						//TODO: Drop the pending channel? (or just let it timeout, but that sucks)
+					});
+					peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&wire::encode_msg(msg)));
+					Self::do_attempt_write_data(&mut descriptor, peer);
+				},
+				Event::SendFundingCreated { ref node_id, ref msg } => {
+					let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+						//TODO: generate a DiscardFunding event indicating to the wallet that
+						//they should just throw away this funding transaction
+					});
+					peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&wire::encode_msg(msg)));
+					Self::do_attempt_write_data(&mut descriptor, peer);
+				},
+				Event::SendFundingLocked { ref node_id, ref msg, ref announcement_sigs } => {
+					let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+						//TODO: Do whatever we're gonna do for handling dropped messages
+					});
+					peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&wire::encode_msg(msg)));
+					if let &Some(ref announce_msg) = announcement_sigs {
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&wire::encode_msg(announce_msg)));
+					}
+					Self::do_attempt_write_data(&mut descriptor, peer);
+				},
+				Event::UpdateHTLCs { ref node_id, ref updates } => {
+					let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+						//TODO: Do whatever we're gonna do for handling dropped messages
+					});
+					for update_add in updates.update_add_htlcs.iter() {
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&wire::encode_msg(update_add)));
+					}
+					for update_fulfill in updates.update_fulfill_htlcs.iter() {
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&wire::encode_msg(update_fulfill)));
+					}
+					for update_fail in updates.update_fail_htlcs.iter() {
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&wire::encode_msg(update_fail)));
+					}
+					peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&wire::encode_msg(&updates.commitment_signed)));
+					Self::do_attempt_write_data(&mut descriptor, peer);
+				},
+				Event::SendShutdown { ref node_id, ref msg } => {
+					let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+						//TODO: Do whatever we're gonna do for handling dropped messages
+					});
+					peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&wire::encode_msg(msg)));
+					Self::do_attempt_write_data(&mut descriptor, peer);
+				},
+				Event::SendClosingSigned { ref node_id, ref msg } => {
+					let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+						//TODO: Do whatever we're gonna do for handling dropped messages
+					});
+					peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&wire::encode_msg(msg)));
+					Self::do_attempt_write_data(&mut descriptor, peer);
+				},
+				Event::BroadcastChannelAnnouncement { ref msg, ref update_msg } => {
+					if self.message_handler.route_handler.handle_channel_announcement(msg).is_ok() && self.message_handler.route_handler.handle_channel_update(update_msg).is_ok() {
+						let encoded_msg = wire::encode_msg(msg);
+						let encoded_update_msg = wire::encode_msg(update_msg);
+
+						for (ref descriptor, ref mut peer) in peers.peers.iter_mut() {
+							if !peer.channel_encryptor.is_ready_for_encryption() || peer.their_global_features.is_none() {
+								continue
+							}
+							match peer.their_node_id {
+								None => continue,
+								Some(their_node_id) => {
+									if their_node_id == msg.contents.node_id_1 || their_node_id == msg.contents.node_id_2 {
+										continue
+									}
+								}
+							}
+							peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encoded_msg[..]));
+							peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encoded_update_msg[..]));
+							Self::do_attempt_write_data(&mut (*descriptor).clone(), peer);
+						}
+					}
+				},
+			}
+		}
+	}
+
+	/// Sends a ping to every peer which has completed the Init exchange and disconnects the peers
+	/// which didn't answer the ping sent on the previous tick. Should be called periodically, eg
+	/// once every 30 seconds, to keep the connections alive and detect dead peers.
+	pub fn timer_tick_occured(&self) {
+		let mut peers_lock = self.peers.lock().unwrap();
+		{
+			let peers = peers_lock.borrow_parts();
+			let node_id_to_descriptor = peers.node_id_to_descriptor;
+			let chan_handler = &self.message_handler.chan_handler;
+
+			peers.peers.retain(|descriptor, peer| {
+				if peer.their_global_features.is_none() {
+					return true;
+				}
+
+				if peer.awaiting_pong {
+					descriptor.clone().disconnect_socket();
+					if let Some(node_id) = peer.their_node_id {
+						node_id_to_descriptor.remove(&node_id);
+						chan_handler.peer_disconnected(&node_id, false);
+					}
+					return false;
+				}
+
+				let ping = msgs::Ping { ponglen: 0, byteslen: 64 };
+				peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&wire::encode_msg(&ping)));
+				Self::do_attempt_write_data(&mut descriptor.clone(), peer);
+				peer.awaiting_pong = true;
+				true
+			});
+		}
+	}
+
+	/// Indicates that the given socket descriptor's connection is now closed.
+	/// This must be called even if a PeerHandleError was given for a read_event or write_event,
+	/// but must NOT be called if a PeerHandleError was provided out of a new_*_connection event!
+	/// Calls for descriptors which were already disconnected by the PeerManager itself (eg after a
+	/// ping timeout) are ignored.
+	pub fn disconnect_event(&self, descriptor: &Descriptor) {
+		self.disconnect_event_internal(descriptor, false);
+	}
+
+	fn disconnect_event_internal(&self, descriptor: &Descriptor, no_connection_possible: bool) {
+		let mut peers = self.peers.lock().unwrap();
+		let peer_option = peers.peers.remove(descriptor);
+		match peer_option {
+			None => {},
+			Some(peer) => {
+				match peer.their_node_id {
+					Some(node_id) => {
+						if peers.node_id_to_descriptor.get(&node_id) == Some(descriptor) {
+							peers.node_id_to_descriptor.remove(&node_id);
+							self.message_handler.chan_handler.peer_disconnected(&node_id, no_connection_possible);
+						}
+					},
+					None => {}
+				}
+			}
+		};
+	}
+}
+
+impl<Descriptor: SocketDescriptor> EventsProvider for PeerManager<Descriptor> {
+	fn get_and_clear_pending_events(&self) -> Vec<Event> {
+		let mut pending_events = self.pending_events.lock().unwrap();
+		let mut ret = Vec::new();
+		mem::swap(&mut ret, &mut *pending_events);
+		ret
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use ln::msgs;
+	use ln::peer_handler::{PeerManager, MessageHandler, SocketDescriptor};
+	use ln::wire;
+	use util::test_utils;
+
+	use secp256k1::Secp256k1;
+	use secp256k1::key::{SecretKey, PublicKey};
+
+	use std::sync::{Arc, Mutex};
+	use std::sync::atomic::Ordering;
+	use std::hash;
+
+	#[derive(Clone)]
+	struct FileDescriptor {
+		fd: u16,
+		outbound_data: Arc<Mutex<Vec<u8>>>,
+		disconnected: Arc<Mutex<bool>>,
+	}
+	impl PartialEq for FileDescriptor {
+		fn eq(&self, other: &FileDescriptor) -> bool {
+			self.fd == other.fd
+		}
+	}
+	impl Eq for FileDescriptor {}
+	impl hash::Hash for FileDescriptor {
+		fn hash<H: hash::Hasher>(&self, hasher: &mut H) {
+			self.fd.hash(hasher)
+		}
+	}
+
+	impl SocketDescriptor for FileDescriptor {
+		fn send_data(&mut self, data: &Vec<u8>, write_offset: usize, _resume_read: bool) -> usize {
+			self.outbound_data.lock().unwrap().extend_from_slice(&data[write_offset..]);
+			data.len() - write_offset
+		}
+
+		fn disconnect_socket(&mut self) {
+			*self.disconnected.lock().unwrap() = true;
+		}
+	}
+
+	impl FileDescriptor {
+		fn new(fd: u16) -> FileDescriptor {
+			FileDescriptor { fd, outbound_data: Arc::new(Mutex::new(Vec::new())), disconnected: Arc::new(Mutex::new(false)) }
+		}
+
+		fn take_outbound_data(&self) -> Vec<u8> {
+			let mut data = self.outbound_data.lock().unwrap();
+			let res = data.clone();
+			data.clear();
+			res
+		}
+	}
+
+	fn create_network(peer_count: usize) -> (Vec<PeerManager<FileDescriptor>>, Vec<Arc<test_utils::TestChannelMessageHandler>>) {
+		let mut peers = Vec::new();
+		let mut chan_handlers = Vec::new();
+		for i in 0..peer_count {
+			let chan_handler = Arc::new(test_utils::TestChannelMessageHandler::new());
+			let node_id = SecretKey::from_slice(&Secp256k1::without_caps(), &[42 + i as u8; 32]).unwrap();
+			let msg_handler = MessageHandler { chan_handler: chan_handler.clone(), route_handler: Arc::new(test_utils::TestRoutingMessageHandler::new()) };
+			peers.push(PeerManager::new(msg_handler, node_id));
+			chan_handlers.push(chan_handler);
+		}
+		(peers, chan_handlers)
+	}
+
+	/// Runs the handshake between the two peers, shuffling bytes until both sides go quiet.
+	fn establish_connection(peer_a: &PeerManager<FileDescriptor>, peer_b: &PeerManager<FileDescriptor>) -> (FileDescriptor, FileDescriptor) {
+		let secp_ctx = Secp256k1::new();
+		let their_id = PublicKey::from_secret_key(&secp_ctx, &peer_b.our_node_secret).unwrap();
+		let mut fd_a = FileDescriptor::new(1);
+		let mut fd_b = FileDescriptor::new(1);
+		let initial_data = peer_a.new_outbound_connection(their_id, fd_a.clone()).unwrap();
+		peer_b.new_inbound_connection(fd_b.clone()).unwrap();
+		assert_eq!(peer_b.read_event(&mut fd_b, initial_data).unwrap(), false);
+		loop {
+			let to_a = fd_b.take_outbound_data();
+			if !to_a.is_empty() {
+				assert_eq!(peer_a.read_event(&mut fd_a, to_a).unwrap(), false);
+			}
+			let to_b = fd_a.take_outbound_data();
+			if to_b.is_empty() {
+				break;
+			}
+			assert_eq!(peer_b.read_event(&mut fd_b, to_b).unwrap(), false);
+		}
+		(fd_a, fd_b)
+	}
+
+	#[test]
+	fn test_handshake_and_init() {
+		let (peers, _) = create_network(2);
+		establish_connection(&peers[0], &peers[1]);
+
+		let secp_ctx = Secp256k1::new();
+		let id_a = PublicKey::from_secret_key(&secp_ctx, &peers[0].our_node_secret).unwrap();
+		let id_b = PublicKey::from_secret_key(&secp_ctx, &peers[1].our_node_secret).unwrap();
+		assert_eq!(peers[0].get_peer_node_ids(), vec![id_b]);
+		assert_eq!(peers[1].get_peer_node_ids(), vec![id_a]);
+	}
+
+	#[test]
+	fn test_ping_timeout_disconnects() {
+		let (peers, chan_handlers) = create_network(2);
+		let (mut fd_a, mut fd_b) = establish_connection(&peers[0], &peers[1]);
+
+		// A ping answered before the next tick keeps the connection alive
+		peers[0].timer_tick_occured();
+		let ping = fd_a.take_outbound_data();
+		assert!(!ping.is_empty());
+		assert_eq!(peers[1].read_event(&mut fd_b, ping).unwrap(), false);
+		let pong = fd_b.take_outbound_data();
+		assert_eq!(peers[0].read_event(&mut fd_a, pong).unwrap(), false);
+		peers[0].timer_tick_occured();
+		assert_eq!(peers[0].get_peer_node_ids().len(), 1);
+		assert!(!*fd_a.disconnected.lock().unwrap());
+
+		// A ping left unanswered disconnects the peer on the next tick
+		fd_a.take_outbound_data();
+		peers[0].timer_tick_occured();
+		assert!(peers[0].get_peer_node_ids().is_empty());
+		assert!(*fd_a.disconnected.lock().unwrap());
+		assert_eq!(chan_handlers[0].peers_disconnected.load(Ordering::SeqCst), 1);
+
+		// The driver's late disconnect_event is ignored
+		peers[0].disconnect_event(&fd_a);
+		assert_eq!(chan_handlers[0].peers_disconnected.load(Ordering::SeqCst), 1);
+	}
+
+	#[test]
+	fn test_channel_error_message() {
+		let (peers, chan_handlers) = create_network(2);
+		let (mut fd_a, fd_b) = establish_connection(&peers[0], &peers[1]);
+
+		let encrypt_from_b = |channel_id: [u8; 32]| {
+			let mut peers_b = peers[1].peers.lock().unwrap();
+			let peer = peers_b.peers.get_mut(&fd_b).unwrap();
+			peer.channel_encryptor.encrypt_message(&wire::encode_msg(&msgs::ErrorMessage {
+				channel_id: channel_id,
+				data: "Channel failed".to_string(),
+			}))
+		};
+
+		// An error about one channel fails just that channel and keeps the connection
+		let error = encrypt_from_b([1; 32]);
+		assert_eq!(peers[0].read_event(&mut fd_a, error).unwrap(), false);
+		assert_eq!(*chan_handlers[0].failed_channels.lock().unwrap(), vec![[1; 32]]);
+		assert_eq!(peers[0].get_peer_node_ids().len(), 1);
+
+		// An error about all channels ends the connection for good
+		let error = encrypt_from_b([0; 32]);
+		assert!(peers[0].read_event(&mut fd_a, error).is_err());
+		assert_eq!(chan_handlers[0].failed_channels.lock().unwrap().len(), 1);
+	}
+}
@@ -0,0 +1,185 @@
+//! A blocking TCP driver for PeerManager.
+//!
+//! Every connection gets a reader thread, which feeds read_event and process_events, and a writer
+//! thread, which drains the data handed to send_data. This is enough to run a node over loopback
+//! or a handful of real peers; a node with many connections should drive the PeerManager from an
+//! event loop instead.
+
+use secp256k1::key::PublicKey;
+
+use ln::peer_handler::{PeerManager, SocketDescriptor};
+
+use std::hash;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::sync::mpsc;
+use std::thread;
+
+static NEXT_DESCRIPTOR_ID: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// A SocketDescriptor backed by a std TcpStream.
+/// Writes are queued for a dedicated writer thread, so send_data always accepts all of the data
+/// and write_event never needs to be called.
+#[derive(Clone)]
+pub struct TcpSocketDescriptor {
+	id: usize,
+	stream: Arc<TcpStream>,
+	sender: mpsc::Sender<Vec<u8>>,
+}
+impl PartialEq for TcpSocketDescriptor {
+	fn eq(&self, other: &TcpSocketDescriptor) -> bool {
+		self.id == other.id
+	}
+}
+impl Eq for TcpSocketDescriptor {}
+impl hash::Hash for TcpSocketDescriptor {
+	fn hash<H: hash::Hasher>(&self, hasher: &mut H) {
+		self.id.hash(hasher)
+	}
+}
+
+impl SocketDescriptor for TcpSocketDescriptor {
+	fn send_data(&mut self, data: &Vec<u8>, write_offset: usize, _resume_read: bool) -> usize {
+		// If the writer thread is gone the reader will see the socket close shortly, until then
+		// the data is simply dropped.
+		let _ = self.sender.send(data[write_offset..].to_vec());
+		data.len() - write_offset
+	}
+
+	fn disconnect_socket(&mut self) {
+		let _ = self.stream.shutdown(Shutdown::Both);
+	}
+}
+
+impl TcpSocketDescriptor {
+	fn new(stream: TcpStream) -> io::Result<TcpSocketDescriptor> {
+		let mut write_stream = stream.try_clone()?;
+		let (sender, receiver) = mpsc::channel::<Vec<u8>>();
+		thread::spawn(move || {
+			for data in receiver {
+				if write_stream.write_all(&data[..]).is_err() {
+					break;
+				}
+			}
+		});
+		Ok(TcpSocketDescriptor {
+			id: NEXT_DESCRIPTOR_ID.fetch_add(1, Ordering::SeqCst),
+			stream: Arc::new(stream),
+			sender: sender,
+		})
+	}
+}
+
+/// Feeds everything read from the socket into the PeerManager until either side closes it.
+fn run_reader(peer_manager: Arc<PeerManager<TcpSocketDescriptor>>, mut descriptor: TcpSocketDescriptor, mut stream: TcpStream) {
+	let mut buf = [0; 4096];
+	loop {
+		match stream.read(&mut buf) {
+			Ok(0) | Err(_) => {
+				peer_manager.disconnect_event(&descriptor);
+				return;
+			},
+			Ok(len) => {
+				if peer_manager.read_event(&mut descriptor, buf[0..len].to_vec()).is_err() {
+					// read_event already forgot the peer, no disconnect_event is needed
+					descriptor.disconnect_socket();
+					return;
+				}
+				peer_manager.process_events();
+			},
+		}
+	}
+}
+
+/// Connects to the node with the given id at the given address, sends act one and spawns the
+/// threads driving the connection.
+pub fn connect_outbound(peer_manager: Arc<PeerManager<TcpSocketDescriptor>>, their_node_id: PublicKey, addr: &SocketAddr) -> io::Result<TcpSocketDescriptor> {
+	let stream = TcpStream::connect(addr)?;
+	let read_stream = stream.try_clone()?;
+	let mut descriptor = TcpSocketDescriptor::new(stream)?;
+	match peer_manager.new_outbound_connection(their_node_id, descriptor.clone()) {
+		Ok(act_one) => {
+			descriptor.send_data(&act_one, 0, true);
+		},
+		Err(e) => {
+			descriptor.disconnect_socket();
+			return Err(io::Error::new(io::ErrorKind::ConnectionRefused, e));
+		},
+	}
+
+	let thread_descriptor = descriptor.clone();
+	thread::spawn(move || run_reader(peer_manager, thread_descriptor, read_stream));
+	Ok(descriptor)
+}
+
+/// Registers an accepted connection with the PeerManager and spawns the threads driving it.
+pub fn setup_inbound(peer_manager: Arc<PeerManager<TcpSocketDescriptor>>, stream: TcpStream) -> io::Result<TcpSocketDescriptor> {
+	let read_stream = stream.try_clone()?;
+	let mut descriptor = TcpSocketDescriptor::new(stream)?;
+	if let Err(e) = peer_manager.new_inbound_connection(descriptor.clone()) {
+		descriptor.disconnect_socket();
+		return Err(io::Error::new(io::ErrorKind::ConnectionRefused, e));
+	}
+
+	let thread_descriptor = descriptor.clone();
+	thread::spawn(move || run_reader(peer_manager, thread_descriptor, read_stream));
+	Ok(descriptor)
+}
+
+/// Accepts inbound connections on the given listener in a background thread.
+pub fn listen(peer_manager: Arc<PeerManager<TcpSocketDescriptor>>, listener: TcpListener) -> thread::JoinHandle<()> {
+	thread::spawn(move || {
+		for stream in listener.incoming() {
+			if let Ok(stream) = stream {
+				let _ = setup_inbound(peer_manager.clone(), stream);
+			}
+		}
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use ln::peer_handler::{PeerManager, MessageHandler};
+	use ln::peer_socket;
+	use util::test_utils;
+
+	use secp256k1::Secp256k1;
+	use secp256k1::key::{SecretKey, PublicKey};
+
+	use std::net::TcpListener;
+	use std::sync::Arc;
+	use std::thread;
+	use std::time::Duration;
+
+	fn create_peer_manager(seed: u8) -> (Arc<PeerManager<peer_socket::TcpSocketDescriptor>>, PublicKey) {
+		let secp_ctx = Secp256k1::new();
+		let node_secret = SecretKey::from_slice(&secp_ctx, &[seed; 32]).unwrap();
+		let node_id = PublicKey::from_secret_key(&secp_ctx, &node_secret).unwrap();
+		let msg_handler = MessageHandler {
+			chan_handler: Arc::new(test_utils::TestChannelMessageHandler::new()),
+			route_handler: Arc::new(test_utils::TestRoutingMessageHandler::new()),
+		};
+		(Arc::new(PeerManager::new(msg_handler, node_secret)), node_id)
+	}
+
+	#[test]
+	fn test_loopback_handshake() {
+		let (peer_a, id_a) = create_peer_manager(42);
+		let (peer_b, id_b) = create_peer_manager(43);
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		peer_socket::listen(peer_b.clone(), listener);
+		peer_socket::connect_outbound(peer_a.clone(), id_b, &addr).unwrap();
+
+		for _ in 0..100 {
+			if peer_a.get_peer_node_ids() == vec![id_b] && peer_b.get_peer_node_ids() == vec![id_a] {
+				return;
+			}
+			thread::sleep(Duration::from_millis(10));
+		}
+		panic!("Init was not exchanged over loopback");
+	}
+}
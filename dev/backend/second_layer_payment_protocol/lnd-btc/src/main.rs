@@ -1,9 +1,15 @@
 extern crate bitcoin;
 extern crate bitcoin_bech32;
 extern crate bitcoin_rpc_json;
+extern crate crypto;
+extern crate rand;
+extern crate secp256k1;
 extern crate serde;
 extern crate tokio;
 
+#[cfg(test)]
+extern crate hex;
+
 use serde::ser::{Serialize, Serializer};
 use std::env;
 use std::io::prelude::*;
@@ -13,6 +19,8 @@ use tokio::net::TcpListener;
 use tokio::prelude::*;
 
 mod lib;
+mod ln;
+mod util;
 
 // программа запускает демона, который ждёт команду, которая что-то сделает
 // нужно указывать сеть: testnet или mainnet
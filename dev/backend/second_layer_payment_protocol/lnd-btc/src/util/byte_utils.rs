@@ -0,0 +1,67 @@
+#[inline]
+pub fn slice_to_be16(v: &[u8]) -> u16 {
+	((v[0] as u16) << 8*1) |
+	((v[1] as u16) << 8*0)
+}
+#[inline]
+pub fn slice_to_be32(v: &[u8]) -> u32 {
+	((v[0] as u32) << 8*3) |
+	((v[1] as u32) << 8*2) |
+	((v[2] as u32) << 8*1) |
+	((v[3] as u32) << 8*0)
+}
+#[inline]
+pub fn slice_to_be64(v: &[u8]) -> u64 {
+	((v[0] as u64) << 8*7) |
+	((v[1] as u64) << 8*6) |
+	((v[2] as u64) << 8*5) |
+	((v[3] as u64) << 8*4) |
+	((v[4] as u64) << 8*3) |
+	((v[5] as u64) << 8*2) |
+	((v[6] as u64) << 8*1) |
+	((v[7] as u64) << 8*0)
+}
+
+#[inline]
+pub fn be16_to_array(u: u16) -> [u8; 2] {
+	let mut v = [0; 2];
+	v[0] = ((u >> 8*1) & 0xff) as u8;
+	v[1] = ((u >> 8*0) & 0xff) as u8;
+	v
+}
+#[inline]
+pub fn be32_to_array(u: u32) -> [u8; 4] {
+	let mut v = [0; 4];
+	v[0] = ((u >> 8*3) & 0xff) as u8;
+	v[1] = ((u >> 8*2) & 0xff) as u8;
+	v[2] = ((u >> 8*1) & 0xff) as u8;
+	v[3] = ((u >> 8*0) & 0xff) as u8;
+	v
+}
+#[inline]
+pub fn be64_to_array(u: u64) -> [u8; 8] {
+	let mut v = [0; 8];
+	v[0] = ((u >> 8*7) & 0xff) as u8;
+	v[1] = ((u >> 8*6) & 0xff) as u8;
+	v[2] = ((u >> 8*5) & 0xff) as u8;
+	v[3] = ((u >> 8*4) & 0xff) as u8;
+	v[4] = ((u >> 8*3) & 0xff) as u8;
+	v[5] = ((u >> 8*2) & 0xff) as u8;
+	v[6] = ((u >> 8*1) & 0xff) as u8;
+	v[7] = ((u >> 8*0) & 0xff) as u8;
+	v
+}
+
+#[inline]
+pub fn le64_to_array(u: u64) -> [u8; 8] {
+	let mut v = [0; 8];
+	v[0] = ((u >> 8*0) & 0xff) as u8;
+	v[1] = ((u >> 8*1) & 0xff) as u8;
+	v[2] = ((u >> 8*2) & 0xff) as u8;
+	v[3] = ((u >> 8*3) & 0xff) as u8;
+	v[4] = ((u >> 8*4) & 0xff) as u8;
+	v[5] = ((u >> 8*5) & 0xff) as u8;
+	v[6] = ((u >> 8*6) & 0xff) as u8;
+	v[7] = ((u >> 8*7) & 0xff) as u8;
+	v
+}
@@ -0,0 +1,94 @@
+// ChaCha20-Poly1305 AEAD as described in RFC 7539, built on top of the rust-crypto primitives.
+// rust-crypto's ChaCha20 takes an 8-byte nonce and a 64-bit block counter, which matches the
+// RFC construction as long as the first 4 bytes of the 12-byte nonce are zero. That is always
+// the case for BOLT #8, which puts a little-endian 64-bit counter in the last 8 bytes.
+
+use crypto::aead::{AeadEncryptor, AeadDecryptor};
+use crypto::symmetriccipher::SynchronousStreamCipher;
+use crypto::chacha20::ChaCha20;
+use crypto::poly1305::Poly1305;
+use crypto::mac::Mac;
+use crypto::util::fixed_time_eq;
+
+use util::byte_utils;
+
+#[derive(Clone, Copy)]
+pub struct ChaCha20Poly1305RFC {
+	cipher: ChaCha20,
+	mac: Poly1305,
+	finished: bool,
+	data_len: usize,
+	aad_len: u64,
+}
+
+impl ChaCha20Poly1305RFC {
+	#[inline]
+	fn pad_mac_16(mac: &mut Poly1305, len: usize) {
+		if len % 16 != 0 {
+			mac.input(&[0; 16][0..16 - (len % 16)]);
+		}
+	}
+
+	pub fn new(key: &[u8], nonce: &[u8], aad: &[u8]) -> ChaCha20Poly1305RFC {
+		assert!(key.len() == 16 || key.len() == 32);
+		assert!(nonce.len() == 12);
+		assert!(nonce[0] == 0 && nonce[1] == 0 && nonce[2] == 0 && nonce[3] == 0);
+
+		let mut cipher = ChaCha20::new(key, &nonce[4..]);
+		let mut mac_key = [0u8; 64];
+		let zero_key = [0u8; 64];
+		cipher.process(&zero_key, &mut mac_key);
+
+		let mut mac = Poly1305::new(&mac_key[..32]);
+		mac.input(aad);
+		ChaCha20Poly1305RFC::pad_mac_16(&mut mac, aad.len());
+
+		ChaCha20Poly1305RFC {
+			cipher: cipher,
+			mac: mac,
+			finished: false,
+			data_len: 0,
+			aad_len: aad.len() as u64,
+		}
+	}
+}
+
+impl AeadEncryptor for ChaCha20Poly1305RFC {
+	fn encrypt(&mut self, input: &[u8], output: &mut [u8], out_tag: &mut [u8]) {
+		assert!(input.len() == output.len());
+		assert!(self.finished == false);
+		self.cipher.process(input, output);
+		self.data_len += input.len();
+		self.mac.input(output);
+		ChaCha20Poly1305RFC::pad_mac_16(&mut self.mac, self.data_len);
+		self.finished = true;
+		self.mac.input(&byte_utils::le64_to_array(self.aad_len));
+		self.mac.input(&byte_utils::le64_to_array(self.data_len as u64));
+		self.mac.raw_result(out_tag);
+	}
+}
+
+impl AeadDecryptor for ChaCha20Poly1305RFC {
+	fn decrypt(&mut self, input: &[u8], output: &mut [u8], tag: &[u8]) -> bool {
+		assert!(input.len() == output.len());
+		assert!(self.finished == false);
+
+		self.finished = true;
+
+		self.mac.input(input);
+
+		self.data_len += input.len();
+		ChaCha20Poly1305RFC::pad_mac_16(&mut self.mac, self.data_len);
+		self.mac.input(&byte_utils::le64_to_array(self.aad_len));
+		self.mac.input(&byte_utils::le64_to_array(self.data_len as u64));
+
+		let mut calc_tag = [0u8; 16];
+		self.mac.raw_result(&mut calc_tag);
+		if fixed_time_eq(&calc_tag, tag) {
+			self.cipher.process(input, output);
+			true
+		} else {
+			false
+		}
+	}
+}
@@ -0,0 +1,84 @@
+use ln::msgs;
+
+use bitcoin::blockdata::transaction::Transaction;
+
+use secp256k1::key::PublicKey;
+
+/// An event generated by a ChannelMessageHandler. Events which indicate a message should be sent
+/// to a peer (or broadcast to most peers) are handled by PeerManager::process_events, the others
+/// are handed up to the user through the PeerManager's own EventsProvider.
+pub enum Event {
+	// Events a user will probably have to handle
+	/// Used to indicate that the given transaction should be broadcast: either a funding
+	/// transaction once the counterparty signed our first commitment transaction, or a
+	/// transaction closing a channel.
+	BroadcastTransaction {
+		tx: Transaction,
+	},
+	/// Indicates we've received money! Once the preimage is known, ChannelManager::claim_funds
+	/// should be called, otherwise ChannelManager::fail_htlc_backwards, so that the HTLC doesn't
+	/// sit in the channel until it expires.
+	PaymentReceived {
+		payment_hash: [u8; 32],
+		amt: u64,
+	},
+	/// Indicates an outbound payment we made succeeded (ie it made it all the way to its target
+	/// and we got back the payment preimage for it).
+	PaymentSent {
+		payment_preimage: [u8; 32],
+	},
+	/// Indicates an outbound payment we made failed and its value is back in our balance.
+	PaymentFailed {
+		payment_hash: [u8; 32],
+	},
+
+	// Events indicating the network loop should send a message to a peer:
+	/// Used to indicate that an open_channel message should be sent to the peer with the given
+	/// node_id.
+	SendOpenChannel {
+		node_id: PublicKey,
+		msg: msgs::OpenChannel,
+	},
+	/// Used to indicate that a funding_created message should be sent to the peer with the given
+	/// node_id.
+	SendFundingCreated {
+		node_id: PublicKey,
+		msg: msgs::FundingCreated,
+	},
+	/// Used to indicate that a funding_locked message should be sent to the peer with the given
+	/// node_id, followed by announcement_signatures if the channel is public.
+	SendFundingLocked {
+		node_id: PublicKey,
+		msg: msgs::FundingLocked,
+		announcement_sigs: Option<msgs::AnnouncementSignatures>,
+	},
+	/// Used to indicate that a series of HTLC update messages, followed by a commitment_signed,
+	/// should be sent to the peer with the given node_id.
+	UpdateHTLCs {
+		node_id: PublicKey,
+		updates: msgs::CommitmentUpdate,
+	},
+	/// Used to indicate that a shutdown message should be sent to the peer with the given node_id.
+	SendShutdown {
+		node_id: PublicKey,
+		msg: msgs::Shutdown,
+	},
+	/// Used to indicate that a closing_signed message should be sent to the peer with the given
+	/// node_id.
+	SendClosingSigned {
+		node_id: PublicKey,
+		msg: msgs::ClosingSigned,
+	},
+	/// Used to indicate that a channel_announcement and channel_update should be broadcast to all
+	/// peers.
+	BroadcastChannelAnnouncement {
+		msg: msgs::ChannelAnnouncement,
+		update_msg: msgs::ChannelUpdate,
+	},
+}
+
+pub trait EventsProvider {
+	/// Gets the list of pending events which were generated by previous actions, clearing the
+	/// list in the process.
+	fn get_and_clear_pending_events(&self) -> Vec<Event>;
+}
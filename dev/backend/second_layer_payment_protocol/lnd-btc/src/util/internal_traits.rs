@@ -0,0 +1,3 @@
+/// A simple marker trait that indicates a type requires no deallocation. Implies we can set_len()
+/// on a Vec of these things and will be safe to overwrite them with mem::uninitialized.
+pub unsafe trait NoDealloc{}
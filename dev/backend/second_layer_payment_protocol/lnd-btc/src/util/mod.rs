@@ -0,0 +1,10 @@
+pub mod events;
+
+pub(crate) mod byte_utils;
+pub(crate) mod chacha20poly1305rfc;
+pub(crate) mod internal_traits;
+pub(crate) mod rng;
+pub(crate) mod sha2;
+
+#[cfg(test)]
+pub(crate) mod test_utils;
@@ -0,0 +1,6 @@
+use rand::{thread_rng, Rng};
+
+pub fn fill_bytes(data: &mut [u8]) {
+	let mut rng = thread_rng();
+	rng.fill_bytes(data);
+}
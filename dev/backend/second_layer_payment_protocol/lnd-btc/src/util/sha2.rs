@@ -0,0 +1 @@
+pub use crypto::sha2::Sha256;
@@ -0,0 +1,103 @@
+use ln::msgs;
+use ln::msgs::HandleError;
+use util::events;
+
+use secp256k1::key::PublicKey;
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub struct TestChannelMessageHandler {
+	pub peers_disconnected: AtomicUsize,
+	pub failed_channels: Mutex<Vec<[u8; 32]>>,
+}
+
+impl TestChannelMessageHandler {
+	pub fn new() -> Self {
+		TestChannelMessageHandler {
+			peers_disconnected: AtomicUsize::new(0),
+			failed_channels: Mutex::new(Vec::new()),
+		}
+	}
+}
+
+impl msgs::ChannelMessageHandler for TestChannelMessageHandler {
+	fn handle_open_channel(&self, _their_node_id: &PublicKey, _msg: &msgs::OpenChannel) -> Result<msgs::AcceptChannel, HandleError> {
+		Err(HandleError { err: "", action: None })
+	}
+	fn handle_accept_channel(&self, _their_node_id: &PublicKey, _msg: &msgs::AcceptChannel) -> Result<(), HandleError> {
+		Err(HandleError { err: "", action: None })
+	}
+	fn handle_funding_created(&self, _their_node_id: &PublicKey, _msg: &msgs::FundingCreated) -> Result<msgs::FundingSigned, HandleError> {
+		Err(HandleError { err: "", action: None })
+	}
+	fn handle_funding_signed(&self, _their_node_id: &PublicKey, _msg: &msgs::FundingSigned) -> Result<(), HandleError> {
+		Err(HandleError { err: "", action: None })
+	}
+	fn handle_funding_locked(&self, _their_node_id: &PublicKey, _msg: &msgs::FundingLocked) -> Result<Option<msgs::AnnouncementSignatures>, HandleError> {
+		Err(HandleError { err: "", action: None })
+	}
+	fn handle_shutdown(&self, _their_node_id: &PublicKey, _msg: &msgs::Shutdown) -> Result<(Option<msgs::Shutdown>, Option<msgs::ClosingSigned>), HandleError> {
+		Err(HandleError { err: "", action: None })
+	}
+	fn handle_closing_signed(&self, _their_node_id: &PublicKey, _msg: &msgs::ClosingSigned) -> Result<Option<msgs::ClosingSigned>, HandleError> {
+		Err(HandleError { err: "", action: None })
+	}
+	fn handle_update_add_htlc(&self, _their_node_id: &PublicKey, _msg: &msgs::UpdateAddHTLC) -> Result<(), HandleError> {
+		Err(HandleError { err: "", action: None })
+	}
+	fn handle_update_fulfill_htlc(&self, _their_node_id: &PublicKey, _msg: &msgs::UpdateFulfillHTLC) -> Result<(), HandleError> {
+		Err(HandleError { err: "", action: None })
+	}
+	fn handle_update_fail_htlc(&self, _their_node_id: &PublicKey, _msg: &msgs::UpdateFailHTLC) -> Result<Option<msgs::HTLCFailChannelUpdate>, HandleError> {
+		Err(HandleError { err: "", action: None })
+	}
+	fn handle_update_fail_malformed_htlc(&self, _their_node_id: &PublicKey, _msg: &msgs::UpdateFailMalformedHTLC) -> Result<(), HandleError> {
+		Err(HandleError { err: "", action: None })
+	}
+	fn handle_commitment_signed(&self, _their_node_id: &PublicKey, _msg: &msgs::CommitmentSigned) -> Result<(msgs::RevokeAndACK, Option<msgs::CommitmentSigned>), HandleError> {
+		Err(HandleError { err: "", action: None })
+	}
+	fn handle_revoke_and_ack(&self, _their_node_id: &PublicKey, _msg: &msgs::RevokeAndACK) -> Result<Option<msgs::CommitmentUpdate>, HandleError> {
+		Err(HandleError { err: "", action: None })
+	}
+	fn handle_update_fee(&self, _their_node_id: &PublicKey, _msg: &msgs::UpdateFee) -> Result<(), HandleError> {
+		Err(HandleError { err: "", action: None })
+	}
+	fn handle_announcement_signatures(&self, _their_node_id: &PublicKey, _msg: &msgs::AnnouncementSignatures) -> Result<(), HandleError> {
+		Err(HandleError { err: "", action: None })
+	}
+	fn peer_disconnected(&self, _their_node_id: &PublicKey, _no_connection_possible: bool) {
+		self.peers_disconnected.fetch_add(1, Ordering::SeqCst);
+	}
+	fn handle_error(&self, _their_node_id: &PublicKey, msg: &msgs::ErrorMessage) {
+		self.failed_channels.lock().unwrap().push(msg.channel_id);
+	}
+}
+
+impl events::EventsProvider for TestChannelMessageHandler {
+	fn get_and_clear_pending_events(&self) -> Vec<events::Event> {
+		Vec::new()
+	}
+}
+
+pub struct TestRoutingMessageHandler {}
+
+impl TestRoutingMessageHandler {
+	pub fn new() -> Self {
+		TestRoutingMessageHandler {}
+	}
+}
+
+impl msgs::RoutingMessageHandler for TestRoutingMessageHandler {
+	fn handle_node_announcement(&self, _msg: &msgs::NodeAnnouncement) -> Result<(), HandleError> {
+		Err(HandleError { err: "", action: None })
+	}
+	fn handle_channel_announcement(&self, _msg: &msgs::ChannelAnnouncement) -> Result<bool, HandleError> {
+		Err(HandleError { err: "", action: None })
+	}
+	fn handle_channel_update(&self, _msg: &msgs::ChannelUpdate) -> Result<(), HandleError> {
+		Err(HandleError { err: "", action: None })
+	}
+	fn handle_htlc_fail_channel_update(&self, _update: &msgs::HTLCFailChannelUpdate) {}
+}